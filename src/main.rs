@@ -6,6 +6,10 @@ use bevy::asset::AssetPath;
 use bevy::winit::WinitWindows;
 use bevy::reflect::std_traits::ReflectDefault;
 use bevy::ecs::schedule::SystemConfig;
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::input::InputSystem;
+use bevy::utils::Uuid;
 use winit::window::Icon;
 
 use std::fs;
@@ -13,15 +17,26 @@ use std::path::{Path};
 use std::fs::File;
 use std::io::Write;
 use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use csv::Reader;
-use csv::StringRecord;
 
+use bevy_quinnet::client::connection::ConnectionLostEvent as ClientConnectionLostEvent;
 use bevy_quinnet::{
     client::{
-        certificate::CertificateVerificationMode, Client, connection::ConnectionConfiguration, connection::ConnectionEvent,
+        certificate::{
+            CertificateConnectionAbortEvent, CertificateInteractionEvent, CertificateUpdateEvent, CertificateVerificationMode,
+            CertVerificationStatus, CertVerifierAction, CertVerifierBehaviour, KnownHosts, TrustOnFirstUseConfig,
+        },
+        Client, connection::ConnectionConfiguration, connection::ConnectionEvent,
         QuinnetClientPlugin, 
     },
+    server::{
+        certificate::CertificateRetrievalMode, ConnectionLostEvent, Endpoint, QuinnetServerPlugin, Server,
+        ServerConfiguration,
+    },
     shared::ClientId,
 };
 
@@ -32,6 +47,7 @@ use clap::{Parser};
 use bevy_inspector_egui::prelude::*;
 use bevy_inspector_egui::quick::ResourceInspectorPlugin;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
+use bevy_egui::{egui, EguiContexts};
 
 use bevy::log::LogPlugin;
 use bevy_log::FileAppenderSettings;
@@ -40,6 +56,8 @@ use bevy_log::Rolling;
 use serde::{Deserialize, Serialize};
 
 use rand::Rng;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
 
 use pathfinding::prelude::astar;
 use std::cell::RefCell;
@@ -66,6 +84,10 @@ enum UnitAction {
 		is_counterattack: bool,
 		damage: usize,
 	},
+	/// Uses a consumable from the unit's inventory, by its index.
+	UseItem {
+		item: usize,
+	},
 	DoNothing,
 }
 
@@ -75,13 +97,16 @@ impl Default for UnitAction {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 enum ClientMessage {
 	GetClientId,
 	StartGame,
 	LoadingComplete,
 	WaitTurnComplete,
-	Wait,
+	/// Ends the turn of the unit, by its ID, so that a late `Wait` can't end the next unit's turn.
+	Wait {
+		unit_id: usize,
+	},
 	Move {
 		origin: Pos,
 		destination: Pos,
@@ -91,9 +116,53 @@ enum ClientMessage {
 		target: Pos,
 		damage: usize,
 	},
+	SetPlayerName {
+		name: String,
+	},
+	ListGames,
+	CreateGame {
+		name: String,
+		scenario: String,
+	},
+	JoinGame {
+		game_id: usize,
+	},
+	LeaveGame,
+	SetReady {
+		ready: bool,
+	},
+	SetTeam {
+		team: usize,
+	},
+	SetScenario {
+		scenario: String,
+	},
+	Chat {
+		scope: ChatScope,
+		message: String,
+	},
+	Spectate,
+	StopSpectating,
+	/// Sent right after `GetClientId`, so the server knows who the player is.
+	Identify {
+		player_id: Uuid,
+		name: String,
+		token: Option<String>,
+	},
+	/// Takes back the current unit's move, as long as it hasn't attacked yet.
+	UndoMove,
+	/// Uses a consumable from the current unit's inventory, by its index.
+	UseItem {
+		user: Pos,
+		item: usize,
+	},
+	/// Where the team's units start the battle, and the way they face, by unit ID.
+	Deploy {
+		units: Vec<(usize, Pos, Direction)>,
+	},
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 enum ServerMessage {
 	ClientId {
 		client_id: ClientId,
@@ -122,10 +191,726 @@ enum ServerMessage {
 	},
 	GameOver {
 		winner: ControlledBy,
+	},
+	GameList {
+		games: Vec<LobbyGameInfo>,
+	},
+	LobbyUpdate {
+		game: LobbyGame,
+	},
+	Chat {
+		sender: ClientId,
+		scope: ChatScope,
+		message: String,
+	},
+	Snapshot {
+		scenario: String,
+		snapshot: BattleSnapshot,
+	},
+	/// Sent after `PlayerTurn` when turns have a time limit.
+	TurnTimer {
+		current_unit: usize,
+		seconds: f32,
+		reserve: f32,
+	},
+	/// Sent to a client whose request the server refused.
+	Error {
+		code: ErrorCode,
+		message: String,
+		/// The origin and destination of the refused move, if it was one.
+		rejected_move: Option<(Pos, Pos)>,
+	},
+	/// The players of the battle, sent when it starts and to spectators.
+	Players {
+		players: Vec<PlayerInfo>,
+	},
+	/// Sent right before `GameOver`, with the team that won the battle.
+	WinningTeam {
+		team: usize,
+	},
+	/// The current unit's move was taken back.
+	UndoMove {
+		origin: Pos,
+		destination: Pos,
+	},
+	/// The unit at `user` used the consumable at `item` in its inventory.
+	UseItem {
+		user: Pos,
+		item: usize,
+	},
+	/// Every team has deployed. Reveals where each unit starts the battle.
+	Deployment {
+		units: Vec<(usize, Pos, Direction)>,
+	},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PlayerInfo {
+	client_id: ClientId,
+	name: String,
+	team: usize,
+}
+
+/// Why the server refused a request.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum ErrorCode {
+	NotYourTurn,
+	InvalidMove,
+	InvalidAttack,
+	UnknownScenario,
+	UnknownGame,
+	InvalidTeam,
+	NotHost,
+	NotInGame,
+	BattleInProgress,
+	NoBattleInProgress,
+	AuthenticationFailed,
+	AlreadyConnected,
+	CannotUndo,
+	InvalidItem,
+	InvalidDeployment,
+	/// The team's deployment was already accepted, e.g. before its player reclaimed their seat.
+	AlreadyDeployed,
+	/// The scenario's files are malformed, so its battle can't start.
+	InvalidScenario,
+}
+
+impl ErrorCode {
+	/// Whether the error refuses an action of the current unit.
+	fn is_action_rejection(&self) -> bool {
+		matches!(self, ErrorCode::NotYourTurn | ErrorCode::InvalidMove | ErrorCode::InvalidAttack | ErrorCode::InvalidItem)
 	}
 }
 
-#[derive(Reflect)]
+impl ClientMessage {
+	/// The variant name, for the network diagnostics.
+	fn name(&self) -> &'static str {
+		match self {
+			ClientMessage::GetClientId => "GetClientId",
+			ClientMessage::StartGame => "StartGame",
+			ClientMessage::LoadingComplete => "LoadingComplete",
+			ClientMessage::WaitTurnComplete => "WaitTurnComplete",
+			ClientMessage::Wait { .. } => "Wait",
+			ClientMessage::Move { .. } => "Move",
+			ClientMessage::BasicAttack { .. } => "BasicAttack",
+			ClientMessage::SetPlayerName { .. } => "SetPlayerName",
+			ClientMessage::ListGames => "ListGames",
+			ClientMessage::CreateGame { .. } => "CreateGame",
+			ClientMessage::JoinGame { .. } => "JoinGame",
+			ClientMessage::LeaveGame => "LeaveGame",
+			ClientMessage::SetReady { .. } => "SetReady",
+			ClientMessage::SetTeam { .. } => "SetTeam",
+			ClientMessage::SetScenario { .. } => "SetScenario",
+			ClientMessage::Chat { .. } => "Chat",
+			ClientMessage::Spectate => "Spectate",
+			ClientMessage::StopSpectating => "StopSpectating",
+			ClientMessage::Identify { .. } => "Identify",
+			ClientMessage::UndoMove => "UndoMove",
+			ClientMessage::UseItem { .. } => "UseItem",
+			ClientMessage::Deploy { .. } => "Deploy",
+		}
+	}
+}
+
+impl ServerMessage {
+	/// The variant name, for the network diagnostics.
+	fn name(&self) -> &'static str {
+		match self {
+			ServerMessage::ClientId { .. } => "ClientId",
+			ServerMessage::StartGame { .. } => "StartGame",
+			ServerMessage::StartGame2 => "StartGame2",
+			ServerMessage::PlayerTurn { .. } => "PlayerTurn",
+			ServerMessage::WaitTurn { .. } => "WaitTurn",
+			ServerMessage::Wait => "Wait",
+			ServerMessage::Move { .. } => "Move",
+			ServerMessage::BasicAttack { .. } => "BasicAttack",
+			ServerMessage::GameOver { .. } => "GameOver",
+			ServerMessage::GameList { .. } => "GameList",
+			ServerMessage::LobbyUpdate { .. } => "LobbyUpdate",
+			ServerMessage::Chat { .. } => "Chat",
+			ServerMessage::Snapshot { .. } => "Snapshot",
+			ServerMessage::TurnTimer { .. } => "TurnTimer",
+			ServerMessage::Error { .. } => "Error",
+			ServerMessage::Players { .. } => "Players",
+			ServerMessage::WinningTeam { .. } => "WinningTeam",
+			ServerMessage::UndoMove { .. } => "UndoMove",
+			ServerMessage::UseItem { .. } => "UseItem",
+			ServerMessage::Deployment { .. } => "Deployment",
+		}
+	}
+}
+
+/// The state of an ongoing battle, sent to spectators when they join.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct BattleSnapshot {
+	units: Vec<UnitSnapshot>,
+	current_unit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct UnitSnapshot {
+	unit_id: usize,
+	pos: Pos,
+	direction: Direction,
+	wt_current: usize,
+	hp_current: usize,
+}
+
+/// A single-player battle saved to disk.
+#[derive(Serialize, Deserialize)]
+struct BattleSave {
+	version: u32,
+	scenario: String,
+	hot_seat: bool,
+	current_unit: usize,
+	current_team: usize,
+	players: HashMap<usize, ControlledBy>,
+	turn_state: TurnState,
+	rng: BattleRng,
+	/// Height and type of every tile.
+	tiles: Vec<Vec<(usize, TileType)>>,
+	units: Vec<SavedUnit>,
+	objective_turns: usize,
+	objective_held_turns: HashMap<Pos, usize>,
+	/// Whether the current unit has already attacked or used an item.
+	#[serde(default)]
+	action_used: bool,
+	/// Roster ids of the deployed units, for a campaign battle.
+	#[serde(default)]
+	campaign_selected: Option<Vec<usize>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedUnit {
+	unit_id: usize,
+	unit_team: usize,
+	unit_name: String,
+	unit_class: String,
+	pos: Pos,
+	wt_max: usize,
+	wt_current: usize,
+	hp_max: usize,
+	hp_current: usize,
+	mp_max: usize,
+	mp_current: usize,
+	str: usize,
+	vit: usize,
+	int: usize,
+	men: usize,
+	agi: usize,
+	dex: usize,
+	luk: usize,
+	#[serde(default = "first_level")]
+	level: usize,
+	#[serde(default)]
+	experience: usize,
+	unit_sprite: String,
+	direction: Direction,
+	movement_range: isize,
+	jump: usize,
+	attack_range: isize,
+	attack_type: AttackType,
+	#[serde(default)]
+	equipment: Equipment,
+	#[serde(default)]
+	inventory: Vec<String>,
+	#[serde(default)]
+	role: UnitRole,
+	/// Queued actions, each with its delay in seconds.
+	unit_actions: Vec<(SavedUnitAction, f32)>,
+	is_current_unit: bool,
+}
+
+impl SavedUnit {
+	fn unit_attributes(&self) -> UnitAttributes {
+		UnitAttributes {
+			unit_id: UnitId { value: self.unit_id, },
+			unit_team: UnitTeam { value: self.unit_team, },
+			unit_name: UnitName { value: self.unit_name.clone(), },
+			unit_class: UnitClass { value: self.unit_class.clone(), },
+			pos_x: PosX { value: self.pos.x, },
+			pos_y: PosY { value: self.pos.y, },
+			wt_max: WTMax { value: self.wt_max, },
+			wt_current: WTCurrent { value: self.wt_current, },
+			hp_max: HPMax { value: self.hp_max, },
+			hp_current: HPCurrent { value: self.hp_current, },
+			mp_max: MPMax { value: self.mp_max, },
+			mp_current: MPCurrent { value: self.mp_current, },
+			str: STR { value: self.str, },
+			vit: VIT { value: self.vit, },
+			int: INT { value: self.int, },
+			men: MEN { value: self.men, },
+			agi: AGI { value: self.agi, },
+			dex: DEX { value: self.dex, },
+			luk: LUK { value: self.luk, },
+			level: Level { value: self.level, },
+			experience: Experience { value: self.experience, },
+			unit_sprite: UnitSprite { value: self.unit_sprite.clone(), },
+			dir: DIR { direction: self.direction, },
+			movement_range: MovementRange { value: self.movement_range, },
+			jump: Jump { value: self.jump, },
+			attack_range: AttackRange { value: self.attack_range, },
+			attack_type: self.attack_type,
+			equipment: self.equipment.clone(),
+			inventory: Inventory { items: self.inventory.clone(), },
+			role: self.role,
+		}
+	}
+}
+
+/// A `UnitAction`, with its timer saved as a duration.
+#[derive(Serialize, Deserialize)]
+enum SavedUnitAction {
+	Move {
+		origin: Pos,
+		destination: Pos,
+		seconds: f32,
+	},
+	Talk {
+		message: String,
+	},
+	BasicAttack {
+		target: Pos,
+		is_counterattack: bool,
+		damage: usize,
+	},
+	UseItem {
+		item: usize,
+	},
+	DoNothing,
+}
+
+impl SavedUnitAction {
+	fn from_unit_action(unit_action: &UnitAction) -> SavedUnitAction {
+		match unit_action {
+			UnitAction::Move { origin, destination, timer } => SavedUnitAction::Move { origin: *origin, destination: *destination, seconds: timer.duration().as_secs_f32(), },
+			UnitAction::Talk { message } => SavedUnitAction::Talk { message: message.clone(), },
+			UnitAction::BasicAttack { target, is_counterattack, damage } => SavedUnitAction::BasicAttack { target: *target, is_counterattack: *is_counterattack, damage: *damage, },
+			UnitAction::UseItem { item } => SavedUnitAction::UseItem { item: *item, },
+			UnitAction::DoNothing => SavedUnitAction::DoNothing,
+		}
+	}
+	
+	fn unit_action(&self) -> UnitAction {
+		match self {
+			SavedUnitAction::Move { origin, destination, seconds } => UnitAction::Move { origin: *origin, destination: *destination, timer: Timer::from_seconds(*seconds, TimerMode::Once), },
+			SavedUnitAction::Talk { message } => UnitAction::Talk { message: message.clone(), },
+			SavedUnitAction::BasicAttack { target, is_counterattack, damage } => UnitAction::BasicAttack { target: *target, is_counterattack: *is_counterattack, damage: *damage, },
+			SavedUnitAction::UseItem { item } => UnitAction::UseItem { item: *item, },
+			SavedUnitAction::DoNothing => UnitAction::DoNothing,
+		}
+	}
+}
+
+/// A recorded battle, replayed by feeding its events through the multiplayer message handlers.
+#[derive(Serialize, Deserialize, Clone)]
+struct Replay {
+	version: u32,
+	scenario: String,
+	seed: u64,
+	events: Vec<ReplayEvent>,
+}
+
+/// A resolved action or turn assignment of a recorded battle.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum ReplayEvent {
+	WaitTurn {
+		wait_turns: Vec<(usize, usize)>,
+	},
+	Turn {
+		unit_id: usize,
+	},
+	Move {
+		origin: Pos,
+		destination: Pos,
+	},
+	BasicAttack {
+		attacker: Pos,
+		target: Pos,
+		damage: usize,
+		is_counterattack: bool,
+	},
+	UndoMove {
+		origin: Pos,
+		destination: Pos,
+	},
+	UseItem {
+		user: Pos,
+		item: usize,
+	},
+	Deploy {
+		units: Vec<(usize, Pos, Direction)>,
+	},
+	Wait,
+}
+
+impl ReplayEvent {
+	/// The server message that replays the event.
+	fn server_message(&self) -> ServerMessage {
+		match self {
+			ReplayEvent::WaitTurn { wait_turns } => ServerMessage::WaitTurn {
+				wait_turns: wait_turns.iter().map(|(unit_id, wt_current)| (UnitId { value: *unit_id, }, WTCurrent { value: *wt_current, })).collect(),
+			},
+			// Nobody watching a replay owns the unit.
+			ReplayEvent::Turn { unit_id } => ServerMessage::PlayerTurn { client_id: ClientId::MAX, current_unit: *unit_id, },
+			ReplayEvent::Move { origin, destination } => ServerMessage::Move { origin: *origin, destination: *destination, },
+			ReplayEvent::BasicAttack { attacker, target, damage, is_counterattack } => ServerMessage::BasicAttack {
+				attacker: *attacker,
+				target: *target,
+				damage: *damage,
+				is_counterattack: *is_counterattack,
+			},
+			ReplayEvent::UndoMove { origin, destination } => ServerMessage::UndoMove { origin: *origin, destination: *destination, },
+			ReplayEvent::UseItem { user, item } => ServerMessage::UseItem { user: *user, item: *item, },
+			ReplayEvent::Deploy { units } => ServerMessage::Deployment { units: units.clone(), },
+			ReplayEvent::Wait => ServerMessage::Wait,
+		}
+	}
+}
+
+/// A campaign: battles fought one after the other, the next one depending on the outcome.
+/// Read from `src/campaign.ron`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CampaignDefinition {
+	name: String,
+	/// The team the roster fights in.
+	team: usize,
+	/// The id of the first battle.
+	start: String,
+	/// The items the campaign starts with, besides those the roster has.
+	#[serde(default)]
+	inventory: Vec<String>,
+	#[serde(default)]
+	recruitment: Recruitment,
+	battles: Vec<CampaignBattle>,
+}
+
+/// Who can join the campaign roster in the barracks.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Recruitment {
+	/// The classes recruits can be of.
+	classes: Vec<String>,
+	/// The names recruits are given, picked at random among those nobody in the roster has.
+	names: Vec<String>,
+	/// How far each stat of a recruit can be from its class's, either way.
+	stat_variance: usize,
+	/// The most living units the roster can have.
+	roster_size: usize,
+}
+
+impl CampaignDefinition {
+	fn battle(&self, id: &str) -> Option<&CampaignBattle> {
+		self.battles.iter().find(|battle| battle.id == id)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CampaignBattle {
+	id: String,
+	name: String,
+	scenario: String,
+	/// The battle fought after a victory. Without one, the campaign ends.
+	on_victory: Option<String>,
+	/// The battle fought after a defeat. Without one, the campaign ends.
+	on_defeat: Option<String>,
+}
+
+/// A unit of the campaign roster. Its stats, HP and death carry over from one battle to the next.
+#[derive(Serialize, Deserialize, Clone)]
+struct RosterUnit {
+	roster_id: usize,
+	name: String,
+	class: String,
+	sprite: String,
+	wt_max: usize,
+	hp_max: usize,
+	hp_current: usize,
+	mp_max: usize,
+	mp_current: usize,
+	str: usize,
+	vit: usize,
+	int: usize,
+	men: usize,
+	agi: usize,
+	dex: usize,
+	luk: usize,
+	#[serde(default = "first_level")]
+	level: usize,
+	#[serde(default)]
+	experience: usize,
+	movement_range: isize,
+	jump: usize,
+	/// The unit's own attack, used without a weapon that has one.
+	attack_range: isize,
+	attack_type: AttackType,
+	#[serde(default)]
+	equipment: Equipment,
+	/// Consumables the unit carries into battle.
+	#[serde(default)]
+	inventory: Vec<String>,
+	/// Battles the unit has been deployed in.
+	battles: usize,
+	dead: bool,
+}
+
+impl RosterUnit {
+	/// Makes a roster unit out of a unit of a scenario.
+	fn from_unit_attributes(roster_id: usize, unit_attributes: &UnitAttributes) -> RosterUnit {
+		RosterUnit {
+			roster_id: roster_id,
+			name: unit_attributes.unit_name.value.clone(),
+			class: unit_attributes.unit_class.value.clone(),
+			sprite: unit_attributes.unit_sprite.value.clone(),
+			wt_max: unit_attributes.wt_max.value,
+			hp_max: unit_attributes.hp_max.value,
+			hp_current: unit_attributes.hp_current.value,
+			mp_max: unit_attributes.mp_max.value,
+			mp_current: unit_attributes.mp_current.value,
+			str: unit_attributes.str.value,
+			vit: unit_attributes.vit.value,
+			int: unit_attributes.int.value,
+			men: unit_attributes.men.value,
+			agi: unit_attributes.agi.value,
+			dex: unit_attributes.dex.value,
+			luk: unit_attributes.luk.value,
+			level: unit_attributes.level.value,
+			experience: unit_attributes.experience.value,
+			movement_range: unit_attributes.movement_range.value,
+			jump: unit_attributes.jump.value,
+			attack_range: unit_attributes.attack_range.value,
+			attack_type: unit_attributes.attack_type,
+			equipment: unit_attributes.equipment.clone(),
+			inventory: unit_attributes.inventory.items.clone(),
+			battles: 0,
+			dead: false,
+		}
+	}
+	
+	/// Makes a new recruit of the class, each of its stats up to `stat_variance` away from the class's.
+	fn recruit(roster_id: usize, name: String, class_name: &str, class: &ClassDefinition, stat_variance: usize, rng: &mut Pcg32) -> RosterUnit {
+		let mut vary = |value: usize| -> usize {
+			// Stats the class doesn't have, like the MP of warriors, stay at 0.
+			if value == 0 || stat_variance == 0 {
+				return value;
+			}
+			let variance = stat_variance as isize;
+			(value as isize + rng.gen_range(-variance..=variance)).max(1) as usize
+		};
+		let hp = vary(class.stats.hp);
+		let mp = vary(class.stats.mp);
+		
+		RosterUnit {
+			roster_id: roster_id,
+			name: name,
+			class: class_name.to_string(),
+			sprite: class.sprite.clone(),
+			wt_max: class.stats.wt,
+			hp_max: hp,
+			hp_current: hp,
+			mp_max: mp,
+			mp_current: mp,
+			str: vary(class.stats.str),
+			vit: vary(class.stats.vit),
+			int: vary(class.stats.int),
+			men: vary(class.stats.men),
+			agi: vary(class.stats.agi),
+			dex: vary(class.stats.dex),
+			luk: vary(class.stats.luk),
+			level: first_level(),
+			experience: 0,
+			movement_range: class.movement,
+			jump: class.jump,
+			attack_range: class.attack.range,
+			attack_type: class.attack.attack_type,
+			equipment: Equipment::default(),
+			inventory: Vec::new(),
+			battles: 0,
+			dead: false,
+		}
+	}
+	
+	/// Puts the unit in a deployment slot of a scenario.
+	/// The slot keeps its unit id, team, position, WT and direction.
+	fn apply(&self, unit_attributes: &mut UnitAttributes) {
+		unit_attributes.unit_name = UnitName { value: self.name.clone(), };
+		unit_attributes.unit_class = UnitClass { value: self.class.clone(), };
+		unit_attributes.wt_max = WTMax { value: self.wt_max, };
+		unit_attributes.hp_max = HPMax { value: self.hp_max, };
+		unit_attributes.hp_current = HPCurrent { value: self.hp_current, };
+		unit_attributes.mp_max = MPMax { value: self.mp_max, };
+		unit_attributes.mp_current = MPCurrent { value: self.mp_current, };
+		unit_attributes.str = STR { value: self.str, };
+		unit_attributes.vit = VIT { value: self.vit, };
+		unit_attributes.int = INT { value: self.int, };
+		unit_attributes.men = MEN { value: self.men, };
+		unit_attributes.agi = AGI { value: self.agi, };
+		unit_attributes.dex = DEX { value: self.dex, };
+		unit_attributes.luk = LUK { value: self.luk, };
+		unit_attributes.level = Level { value: self.level, };
+		unit_attributes.experience = Experience { value: self.experience, };
+		unit_attributes.unit_sprite = UnitSprite { value: self.sprite.clone(), };
+		unit_attributes.movement_range = MovementRange { value: self.movement_range, };
+		unit_attributes.jump = Jump { value: self.jump, };
+		unit_attributes.attack_range = AttackRange { value: self.attack_range, };
+		unit_attributes.attack_type = self.attack_type;
+		unit_attributes.equipment = self.equipment.clone();
+		unit_attributes.inventory = Inventory { items: self.inventory.clone(), };
+	}
+	
+	/// Takes the bonuses of the unit's equipment back off its stats, once a battle is over.
+	/// The unit's own attack is restored by `update_roster`, from the roster unit.
+	fn remove_equipment_bonus(&mut self, items: &Items) {
+		let bonus = items.bonus(&self.equipment);
+		self.hp_max = self.hp_max.saturating_sub(bonus.hp);
+		// Taking off armor doesn't heal the unit, nor kill a unit that survived.
+		self.hp_current = self.hp_current.saturating_sub(bonus.hp).max(self.hp_current.min(1));
+		self.mp_max = self.mp_max.saturating_sub(bonus.mp);
+		self.mp_current = self.mp_current.saturating_sub(bonus.mp);
+		self.str = self.str.saturating_sub(bonus.str);
+		self.vit = self.vit.saturating_sub(bonus.vit);
+		self.int = self.int.saturating_sub(bonus.int);
+		self.men = self.men.saturating_sub(bonus.men);
+		self.agi = self.agi.saturating_sub(bonus.agi);
+		self.dex = self.dex.saturating_sub(bonus.dex);
+		self.luk = self.luk.saturating_sub(bonus.luk);
+	}
+}
+
+/// How far the campaign has gone, saved to disk after every battle.
+#[derive(Serialize, Deserialize, Clone)]
+struct CampaignProgress {
+	version: u32,
+	/// The id of the next battle, or `None` once the campaign is over.
+	current_battle: Option<String>,
+	roster: Vec<RosterUnit>,
+	/// The id of every battle fought so far, and whether it was won.
+	history: Vec<(String, bool)>,
+	/// Items not carried by any roster unit.
+	#[serde(default)]
+	inventory: Vec<String>,
+	/// Draws the names and stats of recruits.
+	/// Its state is saved with the campaign, so reloading doesn't draw them again.
+	#[serde(default)]
+	rng: BattleRng,
+	/// The roster id of the next recruit. Ids of dismissed units aren't given out again.
+	#[serde(default)]
+	next_roster_id: usize,
+}
+
+/// Who receives a chat message.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum ChatScope {
+	All,
+	Team,
+}
+
+/// An open game, as listed in the lobby.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct LobbyGameInfo {
+	game_id: usize,
+	name: String,
+	scenario: String,
+	players: usize,
+	/// How many teams the scenario has, with one player each.
+	teams: usize,
+}
+
+/// A game being set up in the lobby.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct LobbyGame {
+	game_id: usize,
+	name: String,
+	scenario: String,
+	host: ClientId,
+	players: Vec<LobbyPlayer>,
+	/// The teams of the scenario that players can take.
+	teams: Vec<usize>,
+	/// Seconds left before the battle starts, once every player is ready.
+	countdown: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct LobbyPlayer {
+	client_id: ClientId,
+	name: String,
+	team: usize,
+	ready: bool,
+}
+
+/// The longest name a roster unit can be given, in characters.
+const UNIT_NAME_MAX_LENGTH: usize = 24;
+
+/// The longest chat message that can be sent, in characters.
+const CHAT_MESSAGE_MAX_LENGTH: usize = 200;
+
+/// How many chat lines are kept in the history.
+const CHAT_HISTORY_LENGTH: usize = 100;
+
+/// Words replaced with asterisks in chat messages.
+const CHAT_FILTERED_WORDS: [&str; 6] = ["damn", "hell", "crap", "shit", "fuck", "bastard"];
+
+/// The file the fingerprints of known servers are kept in.
+const KNOWN_HOSTS_FILE: &str = "known_hosts";
+
+/// How long a toast stays on screen, in seconds.
+const TOAST_DURATION_SECONDS: f32 = 4.0;
+
+/// The directory battles are saved in.
+const SAVES_DIRECTORY: &str = "saves";
+
+/// The save slot used by quick save and quick load.
+const QUICK_SAVE_SLOT: &str = "quicksave";
+
+/// Bumped whenever `BattleSave` changes in a way older saves can't be read with.
+const SAVE_VERSION: u32 = 2;
+
+/// The directory replays are written to.
+const REPLAYS_DIRECTORY: &str = "replays";
+
+/// Bumped whenever `Replay` changes in a way older replays can't be read with.
+const REPLAY_VERSION: u32 = 1;
+
+/// Seconds between two replayed events at normal speed.
+const REPLAY_EVENT_DELAY_SECONDS: f32 = 0.5;
+
+/// The speed a replay is fast-forwarded at while seeking.
+const REPLAY_SEEK_SPEED: f32 = 20.0;
+
+/// Experience a unit gains for attacking.
+const XP_PER_ATTACK: usize = 10;
+
+/// Extra experience a unit gains for defeating its target.
+const XP_PER_KILL: usize = 20;
+
+/// Experience a unit gains for using an item.
+const XP_PER_ITEM: usize = 5;
+
+/// Experience a unit needs to gain a level.
+const XP_PER_LEVEL: usize = 100;
+
+/// Extra seconds the local server waits for a client to end its own turn.
+const TURN_TIMER_GRACE_SECONDS: f32 = 2.0;
+
+/// The scenarios that can be chosen in the lobby.
+/// Each one has its units in `src/<scenario>_data.csv`.
+const SCENARIOS: [&str; 3] = ["the_patrol_ambush", "the_pursuit", "the_retreat"];
+
+/// The unit classes, with their stats, growths, movement and attacks.
+const CLASSES_FILE: &str = "src/classes.ron";
+
+/// The equipment and consumable items.
+const ITEMS_FILE: &str = "src/items.ron";
+
+/// The campaign played from the main menu.
+const CAMPAIGN_FILE: &str = "src/campaign.ron";
+
+/// The file campaign progress is saved to.
+const CAMPAIGN_SAVE_PATH: &str = "campaign_save.ron";
+
+/// Bumped whenever `CampaignProgress` changes in a way older campaign saves can't be read with.
+const CAMPAIGN_SAVE_VERSION: u32 = 2;
+
+#[derive(Reflect, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 #[reflect(Default)]
 enum Direction {
 	East,
@@ -145,6 +930,16 @@ impl Direction {
 			_ => panic!("Invalid Direction string: {}", dir_string),
 		}
 	}
+	
+	/// The direction a quarter turn clockwise.
+	fn turned(&self) -> Direction {
+		match self {
+			Direction::East => Direction::South,
+			Direction::South => Direction::West,
+			Direction::West => Direction::North,
+			Direction::North => Direction::East,
+		}
+	}
 }
 
 impl Default for Direction {
@@ -187,6 +982,49 @@ struct MoveCommand {
 	y: usize,
 }
 
+/// Netstats command
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "netstats")]
+struct NetstatsCommand {}
+
+/// Save command
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "save")]
+struct SaveCommand {
+	/// The name of the save slot.
+	#[arg(default_value = QUICK_SAVE_SLOT)]
+	slot: String,
+}
+
+/// Load command
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "load")]
+struct LoadCommand {
+	/// The name of the save slot.
+	#[arg(default_value = QUICK_SAVE_SLOT)]
+	slot: String,
+}
+
+/// Replay command
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "replay")]
+struct ReplayCommand {
+	/// The name of the replay, or the path to a replay file.
+	name: String,
+}
+
+/// Say command
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "say")]
+struct SayCommand {
+	/// Only send the message to your team.
+	#[arg(long)]
+	team: bool,
+	
+	/// The message to send.
+	msg: Vec<String>,
+}
+
 // COMPONENTS
 
 #[derive(Component)]
@@ -201,9 +1039,30 @@ struct StartAmbushButton {}
 #[derive(Component)]
 struct StartMultiplayerButton {}
 
+#[derive(Component)]
+struct StartHotSeatButton {}
+
+#[derive(Component)]
+struct PassControlsUI {}
+
+#[derive(Component)]
+struct GameOverUI {}
+
+#[derive(Component)]
+struct ReturnToMainMenuButton {}
+
 #[derive(Component)]
 struct QuitGameButton {}
 
+#[derive(Component)]
+struct LobbyUI {}
+
+#[derive(Component)]
+struct StartCampaignButton {}
+
+#[derive(Component)]
+struct CampaignUI {}
+
 #[derive(Component)]
 struct NakedSwordsman {
 
@@ -223,6 +1082,10 @@ struct Target {}
 #[derive(Component)]
 struct MoveTile {}
 
+/// Marks a tile of the zone the deploying team places its units in.
+#[derive(Component)]
+struct DeploymentTile {}
+
 #[derive(Component)]
 struct MoveTiles {
 	move_tiles: Vec<Pos>,
@@ -260,6 +1123,11 @@ struct BasicAttackAction {
 	damage: usize,
 }
 
+#[derive(Component)]
+struct UseItemAction {
+	item: usize,
+}
+
 #[derive(Component)]
 struct DoNothingAction;
 
@@ -298,7 +1166,7 @@ struct Map {
 #[derive(Component)]
 struct Tile;
 
-#[derive(Component, Debug, Clone, PartialEq)]
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
 enum TileType {
 	Grass, 
 }
@@ -380,6 +1248,13 @@ struct DEX { value: usize, }
 #[derive(Component)]
 struct LUK { value: usize, }
 
+#[derive(Component)]
+struct Level { value: usize, }
+
+/// Experience gained towards the next level.
+#[derive(Component)]
+struct Experience { value: usize, }
+
 #[derive(Component)]
 struct UnitSprite { value: String, }
 
@@ -391,11 +1266,52 @@ struct DIR { direction: Direction, }
 #[reflect(Default)]
 struct MovementRange { value: isize, }
 
+/// The height difference a unit can climb or drop from one tile to the next.
+#[derive(Component)]
+struct Jump { value: usize, }
+
+/// The items a unit has equipped, by name. Their bonuses are included in the unit's stats.
+#[derive(Component, Default, Clone, Serialize, Deserialize, Debug)]
+struct Equipment {
+	weapon: Option<String>,
+	shield: Option<String>,
+	armor: Option<String>,
+	accessory: Option<String>,
+}
+
+impl Equipment {
+	fn slot(&self, slot: EquipmentSlot) -> &Option<String> {
+		match slot {
+			EquipmentSlot::Weapon => &self.weapon,
+			EquipmentSlot::Shield => &self.shield,
+			EquipmentSlot::Armor => &self.armor,
+			EquipmentSlot::Accessory => &self.accessory,
+		}
+	}
+	
+	fn slot_mut(&mut self, slot: EquipmentSlot) -> &mut Option<String> {
+		match slot {
+			EquipmentSlot::Weapon => &mut self.weapon,
+			EquipmentSlot::Shield => &mut self.shield,
+			EquipmentSlot::Armor => &mut self.armor,
+			EquipmentSlot::Accessory => &mut self.accessory,
+		}
+	}
+	
+	fn items(&self) -> impl Iterator<Item = &String> {
+		[&self.weapon, &self.shield, &self.armor, &self.accessory].into_iter().flatten()
+	}
+}
+
+/// The consumables a unit carries, by name.
+#[derive(Component, Default, Clone)]
+struct Inventory { items: Vec<String>, }
+
 #[derive(Component, Default, Reflect)]
 #[reflect(Default)]
 struct AttackRange { value: isize, }
 
-#[derive(Component, Default, Reflect, Clone, Copy)]
+#[derive(Component, Default, Reflect, Clone, Copy, Serialize, Deserialize, Debug)]
 #[reflect(Default)]
 enum AttackType {
 	#[default]
@@ -403,6 +1319,30 @@ enum AttackType {
 	Ranged,
 }
 
+/// How a unit takes part in the battle, besides its team.
+#[derive(Component, Default, Reflect, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[reflect(Default)]
+enum UnitRole {
+	#[default]
+	Regular,
+	/// Fights for its team, but the AI always controls it.
+	Guest,
+	/// Doesn't fight. The AI moves it away from hostile units.
+	/// Objectives only count it by name, e.g. a villager to protect.
+	Neutral,
+}
+
+impl UnitRole {
+	/// The tint of the unit's sprite.
+	fn color(&self) -> Color {
+		match self {
+			UnitRole::Regular => Color::WHITE,
+			UnitRole::Guest => Color::rgb(0.7, 0.9, 1.0),
+			UnitRole::Neutral => Color::rgb(1.0, 0.95, 0.6),
+		}
+	}
+}
+
 impl AttackType {
 	fn from_string(string: String) -> AttackType {
 		match string.as_str() {
@@ -434,11 +1374,41 @@ struct UnitAttributes {
 	agi: AGI,
 	dex: DEX,
 	luk: LUK,
+	level: Level,
+	experience: Experience,
 	unit_sprite: UnitSprite,
 	dir: DIR,
 	movement_range: MovementRange,
+	jump: Jump,
 	attack_range: AttackRange,
 	attack_type: AttackType,
+	equipment: Equipment,
+	inventory: Inventory,
+	role: UnitRole,
+}
+
+impl UnitAttributes {
+	/// Adds the bonuses of the unit's equipment to its stats.
+	/// A weapon with an attack of its own replaces the unit's attack.
+	fn apply_equipment(&mut self, items: &Items) {
+		let bonus = items.bonus(&self.equipment);
+		self.hp_max.value += bonus.hp;
+		self.hp_current.value += bonus.hp;
+		self.mp_max.value += bonus.mp;
+		self.mp_current.value += bonus.mp;
+		self.str.value += bonus.str;
+		self.vit.value += bonus.vit;
+		self.int.value += bonus.int;
+		self.men.value += bonus.men;
+		self.agi.value += bonus.agi;
+		self.dex.value += bonus.dex;
+		self.luk.value += bonus.luk;
+		
+		if let Some(attack) = items.attack(&self.equipment) {
+			self.attack_range = AttackRange { value: attack.range, };
+			self.attack_type = attack.attack_type;
+		}
+	}
 }
 
 // STATES
@@ -456,12 +1426,15 @@ enum GameState {
 	WaitTurn,
 	Wait,
 	LoadAmbush,
+	Deployment,
 	Ambush,
 	SinglePlayerPause,
 	GameOver,
+	Lobby,
+	Campaign,
 }
 
-#[derive(Reflect, States, Debug, Clone, Eq, PartialEq, Hash, Default)]
+#[derive(Reflect, States, Debug, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
 #[reflect(Default)]
 enum TurnState {
 	#[default]
@@ -470,6 +1443,7 @@ enum TurnState {
 	ChooseMove,
 	ChooseAttack,
 	AI,
+	PassControls,
 }
 
 // EVENTS
@@ -478,21 +1452,160 @@ enum TurnState {
 struct GameStartEvent;
 
 #[derive(Event)]
-struct MapReadEvent {
-	pub map: Vec<Vec<String>>,
+struct SaveBattleEvent {
+	slot: String,
 }
 
 #[derive(Event)]
-struct MapSetupEvent;
+struct LoadBattleEvent {
+	slot: String,
+}
+
+#[derive(Event)]
+struct ConfirmDeploymentEvent;
+
+/// Takes a unit's move back, in single-player or once the server accepted it.
+#[derive(Event)]
+struct UndoMoveEvent {
+	unit_id: usize,
+	origin: Pos,
+	destination: Pos,
+}
+
+#[derive(Event)]
+struct PlayReplayEvent {
+	path: String,
+}
+
+#[derive(Event)]
+struct MapReadEvent {
+	pub map: Vec<Vec<String>>,
+}
+
+#[derive(Event)]
+struct MapSetupEvent;
 
 #[derive(Event)]
 struct UnitsReadEvent {
-	pub units: Vec<StringRecord>,
+	pub units: Vec<UnitRecord>,
 }
 
 #[derive(Event)]
 struct UnitsGeneratedEvent;
 
+/// Sent when a unit earns experience, e.g. for an attack.
+#[derive(Event)]
+struct GainExperienceEvent {
+	unit: Entity,
+	experience: usize,
+}
+
+#[derive(Event)]
+struct ClientIdMessageEvent {
+	client_id: ClientId,
+}
+
+#[derive(Event)]
+struct StartGameMessageEvent {
+	client_id: ClientId,
+}
+
+#[derive(Event)]
+struct PlayerTurnMessageEvent {
+	client_id: ClientId,
+	current_unit: usize,
+}
+
+#[derive(Event)]
+struct WaitTurnMessageEvent {
+	wait_turns: Vec<(UnitId, WTCurrent)>,
+}
+
+#[derive(Event)]
+struct WaitMessageEvent;
+
+#[derive(Event)]
+struct MoveMessageEvent {
+	origin: Pos,
+	destination: Pos,
+}
+
+#[derive(Event)]
+struct BasicAttackMessageEvent {
+	attacker: Pos,
+	target: Pos,
+	damage: usize,
+	is_counterattack: bool,
+}
+
+#[derive(Event)]
+struct UseItemMessageEvent {
+	user: Pos,
+	item: usize,
+}
+
+#[derive(Event)]
+struct DeploymentMessageEvent {
+	units: Vec<(usize, Pos, Direction)>,
+}
+
+#[derive(Event)]
+struct GameOverMessageEvent {
+	winner: ControlledBy,
+}
+
+#[derive(Event)]
+struct WinningTeamMessageEvent {
+	team: usize,
+}
+
+#[derive(Event)]
+struct PlayersMessageEvent {
+	players: Vec<PlayerInfo>,
+}
+
+#[derive(Event)]
+struct GameListMessageEvent {
+	games: Vec<LobbyGameInfo>,
+}
+
+#[derive(Event)]
+struct LobbyUpdateMessageEvent {
+	game: LobbyGame,
+}
+
+#[derive(Event)]
+struct ActionRejectedMessageEvent {
+	reason: String,
+	rejected_move: Option<(Pos, Pos)>,
+}
+
+#[derive(Event)]
+struct ErrorMessageEvent {
+	code: ErrorCode,
+	message: String,
+}
+
+#[derive(Event)]
+struct TurnTimerMessageEvent {
+	current_unit: usize,
+	seconds: f32,
+	reserve: f32,
+}
+
+#[derive(Event)]
+struct SnapshotMessageEvent {
+	scenario: String,
+	snapshot: BattleSnapshot,
+}
+
+#[derive(Event)]
+struct ChatMessageEvent {
+	sender: ClientId,
+	scope: ChatScope,
+	message: String,
+}
+
 // RESOURCES
 
 #[derive(Resource)]
@@ -502,6 +1615,8 @@ struct Game {
 	players: HashMap<usize, ControlledBy>,
 	winner: ControlledBy,
 	is_multiplayer: bool,
+	is_spectator: bool,
+	scenario: String,
 }
 
 impl Default for Game {
@@ -512,6 +1627,8 @@ impl Default for Game {
             players: HashMap::new(),
             winner: ControlledBy::None,
             is_multiplayer: false, 
+            is_spectator: false,
+            scenario: SCENARIOS[0].to_string(),
         }
     }
 }
@@ -523,123 +1640,1265 @@ enum ControlledBy {
 	None,
 }
 
-#[derive(Resource, Default)]
-struct ClientData {
-	client_id: ClientId,
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+enum Stance {
+	Allied,
+	Neutral,
+	Hostile,
 }
 
-#[derive(Resource, Default)]
-struct DemoData {
-	current_unit: UnitId,
+impl Stance {
+	fn from_string(string: String) -> Result<Stance, String> {
+		match string.as_str() {
+			"Allied" => { return Ok(Stance::Allied); },
+			"Neutral" => { return Ok(Stance::Neutral); },
+			"Hostile" => { return Ok(Stance::Hostile); },
+			_ => { return Err(format!("Invalid Stance string: {}.", string)); },
+		}
+	}
 }
 
-#[derive(Resource)]
-struct Slog {
-	logger: slog::Logger,
+/// How the teams of the current battle regard each other.
+/// Teams without an entry are hostile to each other.
+#[derive(Resource, Default, Clone)]
+struct Alliances {
+	stances: HashMap<(usize, usize), Stance>,
 }
 
-// Client & Server
-fn main() {
-	std::panic::set_hook(Box::new(custom_panic_hook));
+impl Alliances {
+	fn stance(&self, team: usize, other_team: usize) -> Stance {
+		if team == other_team {
+			return Stance::Allied;
+		}
+		
+		self.stances.get(&(team.min(other_team), team.max(other_team))).copied().unwrap_or(Stance::Hostile)
+	}
 	
-//	// Setup the logger
-//    let log = setup_logging();
+	fn set_stance(&mut self, team: usize, other_team: usize, stance: Stance) {
+		self.stances.insert((team.min(other_team), team.max(other_team)), stance);
+	}
 	
-    let mut app = App::new();
-//  app.insert_resource(Slog { logger: log, });
-	app.add_plugins(
-		DefaultPlugins.set(WindowPlugin {
-			primary_window: Some(Window {
-				title: "Ars Militaris".into(),
-				..default()
-			}),
-			..default()
-		})
-//		.disable::<LogPlugin>()
-		.set(LogPlugin {
-			file_appender_settings: Some(FileAppenderSettings {
-				prefix: String::from("amclient.log"),
-				rolling: Rolling::Minutely,
-				..default()
-			}),
-			..default()
+	/// Whether two of the given teams are still hostile to each other.
+	fn has_hostile_teams(&self, teams: &HashSet<usize>) -> bool {
+		teams.iter().any(|team| teams.iter().any(|other_team| self.stance(*team, *other_team) == Stance::Hostile))
+	}
+}
+
+/// Optional rules of the current battle, read from `src/<scenario>_rules.ron`.
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+struct ScenarioRules {
+	/// Units stop moving when they step next to a hostile unit.
+	zone_of_control: bool,
+}
+
+impl ScenarioRules {
+	/// The tiles moving units have to stop on. Empty unless the scenario turns zone of control on.
+	fn zone_of_control(&self, map: &Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, hostile_positions: &Vec<Pos>) -> HashSet<Pos> {
+		if !self.zone_of_control {
+			return HashSet::new();
+		}
+		
+		zone_of_control(map, hostile_positions)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum Objective {
+	/// No unit hostile to the team is left.
+	DefeatAllEnemies,
+	/// The unit with this name is dead, e.g. an enemy leader or a VIP to protect.
+	UnitDefeated { unit_name: String },
+	/// The team has taken this many team turns.
+	SurviveTurns { turns: usize },
+	/// A unit of the team, or of an ally, stands on the tile.
+	ReachTile { pos: Pos },
+	/// A unit of the team, or of an ally, has started this many team turns in a row on the tile.
+	HoldTile { pos: Pos, turns: usize },
+	/// No unit of the team, or of an ally, is left.
+	AllUnitsLost,
+}
+
+impl Objective {
+	fn describe(&self) -> String {
+		match self {
+			Objective::DefeatAllEnemies => String::from("Defeat all enemies"),
+			Objective::UnitDefeated { unit_name } => format!("{} is defeated", unit_name),
+			Objective::SurviveTurns { turns } => format!("Survive {} turns", turns),
+			Objective::ReachTile { pos } => format!("Reach tile {}, {}", pos.x, pos.y),
+			Objective::HoldTile { pos, turns } => format!("Hold tile {}, {} for {} turns", pos.x, pos.y, turns),
+			Objective::AllUnitsLost => String::from("All units are lost"),
+		}
+	}
+}
+
+/// What a scenario asks of one team to win, and what makes it lose.
+/// Read from `src/<scenario>_objectives.ron`.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+struct Objectives {
+	team: usize,
+	victory: Vec<Objective>,
+	defeat: Vec<Objective>,
+	/// Team turns taken by the team so far.
+	#[serde(skip)]
+	turns: usize,
+	/// Team turns in a row that started with the tile held.
+	#[serde(skip)]
+	held_turns: HashMap<Pos, usize>,
+	/// The team of the unit that took the last turn.
+	#[serde(skip)]
+	last_team: Option<usize>,
+}
+
+impl Default for Objectives {
+	fn default() -> Self {
+		Objectives {
+			team: 1,
+			victory: vec![Objective::DefeatAllEnemies],
+			defeat: vec![Objective::AllUnitsLost],
+			turns: 0,
+			held_turns: HashMap::new(),
+			last_team: None,
+		}
+	}
+}
+
+impl Objectives {
+	/// Checks an objective against the team, name, position and role of every unit still standing.
+	/// Neutral units only count for `UnitDefeated`.
+	fn is_met(&self, objective: &Objective, units: &Vec<(usize, String, Pos, UnitRole)>, alliances: &Alliances) -> bool {
+		let mut fighting_units = units.iter().filter(|(_, _, _, role)| *role != UnitRole::Neutral);
+		match objective {
+			Objective::DefeatAllEnemies => !fighting_units.any(|(team, _, _, _)| alliances.stance(self.team, *team) == Stance::Hostile),
+			Objective::UnitDefeated { unit_name } => !units.iter().any(|(_, name, _, _)| name == unit_name),
+			Objective::SurviveTurns { turns } => self.turns >= *turns,
+			Objective::ReachTile { pos } => fighting_units.any(|(team, _, unit_pos, _)| unit_pos == pos && alliances.stance(self.team, *team) == Stance::Allied),
+			Objective::HoldTile { pos, turns } => self.held_turns.get(pos).copied().unwrap_or(0) >= *turns,
+			Objective::AllUnitsLost => !fighting_units.any(|(team, _, _, _)| alliances.stance(self.team, *team) == Stance::Allied),
+		}
+	}
+	
+	/// The names of the units that `UnitDefeated` objectives are about.
+	fn unit_names(&self) -> Vec<String> {
+		self.victory
+			.iter()
+			.chain(self.defeat.iter())
+			.filter_map(|objective| match objective {
+				Objective::UnitDefeated { unit_name } => Some(unit_name.clone()),
+				_ => None,
+			})
+			.collect()
+	}
+	
+	/// How far the team is on objectives that take several turns.
+	fn progress(&self, objective: &Objective) -> Option<(usize, usize)> {
+		match objective {
+			Objective::SurviveTurns { turns } => Some((self.turns.min(*turns), *turns)),
+			Objective::HoldTile { pos, turns } => Some((self.held_turns.get(pos).copied().unwrap_or(0).min(*turns), *turns)),
+			_ => None,
+		}
+	}
+	
+	/// Whether the battle is won or lost for the team, and the objective that decided it.
+	/// Defeat is checked first, so that losing a VIP on the winning turn still loses the battle.
+	fn outcome(&self, units: &Vec<(usize, String, Pos, UnitRole)>, alliances: &Alliances) -> Option<(bool, Objective)> {
+		if let Some(objective) = self.defeat.iter().find(|objective| self.is_met(objective, units, alliances)) {
+			return Some((false, objective.clone()));
+		}
+		
+		if let Some(objective) = self.victory.iter().find(|objective| self.is_met(objective, units, alliances)) {
+			return Some((true, objective.clone()));
+		}
+		
+		None
+	}
+	
+	/// Starts the turn of a unit of the team. Units of the same team that take their turns
+	/// one after another share one team turn, so a team turn only starts after another team's unit.
+	fn start_unit_turn(&mut self, team: usize, units: &Vec<(usize, Pos)>, alliances: &Alliances) {
+		let starts_team_turn = team == self.team && self.last_team != Some(team);
+		self.last_team = Some(team);
+		
+		if starts_team_turn {
+			self.start_team_turn(units, alliances);
+		}
+	}
+	
+	/// Counts a new turn of the team, and whether it starts with each held tile occupied.
+	fn start_team_turn(&mut self, units: &Vec<(usize, Pos)>, alliances: &Alliances) {
+		self.turns += 1;
+		
+		let held_tiles: Vec<Pos> = self.victory
+			.iter()
+			.chain(self.defeat.iter())
+			.filter_map(|objective| match objective {
+				Objective::HoldTile { pos, .. } => Some(*pos),
+				_ => None,
+			})
+			.collect();
+		
+		for held_tile in held_tiles {
+			let held = units.iter().any(|(team, unit_pos)| *unit_pos == held_tile && alliances.stance(self.team, *team) == Stance::Allied);
+			if held {
+				*self.held_turns.entry(held_tile).or_insert(0) += 1;
+			} else {
+				self.held_turns.insert(held_tile, 0);
+			}
+		}
+	}
+}
+
+/// The tiles, from one corner to the other, a team places its units on before the battle.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct DeploymentZone {
+	team: usize,
+	from: Pos,
+	to: Pos,
+}
+
+/// Where teams deploy their units before a battle.
+/// Read from `src/<scenario>_deployment.ron`.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default)]
+struct DeploymentZones {
+	zones: Vec<DeploymentZone>,
+}
+
+impl DeploymentZones {
+	fn contains(&self, team: usize, pos: Pos) -> bool {
+		self.zones.iter().any(|zone| {
+			zone.team == team
+				&& pos.x >= zone.from.x.min(zone.to.x) && pos.x <= zone.from.x.max(zone.to.x)
+				&& pos.y >= zone.from.y.min(zone.to.y) && pos.y <= zone.from.y.max(zone.to.y)
 		})
-	);
-	app.add_plugin(QuinnetClientPlugin::default());
-	app.add_plugins(ConsolePlugin)
-		.insert_resource(ConsoleConfiguration {
-			// Override config here.
-			keys: vec![ToggleConsoleKey::KeyCode(KeyCode::Backslash)],
-			left_pos: 0.0,
-			top_pos: 600.0,
-			height: 150.0,
-			width: 400.0,
-			..Default::default()
-		});
-	app.add_plugin(WorldInspectorPlugin::new());
-	app.register_type::<ConsoleConfiguration>();
-	app.register_type::<HPCurrent>();
-	app.register_type::<UnitActions>();
-	app.register_type::<UnitAction>();
-	app.register_type::<UnitActionTuple>();
-	app.register_type::<Pos>();
-	app.register_type::<WTCurrent>();
-	app.register_type::<DIR>();
-	app.register_type::<MovementRange>();
-	app.register_type::<AttackRange>();
-	app.register_type::<AttackType>();
-//	app.add_plugin(ResourceInspectorPlugin::<ConsoleConfiguration>::default());
-//	app.add_plugin(ResourceInspectorPlugin::<State<GameState>>::default());
-//	app.add_plugin(ResourceInspectorPlugin::<State<TurnState>>::default());
-//	app.add_plugin(ResourceInspectorPlugin::<NextState<GameState>>::default());
-//	app.add_plugin(ResourceInspectorPlugin::<NextState<TurnState>>::default());
-	app.add_console_command::<DoNothingCommand, _>(do_nothing_command);
-	app.add_console_command::<TalkCommand, _>(talk_command);
-	app.add_console_command::<MoveCommand, _>(move_command);
-	app.add_state::<GameState>();
-	app.add_state::<TurnState>();
-	app.add_event::<GameStartEvent>();
-	app.add_event::<MapReadEvent>();
-	app.add_event::<MapSetupEvent>();
-	app.add_event::<UnitsReadEvent>();
-	app.add_event::<UnitsGeneratedEvent>();
-	app.init_resource::<Game>();
-	app.init_resource::<ClientData>();
-	app.init_resource::<DemoData>();
+	}
 	
-	if cfg!(windows) {
-		app.add_systems(Startup, set_window_icon);
-	} 
+	/// The teams that deploy their units, in order.
+	fn teams(&self) -> Vec<usize> {
+		let mut teams: Vec<usize> = self.zones.iter().map(|zone| zone.team).collect();
+		teams.sort();
+		teams.dedup();
+		teams
+	}
 	
-//	app.add_systems(Startup, test_slog);
-	app.add_systems(OnEnter(GameState::MainMenu),
-		(start_connection, send_get_client_id_message)
-			.chain()
-	);
-	app.add_systems(OnEnter(GameState::MainMenu), setup_main_menu);
-	app.add_systems(OnExit(GameState::MainMenu), tear_down_main_menu);
-	app.add_systems(Update, handle_main_menu_buttons
-		.run_if(in_state(GameState::MainMenu))
-	);
-	app.add_systems(Update,
-		(send_start_game_message_system, handle_server_messages)
-			.run_if(in_state(GameState::MainMenu))
-	);
-//	app.add_systems(Update,
-//		//(read_map_system, setup_map_system, read_battle_system, generate_units_system,  place_units_on_map_system, handle_player_turn_server_message)
-//		(read_map_system, setup_map_system, read_battle_system, (generate_units_system, apply_deferred).chain(),  place_units_on_map_system)
-//			.run_if(in_state(GameState::Loading))
-//	);
-//	app.add_systems(OnExit(GameState::Loading), init_cursor_system);
-//	app.add_systems(OnExit(GameState::Loading), setup_game_resource_system_multiplayer);
-	app.add_systems(OnEnter(GameState::LoadingComplete), loading_complete);
-//	app.add_systems(OnEnter(GameState::Battle), (apply_deferred, setup_cursor_system).chain());
-	app.add_systems(Update,
-		(end_turn_system, (apply_state_transition::<GameState>, handle_player_turn_server_message, apply_state_transition::<GameState>).chain())
-			.run_if(in_state(GameState::Battle))
-	);
-	app.add_systems(Update, handle_player_turn_server_message
-		.run_if(in_state(GameState::Wait))
+	/// The tiles of the team's zones that are on the map.
+	fn tiles(&self, team: usize, width: usize, height: usize) -> Vec<Pos> {
+		let mut tiles: Vec<Pos> = Vec::new();
+		for x in 0..width {
+			for y in 0..height {
+				if self.contains(team, Pos { x: x, y: y, }) {
+					tiles.push(Pos { x: x, y: y, });
+				}
+			}
+		}
+		tiles
+	}
+}
+
+/// What a unit gains on every level up.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+struct StatGrowth {
+	hp: usize,
+	mp: usize,
+	str: usize,
+	vit: usize,
+	int: usize,
+	men: usize,
+	agi: usize,
+	dex: usize,
+	luk: usize,
+}
+
+impl Default for StatGrowth {
+	fn default() -> Self {
+		StatGrowth {
+			hp: 3,
+			mp: 0,
+			str: 1,
+			vit: 1,
+			int: 1,
+			men: 1,
+			agi: 1,
+			dex: 1,
+			luk: 1,
+		}
+	}
+}
+
+/// The stats a unit of a class starts with.
+#[derive(Deserialize, Clone, Copy, Debug)]
+struct BaseStats {
+	wt: usize,
+	hp: usize,
+	mp: usize,
+	str: usize,
+	vit: usize,
+	int: usize,
+	men: usize,
+	agi: usize,
+	dex: usize,
+	luk: usize,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+struct AttackProfile {
+	range: isize,
+	attack_type: AttackType,
+}
+
+/// A `UnitClass`: what every unit of the class has, unless its scenario says otherwise.
+#[derive(Deserialize, Clone, Debug)]
+struct ClassDefinition {
+	/// Sprites are named `<sprite>_<direction>.png`.
+	sprite: String,
+	stats: BaseStats,
+	growth: StatGrowth,
+	movement: isize,
+	jump: usize,
+	attack: AttackProfile,
+	/// What the class's units can do besides moving and waiting.
+	abilities: Vec<Ability>,
+}
+
+/// An action a unit can only take if its class allows it.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+enum Ability {
+	Attack,
+	UseItem,
+}
+
+/// Every unit class, read from `src/classes.ron`.
+#[derive(Resource, Deserialize, Default, Clone)]
+struct Classes {
+	classes: HashMap<String, ClassDefinition>,
+}
+
+impl Classes {
+	fn get(&self, unit_class: &str) -> Option<&ClassDefinition> {
+		self.classes.get(unit_class)
+	}
+	
+	fn growth(&self, unit_class: &str) -> StatGrowth {
+		self.get(unit_class).map_or(StatGrowth::default(), |class| class.growth)
+	}
+	
+	fn allows(&self, unit_class: &str, ability: Ability) -> bool {
+		self.get(unit_class).map_or(false, |class| class.abilities.contains(&ability))
+	}
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+enum EquipmentSlot {
+	Weapon,
+	Shield,
+	Armor,
+	Accessory,
+}
+
+impl EquipmentSlot {
+	const ALL: [EquipmentSlot; 4] = [EquipmentSlot::Weapon, EquipmentSlot::Shield, EquipmentSlot::Armor, EquipmentSlot::Accessory];
+}
+
+/// Stats an item adds while it is equipped.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+struct StatBonus {
+	hp: usize,
+	mp: usize,
+	str: usize,
+	vit: usize,
+	int: usize,
+	men: usize,
+	agi: usize,
+	dex: usize,
+	luk: usize,
+}
+
+impl StatBonus {
+	fn add(&mut self, other: &StatBonus) {
+		self.hp += other.hp;
+		self.mp += other.mp;
+		self.str += other.str;
+		self.vit += other.vit;
+		self.int += other.int;
+		self.men += other.men;
+		self.agi += other.agi;
+		self.dex += other.dex;
+		self.luk += other.luk;
+	}
+	
+	/// The stats the bonus raises, e.g. "STR +6, VIT +5".
+	fn describe(&self) -> String {
+		let stats = [("HP", self.hp), ("MP", self.mp), ("STR", self.str), ("VIT", self.vit), ("INT", self.int), ("MEN", self.men), ("AGI", self.agi), ("DEX", self.dex), ("LUK", self.luk)];
+		stats
+			.iter()
+			.filter(|(_, value)| *value > 0)
+			.map(|(name, value)| format!("{} +{}", name, value))
+			.collect::<Vec<String>>()
+			.join(", ")
+	}
+}
+
+#[derive(Deserialize, Clone, Debug)]
+enum ItemDefinition {
+	Equipment {
+		slot: EquipmentSlot,
+		#[serde(default)]
+		bonus: StatBonus,
+		/// Replaces the attack of the unit wielding it. Only weapons have one.
+		#[serde(default)]
+		attack: Option<AttackProfile>,
+	},
+	/// Used up as a unit's action.
+	Consumable {
+		#[serde(default)]
+		hp: usize,
+		#[serde(default)]
+		mp: usize,
+	},
+}
+
+impl ItemDefinition {
+	fn describe(&self) -> String {
+		match self {
+			ItemDefinition::Equipment { bonus, attack, .. } => {
+				let mut description = bonus.describe();
+				if let Some(attack) = attack {
+					if !description.is_empty() {
+						description.push_str(", ");
+					}
+					description.push_str(&format!("{:?} attack, range {}", attack.attack_type, attack.range));
+				}
+				description
+			},
+			ItemDefinition::Consumable { hp, mp } => format!("Restores {} HP and {} MP", hp, mp),
+		}
+	}
+}
+
+/// Every item, read from `src/items.ron`.
+#[derive(Resource, Deserialize, Default, Clone)]
+struct Items {
+	items: HashMap<String, ItemDefinition>,
+}
+
+impl Items {
+	fn get(&self, item: &str) -> Option<&ItemDefinition> {
+		self.items.get(item)
+	}
+	
+	/// The slot the item is equipped in, or `None` for consumables and unknown items.
+	fn slot(&self, item: &str) -> Option<EquipmentSlot> {
+		match self.get(item) {
+			Some(ItemDefinition::Equipment { slot, .. }) => Some(*slot),
+			_ => None,
+		}
+	}
+	
+	/// What the consumable restores, as HP and MP.
+	fn consumable(&self, item: &str) -> Option<(usize, usize)> {
+		match self.get(item) {
+			Some(ItemDefinition::Consumable { hp, mp }) => Some((*hp, *mp)),
+			_ => None,
+		}
+	}
+	
+	fn bonus(&self, equipment: &Equipment) -> StatBonus {
+		let mut bonus = StatBonus::default();
+		for item in equipment.items() {
+			if let Some(ItemDefinition::Equipment { bonus: item_bonus, .. }) = self.get(item) {
+				bonus.add(item_bonus);
+			}
+		}
+		bonus
+	}
+	
+	fn attack(&self, equipment: &Equipment) -> Option<AttackProfile> {
+		match equipment.weapon.as_ref().and_then(|weapon| self.get(weapon)) {
+			Some(ItemDefinition::Equipment { attack, .. }) => *attack,
+			_ => None,
+		}
+	}
+}
+
+/// A row of a scenario's unit table, in `src/<scenario>_data.csv`.
+/// A unit has the stats of its class, except for the columns the row fills in.
+#[derive(Deserialize, Clone)]
+struct UnitRecord {
+	unit_id: usize,
+	unit_team: usize,
+	unit_name: String,
+	unit_class: String,
+	pos_x: usize,
+	pos_y: usize,
+	#[serde(rename = "WT_CURRENT")]
+	wt_current: usize,
+	#[serde(rename = "DIR")]
+	direction: Direction,
+	#[serde(rename = "WT_MAX", default)]
+	wt_max: Option<usize>,
+	#[serde(rename = "HP_MAX", default)]
+	hp_max: Option<usize>,
+	#[serde(rename = "HP_CURRENT", default)]
+	hp_current: Option<usize>,
+	#[serde(rename = "MP_MAX", default)]
+	mp_max: Option<usize>,
+	#[serde(rename = "MP_CURRENT", default)]
+	mp_current: Option<usize>,
+	#[serde(rename = "STR", default)]
+	str: Option<usize>,
+	#[serde(rename = "VIT", default)]
+	vit: Option<usize>,
+	#[serde(rename = "INT", default)]
+	int: Option<usize>,
+	#[serde(rename = "MEN", default)]
+	men: Option<usize>,
+	#[serde(rename = "AGI", default)]
+	agi: Option<usize>,
+	#[serde(rename = "DEX", default)]
+	dex: Option<usize>,
+	#[serde(rename = "LUK", default)]
+	luk: Option<usize>,
+	#[serde(default)]
+	unit_sprite: Option<String>,
+	#[serde(rename = "MovementRange", default)]
+	movement_range: Option<isize>,
+	#[serde(rename = "Jump", default)]
+	jump: Option<usize>,
+	#[serde(rename = "AttackRange", default)]
+	attack_range: Option<isize>,
+	#[serde(rename = "AttackType", default)]
+	attack_type: Option<AttackType>,
+	#[serde(rename = "Weapon", default)]
+	weapon: Option<String>,
+	#[serde(rename = "Shield", default)]
+	shield: Option<String>,
+	#[serde(rename = "Armor", default)]
+	armor: Option<String>,
+	#[serde(rename = "Accessory", default)]
+	accessory: Option<String>,
+	/// Consumables, separated by `;`.
+	#[serde(rename = "Items", default)]
+	items: Option<String>,
+	#[serde(rename = "Role", default)]
+	role: Option<UnitRole>,
+}
+
+impl UnitRecord {
+	/// The unit's equipment isn't applied to its stats yet, see `UnitAttributes::apply_equipment`.
+	fn unit_attributes(&self, classes: &Classes) -> Result<UnitAttributes, String> {
+		let Some(class) = classes.get(&self.unit_class) else {
+			return Err(format!("Unit {} has the unknown class {}.", self.unit_id, self.unit_class));
+		};
+		let hp_max = self.hp_max.unwrap_or(class.stats.hp);
+		let mp_max = self.mp_max.unwrap_or(class.stats.mp);
+		
+		Ok(UnitAttributes {
+			unit_id: UnitId { value: self.unit_id, },
+			unit_team: UnitTeam { value: self.unit_team, },
+			unit_name: UnitName { value: self.unit_name.clone(), },
+			unit_class: UnitClass { value: self.unit_class.clone(), },
+			pos_x: PosX { value: self.pos_x, },
+			pos_y: PosY { value: self.pos_y, },
+			wt_max: WTMax { value: self.wt_max.unwrap_or(class.stats.wt), },
+			wt_current: WTCurrent { value: self.wt_current, },
+			hp_max: HPMax { value: hp_max, },
+			hp_current: HPCurrent { value: self.hp_current.unwrap_or(hp_max), },
+			mp_max: MPMax { value: mp_max, },
+			mp_current: MPCurrent { value: self.mp_current.unwrap_or(mp_max), },
+			str: STR { value: self.str.unwrap_or(class.stats.str), },
+			vit: VIT { value: self.vit.unwrap_or(class.stats.vit), },
+			int: INT { value: self.int.unwrap_or(class.stats.int), },
+			men: MEN { value: self.men.unwrap_or(class.stats.men), },
+			agi: AGI { value: self.agi.unwrap_or(class.stats.agi), },
+			dex: DEX { value: self.dex.unwrap_or(class.stats.dex), },
+			luk: LUK { value: self.luk.unwrap_or(class.stats.luk), },
+			level: Level { value: 1, },
+			experience: Experience { value: 0, },
+			unit_sprite: UnitSprite { value: self.unit_sprite.clone().unwrap_or(class.sprite.clone()), },
+			dir: DIR { direction: self.direction, },
+			movement_range: MovementRange { value: self.movement_range.unwrap_or(class.movement), },
+			jump: Jump { value: self.jump.unwrap_or(class.jump), },
+			attack_range: AttackRange { value: self.attack_range.unwrap_or(class.attack.range), },
+			attack_type: self.attack_type.unwrap_or(class.attack.attack_type),
+			equipment: Equipment {
+				weapon: self.weapon.clone(),
+				shield: self.shield.clone(),
+				armor: self.armor.clone(),
+				accessory: self.accessory.clone(),
+			},
+			inventory: Inventory { items: self.inventory_items(), },
+			role: self.role.unwrap_or_default(),
+		})
+	}
+	
+	fn inventory_items(&self) -> Vec<String> {
+		self.items
+			.iter()
+			.flat_map(|items| items.split(';'))
+			.map(|item| item.trim().to_string())
+			.filter(|item| !item.is_empty())
+			.collect()
+	}
+}
+
+/// How the last battle ended, shown on the game-over screen.
+#[derive(Resource, Default)]
+struct BattleResult {
+	title: String,
+	message: String,
+}
+
+#[derive(Resource, Default)]
+struct ClientData {
+	client_id: ClientId,
+}
+
+/// The lobby as seen by this client.
+#[derive(Resource)]
+struct Lobby {
+	player_name: String,
+	games: Vec<LobbyGameInfo>,
+	current_game: Option<LobbyGame>,
+	new_game_name: String,
+	new_game_scenario: String,
+}
+
+impl Default for Lobby {
+	fn default() -> Self {
+		Lobby {
+			player_name: String::from("Player"),
+			games: Vec::new(),
+			current_game: None,
+			new_game_name: String::from("New Game"),
+			new_game_scenario: SCENARIOS[0].to_string(),
+		}
+	}
+}
+
+/// The in-battle chat.
+#[derive(Resource)]
+struct Chat {
+	history: Vec<ChatLine>,
+	input: String,
+	scope: ChatScope,
+	/// Whether the chat text field has the keyboard focus.
+	focused: bool,
+}
+
+impl Default for Chat {
+	fn default() -> Self {
+		Chat {
+			history: Vec::new(),
+			input: String::new(),
+			scope: ChatScope::All,
+			focused: false,
+		}
+	}
+}
+
+struct ChatLine {
+	sender: ClientId,
+	scope: ChatScope,
+	message: String,
+}
+
+/// The local player's identity, kept on disk between sessions.
+#[derive(Resource, Serialize, Deserialize, Clone)]
+struct PlayerProfile {
+	player_id: Uuid,
+	name: String,
+	/// Proves to the server that this is the same player when it reconnects.
+	token: Option<String>,
+}
+
+/// The players of the current multiplayer battle.
+#[derive(Resource, Default)]
+struct Players {
+	players: Vec<PlayerInfo>,
+	/// The player whose unit is taking its turn.
+	current_player: Option<ClientId>,
+	/// The team the server announced as the winner of the battle.
+	winning_team: Option<usize>,
+}
+
+/// Counters and connection statistics for the network diagnostics.
+#[derive(Resource, Default)]
+struct NetworkStats {
+	connection_state: NetworkConnectionState,
+	/// Round-trip time to the server.
+	latency: Option<Duration>,
+	sent_packets: u64,
+	lost_packets: u64,
+	bytes_sent: u64,
+	bytes_received: u64,
+	/// Messages sent, by `ClientMessage` variant.
+	sent: HashMap<&'static str, u64>,
+	/// Messages received, by `ServerMessage` variant.
+	received: HashMap<&'static str, u64>,
+	show_overlay: bool,
+}
+
+#[derive(Default, Debug)]
+enum NetworkConnectionState {
+	#[default]
+	Disconnected,
+	Connecting,
+	Connected,
+	Lost,
+}
+
+/// A changed server certificate waiting for the player to decide whether to trust it.
+#[derive(Resource, Default)]
+struct PendingCertificate {
+	interaction: Option<CertificateInteractionEvent>,
+}
+
+/// Short messages shown to the player at the bottom of the screen.
+#[derive(Resource, Default)]
+struct Toasts {
+	toasts: Vec<Toast>,
+}
+
+struct Toast {
+	message: String,
+	/// Seconds until the toast disappears.
+	remaining: f32,
+}
+
+/// Offline multiplayer where several players share this machine and take turns.
+#[derive(Resource, Default)]
+struct HotSeat {
+	enabled: bool,
+	/// The team that last had the controls.
+	current_team: Option<usize>,
+	/// Where each team left the camera at the end of its turn.
+	camera_positions: HashMap<usize, Vec3>,
+}
+
+/// A move this client started before the server confirmed it, as origin and destination.
+#[derive(Resource, Default)]
+struct PredictedMove {
+	value: Option<(Pos, Pos)>,
+}
+
+/// Whether the inventory of the current unit is shown.
+#[derive(Resource, Default)]
+struct InventoryScreen {
+	open: bool,
+}
+
+/// The deployment before a battle.
+#[derive(Resource, Default)]
+struct Deployment {
+	/// The teams placing their units, whose units are hidden from each other.
+	teams: Vec<usize>,
+	/// The team placing its units now.
+	team: Option<usize>,
+	/// The unit picked up to be placed on another tile.
+	selected: Option<Entity>,
+	/// Whether the deployment was sent to the server, which waits for the other teams.
+	confirmed: bool,
+}
+
+/// The current unit's move while it can still be taken back, as unit ID, origin and destination.
+#[derive(Resource, Default)]
+struct UndoableMove {
+	value: Option<(usize, Pos, Pos)>,
+	/// The unit that moved last, and where it faced before its move.
+	direction: Option<(usize, Direction)>,
+}
+
+/// Whether the current unit has used its action, an attack or an item, this turn.
+#[derive(Resource, Default)]
+struct ActionUsed {
+	value: bool,
+}
+
+/// The time left for the current multiplayer turn.
+#[derive(Resource, Default)]
+struct TurnTimer {
+	current_unit: Option<usize>,
+	remaining: f32,
+	/// Time saved from previous turns, used once `remaining` runs out.
+	reserve: f32,
+	expired: bool,
+}
+
+/// The random number generator of a battle, on the client offline and on the local server.
+/// It is seeded at the start of the battle, and offline its state is kept in saves.
+#[derive(Resource, Serialize, Deserialize, Clone)]
+struct BattleRng {
+	seed: u64,
+	rng: Pcg32,
+}
+
+impl BattleRng {
+	fn from_seed(seed: u64) -> BattleRng {
+		BattleRng {
+			seed: seed,
+			rng: Pcg32::seed_from_u64(seed),
+		}
+	}
+}
+
+impl Default for BattleRng {
+	fn default() -> Self {
+		BattleRng::from_seed(rand::thread_rng().gen())
+	}
+}
+
+/// A saved battle waiting for its scenario to finish loading.
+#[derive(Resource, Default)]
+struct PendingSave {
+	save: Option<BattleSave>,
+}
+
+/// The save slots listed in the pause menu.
+#[derive(Resource, Default)]
+struct SaveMenu {
+	slot: String,
+	saves: Vec<String>,
+}
+
+/// The replay of the ongoing battle, written to disk when the battle is over.
+#[derive(Resource, Default)]
+struct ReplayRecorder {
+	replay: Option<Replay>,
+	/// Whether the last unit given a turn hasn't been recorded ending it yet.
+	turn_open: bool,
+}
+
+impl ReplayRecorder {
+	fn start(&mut self, scenario: &str, seed: u64) {
+		self.replay = Some(Replay {
+			version: REPLAY_VERSION,
+			scenario: scenario.to_string(),
+			seed: seed,
+			events: Vec::new(),
+		});
+		self.turn_open = false;
+	}
+	
+	fn record(&mut self, event: ReplayEvent) {
+		if let Some(replay) = &mut self.replay {
+			replay.events.push(event);
+		}
+	}
+	
+	fn record_turn(&mut self, wait_turns: Vec<(usize, usize)>, unit_id: usize) {
+		// The AI ends its turn before its actions are resolved, so single-player
+		// battles record the end of a turn only once the next one starts.
+		if self.turn_open {
+			self.record(ReplayEvent::Wait);
+		}
+		self.record(ReplayEvent::WaitTurn { wait_turns: wait_turns, });
+		self.record(ReplayEvent::Turn { unit_id: unit_id, });
+		self.turn_open = true;
+	}
+}
+
+/// The replay being watched.
+#[derive(Resource)]
+struct ReplayPlayer {
+	replay: Option<Replay>,
+	name: String,
+	next_event: usize,
+	playing: bool,
+	speed: f32,
+	delay: f32,
+	/// Play on without delay until this event is reached.
+	seek_target: Option<usize>,
+	seek_position: usize,
+	step: bool,
+}
+
+impl Default for ReplayPlayer {
+	fn default() -> Self {
+		ReplayPlayer {
+			replay: None,
+			name: String::new(),
+			next_event: 0,
+			playing: true,
+			speed: 1.0,
+			delay: 0.0,
+			seek_target: None,
+			seek_position: 0,
+			step: false,
+		}
+	}
+}
+
+/// The campaign being played, and the roster units chosen for its next battle.
+#[derive(Resource, Default)]
+struct Campaign {
+	definition: Option<CampaignDefinition>,
+	progress: Option<CampaignProgress>,
+	/// Whether the ongoing battle is part of the campaign.
+	active: bool,
+	/// Roster ids of the units to deploy, in the order of the scenario's slots.
+	selected: Vec<usize>,
+	/// The unit id each deployed roster unit was given in the battle, as `(unit_id, roster_id)`.
+	deployed: Vec<(usize, usize)>,
+	/// How many units the next battle has room for.
+	deployment_slots: usize,
+	/// Names of the units the next battle can't be fought without.
+	required_units: Vec<String>,
+	/// The roster id of the unit whose equipment is shown.
+	equipment_unit: Option<usize>,
+	/// Names of the units some battle of the campaign can't be fought without.
+	/// They can't be renamed or dismissed.
+	story_units: Vec<String>,
+	/// Names roster units can't be renamed to, since objectives find units by their names.
+	reserved_names: Vec<String>,
+}
+
+/// What the barracks of the campaign screen show.
+#[derive(Resource, Default)]
+struct Barracks {
+	open: bool,
+	/// The roster id of the unit whose stat sheet is shown.
+	unit: Option<usize>,
+	/// The class of the next recruit.
+	recruit_class: Option<String>,
+	/// The name the unit is renamed to.
+	new_name: String,
+	/// Whether the unit is about to be dismissed, once the player confirms.
+	confirm_dismiss: bool,
+}
+
+/// A change to the campaign roster, made in the barracks.
+enum RosterChange {
+	Recruit {
+		class: String,
+	},
+	Rename {
+		roster_id: usize,
+		name: String,
+	},
+	/// Dismisses the unit. Its equipment and the items it carries go back to the campaign inventory.
+	Dismiss {
+		roster_id: usize,
+	},
+}
+
+/// A change to the campaign inventory, made on the campaign screen.
+enum InventoryChange {
+	/// Equips the item at the index of the campaign inventory, or empties the slot.
+	Equip {
+		roster_id: usize,
+		slot: EquipmentSlot,
+		item: Option<usize>,
+	},
+	/// Gives the consumable at the index of the campaign inventory to the unit.
+	Give {
+		roster_id: usize,
+		item: usize,
+	},
+	/// Puts the consumable at the index of the unit's inventory back into the campaign inventory.
+	TakeBack {
+		roster_id: usize,
+		item: usize,
+	},
+}
+
+/// A battle snapshot waiting for the scenario to finish loading.
+#[derive(Resource, Default)]
+struct PendingSnapshot {
+	snapshot: Option<BattleSnapshot>,
+}
+
+/// Messages received from the server that haven't been handled yet.
+#[derive(Resource, Default)]
+struct ServerMessages {
+	messages: VecDeque<ServerMessage>,
+}
+
+#[derive(Resource, Default)]
+struct DemoData {
+	current_unit: UnitId,
+}
+
+#[derive(Resource)]
+struct Slog {
+	logger: slog::Logger,
+}
+
+/// Command line arguments.
+#[derive(Parser, Resource, Clone)]
+#[command(name = "amclient")]
+struct CliArgs {
+	/// The address of the amserver to connect to.
+	#[arg(long, default_value = "178.79.171.209:6000")]
+	server: String,
+
+	/// Host a local loopback server in-process and connect to it.
+	#[arg(long)]
+	local_server: bool,
+
+	/// Run only the local loopback server, without opening a window.
+	#[arg(long)]
+	dedicated_server: bool,
+
+	/// The port the local loopback server listens on.
+	#[arg(long, default_value_t = 6000)]
+	port: u16,
+
+	/// Seconds a player has for each turn on the local loopback server. 0 disables the limit.
+	#[arg(long, default_value_t = 60)]
+	turn_time_limit: u32,
+
+	/// Carry unused turn time over into a reserve for the team's next turns.
+	#[arg(long)]
+	turn_time_carry_over: bool,
+
+	/// The file the player profile is kept in.
+	#[arg(long, default_value = "profile.ron")]
+	profile: String,
+
+	/// Only accept a server certificate with this fingerprint (base64 SHA-256).
+	#[arg(long)]
+	server_fingerprint: Option<String>,
+
+	/// Watch a replay file instead of playing.
+	#[arg(long)]
+	replay: Option<String>,
+}
+
+// Server
+#[derive(Resource, Default)]
+struct LocalServer {
+	/// Clients that asked for a quick match with `StartGame`.
+	waiting_clients: Vec<ClientId>,
+	player_names: HashMap<ClientId, String>,
+	lobby_games: Vec<LobbyGame>,
+	next_game_id: usize,
+	/// Time since the lobby countdowns last went down by a second.
+	countdown_elapsed: f32,
+	teams: HashMap<ClientId, usize>,
+	loading_complete: HashSet<ClientId>,
+	units: Vec<ServerUnit>,
+	current_unit: Option<usize>,
+	in_game: bool,
+	scenario: String,
+	alliances: Alliances,
+	rules: ScenarioRules,
+	objectives: Objectives,
+	/// The height of every tile of the battle's map.
+	heights: Vec<Vec<usize>>,
+	/// Clients watching the battle without playing in it.
+	spectators: HashSet<ClientId>,
+	turn_time_limit: f32,
+	turn_time_carry_over: bool,
+	/// Time spent on the current turn.
+	turn_elapsed: f32,
+	/// Unused turn time saved by each team.
+	turn_reserves: HashMap<usize, f32>,
+	/// The current unit's move while it can still be taken back, as unit index, origin and destination.
+	undoable_move: Option<(usize, Pos, Pos)>,
+	/// Whether the current unit has attacked or used an item this turn.
+	action_used: bool,
+	/// Players that identified themselves, by their persistent ID.
+	known_players: HashMap<Uuid, KnownPlayer>,
+	client_players: HashMap<ClientId, Uuid>,
+	/// The classes and items loaded at startup.
+	classes: Classes,
+	items: Items,
+	deployment_zones: DeploymentZones,
+	/// The deployment each team sent, revealed once every team has deployed.
+	deployments: HashMap<usize, Vec<(usize, Pos, Direction)>>,
+	/// The teams of players who left the battle, kept for them by their persistent ID.
+	empty_seats: HashMap<Uuid, usize>,
+	/// The fingerprint of the self-signed certificate generated at startup, which the in-process client pins.
+	certificate_fingerprint: Option<String>,
+	/// The random number generator of the battle, seeded when it starts.
+	rng: BattleRng,
+}
+
+// Server
+struct KnownPlayer {
+	name: String,
+	token: Option<String>,
+}
+
+// Server
+#[derive(Clone)]
+struct ServerUnit {
+	unit_id: usize,
+	unit_team: usize,
+	/// Objectives find units by their names.
+	name: String,
+	pos: Pos,
+	/// The facing the scenario or the unit's deployment gave it. Moves and attacks don't turn it on the server.
+	direction: Direction,
+	wt_max: usize,
+	wt_current: usize,
+	hp_max: usize,
+	hp_current: usize,
+	mp_max: usize,
+	mp_current: usize,
+	str: usize,
+	movement_range: isize,
+	jump: usize,
+	attack_range: isize,
+	attack_type: AttackType,
+	inventory: Vec<String>,
+	role: UnitRole,
+	abilities: Vec<Ability>,
+}
+
+// Client & Server
+fn main() {
+	std::panic::set_hook(Box::new(custom_panic_hook));
+
+	let args = CliArgs::parse();
+	if args.dedicated_server {
+		run_dedicated_server(args);
+		return;
+	}
+
+//	// Setup the logger
+//    let log = setup_logging();
+
+    let mut app = App::new();
+//  app.insert_resource(Slog { logger: log, });
+	app.add_plugins(
+		DefaultPlugins.set(WindowPlugin {
+			primary_window: Some(Window {
+				title: "Ars Militaris".into(),
+				..default()
+			}),
+			..default()
+		})
+//		.disable::<LogPlugin>()
+		.set(LogPlugin {
+			file_appender_settings: Some(FileAppenderSettings {
+				prefix: String::from("amclient.log"),
+				rolling: Rolling::Minutely,
+				..default()
+			}),
+			..default()
+		})
+	);
+	app.add_plugin(QuinnetClientPlugin::default());
+	if args.local_server {
+		add_local_server(&mut app);
+	}
+	app.insert_resource(load_player_profile(&args.profile));
+	app.insert_resource(args);
+	app.add_plugins(ConsolePlugin)
+		.insert_resource(ConsoleConfiguration {
+			// Override config here.
+			keys: vec![ToggleConsoleKey::KeyCode(KeyCode::Backslash)],
+			left_pos: 0.0,
+			top_pos: 600.0,
+			height: 150.0,
+			width: 400.0,
+			..Default::default()
+		});
+	app.add_plugin(WorldInspectorPlugin::new());
+	app.register_type::<ConsoleConfiguration>();
+	app.register_type::<HPCurrent>();
+	app.register_type::<UnitActions>();
+	app.register_type::<UnitAction>();
+	app.register_type::<UnitActionTuple>();
+	app.register_type::<Pos>();
+	app.register_type::<WTCurrent>();
+	app.register_type::<DIR>();
+	app.register_type::<MovementRange>();
+	app.register_type::<AttackRange>();
+	app.register_type::<AttackType>();
+	app.register_type::<UnitRole>();
+//	app.add_plugin(ResourceInspectorPlugin::<ConsoleConfiguration>::default());
+//	app.add_plugin(ResourceInspectorPlugin::<State<GameState>>::default());
+//	app.add_plugin(ResourceInspectorPlugin::<State<TurnState>>::default());
+//	app.add_plugin(ResourceInspectorPlugin::<NextState<GameState>>::default());
+//	app.add_plugin(ResourceInspectorPlugin::<NextState<TurnState>>::default());
+	app.add_console_command::<DoNothingCommand, _>(do_nothing_command);
+	app.add_console_command::<TalkCommand, _>(talk_command);
+	app.add_console_command::<MoveCommand, _>(move_command);
+	app.add_console_command::<SayCommand, _>(say_command);
+	app.add_console_command::<NetstatsCommand, _>(netstats_command);
+	app.add_console_command::<SaveCommand, _>(save_command);
+	app.add_console_command::<LoadCommand, _>(load_command);
+	app.add_console_command::<ReplayCommand, _>(replay_command);
+	app.add_state::<GameState>();
+	app.add_state::<TurnState>();
+	app.add_event::<GameStartEvent>();
+	app.add_event::<MapReadEvent>();
+	app.add_event::<MapSetupEvent>();
+	app.add_event::<UnitsReadEvent>();
+	app.add_event::<UnitsGeneratedEvent>();
+	app.init_resource::<Game>();
+	app.init_resource::<ClientData>();
+	app.init_resource::<DemoData>();
+	app.init_resource::<HotSeat>();
+	app.init_resource::<Alliances>();
+	app.init_resource::<ScenarioRules>();
+	app.init_resource::<Objectives>();
+	app.init_resource::<DeploymentZones>();
+	app.init_resource::<Deployment>();
+	app.init_resource::<BattleResult>();
+	app.init_resource::<BattleRng>();
+	add_server_message_handlers(&mut app);
+	
+	if cfg!(windows) {
+		app.add_systems(Startup, set_window_icon);
+	} 
+	
+//	app.add_systems(Startup, test_slog);
+	app.add_systems(OnEnter(GameState::MainMenu),
+		(start_connection, send_get_client_id_message)
+			.chain()
+	);
+	app.add_systems(OnEnter(GameState::MainMenu), setup_main_menu);
+	app.add_systems(OnEnter(GameState::Lobby), setup_lobby);
+	app.add_systems(OnExit(GameState::Lobby), tear_down_lobby);
+	app.add_systems(Update, lobby_ui
+		.run_if(in_state(GameState::Lobby))
+	);
+	app.add_systems(OnExit(GameState::MainMenu), tear_down_main_menu);
+	app.init_resource::<Campaign>();
+	let (classes, items) = load_game_data().unwrap_or_else(|error| {
+		error!("Invalid game data: {}", error);
+		std::process::exit(1);
+	});
+	app.insert_resource(classes);
+	app.insert_resource(items);
+	app.add_systems(Update, handle_experience_events);
+	app.add_systems(OnEnter(GameState::Campaign), setup_campaign);
+	app.add_systems(OnExit(GameState::Campaign), tear_down_campaign);
+	app.add_systems(Update, campaign_ui
+		.run_if(in_state(GameState::Campaign))
+	);
+	app.init_resource::<Barracks>();
+	app.add_systems(Update, barracks_ui
+		.run_if(in_state(GameState::Campaign))
+		.after(campaign_ui)
+	);
+	app.add_systems(OnEnter(GameState::MainMenu), leave_campaign);
+	app.add_systems(Update, handle_main_menu_buttons
+		.run_if(in_state(GameState::MainMenu))
+	);
+	app.add_systems(Update,
+		send_start_game_message_system
+			.run_if(in_state(GameState::MainMenu))
+	);
+//	app.add_systems(Update,
+//		//(read_map_system, setup_map_system, read_battle_system, generate_units_system,  place_units_on_map_system, handle_player_turn_server_message)
+//		(read_map_system, setup_map_system, read_battle_system, (generate_units_system, apply_deferred).chain(),  place_units_on_map_system)
+//			.run_if(in_state(GameState::Loading))
+//	);
+//	app.add_systems(OnExit(GameState::Loading), init_cursor_system);
+//	app.add_systems(OnExit(GameState::Loading), setup_game_resource_system_multiplayer);
+	app.add_systems(OnEnter(GameState::LoadingComplete), loading_complete);
+	app.add_systems(OnEnter(GameState::LoadingComplete), apply_pending_snapshot
+		.after(loading_complete)
+	);
+//	app.add_systems(OnEnter(GameState::Battle), (apply_deferred, setup_cursor_system).chain());
+	app.add_systems(Update,
+		end_turn_system
+			.run_if(in_state(GameState::Battle))
+			.run_if(not(is_spectator))
 	);
 	app.add_systems(OnEnter(GameState::Loading), setup_grid_system);
 	app.add_systems(OnEnter(GameState::Loading), setup_camera_system);
@@ -671,6 +2930,7 @@ fn main() {
 	app.add_systems(OnEnter(GameState::LoadAmbush), (apply_deferred, spawn_units)
 		.chain()
 		.after(setup_text_system)
+		.after(setup_game_resource_system)
 	);
 	app.add_systems(OnEnter(GameState::LoadAmbush), (apply_deferred, z_order_system)
 		.chain()
@@ -680,6 +2940,16 @@ fn main() {
 		.after(spawn_units)
 	);
 	app.add_systems(OnExit(GameState::LoadAmbush), handle_unit_directions);
+	app.add_event::<ConfirmDeploymentEvent>();
+	app.add_systems(OnEnter(GameState::Deployment), setup_deployment);
+	app.add_systems(Update, (deployment_controls, deployment_ui, handle_confirm_deployment_events, hide_deploying_units, handle_unit_directions)
+		.chain()
+		.run_if(in_state(GameState::Deployment))
+	);
+	app.add_systems(Update, (move_cursor_2, position_cursor, move_camera_system)
+		.run_if(in_state(GameState::Deployment))
+	);
+	app.add_systems(OnExit(GameState::Deployment), finish_deployment);
 	app.add_systems(OnExit(GameState::LoadAmbush), test_write_to_console);
 	app.add_systems(OnExit(GameState::LoadAmbush), toggle_console);
 	app.add_systems(Update, z_order_system
@@ -717,6 +2987,22 @@ fn main() {
 	);
 	app.add_systems(OnTransition { from: GameState::Ambush, to: GameState::MainMenu, }, handle_ambush_to_main_menu_transition);
 	app.add_systems(OnTransition { from: GameState::Battle, to: GameState::MainMenu, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::Wait, to: GameState::MainMenu, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::Deployment, to: GameState::MainMenu, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::GameOver, to: GameState::MainMenu, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnEnter(GameState::GameOver), setup_game_over_screen);
+	app.add_systems(OnEnter(GameState::GameOver), finish_campaign_battle
+		.run_if(is_campaign_battle)
+		.before(setup_game_over_screen)
+	);
+	app.add_systems(OnTransition { from: GameState::GameOver, to: GameState::Campaign, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(Update, handle_game_over_screen_buttons
+		.run_if(in_state(GameState::GameOver))
+	);
+	app.add_systems(Update, objectives_ui
+		.run_if(is_singleplayer)
+		.run_if(in_state(GameState::Ambush).or_else(in_state(GameState::Move)))
+	);
 	app.add_systems(Update, position_cursor
 		.run_if(in_state(TurnState::Turn))
 	);
@@ -756,7 +3042,9 @@ fn main() {
 		.run_if(in_state(GameState::Wait))
 	);
 	//app.add_systems(OnEnter(GameState::Ambush), ars_militaris_demo);
-	app.add_systems(Update, single_player_pause);
+	app.add_systems(Update, single_player_pause
+		.run_if(not(in_state(GameState::Deployment)))
+	);
 	app.add_systems(Update, handle_single_player_pause_state
 		.run_if(in_state(GameState::SinglePlayerPause))
 	);
@@ -766,6 +3054,11 @@ fn main() {
 		.run_if(not(in_state(TurnState::ChooseMove)))
 		.run_if(not(in_state(TurnState::ChooseAttack)))
 		.run_if(not(in_state(TurnState::AI)))
+		.run_if(not(in_state(TurnState::PassControls)))
+	);
+	app.add_systems(OnEnter(TurnState::PassControls), setup_pass_controls);
+	app.add_systems(Update, handle_pass_controls
+		.run_if(in_state(TurnState::PassControls))
 	);
 	app.add_systems(Update, end_turn_single_player
 		.run_if(in_state(TurnState::Turn).and_then(is_singleplayer))
@@ -776,18 +3069,64 @@ fn main() {
 	app.add_systems(OnEnter(TurnState::ChooseMove), choose_move);
 	app.add_systems(Update, start_choose_move
 		.run_if(in_state(TurnState::Turn))
+		.run_if(not(is_spectator))
 	);
 	app.add_systems(Update, handle_choose_move
 		.run_if(in_state(TurnState::ChooseMove))
 	);
+	app.add_systems(Update, undo_move
+		.run_if(in_state(TurnState::Turn))
+		.run_if(not(is_spectator))
+	);
+	app.init_resource::<InventoryScreen>();
+	app.add_systems(Update, (toggle_inventory_screen, inventory_ui)
+		.chain()
+		.run_if(not(is_replay))
+		.run_if(in_state(GameState::Ambush).or_else(in_state(GameState::Battle)).or_else(in_state(GameState::Wait)).or_else(in_state(GameState::Move)))
+	);
 	app.add_systems(OnEnter(TurnState::ChooseAttack), choose_attack);
 	app.add_systems(Update, start_choose_attack
 		.run_if(in_state(TurnState::Turn))
+		.run_if(not(is_spectator))
 	);
 	app.add_systems(Update, handle_choose_attack
 		.run_if(in_state(TurnState::ChooseAttack))
 	);
 	app.add_systems(OnEnter(GameState::LoadAmbush), setup_game_resource_system);
+	app.add_systems(OnEnter(GameState::LoadAmbush), (apply_deferred, apply_pending_save)
+		.chain()
+		.after(spawn_units)
+		.after(setup_game_resource_system)
+	);
+	app.add_event::<SaveBattleEvent>();
+	app.add_event::<LoadBattleEvent>();
+	app.init_resource::<PendingSave>();
+	app.init_resource::<SaveMenu>();
+	app.add_systems(Update, (quick_save_and_load, handle_save_battle_events, handle_load_battle_events).chain());
+	app.add_systems(OnEnter(GameState::SinglePlayerPause), refresh_save_menu);
+	app.add_systems(Update, save_menu_ui
+		.run_if(in_state(GameState::SinglePlayerPause))
+	);
+	app.add_event::<PlayReplayEvent>();
+	app.add_systems(Startup, play_replay_from_args);
+	app.add_systems(Update, handle_play_replay_events);
+	app.add_systems(Update, play_replay
+		.run_if(is_replay)
+		.run_if(in_state(GameState::Battle).or_else(in_state(GameState::Wait)))
+		.before(receive_server_messages)
+	);
+	app.add_systems(Update, replay_controls_ui
+		.run_if(is_replay)
+		.run_if(in_state(GameState::Battle).or_else(in_state(GameState::Wait)).or_else(in_state(GameState::Move)))
+	);
+	app.add_systems(OnEnter(GameState::GameOver), write_replay);
+	app.add_systems(OnEnter(GameState::MainMenu), stop_replay);
+	app.add_systems(OnTransition { from: GameState::Battle, to: GameState::Loading, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::Wait, to: GameState::Loading, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::Ambush, to: GameState::LoadAmbush, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::SinglePlayerPause, to: GameState::LoadAmbush, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::Deployment, to: GameState::LoadAmbush, }, handle_ambush_to_main_menu_transition);
+	app.add_systems(OnTransition { from: GameState::GameOver, to: GameState::LoadAmbush, }, handle_ambush_to_main_menu_transition);
 //	app.add_systems(Update, move_gaul_warrior
 //		.run_if(in_state(GameState::Ambush))
 //		//.run_if(warrior_already_spawned)
@@ -815,6 +3154,10 @@ fn main() {
 		.chain()
 		.run_if(in_state(GameState::Ambush))
 	);
+	app.add_systems(Update, (apply_deferred, process_use_item_actions, apply_deferred)
+		.chain()
+		.run_if(in_state(GameState::Ambush))
+	);
 	app.add_systems(Update, (process_unit_actions, apply_deferred)
 		.chain()
 		.run_if(in_state(GameState::Battle))
@@ -831,2772 +3174,8445 @@ fn main() {
 		.chain()
 		.run_if(in_state(GameState::Battle))
 	);
-	app.add_systems(Update, center_camera_on_unit
-		.run_if(in_state(GameState::Move))
+	app.add_systems(Update, (apply_deferred, process_use_item_actions, apply_deferred)
+		.chain()
+		.run_if(in_state(GameState::Battle))
+	);
+	app.add_systems(Update, center_camera_on_unit
+		.run_if(in_state(GameState::Move))
+		.run_if(not(is_spectator))
+	);
+	app.add_systems(Update, (tick_turn_timer, turn_ui)
+		.run_if(is_multiplayer)
+		.run_if(not(is_replay))
+		.run_if(in_state(GameState::Battle).or_else(in_state(GameState::Wait)).or_else(in_state(GameState::Move)))
+	);
+	app.add_systems(Update, spectator_overlay_ui
+		.run_if(is_spectator)
+		.run_if(not(is_replay))
+		.run_if(in_state(GameState::Battle).or_else(in_state(GameState::Wait)).or_else(in_state(GameState::Move)))
+	);
+	app.add_systems(Update, spectator_camera_system
+		.run_if(is_spectator)
+		.run_if(in_state(GameState::Battle).or_else(in_state(GameState::Wait)).or_else(in_state(GameState::Move)))
+	);
+	app.add_systems(Update, chat_ui
+		.run_if(is_multiplayer)
+		.run_if(not(is_replay))
+		.run_if(in_state(GameState::Battle).or_else(in_state(GameState::Wait)).or_else(in_state(GameState::Move)))
+	);
+	app.init_resource::<PendingCertificate>();
+	app.add_systems(Update, (handle_certificate_interactions, handle_certificate_events, certificate_prompt_ui).chain());
+	app.add_systems(Update, toast_ui);
+	app.add_systems(Update, (update_network_stats, toggle_network_stats_overlay, network_stats_overlay_ui).chain());
+	app.add_systems(PreUpdate, block_input_while_chatting
+		.after(InputSystem)
+		.run_if(in_state(GameState::Battle).or_else(in_state(GameState::Wait)).or_else(in_state(GameState::Move)))
+	);
+	app.add_systems(OnEnter(GameState::GameOver), reset_chat);
+	app.add_systems(OnEnter(GameState::MainMenu), reset_chat);
+	app.add_systems(Startup, get_toggle_console_key);
+	app.run();
+}
+
+// SYSTEMS
+
+// Client
+fn set_window_icon(
+    // we have to use `NonSend` here
+    windows: NonSend<WinitWindows>,
+    window_query: Query<(Entity, &Window), With<PrimaryWindow>>,
+) {
+	let (entity, window) = window_query.single();
+	
+	let primary = windows.get_window(entity).unwrap();
+	
+	// here we use the `image` crate to load our icon data from a png file
+	// this is not a very bevy-native solution, but it will do
+	let (icon_rgba, icon_width, icon_height) = {
+		let image = image::open("amlogo.png")
+			.expect("Failed to open icon path")
+			.into_rgba8();
+		let (width, height) = image.dimensions();
+		let rgba = image.into_raw();
+		(rgba, width, height)
+	};
+
+	let icon = Icon::from_rgba(icon_rgba, icon_width, icon_height).unwrap();
+
+	primary.set_window_icon(Some(icon));
+	
+    
+
+    
+}
+
+// Server
+fn read_map_system(mut events: EventReader<GameStartEvent>, mut events2: EventWriter<MapReadEvent>) {
+	
+	for event in events.iter() {
+		//info!("DEBUG: Reading map file...");
+	
+		let file_contents = fs::read_to_string("src/map.txt").unwrap();
+		
+		//info!("DEBUG: Read map file.");
+		
+		// Separate map into lines.
+		let map_lines: Vec<&str> = file_contents.split('\n').collect();
+		//info!("DEBUG: Map line 1 is: \n{}", map_lines[0]);
+		
+		// Separate lines and build 2D-array.
+		info!("DEBUG: Starting to build 2D array of map...");
+		let mut map: Vec<Vec<String>> = Vec::new();
+		for i in 0..map_lines.len() {
+			let mut map_line: Vec<String> = Vec::new();
+			let line = map_lines[i];
+			let line_splitted: Vec<&str> = line.split(' ').collect();
+			for j in 0..line_splitted.len() {
+				let map_cell = line_splitted[j].to_owned();
+				map_line.push(map_cell);
+			}
+			map.push(map_line);
+		}
+		info!("DEBUG: Finished building 2D array of map.");
+		
+		//info!("DEBUG: Printing map file...");
+		//info!("{}", file_contents);
+		//info!("DEBUG: Printed map file...");
+		
+		events2.send(MapReadEvent {
+						map: map,
+					});
+	}
+}
+
+// Client & Server
+fn setup_map_system(mut events: EventReader<MapReadEvent>, mut events2: EventWriter<MapSetupEvent>, mut commands: Commands, asset_server: Res<AssetServer>) {
+	
+	for event in events.iter() {
+		// Spawn camera.
+		commands.spawn(Camera2dBundle::default());
+		
+		let map = &event.map;
+		
+		info!("DEBUG: Starting to set up map in the ECS World...");
+		// For each cell in map, generate a 2D text and position it.
+		for i in 0..map.len() {
+			for j in 0..map[i].len() {
+			
+				// Compute position.
+				let i_as_float = i as f32;
+				let j_as_float = j as f32;
+			
+				// Spawn text.
+				commands.spawn((
+					TextBundle::from_section(
+						map[i][j].as_str(),
+						TextStyle {
+							font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+							font_size: 80.0,
+							color: Color::WHITE,
+						},
+					)
+					.with_text_alignment(TextAlignment::Center)
+					.with_style(Style {
+						position_type: PositionType::Absolute,
+						top: Val::Px(60.0 * i_as_float),
+						right: Val::Px(60.0 * j_as_float),
+						max_width: Val::Px(100.0),
+						max_height: Val::Px(300.0),
+						..default()
+					}),
+					Tile,
+					Pos {
+						x: i,
+						y: j,
+					},
+				));
+			}
+		}
+		info!("DEBUG: Finished setting up map in the ECS World.");
+		events2.send(MapSetupEvent);
+	}
+}
+
+// Server
+fn read_battle_system(mut events: EventReader<MapSetupEvent>, mut events2: EventWriter<UnitsReadEvent>) {
+	for event in events.iter() {
+		let records = match read_unit_records("the_patrol_ambush") {
+			Ok(records) => records,
+			Err(error) => {
+				info!("DEBUG: {}", error);
+				continue;
+			},
+		};
+		events2.send(UnitsReadEvent {
+							units: records,
+						});
+	}
+}
+
+// Server
+fn generate_units_system(mut events: EventReader<UnitsReadEvent>, mut events2: EventWriter<UnitsGeneratedEvent>, mut commands: Commands, classes: Res<Classes>, items: Res<Items>) {
+	
+	for event in events.iter() {
+		// For each record, create an Entity for an unit.
+		let records = &event.units;
+		for record in records {
+			info!("DEBUG: Creating new unit...");
+			let mut unit_attributes = match record.unit_attributes(&classes) {
+				Ok(unit_attributes) => unit_attributes,
+				Err(error) => {
+					info!("DEBUG: {}", error);
+					continue;
+				},
+			};
+			unit_attributes.apply_equipment(&items);
+			commands.spawn((
+				unit_attributes,
+				Unit,
+			));
+		}
+		events2.send(UnitsGeneratedEvent);
+	}
+}
+
+// Server
+fn place_units_on_map_system(mut events: EventReader<UnitsGeneratedEvent>, unit_positions: Query<(&UnitId, &PosX, &PosY)>, mut tiles: Query<(&Tile, &Pos, &mut Text)>, mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
+	
+	for event in events.iter() {
+		info!("DEBUG: Starting to place units on map...");
+		// For each Unit...
+		for (unit_id, unit_position_x, unit_position_y) in unit_positions.iter() {
+			// Get the unit X and Y coordinates.
+			let x = unit_position_x.value;
+			let y = unit_position_y.value;
+			
+			// Get the tile at coordinates (x, y)
+			for (tile, pos, mut text) in tiles.iter_mut() {
+				if pos.x == x && pos.y == y {
+					// Assign unit ID to tile.
+					info!("DEBUG: Assigning unit ID to tile.");
+					text.sections[0].value = unit_id.value.to_string();
+				}
+			}
+		}
+		info!("DEBUG: Finished placing units on map.");
+		
+		//info!("DEBUG: Setting GameState to Wait...");
+		////commands.insert_resource(NextState(GameState::Wait));
+		//next_state.set(GameState::Wait);	
+		//info!("DEBUG: Set GameState to Wait.");
+		
+		info!("DEBUG: Setting GameState to LoadingComplete...");
+		//commands.insert_resource(NextState(GameState::Wait));
+		next_state.set(GameState::LoadingComplete);	
+		info!("DEBUG: Set GameState to LoadingComplete.");
+	}
+}
+
+// Client
+fn init_cursor_system(mut commands: Commands, asset_server: Res<AssetServer>,) {
+	commands.spawn((
+		Cursor {
+			x: 0,
+			y: 0,
+		},
+		SpriteBundle {
+			texture: asset_server.load("cursor.png"),
+			visibility: Visibility::Hidden,
+			..default()
+		}));
+}
+
+// Client & Server
+fn start_game_system(mut input: ResMut<Input<KeyCode>>, mut events: EventWriter<GameStartEvent>, mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
+	if input.just_pressed(KeyCode::Space) {
+		info!("DEBUG: Setting GameState to Loading...");
+		//commands.insert_resource(NextState(GameState::Loading));
+		next_state.set(GameState::Loading);
+		info!("DEBUG: Set GameState to Loading.");
+        events.send(GameStartEvent);
+    } 
+}
+
+// Client
+fn setup_cursor_system(mut commands: Commands, mut tiles: Query<(&Tile, &Pos, &mut Text)>, game: Res<Game>, units: Query<(&UnitId, &PosX, &PosY)>, mut cursors: Query<&mut Cursor>) {
+	
+	info!("DEBUG: setup_cursor_system running...");
+	// Setup cursor.
+	
+	for (unit, pos_x, pos_y) in units.iter() {
+		if unit.value == game.current_unit {
+			for (tile, pos, mut text) in tiles.iter_mut() {
+				if pos.x == pos_x.value && pos.y == pos_y.value {
+					// Place cursor at the current unit position.
+					info!("DEBUG: Current unit is {}, at coordinates ({}, {}). Placing cursor there.", unit.value, pos_x.value, pos_y.value);
+					
+					// Build cursor string.
+					let mut cursor = "[".to_owned();
+					cursor.push_str(&text.sections[0].value);
+					cursor.push_str("]");
+					
+					// Assign cursor string to map.
+					text.sections[0].value = cursor;
+					
+					// Update cursor entity.
+					for mut _cursor in cursors.iter_mut() {
+						_cursor.x = pos.x;
+						_cursor.y = pos.y;
+					}
+				}
+			}
+		}
+	}
+}
+
+// Client
+fn setup_cursor_system_2(
+mut commands: Commands,
+mut tiles: Query<(Entity, &Pos), With<GameText>>,
+game: Res<Game>,
+units: Query<(&UnitId, &Pos)>,
+mut cursor_query: Query<(Entity, &mut Cursor, &mut Visibility)>,
+asset_server: Res<AssetServer>,
+) {
+	
+	info!("DEBUG: setup_cursor_system_2 running...");
+	// Setup cursor.
+	let (entity, mut cursor, mut visibility) = cursor_query.single_mut();
+	
+	// Find the tile the current unit is on.
+	for (unit_id, pos) in units.iter() {
+		if unit_id.value == game.current_unit {
+			// Set the cursor to the current unit tile.
+			cursor.x = pos.x;
+			cursor.y = pos.y;
+			
+			// Make the cursor sprite visible.
+			*visibility = Visibility::Visible;
+			
+		}
+	}
+}
+
+// Prototype
+fn move_cursor_2(
+map_query: Query<&Map>,
+mut cursor_query: Query<(&mut Cursor)>,
+mut input: ResMut<Input<KeyCode>>,
+) {
+	let mut cursor = cursor_query.single_mut();
+	let map = &map_query.single().map;
+	if input.just_pressed(KeyCode::W) {
+		if cursor.y == map[0].len() - 1 {
+			info!("DEBUG: You can't move the cursor there.");
+		} else {		
+			// Move Cursor North.
+			cursor.y += 1;
+		}
+	}
+
+	if input.just_pressed(KeyCode::A) {
+		
+		if cursor.x == 0 {
+			info!("DEBUG: You can't move the cursor there.");
+		} else {		
+			// Move Cursor West.
+			cursor.x -= 1;
+		}
+	}
+	
+	if input.just_pressed(KeyCode::S) {
+		if cursor.y == 0 {
+			info!("DEBUG: You can't move the cursor there.");
+		} else {		
+			// Move Cursor South.
+			cursor.y -= 1;
+		}
+	}
+	
+	if input.just_pressed(KeyCode::D) {
+		if cursor.x == map.len() - 1 {
+			info!("DEBUG: You can't move the cursor there.");
+		} else {		
+			// Move Cursor East.
+			cursor.x += 1;
+		}
+	}
+}
+
+// Prototype
+fn position_cursor(
+map_query: Query<&Map>,
+mut cursor_query: Query<(&Cursor, &mut Transform), Without<GameText>>,
+tiles_query: Query<&Transform, With<GameText>>,
+) {
+	// Get the cursor.
+	let (cursor, mut transform) = cursor_query.single_mut();
+	
+	// Get the map.
+	let map = &map_query.single().map;
+	
+	// Get the transform of the tile the unit is on.
+	let tile_entity = map[cursor.x][cursor.y].3[map[cursor.x][cursor.y].3.len() - 1];
+	if let Ok(tile_transform) = tiles_query.get(tile_entity) {
+		// Set the transform of the cursor to be the same as the tile's transform.
+		transform.translation = tile_transform.translation.clone();
+		
+		// Add the required Y modifier to ensure the correct placement
+		// at the center of the tile.
+		transform.translation.y += 16.0;
+		// Add the required Z modifier to ensure the correct Z-ordering.
+		// This modifier is equal to half the unit's Z modifier,
+		// so that the cursor will be placed above the tile but below the unit.
+		transform.translation.z += 0.000000005;
+	}
+}
+
+fn hide_cursor(
+mut commands: Commands,
+mut cursor_query: Query<(Entity, &mut Visibility), With<Cursor>>,
+) {
+	let (entity, mut visibility) = cursor_query.single();
+	
+	visibility = &Visibility::Hidden;
+}
+
+// Client
+fn wait_turn_system(mut units: Query<(Entity, &mut WTCurrent, &WTMax, &UnitId, &UnitTeam, &Pos, &UnitRole)>, mut game: ResMut<Game>, hot_seat: Res<HotSeat>, mut objectives: ResMut<Objectives>, alliances: Res<Alliances>, mut replay_recorder: ResMut<ReplayRecorder>, mut undoable_move: ResMut<UndoableMove>, mut action_used: ResMut<ActionUsed>, mut commands: Commands, mut next_state: ResMut<NextState<TurnState>>) {
+	
+	// Neutral units don't hold tiles for a team.
+	let unit_positions: Vec<(usize, Pos)> = units
+		.iter()
+		.filter(|(_, _, _, _, _, _, role)| **role != UnitRole::Neutral)
+		.map(|(_, _, _, _, unit_team, pos, _)| (unit_team.value, *pos))
+		.collect();
+	let mut turn_started = false;
+	
+	// Decrease all units WT. If WT equals 0, set the unit as the current unit turn.
+	for (entity, mut wt_current, wt_max, unit_id, unit_team, _, role) in units.iter_mut() {
+		if wt_current.value == 0 {
+			info!("DEBUG: Unit with UnitId {} has WTCurrent of 0.", unit_id.value);
+		
+			game.current_unit = unit_id.value;
+			info!("DEBUG: It is now unit {} turn.", unit_id.value);
+			turn_started = true;
+			
+			game.current_team = unit_team.value;
+			info!("DEBUG: It is now team {} turn.", unit_team.value);
+			
+			// Neutral units don't take turns for a team.
+			if *role != UnitRole::Neutral {
+				objectives.start_unit_turn(unit_team.value, &unit_positions, &alliances);
+			}
+			
+			commands.entity(entity).insert(CurrentUnit {});
+			
+			// Guests and neutral units are controlled by the AI, whoever controls their team.
+			if *role != UnitRole::Regular {
+				info!("DEBUG: It is now the turn of a {:?} unit.", role);
+				info!("DEBUG: Setting TurnState to AI...");
+				next_state.set(TurnState::AI);
+				info!("DEBUG: Set TurnState to AI.");
+			} else if let Some(player) = game.players.get(&game.current_team) {
+				// Find if team is controlled by player.
+				match player {
+					ControlledBy::Player => {
+						// Player turn.
+						info!("DEBUG: It is now the player's turn.");
+						if hot_seat.enabled && hot_seat.current_team != Some(game.current_team) {
+							// Another player shares this machine. Let them take the controls first.
+							info!("DEBUG: Setting TurnState to PassControls...");
+							next_state.set(TurnState::PassControls);
+							info!("DEBUG: Set TurnState to PassControls.");
+						} else {
+							// Set TurnState to Turn.
+							info!("DEBUG: Setting TurnState to Turn...");
+							next_state.set(TurnState::Turn);
+							info!("DEBUG: Set TurnState to Turn.");
+						}
+					},
+					ControlledBy::AI => {
+						// AI turn.
+						// Set TurnState to AI.
+						info!("DEBUG: It is now the AI's turn.");
+						info!("DEBUG: Setting TurnState to AI...");
+						next_state.set(TurnState::AI);
+						info!("DEBUG: Set TurnState to AI.");
+					},
+					ControlledBy::None => {
+						info!("DEBUG: There isn't any player or AI controlling the current team. Please assign a controller to this team.");
+					}
+				}
+			}
+		} else {
+			wt_current.value = wt_current.value - 1;
+		}
+	}
+	
+	if turn_started {
+		undoable_move.value = None;
+		action_used.value = false;
+		
+		let wait_turns: Vec<(usize, usize)> = units.iter().map(|(_, wt_current, _, unit_id, _, _, _)| (unit_id.value, wt_current.value)).collect();
+		replay_recorder.record_turn(wait_turns, game.current_unit);
+	}
+}
+
+// Client
+fn end_turn_single_player(mut input: ResMut<Input<KeyCode>>, mut units: Query<(Entity, &mut WTCurrent, &WTMax), With<CurrentUnit>>, mut commands: Commands, mut next_state: ResMut<NextState<TurnState>>) {
+	if input.just_pressed(KeyCode::T) {
+		info!("DEBUG: The current unit has ended its turn.");
+		info!("DEBUG: Reseting the unit's WT.");
+		for (entity, mut wt_current, wt_max) in units.iter_mut() {
+			if wt_current.value == 0 {
+				wt_current.value = wt_max.value;
+				commands.entity(entity).remove::<CurrentUnit>();
+				break;
+			}
+		}
+		
+		// Set TurnState to Wait.
+		info!("DEBUG: Setting TurnState to Wait...");
+		next_state.set(TurnState::Wait);
+		info!("DEBUG: Set TurnState to Wait.");
+	}
+}
+
+// Client
+fn end_turn_system(mut input: ResMut<Input<KeyCode>>, mut units: Query<(&mut WTCurrent, &WTMax)>, mut commands: Commands, game: Res<Game>, mut client: ResMut<Client>, mut network_stats: ResMut<NetworkStats>) {
+	if input.just_pressed(KeyCode::T) {
+		//info!("DEBUG: The current unit has ended its turn.");
+		//info!("DEBUG: Reseting the unit's WT.");
+		//for (mut wt_current, wt_max) in units.iter_mut() {
+			//if wt_current.value == 0 {
+				//wt_current.value = wt_max.value;
+				//break;
+			//}
+		//}
+		
+		info!("DEBUG: Sending Wait message...");
+		send_client_message(&client, &mut network_stats, ClientMessage::Wait { unit_id: game.current_unit, });
+		info!("DEBUG: Sent Wait message.");
+		
+		//// Set GameState to Wait.
+		//info!("DEBUG: Setting GameState to Wait...");
+		//commands.insert_resource(NextState(GameState::Wait));
+		//info!("DEBUG: Set GameState to Wait.");
+	}
+}
+
+// Client
+fn setup_game_resource_system(mut commands: Commands, game: Res<Game>, hot_seat: Res<HotSeat>, campaign: Res<Campaign>, mut replay_recorder: ResMut<ReplayRecorder>) {
+	let battle_rng = BattleRng::default();
+	// Replays spawn the units of the scenario, not the ones of a campaign roster.
+	if campaign.active {
+		replay_recorder.replay = None;
+	} else {
+		replay_recorder.start(&game.scenario, battle_rng.seed);
+	}
+	commands.insert_resource(battle_rng);
+	
+	let mut players = HashMap::new();
+	players.insert(1, ControlledBy::Player);
+	if hot_seat.enabled {
+		players.insert(2, ControlledBy::Player);
+	} else {
+		players.insert(2, ControlledBy::AI);
+	}
+	
+	commands.insert_resource(Game {
+		current_unit: 0,
+		current_team: 1,
+		players: players,
+		winner: ControlledBy::None,
+		is_multiplayer: false,
+		is_spectator: false,
+		scenario: game.scenario.clone(),
+	});
+}
+
+// Client
+fn setup_game_resource_system_multiplayer(mut commands: Commands) {
+	let mut players = HashMap::new();
+	players.insert(1, ControlledBy::Player);
+	players.insert(2, ControlledBy::Player);
+	
+	commands.insert_resource(Game {
+		current_unit: 0,
+		current_team: 1,
+		players: players,
+		winner: ControlledBy::None,
+		is_multiplayer: true,
+		is_spectator: false,
+		scenario: SCENARIOS[0].to_string(),
+	});
+}
+
+// Client
+fn start_connection(mut client: ResMut<Client>, args: Res<CliArgs>, local_server: Option<Res<LocalServer>>, mut network_stats: ResMut<NetworkStats>) {
+	// Coming back to the main menu, e.g. from the lobby, keeps the existing connection.
+	if client.get_connection().is_some() {
+		return;
+	}
+	
+	// When hosting the local loopback server, connect to it instead of the remote amserver.
+	let server_address = if args.local_server {
+		format!("127.0.0.1:{}", args.port)
+	} else {
+		args.server.clone()
+	};
+
+	info!("DEBUG: Connecting to server at {}...", server_address);
+	network_stats.connection_state = NetworkConnectionState::Connecting;
+	client
+		.open_connection(
+			ConnectionConfiguration::from_strings(
+				//"127.0.0.1:6000",
+				//"139.162.244.70:6000",
+				&server_address,
+                "0.0.0.0:0",
+            ).unwrap(),
+            certificate_verification_mode(pinned_fingerprint(&args, local_server.as_deref()).is_some()),
+        ).unwrap();
+}
+
+// Client
+/// The fingerprint the server's certificate has to match, if any.
+fn pinned_fingerprint(args: &CliArgs, local_server: Option<&LocalServer>) -> Option<String> {
+	// The local loopback server generates a new certificate every time it starts, so its own is pinned.
+	if args.local_server {
+		return local_server.and_then(|local_server| local_server.certificate_fingerprint.clone());
+	}
+	
+	args.server_fingerprint.clone()
+}
+
+// Client
+fn certificate_verification_mode(is_pinned: bool) -> CertificateVerificationMode {
+	let mut verifier_behaviour = HashMap::new();
+	if is_pinned {
+		// Every certificate is checked against the pinned fingerprint in `handle_certificate_interactions`.
+		verifier_behaviour.insert(CertVerificationStatus::UnknownCertificate, CertVerifierBehaviour::RequestClientAction);
+		verifier_behaviour.insert(CertVerificationStatus::UntrustedCertificate, CertVerifierBehaviour::RequestClientAction);
+		verifier_behaviour.insert(CertVerificationStatus::TrustedCertificate, CertVerifierBehaviour::RequestClientAction);
+	} else {
+		// Trust on first use, and ask the player when a known server's certificate changes.
+		verifier_behaviour.insert(CertVerificationStatus::UnknownCertificate, CertVerifierBehaviour::ImmediateAction(CertVerifierAction::TrustAndStore));
+		verifier_behaviour.insert(CertVerificationStatus::UntrustedCertificate, CertVerifierBehaviour::RequestClientAction);
+		verifier_behaviour.insert(CertVerificationStatus::TrustedCertificate, CertVerifierBehaviour::ImmediateAction(CertVerifierAction::TrustOnce));
+	}
+	
+	CertificateVerificationMode::TrustOnFirstUse(TrustOnFirstUseConfig {
+		known_hosts: KnownHosts::HostsFile(KNOWN_HOSTS_FILE.to_string()),
+		verifier_behaviour: verifier_behaviour,
+	})
+}
+
+// Client
+/// Trusts a certificate that matches the pinned fingerprint for this session, and aborts the connection otherwise.
+fn pinned_certificate_action(fingerprint: &str, pinned_fingerprint: &str) -> CertVerifierAction {
+	if fingerprint == pinned_fingerprint {
+		CertVerifierAction::TrustOnce
+	} else {
+		CertVerifierAction::AbortConnection
+	}
+}
+
+// Client
+fn handle_certificate_interactions(
+mut interaction_events: ResMut<Events<CertificateInteractionEvent>>,
+mut pending_certificate: ResMut<PendingCertificate>,
+mut toasts: ResMut<Toasts>,
+args: Res<CliArgs>,
+local_server: Option<Res<LocalServer>>,
+) {
+	for interaction in interaction_events.drain() {
+		let fingerprint = interaction.info.fingerprint.to_base64();
+		
+		// With a pinned fingerprint, the player isn't asked.
+		if let Some(pinned_fingerprint) = pinned_fingerprint(&args, local_server.as_deref()) {
+			let action = pinned_certificate_action(&fingerprint, &pinned_fingerprint);
+			if matches!(action, CertVerifierAction::TrustOnce) {
+				info!("DEBUG: Server certificate matches the pinned fingerprint.");
+			} else {
+				info!("DEBUG: Server certificate {} doesn't match the pinned fingerprint {}. Aborting connection.", fingerprint, pinned_fingerprint);
+				toasts.toasts.push(Toast { message: String::from("The server certificate doesn't match the pinned fingerprint."), remaining: TOAST_DURATION_SECONDS, });
+			}
+			let _ = interaction.apply_cert_verifier_action(action);
+			continue;
+		}
+		
+		info!("DEBUG: Server certificate changed to {}. Asking the player...", fingerprint);
+		pending_certificate.interaction = Some(interaction);
+	}
+}
+
+// Client
+fn handle_certificate_events(
+mut update_events: EventReader<CertificateUpdateEvent>,
+mut abort_events: EventReader<CertificateConnectionAbortEvent>,
+mut toasts: ResMut<Toasts>,
+) {
+	for event in update_events.iter() {
+		info!("DEBUG: Stored new certificate fingerprint for {:?}.", event.server_name);
+	}
+	for event in abort_events.iter() {
+		info!("DEBUG: Connection aborted because of the server certificate: {:?}.", event.status);
+		toasts.toasts.push(Toast { message: String::from("Connection aborted: the server certificate isn't trusted."), remaining: TOAST_DURATION_SECONDS, });
+	}
+}
+
+// Client
+fn certificate_prompt_ui(
+mut contexts: EguiContexts,
+mut pending_certificate: ResMut<PendingCertificate>,
+) {
+	let Some(interaction) = &pending_certificate.interaction else {
+		return;
+	};
+	
+	let mut action = None;
+	egui::Window::new("Server certificate changed")
+		.anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+		.collapsible(false)
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			ui.label(format!("The certificate of {:?} is not the one it used before.", interaction.info.server_name));
+			ui.label("This can happen after a server update, but it can also mean someone is impersonating the server.");
+			ui.separator();
+			if let Some(known_fingerprint) = &interaction.info.known_fingerprint {
+				ui.label(format!("Known fingerprint: {}", known_fingerprint.to_base64()));
+			}
+			ui.label(format!("New fingerprint: {}", interaction.info.fingerprint.to_base64()));
+			ui.separator();
+			
+			ui.horizontal(|ui| {
+				if ui.button("Trust and remember").clicked() {
+					action = Some(CertVerifierAction::TrustAndStore);
+				}
+				if ui.button("Trust once").clicked() {
+					action = Some(CertVerifierAction::TrustOnce);
+				}
+				if ui.button("Disconnect").clicked() {
+					action = Some(CertVerifierAction::AbortConnection);
+				}
+			});
+		});
+	
+	if let Some(action) = action {
+		info!("DEBUG: Player chose {:?} for the server certificate.", action);
+		if let Some(interaction) = pending_certificate.interaction.take() {
+			let _ = interaction.apply_cert_verifier_action(action);
+		}
+	}
+}
+
+// Client
+fn update_network_stats(
+client: Res<Client>,
+mut network_stats: ResMut<NetworkStats>,
+mut connection_events: EventReader<ConnectionEvent>,
+mut connection_lost_events: EventReader<ClientConnectionLostEvent>,
+) {
+	for _ in connection_events.iter() {
+		info!("DEBUG: Connected to the server.");
+		network_stats.connection_state = NetworkConnectionState::Connected;
+	}
+	for _ in connection_lost_events.iter() {
+		info!("DEBUG: Lost the connection to the server.");
+		network_stats.connection_state = NetworkConnectionState::Lost;
+	}
+	
+	let Some(connection) = client.get_connection() else {
+		return;
+	};
+	let Some(stats) = connection.connection_stats() else {
+		return;
+	};
+	network_stats.latency = Some(stats.path.rtt);
+	network_stats.sent_packets = stats.path.sent_packets;
+	network_stats.lost_packets = stats.path.lost_packets;
+	network_stats.bytes_sent = stats.udp_tx.bytes;
+	network_stats.bytes_received = stats.udp_rx.bytes;
+}
+
+// Client
+fn toggle_network_stats_overlay(
+input: Res<Input<KeyCode>>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	if input.just_pressed(KeyCode::F9) {
+		network_stats.show_overlay = !network_stats.show_overlay;
+	}
+}
+
+// Client
+fn network_stats_overlay_ui(
+mut contexts: EguiContexts,
+network_stats: Res<NetworkStats>,
+) {
+	if !network_stats.show_overlay {
+		return;
+	}
+	
+	egui::Window::new("Network")
+		.anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			for line in format_network_stats(&network_stats) {
+				ui.monospace(line);
+			}
+		});
+}
+
+// Client
+fn netstats_command(mut log: ConsoleCommand<NetstatsCommand>, network_stats: Res<NetworkStats>) {
+	if let Some(Ok(NetstatsCommand {})) = log.take() {
+		for line in format_network_stats(&network_stats) {
+			reply!(log, "{}", line);
+		}
+	}
+}
+
+// Utility
+fn format_network_stats(network_stats: &NetworkStats) -> Vec<String> {
+	let mut lines = Vec::new();
+	
+	lines.push(format!("State: {:?}", network_stats.connection_state));
+	match network_stats.latency {
+		Some(latency) => lines.push(format!("Latency: {} ms", latency.as_millis())),
+		None => lines.push(String::from("Latency: -")),
+	}
+	let loss = if network_stats.sent_packets > 0 {
+		network_stats.lost_packets as f64 * 100.0 / network_stats.sent_packets as f64
+	} else {
+		0.0
+	};
+	lines.push(format!("Packet loss: {:.1}% ({}/{})", loss, network_stats.lost_packets, network_stats.sent_packets));
+	lines.push(format!("Bytes sent: {}", network_stats.bytes_sent));
+	lines.push(format!("Bytes received: {}", network_stats.bytes_received));
+	
+	let mut sent: Vec<_> = network_stats.sent.iter().collect();
+	sent.sort();
+	lines.push(String::from("Sent:"));
+	for (name, count) in sent {
+		lines.push(format!("  {}: {}", name, count));
+	}
+	
+	let mut received: Vec<_> = network_stats.received.iter().collect();
+	received.sort();
+	lines.push(String::from("Received:"));
+	for (name, count) in received {
+		lines.push(format!("  {}: {}", name, count));
+	}
+	
+	lines
+}
+
+// Client
+fn send_client_message(client: &Client, network_stats: &mut NetworkStats, message: ClientMessage) {
+	*network_stats.sent.entry(message.name()).or_insert(0) += 1;
+	client
+		.connection()
+		.try_send_message(message);
+}
+
+// Client
+fn send_get_client_id_message(client: ResMut<Client>, profile: Res<PlayerProfile>, mut network_stats: ResMut<NetworkStats>) {
+	info!("DEUBG: Sending GetClientId message...");
+	send_client_message(&client, &mut network_stats, ClientMessage::GetClientId);
+	info!("DEUBG: Sent GetClientId message.");
+	
+	info!("DEBUG: Sending Identify message...");
+	send_client_message(&client, &mut network_stats, ClientMessage::Identify {
+		player_id: profile.player_id,
+		name: profile.name.clone(),
+		token: profile.token.clone(),
+	});
+	info!("DEBUG: Sent Identify message.");
+}
+
+// Client
+fn add_server_message_handlers(app: &mut App) {
+	app.add_event::<ClientIdMessageEvent>();
+	app.add_event::<StartGameMessageEvent>();
+	app.add_event::<PlayerTurnMessageEvent>();
+	app.add_event::<WaitTurnMessageEvent>();
+	app.add_event::<WaitMessageEvent>();
+	app.add_event::<MoveMessageEvent>();
+	app.add_event::<BasicAttackMessageEvent>();
+	app.add_event::<UseItemMessageEvent>();
+	app.add_event::<DeploymentMessageEvent>();
+	app.add_event::<GameOverMessageEvent>();
+	app.add_event::<WinningTeamMessageEvent>();
+	app.add_event::<GameListMessageEvent>();
+	app.add_event::<LobbyUpdateMessageEvent>();
+	app.add_event::<ChatMessageEvent>();
+	app.add_event::<SnapshotMessageEvent>();
+	app.add_event::<TurnTimerMessageEvent>();
+	app.add_event::<ActionRejectedMessageEvent>();
+	app.add_event::<ErrorMessageEvent>();
+	app.add_event::<PlayersMessageEvent>();
+	app.add_event::<UndoMoveEvent>();
+	app.add_event::<GainExperienceEvent>();
+	app.init_resource::<Players>();
+	app.init_resource::<Toasts>();
+	app.init_resource::<ServerMessages>();
+	app.init_resource::<NetworkStats>();
+	app.init_resource::<PredictedMove>();
+	app.init_resource::<UndoableMove>();
+	app.init_resource::<ActionUsed>();
+	app.init_resource::<TurnTimer>();
+	app.init_resource::<PendingSnapshot>();
+	app.init_resource::<Lobby>();
+	app.init_resource::<Chat>();
+	app.init_resource::<ReplayRecorder>();
+	app.init_resource::<ReplayPlayer>();
+	
+	// The handlers run in their own schedule, which `process_server_messages` runs once per message,
+	// so that messages are handled in the order the server sent them.
+	app.add_systems(ServerMessageHandlers, (
+		handle_client_id_message,
+		handle_players_message,
+		handle_game_list_message,
+		handle_lobby_update_message,
+		handle_chat_message,
+		handle_start_game_message,
+		handle_snapshot_message,
+		handle_move_message,
+		handle_action_rejected_message,
+		handle_undo_move_events,
+		handle_error_message,
+		handle_basic_attack_message,
+		handle_use_item_message,
+		handle_wait_message,
+		handle_deployment_message,
+		handle_wait_turn_message,
+		handle_player_turn_message,
+		handle_turn_timer_message,
+		handle_winning_team_message,
+		handle_game_over_message,
+	).chain());
+	
+	// Messages are handled in every state, before unit actions are processed,
+	// so that a rejected move is rolled back cleanly.
+	app.add_systems(Update, (
+		receive_server_messages,
+		process_server_messages,
+	).chain()
+		.before(process_unit_actions)
+		.before(process_move_actions)
+		.before(handle_move_state)
+	);
+	// Offline, `undo_move` sends the undo itself, without a server message to run the handlers.
+	app.add_systems(Update, handle_undo_move_events
+		.run_if(is_singleplayer)
+		.before(process_unit_actions)
 	);
-	app.add_systems(Startup, get_toggle_console_key);
-	app.run();
 }
 
-// SYSTEMS
+/// The schedule holding the handlers of server messages.
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+struct ServerMessageHandlers;
+
+// Client
+fn receive_server_messages(
+client: Option<ResMut<Client>>,
+mut server_messages: ResMut<ServerMessages>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	if let Some(mut client) = client {
+		if let Some(connection) = client.get_connection_mut() {
+			while let Ok(Some(message)) = connection.receive_message::<ServerMessage>() {
+				*network_stats.received.entry(message.name()).or_insert(0) += 1;
+				server_messages.messages.push_back(message);
+			}
+		}
+	}
+}
+
+// Client
+/// Handles the queued server messages one at a time, in the order they were received.
+fn process_server_messages(world: &mut World) {
+	while let Some(message) = world.resource_mut::<ServerMessages>().messages.pop_front() {
+		send_server_message_event(world, message);
+		world.run_schedule(ServerMessageHandlers);
+		
+		// A state change has to happen before the next message is handled,
+		// so the rest of the queue waits for the next frame.
+		if world.resource::<NextState<GameState>>().0.is_some() || world.resource::<NextState<TurnState>>().0.is_some() {
+			break;
+		}
+	}
+}
+
+// Client
+/// Converts a server message into its typed event.
+fn send_server_message_event(world: &mut World, message: ServerMessage) {
+	match message {
+		ServerMessage::ClientId { client_id } => {
+			world.send_event(ClientIdMessageEvent { client_id: client_id, });
+		},
+		ServerMessage::StartGame { client_id } => {
+			world.send_event(StartGameMessageEvent { client_id: client_id, });
+		},
+		ServerMessage::StartGame2 => {
+			info!("DEBUG: Received StartGame2 message. This client doesn't use it.");
+		},
+		ServerMessage::PlayerTurn { client_id, current_unit } => {
+			world.send_event(PlayerTurnMessageEvent { client_id: client_id, current_unit: current_unit, });
+		},
+		ServerMessage::WaitTurn { wait_turns } => {
+			world.send_event(WaitTurnMessageEvent { wait_turns: wait_turns, });
+		},
+		ServerMessage::Wait => {
+			world.send_event(WaitMessageEvent);
+		},
+		ServerMessage::Move { origin, destination } => {
+			world.send_event(MoveMessageEvent { origin: origin, destination: destination, });
+		},
+		ServerMessage::BasicAttack { attacker, target, damage, is_counterattack } => {
+			world.send_event(BasicAttackMessageEvent {
+				attacker: attacker,
+				target: target,
+				damage: damage,
+				is_counterattack: is_counterattack,
+			});
+		},
+		ServerMessage::GameOver { winner } => {
+			world.send_event(GameOverMessageEvent { winner: winner, });
+		},
+		ServerMessage::GameList { games } => {
+			world.send_event(GameListMessageEvent { games: games, });
+		},
+		ServerMessage::LobbyUpdate { game } => {
+			world.send_event(LobbyUpdateMessageEvent { game: game, });
+		},
+		ServerMessage::Chat { sender, scope, message } => {
+			world.send_event(ChatMessageEvent { sender: sender, scope: scope, message: message, });
+		},
+		ServerMessage::Snapshot { scenario, snapshot } => {
+			world.send_event(SnapshotMessageEvent { scenario: scenario, snapshot: snapshot, });
+		},
+		ServerMessage::TurnTimer { current_unit, seconds, reserve } => {
+			world.send_event(TurnTimerMessageEvent { current_unit: current_unit, seconds: seconds, reserve: reserve, });
+		},
+		ServerMessage::Error { code, message, rejected_move } => {
+			// A refused action also has to roll back what the client predicted.
+			if code.is_action_rejection() {
+				world.send_event(ActionRejectedMessageEvent { reason: message.clone(), rejected_move: rejected_move, });
+			}
+			world.send_event(ErrorMessageEvent { code: code, message: message, });
+		},
+		ServerMessage::Players { players } => {
+			world.send_event(PlayersMessageEvent { players: players, });
+		},
+		ServerMessage::WinningTeam { team } => {
+			world.send_event(WinningTeamMessageEvent { team: team, });
+		},
+		ServerMessage::UndoMove { origin, destination } => {
+			// Only the current unit's move can be undone.
+			let unit_id = world.resource::<Game>().current_unit;
+			world.send_event(UndoMoveEvent { unit_id: unit_id, origin: origin, destination: destination, });
+		},
+		ServerMessage::UseItem { user, item } => {
+			world.send_event(UseItemMessageEvent { user: user, item: item, });
+		},
+		ServerMessage::Deployment { units } => {
+			world.send_event(DeploymentMessageEvent { units: units, });
+		},
+	}
+}
+
+// Client
+fn handle_client_id_message(
+mut events: EventReader<ClientIdMessageEvent>,
+mut client_data: ResMut<ClientData>,
+) {
+	for event in events.iter() {
+		// Configure ClientId.
+		client_data.client_id = event.client_id;
+		info!("DEBUG: Client ID is now {}.", client_data.client_id);
+	}
+}
+
+// Client
+fn handle_start_game_message(
+mut events: EventReader<StartGameMessageEvent>,
+mut game_start_events: EventWriter<GameStartEvent>,
+mut game: ResMut<Game>,
+mut lobby: ResMut<Lobby>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Server has sent StartGame message.");
+		
+		// Games started from the lobby use the scenario chosen there.
+		if let Some(lobby_game) = lobby.current_game.take() {
+			game.scenario = lobby_game.scenario;
+		} else {
+			game.scenario = SCENARIOS[0].to_string();
+		}
+		
+		// The server resolves the random numbers, so the replay has no seed of its own.
+		replay_recorder.start(&game.scenario, 0);
+		
+		// Start game.
+		info!("DEBUG: Starting game...");
+		info!("DEBUG: Setting GameState to Loading...");
+		next_state.set(GameState::Loading);
+		info!("DEBUG: Set GameState to Loading.");
+		game_start_events.send(GameStartEvent);
+		
+		// Set `Game` Resource `is_multiplayer` to true.
+		game.is_multiplayer = true;
+		game.is_spectator = false;
+	}
+}
+
+// Client
+fn handle_players_message(
+mut events: EventReader<PlayersMessageEvent>,
+mut players: ResMut<Players>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received Players message with {} players.", event.players.len());
+		players.players = event.players.clone();
+	}
+}
+
+// Client
+fn handle_winning_team_message(
+mut events: EventReader<WinningTeamMessageEvent>,
+mut players: ResMut<Players>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received WinningTeam message. Team {} won.", event.team);
+		players.winning_team = Some(event.team);
+	}
+}
+
+// Client
+fn handle_game_list_message(
+mut events: EventReader<GameListMessageEvent>,
+mut lobby: ResMut<Lobby>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received GameList message with {} games.", event.games.len());
+		lobby.games = event.games.clone();
+	}
+}
+
+// Client
+fn handle_lobby_update_message(
+mut events: EventReader<LobbyUpdateMessageEvent>,
+mut lobby: ResMut<Lobby>,
+client_data: Res<ClientData>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received LobbyUpdate message for game {}.", event.game.game_id);
+		
+		if event.game.players.iter().any(|player| player.client_id == client_data.client_id) {
+			lobby.current_game = Some(event.game.clone());
+		} else {
+			lobby.current_game = None;
+		}
+	}
+}
+
+// Client
+fn handle_chat_message(
+mut events: EventReader<ChatMessageEvent>,
+mut chat: ResMut<Chat>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received Chat message from client {}.", event.sender);
+		chat.history.push(ChatLine {
+			sender: event.sender,
+			scope: event.scope,
+			message: event.message.clone(),
+		});
+		
+		// Only keep the most recent lines.
+		if chat.history.len() > CHAT_HISTORY_LENGTH {
+			chat.history.remove(0);
+		}
+	}
+}
+
+// Client
+fn send_chat_message(client: &Client, network_stats: &mut NetworkStats, scope: ChatScope, message: &str) -> bool {
+	let Some(message) = filter_chat_message(message) else {
+		return false;
+	};
+	
+	info!("DEBUG: Sending Chat message...");
+	send_client_message(client, network_stats, ClientMessage::Chat { scope: scope, message: message, });
+	info!("DEBUG: Sent Chat message.");
+	true
+}
+
+// Client
+fn chat_ui(
+mut contexts: EguiContexts,
+mut chat: ResMut<Chat>,
+client: Res<Client>,
+client_data: Res<ClientData>,
+players: Res<Players>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	let chat = &mut *chat;
+	
+	egui::Window::new("Chat")
+		.anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+		.default_width(300.0)
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			egui::ScrollArea::vertical()
+				.max_height(150.0)
+				.stick_to_bottom(true)
+				.show(ui, |ui| {
+					for line in &chat.history {
+						let sender = if line.sender == client_data.client_id {
+							String::from("You")
+						} else {
+							display_name(&players, line.sender)
+						};
+						let scope = match line.scope {
+							ChatScope::All => "All",
+							ChatScope::Team => "Team",
+						};
+						ui.label(format!("[{}] {}: {}", scope, sender, line.message));
+					}
+				});
+			
+			ui.horizontal(|ui| {
+				egui::ComboBox::from_id_source("chat_scope")
+					.selected_text(match chat.scope {
+						ChatScope::All => "All",
+						ChatScope::Team => "Team",
+					})
+					.width(60.0)
+					.show_ui(ui, |ui| {
+						ui.selectable_value(&mut chat.scope, ChatScope::All, "All");
+						ui.selectable_value(&mut chat.scope, ChatScope::Team, "Team");
+					});
+				
+				let response = ui.add(egui::TextEdit::singleline(&mut chat.input).char_limit(CHAT_MESSAGE_MAX_LENGTH));
+				chat.focused = response.has_focus();
+				
+				let enter_pressed = response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+				if enter_pressed || ui.button("Send").clicked() {
+					send_chat_message(&client, &mut network_stats, chat.scope, &chat.input);
+					chat.input.clear();
+				}
+			});
+		});
+}
+
+// Client
+fn block_input_while_chatting(
+chat: Res<Chat>,
+mut input: ResMut<Input<KeyCode>>,
+) {
+	// Keys typed in the chat shouldn't also move the cursor or end the turn.
+	if chat.focused {
+		input.reset_all();
+	}
+}
+
+// Client
+fn reset_chat(
+mut chat: ResMut<Chat>,
+) {
+	// The chat of a battle ends with it, and must not keep the keyboard once its window is gone.
+	chat.history.clear();
+	chat.input.clear();
+	chat.focused = false;
+}
+
+// Utility
+fn filter_chat_message(message: &str) -> Option<String> {
+	let message: String = message.trim().chars().take(CHAT_MESSAGE_MAX_LENGTH).collect();
+	if message.is_empty() {
+		return None;
+	}
+	
+	// Replace every filtered word with asterisks.
+	let filtered_words: Vec<String> = message
+		.split(' ')
+		.map(|word| {
+			let bare_word: String = word.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+			if CHAT_FILTERED_WORDS.contains(&bare_word.as_str()) {
+				"*".repeat(word.chars().count())
+			} else {
+				word.to_string()
+			}
+		})
+		.collect();
+	
+	Some(filtered_words.join(" "))
+}
+
+// Client
+fn handle_turn_timer_message(
+mut events: EventReader<TurnTimerMessageEvent>,
+mut turn_timer: ResMut<TurnTimer>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received TurnTimer message. Unit {} has {} seconds, with {} seconds of reserve.", event.current_unit, event.seconds, event.reserve);
+		turn_timer.current_unit = Some(event.current_unit);
+		turn_timer.remaining = event.seconds;
+		turn_timer.reserve = event.reserve;
+		turn_timer.expired = false;
+	}
+}
+
+// Client
+fn tick_turn_timer(
+mut commands: Commands,
+mut turn_timer: ResMut<TurnTimer>,
+client: Res<Client>,
+game: Res<Game>,
+current_unit_query: Query<Entity, With<CurrentUnit>>,
+tiles_query: Query<Entity, Or<(With<MoveTile>, With<AttackTile>)>>,
+turn_state: Res<State<TurnState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+time: Res<Time>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	if turn_timer.current_unit.is_none() || turn_timer.expired {
+		return;
+	}
+	
+	// The turn time is used first, then the reserve.
+	let mut delta = time.delta_seconds();
+	let from_turn = delta.min(turn_timer.remaining);
+	turn_timer.remaining -= from_turn;
+	delta -= from_turn;
+	turn_timer.reserve = (turn_timer.reserve - delta).max(0.0);
+	
+	if turn_timer.remaining > 0.0 || turn_timer.reserve > 0.0 {
+		return;
+	}
+	
+	turn_timer.expired = true;
+	
+	// Only the player whose turn it is ends it. The server ends it anyway if the message doesn't arrive.
+	let is_our_turn = matches!(turn_state.get(), TurnState::Turn | TurnState::ChooseMove | TurnState::ChooseAttack);
+	if is_our_turn && !game.is_spectator && turn_timer.current_unit == Some(game.current_unit) {
+		info!("DEBUG: Turn time is over. Sending Wait message...");
+		send_client_message(&client, &mut network_stats, ClientMessage::Wait { unit_id: game.current_unit, });
+		info!("DEBUG: Sent Wait message.");
+		
+		// A tile that was being chosen can't be confirmed anymore.
+		if *turn_state.get() != TurnState::Turn {
+			for entity in tiles_query.iter() {
+				commands.entity(entity).despawn();
+			}
+			for entity in current_unit_query.iter() {
+				commands.entity(entity).remove::<MoveTiles>();
+				commands.entity(entity).remove::<AttackTiles>();
+			}
+			
+			info!("DEBUG: Setting TurnState back to Turn...");
+			next_turn_state.set(TurnState::Turn);
+			info!("DEBUG: Set TurnState back to Turn.");
+		}
+	}
+}
+
+// Client
+fn turn_ui(
+mut contexts: EguiContexts,
+turn_timer: Res<TurnTimer>,
+players: Res<Players>,
+client_data: Res<ClientData>,
+) {
+	let Some(current_player) = players.current_player else {
+		return;
+	};
+	
+	egui::Window::new("Turn")
+		.title_bar(false)
+		.anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			if current_player == client_data.client_id {
+				ui.label("Your turn");
+			} else {
+				ui.label(format!("{}'s turn", display_name(&players, current_player)));
+			}
+			
+			if turn_timer.current_unit.is_none() {
+				return;
+			}
+			let remaining = turn_timer.remaining.ceil() as u32;
+			if turn_timer.reserve > 0.0 {
+				let reserve = turn_timer.reserve.ceil() as u32;
+				ui.label(format!("Time: {}:{:02} (+{}:{:02})", remaining / 60, remaining % 60, reserve / 60, reserve % 60));
+			} else {
+				ui.label(format!("Time: {}:{:02}", remaining / 60, remaining % 60));
+			}
+		});
+}
+
+// Client
+fn handle_snapshot_message(
+mut events: EventReader<SnapshotMessageEvent>,
+mut game_start_events: EventWriter<GameStartEvent>,
+mut game: ResMut<Game>,
+players: Res<Players>,
+client_data: Res<ClientData>,
+mut pending_snapshot: ResMut<PendingSnapshot>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	for event in events.iter() {
+		// A player taking back their seat is among the players sent before the snapshot.
+		let is_player = players.players.iter().any(|player| player.client_id == client_data.client_id);
+		info!("DEBUG: Received Snapshot message. Loading the battle {}...", if is_player { "as a player" } else { "as a spectator" });
+		
+		game.is_multiplayer = true;
+		game.is_spectator = !is_player;
+		game.scenario = event.scenario.clone();
+		
+		// The snapshot is applied once the scenario has finished loading.
+		pending_snapshot.snapshot = Some(event.snapshot.clone());
+		
+		info!("DEBUG: Setting GameState to Loading...");
+		next_state.set(GameState::Loading);
+		info!("DEBUG: Set GameState to Loading.");
+		game_start_events.send(GameStartEvent);
+	}
+}
+
+// Client
+fn apply_pending_snapshot(
+mut commands: Commands,
+mut pending_snapshot: ResMut<PendingSnapshot>,
+mut map_query: Query<&mut Map>,
+mut units: Query<(Entity, &UnitId, &mut Pos, &mut DIR, &mut HPCurrent, &mut WTCurrent, &mut Transform), (With<Unit>, Without<GameText>)>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+mut game: ResMut<Game>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+	let Some(snapshot) = pending_snapshot.snapshot.take() else {
+		return;
+	};
+	
+	info!("DEBUG: Applying battle snapshot...");
+	let map = &mut map_query.single_mut().map;
+	
+	for (entity, unit_id, mut pos, mut dir, mut hp_current, mut wt_current, mut transform) in units.iter_mut() {
+		map[pos.x][pos.y].2.retain(|unit_entity| *unit_entity != entity);
+		
+		// Units missing from the snapshot have already died.
+		let Some(unit_snapshot) = snapshot.units.iter().find(|unit_snapshot| unit_snapshot.unit_id == unit_id.value) else {
+			commands.entity(entity).despawn();
+			continue;
+		};
+		
+		*pos = unit_snapshot.pos;
+		dir.direction = unit_snapshot.direction;
+		hp_current.value = unit_snapshot.hp_current;
+		wt_current.value = unit_snapshot.wt_current;
+		map[pos.x][pos.y].2.push(entity);
+		
+		// Place the unit on its tile, the same way `spawn_units` does.
+		let tile_entity = map[pos.x][pos.y].3[map[pos.x][pos.y].3.len() - 1];
+		if let Ok(tile_transform) = tile_transform_query.get(tile_entity) {
+			transform.translation = Vec3::new(tile_transform.translation.x, tile_transform.translation.y + 100.0, tile_transform.translation.z + 0.00000001);
+		}
+		
+		if snapshot.current_unit == Some(unit_id.value) {
+			game.current_unit = unit_id.value;
+			commands.entity(entity).insert(CurrentUnit {});
+		}
+	}
+	info!("DEBUG: Applied battle snapshot.");
+	
+	// Before the battle starts, a returning player goes where `loading_complete` sent them.
+	if !game.is_spectator && snapshot.current_unit.is_none() {
+		return;
+	}
+	
+	info!("DEBUG: Setting GameState to Battle...");
+	next_state.set(GameState::Battle);
+	info!("DEBUG: Set GameState to Battle.");
+	info!("DEBUG: Setting TurnState to Wait...");
+	next_turn_state.set(TurnState::Wait);
+	info!("DEBUG: Set TurnState to Wait.");
+}
+
+// Client
+fn spectator_overlay_ui(
+mut contexts: EguiContexts,
+units: Query<(&UnitId, &UnitTeam, &UnitName, &UnitClass, &HPCurrent, &HPMax, &WTCurrent, &Pos, &UnitRole, Option<&CurrentUnit>)>,
+client: Res<Client>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	let mut units: Vec<_> = units.iter().collect();
+	units.sort_by_key(|(unit_id, unit_team, ..)| (unit_team.value, unit_id.value));
+	
+	egui::Window::new("Spectating")
+		.anchor(egui::Align2::LEFT_TOP, egui::vec2(10.0, 10.0))
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			egui::Grid::new("spectator_units").striped(true).show(ui, |ui| {
+				ui.label("Team");
+				ui.label("Unit");
+				ui.label("Class");
+				ui.label("HP");
+				ui.label("WT");
+				ui.label("Position");
+				ui.end_row();
+				
+				for (unit_id, unit_team, unit_name, unit_class, hp_current, hp_max, wt_current, pos, role, current_unit) in &units {
+					match role {
+						UnitRole::Regular => ui.label(format!("{}", unit_team.value)),
+						_ => ui.label(format!("{} ({:?})", unit_team.value, role)),
+					};
+					if current_unit.is_some() {
+						ui.label(format!("> {} ({})", unit_name.value, unit_id.value));
+					} else {
+						ui.label(format!("{} ({})", unit_name.value, unit_id.value));
+					}
+					ui.label(&unit_class.value);
+					ui.label(format!("{}/{}", hp_current.value, hp_max.value));
+					ui.label(format!("{}", wt_current.value));
+					ui.label(format!("{}, {}", pos.x, pos.y));
+					ui.end_row();
+				}
+			});
+			
+			if ui.button("Stop spectating").clicked() {
+				send_client_message(&client, &mut network_stats, ClientMessage::StopSpectating);
+				
+				info!("DEBUG: Setting GameState to MainMenu...");
+				next_state.set(GameState::MainMenu);
+				info!("DEBUG: Set GameState to MainMenu.");
+				next_turn_state.set(TurnState::Wait);
+			}
+		});
+}
+
+// Client
+fn spectator_camera_system(
+mut camera_transform_query: Query<&mut Transform, With<Camera>>,
+input: Res<Input<KeyCode>>,
+) {
+	let mut camera_transform = camera_transform_query.single_mut();
+	
+	if input.pressed(KeyCode::Left) {
+		camera_transform.translation -= Vec3::X * 6.0;
+	}
+	if input.pressed(KeyCode::Right) {
+		camera_transform.translation += Vec3::X * 6.0;
+	}
+	if input.pressed(KeyCode::Up) {
+		camera_transform.translation += Vec3::Y * 6.0;
+	}
+	if input.pressed(KeyCode::Down) {
+		camera_transform.translation -= Vec3::Y * 6.0;
+	}
+}
+
+// Client
+fn send_start_game_message_system(mut input: ResMut<Input<KeyCode>>, client: Res<Client>, mut next_state: ResMut<NextState<GameState>>, mut network_stats: ResMut<NetworkStats>) {
+	if input.just_pressed(KeyCode::Space) {
+		send_client_message(&client, &mut network_stats, ClientMessage::StartGame);
+	} else if input.just_pressed(KeyCode::M) {
+		next_state.set(GameState::LoadAmbush);
+	}
+}
+
+// Client
+fn handle_player_turn_message(
+mut events: EventReader<PlayerTurnMessageEvent>,
+mut commands: Commands,
+client_data: Res<ClientData>,
+units: Query<(Entity, &UnitId)>,
+mut game: ResMut<Game>,
+mut players: ResMut<Players>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut undoable_move: ResMut<UndoableMove>,
+mut action_used: ResMut<ActionUsed>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+state: Res<State<GameState>>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received PlayerTurn message.");
+		replay_recorder.record(ReplayEvent::Turn { unit_id: event.current_unit, });
+		undoable_move.value = None;
+		action_used.value = false;
+		info!("DEBUG: Current state is {:?}.", state.get());
+		// Update Game resouce.
+		info!("DEBUG: Setting current unit to {}.", event.current_unit);
+		game.current_unit = event.current_unit;
+		info!("DEBUG: Set current unit to {}.", game.current_unit);
+		players.current_player = Some(event.client_id);
+		
+		// Assign the `CurrentUnit` component to the current unit.
+		for (entity, unit_id) in units.iter() {
+			if unit_id.value == game.current_unit {
+				info!("DEBUG: Inserting `CurrentUnit` component into unit {}.", unit_id.value);
+				commands.entity(entity).insert(CurrentUnit {});
+			}
+		}
+		
+		// Set state to Battle.
+		info!("DEBUG: Setting GameState to Battle...");
+		next_state.set(GameState::Battle);
+		info!("DEBUG: Set GameState to Battle.");
+		
+		if event.client_id == client_data.client_id {
+			// Set `TurnState` to `Turn`.
+			info!("DEBUG: Setting TurnState to Turn...");
+			next_turn_state.set(TurnState::Turn);
+			info!("DEBUG: Set TurnState to Turn.");
+		} else {
+			// Set `TurnState` to `Wait`.
+			info!("DEBUG: Setting TurnState to Wait...");
+			next_turn_state.set(TurnState::Wait);
+			info!("DEBUG: Set TurnState to Wait.");
+		}
+	}
+}
+
+// Client
+fn handle_wait_turn_message(
+mut events: EventReader<WaitTurnMessageEvent>,
+mut units: Query<(&UnitId, &mut WTCurrent)>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received WaitTurn message.");
+		replay_recorder.record(ReplayEvent::WaitTurn {
+			wait_turns: event.wait_turns.iter().map(|(unit_id, wt_current)| (unit_id.value, wt_current.value)).collect(),
+		});
+		
+		// Update unit WTs.
+		for unit_wt in &event.wait_turns {
+			for (unit_id, mut current_wt) in units.iter_mut() {
+				if unit_id.value == unit_wt.0.value {
+					current_wt.value = unit_wt.1.value;
+					break;
+				}
+			}
+		}
+	}
+}
+
+// Client
+fn handle_wait_message(
+mut events: EventReader<WaitMessageEvent>,
+mut commands: Commands,
+units: Query<(Entity, &UnitId)>,
+game: Res<Game>,
+mut turn_timer: ResMut<TurnTimer>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received Wait message.");
+		replay_recorder.record(ReplayEvent::Wait);
+		
+		// The turn is over, so its timer is too.
+		*turn_timer = TurnTimer::default();
+		
+		// Remove the `CurrentUnit` component from the current unit.
+		for (entity, unit_id) in units.iter() {
+			if unit_id.value == game.current_unit {
+				info!("DEBUG: Removing `CurrentUnit` component from unit.");
+				commands.entity(entity).remove::<CurrentUnit>();
+			}
+		}
+		
+		info!("DEBUG: Setting GameState to Wait...");
+		next_state.set(GameState::Wait);
+		info!("DEBUG: Set GameState to Wait.");
+		
+		info!("DEBUG: Setting TurnState to Wait...");
+		next_turn_state.set(TurnState::Wait);
+		info!("DEBUG: Set TurnState to Wait.");
+	}
+}
+
+// Client
+fn handle_move_message(
+mut events: EventReader<MoveMessageEvent>,
+mut units: Query<(&UnitId, &mut UnitActions)>,
+mut predicted_move: ResMut<PredictedMove>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+game: Res<Game>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received `Move` message from server.");
+		replay_recorder.record(ReplayEvent::Move { origin: event.origin, destination: event.destination, });
+		
+		// The move was already started locally, so there is nothing left to do.
+		if predicted_move.value == Some((event.origin, event.destination)) {
+			info!("DEBUG: Server confirmed predicted move.");
+			predicted_move.value = None;
+			continue;
+		}
+		
+		// Insert `Move` `UnitAction` into current unit.
+		info!("DEBUG: Inserting `Move` `UnitAction` into current unit...");
+		for (unit_id, mut unit_actions) in units.iter_mut() {
+			if unit_id.value == game.current_unit {
+				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+					origin: Pos { x: event.origin.x, y: event.origin.y, },
+					destination: Pos { x: event.destination.x, y: event.destination.y },
+					timer: Timer::from_seconds(4.0, TimerMode::Once),
+				}, 0.0));
+				info!("DEBUG: Finished inserting `Move` `UnitAction` into current unit.");
+			}
+		}
+	}
+}
+
+// Client
+fn handle_action_rejected_message(
+mut events: EventReader<ActionRejectedMessageEvent>,
+mut predicted_move: ResMut<PredictedMove>,
+mut map_query: Query<&mut Map>,
+mut units: Query<(Entity, &UnitId, &mut UnitActions, &mut Pos, &mut Transform, &mut MoveActions, Option<&mut MoveAction>), Without<GameText>>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+mut undoable_move: ResMut<UndoableMove>,
+game: Res<Game>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: The server refused an action: {}.", event.reason);
+		
+		// A refusal naming another move leaves the predicted move alone.
+		// One that names no move still rolls back the predicted move, since the server never confirmed it.
+		let Some((origin, destination)) = predicted_move.value.filter(|predicted| event.rejected_move.map_or(true, |rejected_move| rejected_move == *predicted)) else {
+			continue;
+		};
+		predicted_move.value = None;
+		// A rolled back move has nothing left to undo.
+		undoable_move.value = None;
+		let Ok(mut map_component) = map_query.get_single_mut() else {
+			continue;
+		};
+		let map = &mut map_component.map;
+		
+		// Roll the current unit back to where the server has it.
+		info!("DEBUG: Rolling back predicted move from {}, {} to {}, {}...", origin.x, origin.y, destination.x, destination.y);
+		for (entity, unit_id, mut unit_actions, mut pos, mut transform, mut move_actions, move_action) in units.iter_mut() {
+			if unit_id.value != game.current_unit {
+				continue;
+			}
+			
+			// `handle_move_state` finishes a started move once it has no steps left.
+			// A move that hasn't been started yet is removed from the queue.
+			move_actions.move_actions.clear();
+			if let Some(mut move_action) = move_action {
+				move_action.destination = move_action.origin;
+			} else if !unit_actions.processing_unit_action && matches!(unit_actions.unit_actions.first(), Some(UnitActionTuple(UnitAction::Move { .. }, _))) {
+				unit_actions.unit_actions.remove(0);
+			}
+			
+			map[pos.x][pos.y].2.retain(|unit_entity| *unit_entity != entity);
+			map[origin.x][origin.y].2.push(entity);
+			*pos = origin;
+			
+			if let Some(tile_transform) = map[origin.x][origin.y].3.last().and_then(|tile_entity| tile_transform_query.get(*tile_entity).ok()) {
+				transform.translation.x = tile_transform.translation.x;
+				transform.translation.y = tile_transform.translation.y + 100.0;
+			}
+		}
+		info!("DEBUG: Rolled back predicted move.");
+	}
+}
+
+// Client
+fn handle_error_message(
+mut events: EventReader<ErrorMessageEvent>,
+mut commands: Commands,
+mut toasts: ResMut<Toasts>,
+current_unit_query: Query<Entity, With<CurrentUnit>>,
+tiles_query: Query<Entity, Or<(With<MoveTile>, With<AttackTile>)>>,
+mut deployment: ResMut<Deployment>,
+turn_state: Res<State<TurnState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received Error message: {:?}, {}.", event.code, event.message);
+		toasts.toasts.push(Toast { message: event.message.clone(), remaining: TOAST_DURATION_SECONDS, });
+		
+		// The team can change its deployment and send it again.
+		// A deployment the server already has stands, so it is confirmed.
+		if event.code == ErrorCode::InvalidDeployment {
+			deployment.confirmed = false;
+		} else if event.code == ErrorCode::AlreadyDeployed {
+			deployment.confirmed = true;
+		}
+		
+		if !event.code.is_action_rejection() || *turn_state.get() == TurnState::Wait {
+			continue;
+		}
+		
+		// The tile indicators may already be gone, but the unit still thinks it is choosing.
+		for entity in tiles_query.iter() {
+			commands.entity(entity).despawn();
+		}
+		for entity in current_unit_query.iter() {
+			commands.entity(entity).remove::<MoveTiles>();
+			commands.entity(entity).remove::<AttackTiles>();
+		}
+		
+		if event.code == ErrorCode::NotYourTurn {
+			info!("DEBUG: Setting TurnState to Wait...");
+			next_turn_state.set(TurnState::Wait);
+			info!("DEBUG: Set TurnState to Wait.");
+		} else {
+			info!("DEBUG: Setting TurnState back to Turn...");
+			next_turn_state.set(TurnState::Turn);
+			info!("DEBUG: Set TurnState back to Turn.");
+		}
+	}
+}
+
+// Client
+fn toast_ui(
+mut contexts: EguiContexts,
+mut toasts: ResMut<Toasts>,
+time: Res<Time>,
+) {
+	for toast in toasts.toasts.iter_mut() {
+		toast.remaining -= time.delta_seconds();
+	}
+	toasts.toasts.retain(|toast| toast.remaining > 0.0);
+	
+	if toasts.toasts.len() == 0 {
+		return;
+	}
+	
+	egui::Area::new("toasts")
+		.anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -60.0))
+		.show(contexts.ctx_mut(), |ui| {
+			for toast in toasts.toasts.iter() {
+				egui::Frame::popup(ui.style()).show(ui, |ui| {
+					ui.label(&toast.message);
+				});
+			}
+		});
+}
+
+// Client
+fn handle_basic_attack_message(
+mut events: EventReader<BasicAttackMessageEvent>,
+mut commands: Commands,
+mut units: Query<&mut UnitActions>,
+map_query: Query<&Map>,
+game: Res<Game>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut undoable_move: ResMut<UndoableMove>,
+mut action_used: ResMut<ActionUsed>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received `BasicAttack` message from server.");
+		undoable_move.value = None;
+		// Striking back doesn't use up the action of the unit whose turn it is.
+		if !event.is_counterattack {
+			action_used.value = true;
+		}
+		replay_recorder.record(ReplayEvent::BasicAttack {
+			attacker: event.attacker,
+			target: event.target,
+			damage: event.damage,
+			is_counterattack: event.is_counterattack,
+		});
+		
+		let Ok(map_component) = map_query.get_single() else {
+			info!("DEBUG: There is no map to resolve the `BasicAttack` on.");
+			continue;
+		};
+		let map = &map_component.map;
+		let attacker = event.attacker;
+		let target = event.target;
+		
+		// Get the attacker and target entities from map.
+		let Some(entity) = map[attacker.x][attacker.y].2.last().copied() else {
+			info!("DEBUG: There is no unit at {}, {} to attack.", attacker.x, attacker.y);
+			continue;
+		};
+		let Some(target_entity) = map[target.x][target.y].2.last().copied() else {
+			info!("DEBUG: There is no unit at {}, {} to be attacked.", target.x, target.y);
+			continue;
+		};
+		
+		// A counter-attack waits for the attack it answers, which starts it once it's over.
+		let answers_pending_attack = event.is_counterattack && units.get(target_entity).map_or(false, |target_unit_actions| {
+			target_unit_actions.unit_actions.iter().any(|unit_action| matches!(unit_action.0, UnitAction::BasicAttack { is_counterattack: false, .. }))
+		});
+		
+		// Insert `BasicAttack` `UnitAction` into the attacking unit.
+		info!("DEBUG: Inserting `BasicAttack` `UnitAction` into current unit...");
+		if let Ok(mut unit_actions) = units.get_mut(entity) {
+			unit_actions.unit_actions.push(UnitActionTuple(UnitAction::BasicAttack {
+				target: Pos { x: target.x, y: target.y, },
+				is_counterattack: event.is_counterattack,
+				damage: event.damage,
+			}, 0.0));
+			info!("DEBUG: Finished inserting `BasicAttack` `UnitAction` into current unit.");
+		}
+		
+		if !answers_pending_attack {
+			// Insert an `Attacker` marker component on the attacking unit.
+			commands.entity(entity).insert(Attacker {});
+			
+			// Insert the `Target` marker component on the target unit.
+			commands.entity(target_entity).insert(Target {});
+		}
+						
+		// Remove the AttackTiles component from the unit.
+		commands.entity(entity).remove::<AttackTiles>();
+		
+		// Set State
+		if !game.is_spectator {
+			next_turn_state.set(TurnState::Turn);
+		}
+	}
+}
+
+// Client
+fn handle_use_item_message(
+mut events: EventReader<UseItemMessageEvent>,
+mut units: Query<&mut UnitActions>,
+map_query: Query<&Map>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut undoable_move: ResMut<UndoableMove>,
+mut action_used: ResMut<ActionUsed>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received `UseItem` message from server.");
+		undoable_move.value = None;
+		action_used.value = true;
+		replay_recorder.record(ReplayEvent::UseItem { user: event.user, item: event.item, });
+		
+		let Ok(map_component) = map_query.get_single() else {
+			info!("DEBUG: There is no map to resolve the `UseItem` on.");
+			continue;
+		};
+		let map = &map_component.map;
+		
+		let Some(entity) = map[event.user.x][event.user.y].2.last().copied() else {
+			info!("DEBUG: There is no unit at {}, {} to use the item.", event.user.x, event.user.y);
+			continue;
+		};
+		if let Ok(mut unit_actions) = units.get_mut(entity) {
+			unit_actions.unit_actions.push(UnitActionTuple(UnitAction::UseItem { item: event.item, }, 0.0));
+		}
+	}
+}
+
+// Client
+fn handle_deployment_message(
+mut events: EventReader<DeploymentMessageEvent>,
+mut map_query: Query<&mut Map>,
+mut units: Query<(Entity, &UnitId, &mut Pos, &mut DIR, Option<&mut Transform>), (With<Unit>, Without<GameText>)>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+state: Res<State<GameState>>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Received Deployment message with {} units.", event.units.len());
+		replay_recorder.record(ReplayEvent::Deploy { units: event.units.clone(), });
+		
+		let Ok(mut map_component) = map_query.get_single_mut() else {
+			info!("DEBUG: There is no map to deploy the units on.");
+			continue;
+		};
+		let map = &mut map_component.map;
+		
+		for (entity, unit_id, mut pos, mut dir, transform) in units.iter_mut() {
+			let Some((_, destination, direction)) = event.units.iter().find(|(deployed_unit_id, _, _)| *deployed_unit_id == unit_id.value) else {
+				continue;
+			};
+			
+			place_unit(map, entity, &mut pos, *destination);
+			dir.direction = *direction;
+			if let (Some(mut transform), Some(translation)) = (transform, unit_translation(map, &tile_transform_query, *destination)) {
+				transform.translation = translation;
+			}
+		}
+		
+		// Spectators and replays never deploy.
+		if *state.get() == GameState::Deployment {
+			info!("DEBUG: Setting GameState to Wait...");
+			next_state.set(GameState::Wait);
+			info!("DEBUG: Set GameState to Wait.");
+		}
+	}
+}
+
+// Client
+fn handle_game_over_message(
+mut events: EventReader<GameOverMessageEvent>,
+mut turn_timer: ResMut<TurnTimer>,
+mut predicted_move: ResMut<PredictedMove>,
+mut players: ResMut<Players>,
+mut battle_result: ResMut<BattleResult>,
+client_data: Res<ClientData>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+	for event in events.iter() {
+		info!("DEBUG: Battle is over.");
+		info!("DEBUG: Winner is: {:?}.", event.winner);
+		*turn_timer = TurnTimer::default();
+		predicted_move.value = None;
+		
+		// Announce the winners by name.
+		*battle_result = BattleResult {
+			title: String::from("Game over"),
+			message: String::new(),
+		};
+		if let Some(winning_team) = players.winning_team {
+			let winners: Vec<&PlayerInfo> = players.players.iter().filter(|player| player.team == winning_team).collect();
+			let we_played = players.players.iter().any(|player| player.client_id == client_data.client_id);
+			if winners.iter().any(|player| player.client_id == client_data.client_id) {
+				battle_result.title = String::from("Victory");
+				battle_result.message = String::from("You won the battle!");
+			} else {
+				if we_played {
+					battle_result.title = String::from("Defeat");
+				}
+				battle_result.message = if winners.len() > 0 {
+					let names: Vec<String> = winners.iter().map(|player| player.name.clone()).collect();
+					format!("{} won the battle.", names.join(", "))
+				} else {
+					format!("Team {} won the battle.", winning_team)
+				};
+			}
+		}
+		*players = Players::default();
+		
+		info!("DEBUG: Setting GameState to GameOver...");
+		next_state.set(GameState::GameOver);
+		info!("DEBUG: Set GameState to GameOver.");
+		info!("DEBUG: Setting TurnState to Wait...");
+		next_turn_state.set(TurnState::Wait);
+		info!("DEBUG: Set TurnState to Wait.");
+	}
+}
+
+// Server
+fn cursor_already_spawned(cursors: Query<&Cursor>) -> bool {
+	let mut cursor_spawned = false;
+	for cursor in cursors.iter() {
+		cursor_spawned = true;
+	}
+	return cursor_spawned;
+}
+
+// Client
+fn setup_grid_system(
+mut commands: Commands,
+game: Res<Game>,
+mut toasts: ResMut<Toasts>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	// Create map.
+	info!("DEBUG: Creating map...");
+	let heights = load_map_heights(&game.scenario).unwrap_or_else(|error| {
+		// The battle is refused. The loading systems that follow still need a map until the main menu is back.
+		info!("DEBUG: {}", error);
+		toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+		next_state.set(GameState::MainMenu);
+		flat_map_heights()
+	});
+	let map = build_map(&heights);
+	
+	info!("DEBUG: Created map.");
+	
+	commands.spawn((
+		Map { map: map },
+	));
+}
+
+// Client
+fn setup_camera_system(mut commands: Commands) {
+	info!("DEBUG: Spawning camera...");
+	commands.spawn(Camera2dBundle::default());
+	info!("DEBUG: Spawned camera.");
+}
+
+// Client
+fn setup_text_system(mut query: Query<&mut Map>, mut commands: Commands, asset_server: Res<AssetServer>) {
+	let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+	let text_style = TextStyle {
+		font,
+		font_size: 20.0,
+		color: Color::WHITE,
+	};
+	let text_alignment = TextAlignment::Center;
+	
+	info!("DEBUG: Spawning tiles...");
+	
+	let mut map = query.single_mut();
+	for i in 0..map.map.len() {
+		for j in 0..map.map[i].len() {
+			//let tile_string: String = map.map[i][j].2.to_string();
+			
+			for k in 0..map.map[i][j].0 {
+				let entity_id = commands.spawn((
+					//Text2dBundle {
+					//	text: Text::from_section(tile_string, text_style.clone()).with_alignment(text_alignment),
+					//	transform: Transform::from_xyz((i as f32) * 256.0 / 2.0 - (j as f32) * 256.0 / 2.0, (i as f32) * 128.0 / 2.0 + (j as f32) * 128.0 / 2.0, 0.0),
+					//	..default()
+					//},
+					SpriteBundle {
+						texture: asset_server.load("tile.png"),
+						transform: Transform::from_xyz((i as f32) * 256.0 / 2.0 - (j as f32) * 256.0 / 2.0, ((i as f32) * 128.0 / 2.0 + (j as f32) * 128.0 / 2.0) * (111.0 / (128.0 / 2.0) - 1.0) + (k as f32) * 30.0, 1.0),
+						..default()
+					},
+					GameText,
+				)).id();
+				
+				map.map[i][j].3.push(entity_id);
+			}
+		}
+	}
+	
+	info!("DEBUG: Spawned tiles.");
+}
+
+// Client
+fn z_order_system(
+mut query: Query<&mut Transform, With<GameText>>,
+mut unit_query: Query<&mut Transform, (With<Unit>, Without<GameText>)>,
+map_query: Query<&Map>,
+) {
+	//info!("DEBUG: Z-Order system running...");
+	let map = &map_query.single().map;
+	//info!("DEBUG: Map length is: {}.", map.len());
+	
+	let mut counter = 0.0;
+	let mut counter_2 = 0.0;
+	
+	for i in (0..map.len()).rev() {
+		for j in (0..map[i].len()).rev() {
+			counter += 0.00001;
+			
+			//info!("Tile Entity ID is: {:?}.", map[i][j].3);
+			for k in 0..map[i][j].3.len() {
+				counter_2 += 0.0000001;
+				if let Ok(mut tile_transform) = query.get_mut(map[i][j].3[k]) {
+					//info!("DEBUG: Tile position is: {:?}.", tile_transform.translation);
+					tile_transform.translation.z = counter + counter_2;
+				}
+			}
+			
+			// If there is a unit on the tile, order it.
+			if map[i][j].2.len() > 0 {
+				// Order unit.
+				if let Ok(mut unit_transform) = unit_query.get_mut(map[i][j].2[0]) {
+					if let Ok(mut tile_transform) = query.get_mut(map[i][j].3[map[i][j].3.len() - 1]) {
+						//unit_transform.translation.y += 100.0;
+					}
+					//unit_transform.translation.z = counter + counter_2 + 0.0000001;
+				}
+			}
+		}
+	}
+}
+
+// Client
+fn z_unit_order_system(
+mut query: Query<&mut Transform, (With<GameText>, Without<Unit>)>,
+mut unit_query: Query<(&mut Transform, &Pos), (With<Unit>, Without<GameText>)>,
+map_query: Query<&Map>,
+) {
+	let map = &map_query.single().map;
+
+	for (mut unit_transform, pos) in unit_query.iter_mut() {
+		// Get the tile the unit is on.
+		//map[pos.x][pos.y]
+		
+		if let Ok(mut tile_transform) = query.get(map[pos.x][pos.y].3[map[pos.x][pos.y].3.len() - 1]) {
+			unit_transform.translation.z = tile_transform.translation.z + 0.00000001;
+		}
+	}
+}
+
+// Client
+fn move_camera_system(
+mut camera_transform_query: Query<&mut Transform, With<Camera>>,
+windows: Query<&Window, With<PrimaryWindow>>,
+) {
+	let window = windows.single();
+	
+	let mut camera_transform = camera_transform_query.single_mut();
+	
+	if let Some(_position) = window.cursor_position() {
+		// Cursor is inside the window.
+		//info!("DEBUG: Cursor position is: {:?}.", _position);
+		//info!("DEBUG: Window width is: {:?}.", window.width());
+		//info!("DEBUG: Window height is: {:?}.", window.height());
+		
+		if _position.x <= 0.0 + 20.0 {
+			//info!("DEBUG: Camera translation is: {:?}.", camera_transform.translation);
+			camera_transform.translation -= Vec3::X * 3.0;
+		} else if _position.x >= window.width() - 20.0 {
+			camera_transform.translation += Vec3::X * 3.0;
+		}
+		
+		if _position.y <= 0.0 + 20.0 {
+			//info!("DEBUG: Camera translation is: {:?}.", camera_transform.translation);
+			camera_transform.translation += Vec3::Y * 3.0;
+		} else if _position.y >= window.height() - 20.0 {
+			camera_transform.translation -= Vec3::Y * 3.0;
+		}
+		
+	} else {
+		// Cursor is not inside the window.
+	}
+}
+
+// Prototype
+fn spawn_naked_swordsman(
+mut commands: Commands,
+mut map_query: Query<&mut Map>,
+asset_server: Res<AssetServer>,
+) {
+	let mut map = &mut map_query.single_mut().map;
+	
+	info!("DEBUG: Spawning Naked Swordsman...");
+	
+	let entity_id = commands.spawn((
+		SpriteBundle {
+			texture: asset_server.load("naked_fanatic_swordsman_east.png"),
+			transform: Transform::from_xyz((4 as f32) * 256.0 / 2.0 - (4 as f32) * 256.0 / 2.0, (4 as f32) * 128.0 / 2.0 + (4 as f32) * 128.0 / 2.0 + (map[4][4].0 as f32) * 15.0 + 100.0, 1.0),
+			..default()
+		},
+		Unit,
+		Pos {
+			x: 4,
+			y: 4,
+		},
+		UnitActions { unit_actions: Default::default(), processing_unit_action: false, },
+		NakedSwordsman {},
+	)).id();
+	
+	map[4][4].2.push(entity_id);
+	
+	info!("DEBUG: Spawned Naked Swordsman.");
+}
+
+// Prototype
+fn move_gaul_warrior(
+mut map_query: Query<&mut Map>,
+mut unit_query: Query<(Entity, &mut Pos, &mut Handle<Image>), With<NakedSwordsman>>,
+mut input: ResMut<Input<KeyCode>>,
+mut asset_server: Res<AssetServer>,
+) {
+	let mut map = &mut map_query.single_mut().map;
+	
+	if input.just_pressed(KeyCode::W) {
+		info!("DEBUG: W pressed.");
+		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
+		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
+			if pos.y == (map.len() - 1) {
+				info!("DEBUG: You can't move there.");
+			} else {
+				// Change unit sprite to face north.
+				//info!("DEBUG: Sprite is: {:?}.", sprite);
+				*sprite = asset_server.load("naked_fanatic_swordsman_north.png");
+				map[pos.x][pos.y + 1].2.push(entity_id);
+				map[pos.x][pos.y].2.pop();
+				pos.y += 1;
+			}
+		}
+	}
+	
+	if input.just_pressed(KeyCode::S) {
+		info!("DEBUG: S pressed.");
+		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
+		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
+			if pos.y == 0 {
+				info!("DEBUG: You can't move there.");
+			} else {
+				// Change unit sprite to face south.
+				//info!("DEBUG: Sprite is: {:?}.", sprite);
+				*sprite = asset_server.load("naked_fanatic_swordsman_south.png");
+				map[pos.x][pos.y - 1].2.push(entity_id);
+				map[pos.x][pos.y].2.pop();
+				pos.y -= 1;
+			}
+		}
+	} 
+	
+	if input.just_pressed(KeyCode::D) {
+		info!("DEBUG: D pressed.");
+		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
+		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
+			if pos.x == (map.len() - 1) {
+				info!("DEBUG: You can't move there.");
+			} else {
+				// Change unit sprite to face east.
+				//info!("DEBUG: Sprite is: {:?}.", sprite);
+				*sprite = asset_server.load("naked_fanatic_swordsman_east.png");
+				map[pos.x + 1][pos.y].2.push(entity_id);
+				map[pos.x][pos.y].2.pop();
+				pos.x += 1;
+			}
+		}
+	} 
+	
+	if input.just_pressed(KeyCode::A) {
+		info!("DEBUG: A pressed.");
+		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
+		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
+			if pos.x == 0 {
+				info!("DEBUG: You can't move there.");
+			} else {
+				// Change unit sprite to face south.
+				//info!("DEBUG: Sprite is: {:?}.", sprite);
+				*sprite = asset_server.load("naked_fanatic_swordsman_west.png");
+				map[pos.x - 1][pos.y].2.push(entity_id);
+				map[pos.x][pos.y].2.pop();
+				pos.x -= 1;
+			}
+		}
+	} 
+}
+
+// Client & Server
+fn grid_already_setup(query: Query<&Map>) -> bool {
+	if query.iter().len() == 0 {
+		return false;
+	} else {
+		return true;
+	}
+}
+
+// Client
+fn text_already_setup(query: Query<&GameText>) -> bool {
+	if query.iter().len() == 0 {
+		return false;
+	} else {
+		return true;
+	}
+}
+
+// Prototype
+fn center_camera_on_unit(
+unit_transform_query: Query<&Transform, With<CurrentUnit>>,
+mut camera_transform_query: Query<&mut Transform, (With<Camera>, Without<CurrentUnit>)>,
+) {
+	let unit_transform = unit_transform_query.single();
+	let mut camera_transform = camera_transform_query.single_mut();
+	
+	camera_transform.translation = Vec3::new(unit_transform.translation.x, unit_transform.translation.y, camera_transform.translation.z);
+}
+
+fn test_ortho_projection(
+ortho_projection_query: Query<&OrthographicProjection>,
+) {
+	let ortho_projection = ortho_projection_query.single();
+	
+	info!("DEBUG: Orthographic projection far is: {:?}.", ortho_projection.far);
+}
+
+
+// Prototype
+fn process_unit_actions(
+mut commands: Commands,
+mut unit_actions_query: Query<(Entity, &mut UnitActions)>,
+mut map_query: Query<&mut Map>,
+game: Res<Game>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+time: Res<Time>,
+) {
+	let map = &map_query.single_mut().map;
+	
+	//info!("DEBUG: unit_actions_query length is: {}.", unit_actions_query.iter().len());
+	
+	for (entity, mut unit_actions) in unit_actions_query.iter_mut() {
+		
+		if unit_actions.unit_actions.len() == 0 {
+			continue;
+		} else if unit_actions.processing_unit_action == true {
+			continue;
+		} else if time.elapsed_seconds() < unit_actions.unit_actions[0].1 {
+			continue;
+		} else if unit_actions.unit_actions[0].1 == 0.0 || time.elapsed_seconds() >= unit_actions.unit_actions[0].1 {
+			unit_actions.processing_unit_action = true;
+			
+			let current_unit_action = &unit_actions.unit_actions[0].0;
+		
+			match current_unit_action {
+				UnitAction::Move { origin, destination, timer, } => {
+					info!("DEBUG: Current unit action is Move.");
+					// Multiplayer moves are recorded when the server sends them.
+					if !game.is_multiplayer {
+						replay_recorder.record(ReplayEvent::Move { origin: *origin, destination: *destination, });
+					}
+					commands.entity(entity).insert(MoveAction { 
+						origin: origin.clone(),
+						destination: destination.clone(),
+						timer: timer.clone(),
+					});
+				},
+				UnitAction::Talk { message } => {
+					info!("DEBUG: Current unit action is Talk.");
+					commands.entity(entity).insert(TalkAction { message: message.clone(), });
+				},
+				UnitAction::BasicAttack { target, is_counterattack, damage } => {
+					info!("DEBUG: Current unit action is BasicAttack.");
+					commands.entity(entity).insert(BasicAttackAction { target: target.clone(), is_counterattack: is_counterattack.clone(), damage: damage.clone(), });
+				}
+				UnitAction::UseItem { item } => {
+					info!("DEBUG: Current unit action is UseItem.");
+					commands.entity(entity).insert(UseItemAction { item: *item, });
+				}
+				UnitAction::DoNothing => {
+					info!("DEBUG: Current unit action is DoNothing.");
+					commands.entity(entity).insert(DoNothingAction);
+				}
+			}
+		}
+	}
+}
+
+// Prototype
+fn process_move_actions(
+mut commands: Commands,
+mut map_query: Query<&mut Map>,
+mut unit_query: Query<(Entity, &mut UnitActions, &mut Pos, &Jump, &MoveAction, &mut MoveActions, &UnitId, &DIR)>,
+mut undoable_move: ResMut<UndoableMove>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	let map = &mut map_query.single_mut().map;
+	
+	for (entity, mut unit_actions, mut pos, jump, move_action, mut move_actions, unit_id, dir) in unit_query.iter_mut() {
+		info!("DEBUG: Processing MoveAction...");
+		info!("DEBUG: Move destination is: {}, {}.", move_action.destination.x, move_action.destination.y);
+		
+		// Undoing the move turns the unit back the way it faced.
+		undoable_move.direction = Some((unit_id.value, dir.direction));
+		
+		// Calculate path.
+		let path = find_path(map.to_vec(), move_action.origin, move_action.destination, jump.value);
+		if let Some(mut path) = path {
+			
+			let origin_backup: Pos = path[0];
+			
+			// Remove the first entry because it is the tile the unit is on.
+			path.remove(0);
+			
+			for i in 0..path.len() {
+				info!("DEBUG: Path step is {}, {}.", path[i].x, path[i].y);
+				// Create MoveAction
+				if i == 0 {
+					let new_move_action = MoveAction { origin: origin_backup, destination: path[i], timer: Timer::from_seconds(2.0, TimerMode::Once)};
+					// Add MoveAction to MoveActions component.
+					move_actions.move_actions.push(new_move_action);
+				} else {
+					let new_move_action = MoveAction { origin: path[i - 1], destination: path[i], timer: Timer::from_seconds(2.0, TimerMode::Once)};
+					// Add MoveAction to MoveActions component.
+					move_actions.move_actions.push(new_move_action);
+				}
+			}
+		}
+		
+		// Set GameState to Move
+		info!("DEBUG: Setting GameState to Move...");
+		next_state.set(GameState::Move);
+		info!("DEBUG: Set GameState to Move.");
+		
+		break;
+	}
+}
+
+// Prototype
+fn handle_move_state(
+mut commands: Commands,
+mut map_query: Query<&mut Map>,
+mut unit_query: Query<(Entity, &mut Transform, &mut UnitActions, &mut Pos, &MoveAction, &mut MoveActions, &mut DIR), Without<GameText>>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+game: Res<Game>,
+time: Res<Time>,
+) {
+	let mut map_component = map_query.single_mut();
+	let mut map = &mut map_component.map;
+
+	if unit_query.iter_mut().len() == 0 {
+		if !game.is_multiplayer {
+			// Game is in Single-Player mode.
+			info!("DEBUG: No MoveActions remaining. Setting GameState to Ambush.");
+			next_state.set(GameState::Ambush);
+			info!("DEBUG: No MoveActions remaining. Set GameState to Ambush.");
+			// Set TurnState to Turn.
+			info!("DEBUG: Setting TurnState to Turn...");
+			next_turn_state.set(TurnState::Turn);
+			info!("DEBUG: Set TurnState to Turn.");
+		} else {
+			// Game is in Multiplayer mode.
+			info!("DEBUG: No MoveActions remaining. Setting GameState to Battle.");
+			next_state.set(GameState::Battle);
+			info!("DEBUG: No MoveActions remaining. Set GameState to Battle.");
+			if !game.is_spectator {
+				// Set TurnState to Turn.
+				info!("DEBUG: Setting TurnState to Turn...");
+				next_turn_state.set(TurnState::Turn);
+				info!("DEBUG: Set TurnState to Turn.");
+			}
+		}
+	} else {
+	
+		for (entity, mut transform, mut unit_actions, mut pos, move_action_component, mut move_actions, mut dir) in unit_query.iter_mut() {
+			
+			if move_actions.move_actions.len() == 0 {
+				// This unit has completed its movement.
+				unit_actions.processing_unit_action = false;
+				unit_actions.unit_actions.remove(0);
+				commands.entity(entity).remove::<MoveAction>();
+				info!("DEBUG: Processed MoveAction.");
+			} else {
+				let move_action = &move_actions.move_actions[0];
+				
+				if map[move_action.destination.x][move_action.destination.y].2.len() > 0 {
+					info!("DEBUG: Couldn't move unit. There's an unit already there.");
+					unit_actions.processing_unit_action = false;
+					unit_actions.unit_actions.remove(0);
+					commands.entity(entity).remove::<MoveAction>();
+					move_actions.move_actions.truncate(0);
+					info!("DEBUG: Processed MoveAction.");
+				} else {
+					if move_action.timer.just_finished() {
+						// Complete processing of MoveAction.
+						
+						info!("DEBUG: Completing processing of MoveAction...");
+						map[move_action.destination.x][move_action.destination.y].2.push(entity);
+						map[pos.x][pos.y].2.pop();
+						
+						pos.x = move_action.destination.x;
+						pos.y = move_action.destination.y;
+						
+						
+						
+						move_actions.move_actions.remove(0);
+						info!("DEBUG: Processed MoveAction.");
+					
+					} else {
+						// Move is still in progress, update the unit's position gradually
+						
+						//info!("DEBUG: Move in progress, updating unit position.");
+						//move_action.timer.tick(time.delta());
+											
+						
+						let start_pos = &move_action.origin;
+						let end_pos = &move_action.destination;
+						
+						// Set the unit's direction.
+						if end_pos.x < start_pos.x {
+							dir.direction = Direction::West;
+						} else if end_pos.x > start_pos.x {
+							dir.direction = Direction::East;
+						}
+						if end_pos.y < start_pos.y {
+							dir.direction = Direction::South;
+						} else if end_pos.y > start_pos.y {
+							dir.direction = Direction::North;
+						}
+						
+						
+						let progress = move_action.timer.elapsed_secs() / 2.0;
+
+						// Get target tile transform.
+						let target_tile_entity = map[end_pos.x][end_pos.y].3[map[end_pos.x][end_pos.y].3.len() - 1];
+						
+						let target_tile_transform = tile_transform_query.get(target_tile_entity).unwrap();
+						
+						transform.translation.x += ((target_tile_transform.translation.x - transform.translation.x) * progress);
+						transform.translation.y += ((target_tile_transform.translation.y - transform.translation.y  + 100.0) * progress);
+						
+
+
+						
+	//					// To fix bug with z-ordering when the unit is moving.
+	//					// ATTEMPT#1:
+	//					transform.translation.z = target_tile_transform.translation.z + 0.00000001;
+
+						// ATTEMPT#2:
+						// The correct z-order depends on the tile the unit is moving to.
+						if end_pos.x > start_pos.x {
+							
+						} else if end_pos.x < start_pos.x {
+							transform.translation.z = target_tile_transform.translation.z  + 0.00000001;
+						} else if end_pos.y < start_pos.y {
+							transform.translation.z = target_tile_transform.translation.z  + 0.00000001;
+						} else if end_pos.y > start_pos.y {
+						
+						}
+						
+					}
+				}
+			}
+		}
+	}
+}
+
+// Prototype
+fn tick_move_timer(
+mut move_actions_query: Query<&mut MoveActions, With<MoveAction>>,
+time: Res<Time>
+) {
+	for (mut move_actions) in move_actions_query.iter_mut() {
+		if move_actions.move_actions.len() != 0 {
+			move_actions.move_actions[0].timer.tick(time.delta());
+		}
+	}
+}
+
+// Prototype
+fn process_talk_actions(
+mut commands: Commands,
+mut unit_query: Query<(Entity, &mut UnitActions, &TalkAction)>,
+asset_server: Res<AssetServer>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	
+	
+	for (entity, mut unit_actions, talk_action) in unit_query.iter_mut() {
+		
+		info!("DEBUG: Processing talk action...");
+		
+		info!("DEBUG: Setting GameState to Talk.");
+		next_state.set(GameState::Talk);
+		info!("DEBUG: Set GameState to Talk.");
+		
+		let portrait = asset_server.load("gaul_spearman_portrait.png");
+		
+		commands
+			.spawn((NodeBundle {
+				style: Style {
+					width: Val::Percent(100.0),
+					height: Val::Percent(20.0),
+					position_type: PositionType::Absolute,
+					top: Val::Percent(0.0),
+					..Default::default()
+				},
+				background_color: BackgroundColor(Color::BLACK),
+				..Default::default()
+			},
+			TalkUI {},
+			))
+			.with_children(|parent| {
+			
+				// Add the character portrait
+				parent.spawn((ImageBundle {
+					style: Style {
+						width: Val::Percent(20.0),
+						height: Val::Percent(100.0),
+						..Default::default()
+					},
+					image: UiImage::new(portrait.clone()),
+					..Default::default()
+				}));
+				
+				// Add the message text
+				parent.spawn((TextBundle {
+					text: Text::from_section(
+						talk_action.message.clone(),
+						TextStyle {
+							font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+							font_size: 20.0,
+							color: Color::WHITE,
+						},
+					),
+					..Default::default()
+				}));
+			});
+		
+	}
+}
+
+// Prototype
+fn handle_talk_state(
+mouse_button_input: Res<Input<MouseButton>>,
+mut commands: Commands,
+mut unit_query: Query<(Entity, &mut UnitActions, &TalkAction)>,
+talk_ui_query: Query<(Entity, &TalkUI)>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	if mouse_button_input.just_pressed(MouseButton::Left) {
+        info!("DEBUG: Left mouse button just pressed.");
+        
+        for (entity, mut unit_actions, talk_action) in unit_query.iter_mut() {
+			
+			// There should be only one unit with a `TalkAction`
+			
+			// Despawn the TalkUI
+			let (ui_entity, talk_ui) = talk_ui_query.single();
+			commands.entity(ui_entity).despawn();
+			
+			// Process unit action.
+			unit_actions.unit_actions.remove(0);
+			unit_actions.processing_unit_action = false;
+			commands.entity(entity).remove::<TalkAction>();
+			info!("DEBUG: Processed talk action.");
+			
+			// Set GameState back to Ambush.
+			info!("DEBUG: Setting GameState to Ambush.");
+			next_state.set(GameState::Ambush);
+			info!("DEBUG: Set GameState to Ambush.");
+        }
+    }
+}
+
+// Prototype
+fn process_do_nothing_actions(mut commands: Commands, mut unit_query: Query<(Entity, &mut UnitActions, &DoNothingAction)>) {
+	for (entity, mut unit_actions, do_nothing_action) in unit_query.iter_mut() {
+		info!("DEBUG: Processing DoNothing action...");
+		
+		unit_actions.unit_actions.remove(0);
+		unit_actions.processing_unit_action = false;
+		commands.entity(entity).remove::<DoNothingAction>();
+		
+		info!("DEBUG: Processed DoNothing action.");
+	}	
+}
+
+// Prototype
+fn process_basic_attack_actions(
+mut commands: Commands,
+map_query: Query<&Map>,
+mut attack_unit_query: Query<(Entity, &UnitId, &mut UnitActions, &STR, &Pos, &mut DIR, &BasicAttackAction), (With<Attacker>, Without<Target>)>,
+mut target_unit_query: Query<(&UnitId, &mut UnitActions, &Pos, &mut HPCurrent, &AttackRange, &AttackType, &UnitClass), (With<Target>, Without<Attacker>)>,
+game: Res<Game>,
+classes: Res<Classes>,
+mut battle_rng: ResMut<BattleRng>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut experience_events: EventWriter<GainExperienceEvent>,
+) {
+	let map = &map_query.single().map;
+	
+	//info!("DEBUG: attack_unit_query length is: {}.", attack_unit_query.iter().len());
+	
+	for (entity, unit_id, mut unit_actions, str, pos, mut dir, basic_attack_action) in attack_unit_query.iter_mut() {
+		info!("DEBUG: Processing BasicAttack action...");
+		
+		// Get target entity from map.
+		let target_entity = map[basic_attack_action.target.x][basic_attack_action.target.y].2[0];
+		
+		// Get target health.
+		if let Ok((target_id, mut target_unit_actions, target_pos, mut hp_current, attack_range, attack_type, target_class)) = target_unit_query.get_mut(target_entity) {
+			// Change attacker's direction to face the target.
+			// Set the unit's direction.
+			if target_pos.x < pos.x {
+				dir.direction = Direction::West;
+			} else if target_pos.x > pos.x {
+				dir.direction = Direction::East;
+			}
+			if target_pos.y < pos.y {
+				dir.direction = Direction::South;
+			} else if target_pos.y > pos.y {
+				dir.direction = Direction::North;
+			}
+			
+			let mut damage: usize = 0;
+			if !game.is_multiplayer {
+				// Damage is (STR / 3) + a random modifier between -3 and 3, and never goes below 0.
+				damage = ((str.value / 3) as isize + battle_rng.rng.gen_range(-3..=3)).max(0) as usize;
+				
+				replay_recorder.record(ReplayEvent::BasicAttack {
+					attacker: *pos,
+					target: basic_attack_action.target,
+					damage: damage,
+					is_counterattack: basic_attack_action.is_counterattack,
+				});
+			} else {
+				damage = basic_attack_action.damage;
+			}
+			
+			// Offline, units grow by attacking, and more so by defeating their target.
+			if !game.is_multiplayer {
+				let experience = if damage >= hp_current.value { XP_PER_ATTACK + XP_PER_KILL } else { XP_PER_ATTACK };
+				experience_events.send(GainExperienceEvent { unit: entity, experience: experience, });
+			}
+			
+			// Subtract damage from target HP.
+			if damage > hp_current.value {
+				hp_current.value = 0;
+				
+				// Remove Attacker marker component.
+				commands.entity(entity).remove::<Attacker>();
+			
+				// Remove BasicAttack UnitAction.
+				unit_actions.unit_actions.remove(0);
+				unit_actions.processing_unit_action = false;
+				commands.entity(entity).remove::<BasicAttackAction>();
+				
+				info!("DEBUG: Processed BasicAttack action.");
+				
+				return;
+			} else {
+				hp_current.value -= damage;
+			}
+			
+			info!("DEBUG: Unit {:?} did {:?} damage to unit {:?}.", unit_id, damage, target_id);
+			info!("DEBUG: Unit {} now has {} HP.", target_id.value, hp_current.value);
+			
+			// Remove Target marker component from the target.
+			commands.entity(target_entity).remove::<Target>();
+			
+			if game.is_multiplayer {
+				// The server resolves counter-attacks, and sends them right after the attack.
+				let is_counterattacking = target_unit_actions.unit_actions.first().map_or(false, |unit_action| matches!(unit_action.0, UnitAction::BasicAttack { is_counterattack: true, .. }));
+				if is_counterattacking {
+					commands.entity(target_entity).insert(Attacker {});
+					commands.entity(entity).insert(Target {});
+				}
+				
+				// Remove Attacker marker component.
+				commands.entity(entity).remove::<Attacker>();
+				
+				// Remove BasicAttack UnitAction.
+				unit_actions.unit_actions.remove(0);
+				unit_actions.processing_unit_action = false;
+				commands.entity(entity).remove::<BasicAttackAction>();
+				
+				info!("DEBUG: Processed BasicAttack action.");
+				
+				return;
+			}
+			
+			match unit_actions.unit_actions[0].0 {
+				UnitAction::BasicAttack { target, is_counterattack, damage } => {
+					// If it is not already a counter-attack...
+					if !is_counterattack {
+						// If target is not a ranged unit...
+						// Insert a counter-attack.
+						match attack_type {
+							AttackType::Ranged => { 
+								info!("DEBUG: Target is a ranged unit. Won't make a counter-attack.");
+							},
+							AttackType::Melee => {
+								info!("DEBUG: Target is a melee unit. Will make a counter-attack if at range.");
+								let target_possible_attacks = find_possible_attacks(map.to_vec(), *target_pos, attack_range.value, *attack_type);
+								
+								// Only a target whose class can attack strikes back.
+								if target_possible_attacks.contains(pos) && classes.allows(&target_class.value, Ability::Attack) {
+									// Insert a BasicAttack as a counter-attack.
+									target_unit_actions.unit_actions.push(UnitActionTuple(UnitAction::BasicAttack {
+										target: Pos { x: pos.x, y: pos.y, },
+										is_counterattack: true,
+										damage: 0,
+									}, 0.0));
+									
+									// Insert the Attacker marker component on the counter-attacking unit.
+									commands.entity(target_entity).insert(Attacker {});
+									
+									// Insert the Target marker component on the unit that did the initial attack.
+									// This will result in an infinite Attack and CounterAttack loop for now.
+									commands.entity(entity).insert(Target {});
+								}
+							}
+						}
+					}
+				},
+				_ => { empty_system() },
+			}
+			
+		}
+		
+		// Remove Attacker marker component.
+		commands.entity(entity).remove::<Attacker>();
+	
+		// Remove BasicAttack UnitAction.
+		unit_actions.unit_actions.remove(0);
+		unit_actions.processing_unit_action = false;
+		commands.entity(entity).remove::<BasicAttackAction>();
+		
+		info!("DEBUG: Processed BasicAttack action.");
+	}	
+}
+
+// Client
+fn process_use_item_actions(
+mut commands: Commands,
+mut unit_query: Query<(Entity, &UnitName, &Pos, &mut UnitActions, &mut Inventory, (&HPMax, &mut HPCurrent, &MPMax, &mut MPCurrent), &UseItemAction)>,
+items: Res<Items>,
+game: Res<Game>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut toasts: ResMut<Toasts>,
+mut experience_events: EventWriter<GainExperienceEvent>,
+) {
+	for (entity, unit_name, pos, mut unit_actions, mut inventory, (hp_max, mut hp_current, mp_max, mut mp_current), use_item_action) in unit_query.iter_mut() {
+		info!("DEBUG: Processing UseItem action...");
+		
+		if use_item_action.item < inventory.items.len() {
+			// Multiplayer items are recorded when the server sends them.
+			if !game.is_multiplayer {
+				replay_recorder.record(ReplayEvent::UseItem { user: *pos, item: use_item_action.item, });
+			}
+			
+			let item = inventory.items.remove(use_item_action.item);
+			let (hp, mp) = items.consumable(&item).unwrap_or((0, 0));
+			hp_current.value = (hp_current.value + hp).min(hp_max.value);
+			mp_current.value = (mp_current.value + mp).min(mp_max.value);
+			
+			info!("DEBUG: {} used {}.", unit_name.value, item);
+			toasts.toasts.push(Toast { message: format!("{} used {}.", unit_name.value, item), remaining: TOAST_DURATION_SECONDS, });
+			
+			// Multiplayer battles have no roster to keep experience in.
+			if !game.is_multiplayer {
+				experience_events.send(GainExperienceEvent { unit: entity, experience: XP_PER_ITEM, });
+			}
+		} else {
+			info!("DEBUG: {} has no item {}.", unit_name.value, use_item_action.item);
+		}
+		
+		unit_actions.unit_actions.remove(0);
+		unit_actions.processing_unit_action = false;
+		commands.entity(entity).remove::<UseItemAction>();
+		
+		info!("DEBUG: Processed UseItem action.");
+	}
+}
+
+//// Prototype
+//fn cutscene_1(mut commands: Commands,
+//mut swordsman_query: Query<(Entity, &mut UnitActions), With<NakedSwordsman>>,
+//) {
+//	//info!("DEBUG: Adding third Move UnitAction...");
+//	let (entity, mut unit_actions) = swordsman_query.single_mut();
+//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: Pos { x: 2, y: 2, } }, 0.0));
+//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: Pos { x: 4, y: 2, } }, 4.0));
+//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: Pos { x: 6, y: 2, } }, 8.0));
+//	info!("DEBUG: Added Move UnitActions.");
+//	
+//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Talk { message: "Lorem ipsum".to_string() }, 12.0));
+//	info!("DEBUG: Added Talk UnitAction.");
+//}
+
+// Client
+fn spawn_units(
+mut commands: Commands,
+asset_server: Res<AssetServer>,
+mut map_query: Query<&mut Map>,
+tile_transform_query: Query<&Transform, With<GameText>>,
+mut game: ResMut<Game>,
+hot_seat: Res<HotSeat>,
+classes: Res<Classes>,
+items: Res<Items>,
+mut campaign: ResMut<Campaign>,
+mut toasts: ResMut<Toasts>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	info!("DEBUG: Starting to spawn units...");
+	
+	// A scenario file that can't be read refuses the battle, rather than playing it with defaults.
+	match load_alliances(&game.scenario) {
+		Ok(alliances) => commands.insert_resource(alliances),
+		Err(error) => {
+			info!("DEBUG: {}", error);
+			toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+			next_state.set(GameState::MainMenu);
+			return;
+		},
+	}
+	match load_scenario_rules(&game.scenario) {
+		Ok(rules) => commands.insert_resource(rules),
+		Err(error) => {
+			info!("DEBUG: {}", error);
+			toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+			next_state.set(GameState::MainMenu);
+			return;
+		},
+	}
+	match load_objectives(&game.scenario) {
+		Ok(objectives) => commands.insert_resource(objectives),
+		Err(error) => {
+			info!("DEBUG: {}", error);
+			toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+			next_state.set(GameState::MainMenu);
+			return;
+		},
+	}
+	let deployment_zones = match load_deployment_zones(&game.scenario) {
+		Ok(deployment_zones) => deployment_zones,
+		Err(error) => {
+			info!("DEBUG: {}", error);
+			toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+			next_state.set(GameState::MainMenu);
+			return;
+		},
+	};
+
+	let mut map = &mut map_query.single_mut().map;
+
+	let records = match read_unit_records(&game.scenario) {
+		Ok(records) => records,
+		Err(error) => {
+			info!("DEBUG: {}", error);
+			toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+			next_state.set(GameState::MainMenu);
+			return;
+		},
+	};
+	
+	let campaign = &mut *campaign;
+	let campaign_team = campaign.definition.as_ref().map(|definition| definition.team);
+	let mut campaign_slot = 0;
+	campaign.deployed.clear();
+	
+	// A unit of an unknown class can't be spawned, so the battle doesn't start.
+	let units = match records.iter().map(|record| record.unit_attributes(&classes)).collect::<Result<Vec<UnitAttributes>, String>>() {
+		Ok(units) => units,
+		Err(error) => {
+			info!("DEBUG: {}", error);
+			toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+			next_state.set(GameState::MainMenu);
+			return;
+		},
+	};
+	
+	for (record, mut unit_attributes) in records.into_iter().zip(units) {
+		// Offline, teams beyond the ones set up for the game are controlled by the AI,
+		// or by another player in hot-seat games. Teams of only guests and neutral units are left to the AI.
+		if !game.is_multiplayer {
+			if hot_seat.enabled && record.role.unwrap_or_default() == UnitRole::Regular {
+				game.players.insert(record.unit_team, ControlledBy::Player);
+			} else {
+				game.players.entry(record.unit_team).or_insert(ControlledBy::AI);
+			}
+		}
+		
+		// In a campaign battle, the rows of the roster's team are deployment slots,
+		// filled in order with the roster units chosen before the battle.
+		// Guests and neutral units are part of the scenario, not of the roster.
+		if campaign.active && Some(unit_attributes.unit_team.value) == campaign_team && unit_attributes.role == UnitRole::Regular {
+			let roster_unit = campaign.selected
+				.get(campaign_slot)
+				.and_then(|roster_id| campaign.progress.as_ref().and_then(|progress| progress.roster.iter().find(|roster_unit| roster_unit.roster_id == *roster_id)));
+			campaign_slot += 1;
+			let Some(roster_unit) = roster_unit else {
+				continue;
+			};
+			roster_unit.apply(&mut unit_attributes);
+			campaign.deployed.push((unit_attributes.unit_id.value, roster_unit.roster_id));
+		}
+		unit_attributes.apply_equipment(&items);
+		
+		let mut path_string: String = unit_attributes.unit_sprite.value.clone();
+		path_string.push_str("_east.png");
+		let role = unit_attributes.role;
+		
+		info!("DEBUG: Creating new unit...");
+		let entity_id = commands.spawn((
+			unit_attributes,
+			Unit,
+			UnitActions { unit_actions: Default::default(), processing_unit_action: false, },
+			Pos {
+				x: record.pos_x,
+				y: record.pos_y,
+			},
+			MoveActions { move_actions: Vec::new(), },
+		)).id();
+		
+		// Get tile transform.
+		let tile_entity = map[record.pos_x][record.pos_y].3[map[record.pos_x][record.pos_y].3.len() - 1];
+		
+		if let Ok(tile_transform) = tile_transform_query.get(tile_entity) {
+			let unit_transform = Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y + 100.0, tile_transform.translation.z + 0.00000001);
+			
+			commands.entity(entity_id).insert(SpriteBundle {
+						sprite: Sprite {
+							color: role.color(),
+							..default()
+						},
+						texture: asset_server.load(path_string),
+						transform: unit_transform,
+						..default()
+			},);
+		}
+		
+		
+		map[record.pos_x][record.pos_y].2.push(entity_id);
+		
+		//info!("DEBUG: Unit transform is: {:?}.", Transform::from_xyz((record.pos_x as f32) * 256.0 / 2.0 - (record.pos_y as f32) * 256.0 / 2.0, (record.pos_x as f32) * 128.0 / 2.0 + (record.pos_y as f32) * 128.0 / 2.0 + (map[record.pos_x][record.pos_y].0 as f32) * 15.0 + 100.0, 1.0));
+		 
+		
+		//* (111.0 / (128.0 / 2.0) - 1.0)
+	}
+	
+	info!("DEBUG: Finished spawning units.");
+	
+	// Offline, the battle starts once the teams of players have deployed their units.
+	// Online, `loading_complete` decides, as the client only knows its team then.
+	let has_deployment = deployment_zones.teams().iter().any(|team| matches!(game.players.get(team), Some(ControlledBy::Player)));
+	commands.insert_resource(deployment_zones);
+	if !game.is_multiplayer && has_deployment {
+		info!("DEBUG: Setting GameState to Deployment.");
+		next_state.set(GameState::Deployment);
+		info!("DEBUG: Set GameState to Deployment.");
+		return;
+	}
+	
+	info!("DEBUG: Setting GameState to Ambush.");
+	next_state.set(GameState::Ambush);
+	info!("DEBUG: Set GameState to Ambush.");
+}
+
+// Client
+fn loading_complete(client: Res<Client>, game: Res<Game>, players: Res<Players>, client_data: Res<ClientData>, deployment_zones: Res<DeploymentZones>, mut next_state: ResMut<NextState<GameState>>, state: Res<State<GameState>>, mut network_stats: ResMut<NetworkStats>) {
+	// Spectators don't take part in the battle, so the server isn't waiting for them.
+	if !game.is_spectator {
+		info!("DEBUG: Sending LoadingComplete message...");
+		send_client_message(&client, &mut network_stats, ClientMessage::LoadingComplete);
+		info!("DEBUG: Sent LoadingComplete message.");
+		
+		let team = players.players.iter().find(|player| player.client_id == client_data.client_id).map(|player| player.team);
+		if team.map_or(false, |team| deployment_zones.teams().contains(&team)) {
+			info!("DEBUG: Setting GameState to Deployment...");
+			next_state.set(GameState::Deployment);
+			info!("DEBUG: Set GameState to Deployment.");
+			return;
+		}
+	}
+	
+	info!("DEBUG: Setting GameState to Wait...");
+	//commands.insert_resource(NextState(GameState::Wait));
+	next_state.set(GameState::Wait);	
+	info!("DEBUG: Set GameState to Wait.");
+	info!("DEBUG: Current state is {:?}.", state.get());
+}
+
+fn current_state(state: Res<State<GameState>>) {
+	info!("DEBUG: Current state is: {:?}.", state.get());
+}
+
+fn current_state2(state: Res<State<GameState>>) {
+	info!("DEBUG: Current state is: {:?}.", state.get());
+}
+
+fn change_state(mut next_state: ResMut<NextState<GameState>>, mut input: ResMut<Input<KeyCode>>) {
+
+	if input.just_pressed(KeyCode::Space) {
+
+		next_state.set(GameState::Wait);
+	}
+}
+
+// Test
+fn get_toggle_console_key(console_config: Res<ConsoleConfiguration>) {
+	for key in &console_config.keys {
+		
+		match key {
+			ToggleConsoleKey::KeyCode(key_code) => {
+				info!("Console toggle key is: {:?}.", key_code);
+			},
+			_ => { empty_system(); },
+		}
+	}
+}
+
+// Test
+fn do_nothing_command(mut log: ConsoleCommand<DoNothingCommand>, mut unit_actions_query: Query<&mut UnitActions>) {
+    if let Some(Ok(do_nothing_command)) = log.take() {
+        // handle command
+        for mut unit_actions in unit_actions_query.iter_mut() {
+			unit_actions.unit_actions.push(UnitActionTuple::default());
+			info!("DEBUG: Added DoNothing UnitAction.");
+        }
+    }
+}
+
+// Prototype
+fn talk_command(mut log: ConsoleCommand<TalkCommand>, mut unit_query: Query<(Entity, &UnitId, &mut UnitActions)>) {
+	if let Some(Ok(TalkCommand { unit_id, msg })) = log.take() {
+        // handle command
+        // Find unit with ID = unit_id.
+        for (entity_id, unit_id2, mut unit_actions) in unit_query.iter_mut() {
+			if unit_id2.value == unit_id {
+				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Talk { message: msg.clone() }, 0.0));
+			}
+        }
+    }
+}
+
+// Prototype
+fn move_command(mut log: ConsoleCommand<MoveCommand>, mut unit_query: Query<(Entity, &UnitId, &mut UnitActions, &Pos)>) {
+	if let Some(Ok(MoveCommand { unit_id, x, y })) = log.take() {
+        // handle command
+        // Find unit with ID = unit_id.
+        for (entity_id, unit_id2, mut unit_actions, pos) in unit_query.iter_mut() {
+			if unit_id2.value == unit_id {
+				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+					origin: Pos { x: pos.x, y: pos.y, },
+					destination: Pos { x: x, y: y },
+					timer: Timer::from_seconds(4.0, TimerMode::Once),
+				}, 0.0));
+			}
+        }
+    }
+}
+
+// Client
+fn say_command(mut log: ConsoleCommand<SayCommand>, client: Res<Client>, game: Res<Game>, mut network_stats: ResMut<NetworkStats>) {
+	if let Some(Ok(SayCommand { team, msg })) = log.take() {
+		if !game.is_multiplayer {
+			reply!(log, "Chat is only available in multiplayer battles.");
+			return;
+		}
+		
+		let scope = if team { ChatScope::Team } else { ChatScope::All };
+		if !send_chat_message(&client, &mut network_stats, scope, &msg.join(" ")) {
+			reply!(log, "Can't send an empty message.");
+		}
+	}
+}
+
+// Prototype
+fn ars_militaris_demo(
+mut map_query: Query<&mut Map>,
+mut units_query: Query<(Entity, &UnitId, &mut UnitActions, &Pos)>,
+mut target_units_query: Query<(Entity, &Pos), With<Unit>>,
+mut demo_data: ResMut<DemoData>,
+time: Res<Time>,
+) {
+	let map = &map_query.single().map;
+	
+//	let mut rng = rand::thread_rng();
+//	// Compute a random unit.
+//	let random_unit_id = rng.gen_range(0..units_query.iter().len());
+//	
+//	// Find a random unit.
+//	for (entity, unit_id, mut unit_actions, pos) in units_query.iter_mut() {
+//		if (unit_id.value - 1) == random_unit_id {
+//			// Find another unit.
+//			for (target_entity, target_pos) in target_units_query.iter_mut() {
+//				if target_entity != entity {
+//					// Compute the new target position.
+//					let target_pos = Pos { x: pos.x + 1, y: pos.y, };
+//					
+//					// Insert a random Move UnitAction on the first unit.
+//					let mut rng2 = rand::thread_rng();
+//					let random_usize: usize = rng2.gen_range(0..map.len());
+//					
+//					let mut rng3 = rand::thread_rng();
+//					let random_usize2: usize = rng3.gen_range(0..map[0].len());
+//					
+//					// Compute a move of a single tile in a random direction
+//										
+//					unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+//						origin: Pos { x: pos.x, y: pos.y, },
+//						destination: Pos { x: target_pos.x, y: target_pos.y, },
+//						timer: Timer::from_seconds(4.0, TimerMode::Once),
+//					}, (time.elapsed().as_secs_f32()) + 6.0));
+//					
+//	//				// Insert a Move UnitAction on the first unit to move towards the target unit.
+//	//				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: target_pos, }, 0.0));
+//					break;
+//				} else {
+//					continue;
+//				}			
+//			}
+//		} else {
+//			continue;
+//		}
+//		break;
+//	}
+	
+//	// Find a unit with UnitId 1.
+//	for (entity, unit_id, mut unit_actions, pos) in units_query.iter_mut() {
+//		if unit_id.value == 1 {
+//			// Move 1 tile forward.
+//			unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+//						origin: Pos { x: pos.x, y: pos.y, },
+//						destination: Pos { x: pos.x + 1, y: pos.y, },
+//						timer: Timer::from_seconds(4.0, TimerMode::Once),
+//					}, (time.elapsed().as_secs_f32()) + 6.0));
+//			
+//		} else {
+//			continue;
+//		}
+//		break;
+//	}
+
+	// Get the current unit and make a `Move` `UnitAction` towards its target.
+	for (entity, unit_id, mut unit_actions, pos) in units_query.iter_mut() {
+		if unit_id.value == demo_data.current_unit.value {
+			unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+				origin: Pos { x: pos.x, y: pos.y, },
+				destination: Pos { x: pos.x + 1, y: pos.y, },
+				timer: Timer::from_seconds(4.0, TimerMode::Once),
+			}, (time.elapsed().as_secs_f32()) + 6.0));
+			
+			// Set the next current unit.
+			if demo_data.current_unit.value == units_query.iter_mut().len() {
+				demo_data.current_unit.value = 1;
+			} else {
+				demo_data.current_unit.value += 1;
+			}
+		} else {
+			continue;
+		}
+		break;
+	}
+}
+
+// Prototype
+fn single_player_pause(
+mut input: ResMut<Input<KeyCode>>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	if input.just_pressed(KeyCode::F3) {
+		info!("DEBUG: Pausing the game...");
+		info!("DEBUG: Setting GameState to SinglePlayerPause...");
+		next_state.set(GameState::SinglePlayerPause);
+		info!("DEBUG: Set GameState to SinglePlayerPause.");
+	}
+}
+
+// Prototype
+fn handle_single_player_pause_state(
+mut input: ResMut<Input<KeyCode>>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	if input.just_pressed(KeyCode::F3) {
+		info!("DEBUG: Unpausing the game...");
+		info!("DEBUG: Setting GameState to Ambush...");
+		next_state.set(GameState::Ambush);
+		info!("DEBUG: Set GameState to Ambush.");
+	}
+}
 
 // Client
-fn set_window_icon(
-    // we have to use `NonSend` here
-    windows: NonSend<WinitWindows>,
-    window_query: Query<(Entity, &Window), With<PrimaryWindow>>,
+fn quick_save_and_load(
+input: Res<Input<KeyCode>>,
+mut save_events: EventWriter<SaveBattleEvent>,
+mut load_events: EventWriter<LoadBattleEvent>,
 ) {
-	let (entity, window) = window_query.single();
-	
-	let primary = windows.get_window(entity).unwrap();
+	if input.just_pressed(KeyCode::F5) {
+		save_events.send(SaveBattleEvent { slot: QUICK_SAVE_SLOT.to_string(), });
+	}
 	
-	// here we use the `image` crate to load our icon data from a png file
-	// this is not a very bevy-native solution, but it will do
-	let (icon_rgba, icon_width, icon_height) = {
-		let image = image::open("amlogo.png")
-			.expect("Failed to open icon path")
-			.into_rgba8();
-		let (width, height) = image.dimensions();
-		let rgba = image.into_raw();
-		(rgba, width, height)
-	};
+	if input.just_pressed(KeyCode::F8) {
+		load_events.send(LoadBattleEvent { slot: QUICK_SAVE_SLOT.to_string(), });
+	}
+}
 
-	let icon = Icon::from_rgba(icon_rgba, icon_width, icon_height).unwrap();
+// Client
+fn save_command(mut log: ConsoleCommand<SaveCommand>, mut save_events: EventWriter<SaveBattleEvent>) {
+	if let Some(Ok(SaveCommand { slot })) = log.take() {
+		reply!(log, "Saving the battle to {}...", slot);
+		save_events.send(SaveBattleEvent { slot: slot, });
+	}
+}
 
-	primary.set_window_icon(Some(icon));
-	
-    
+// Client
+fn load_command(mut log: ConsoleCommand<LoadCommand>, mut load_events: EventWriter<LoadBattleEvent>) {
+	if let Some(Ok(LoadCommand { slot })) = log.take() {
+		reply!(log, "Loading the battle from {}...", slot);
+		load_events.send(LoadBattleEvent { slot: slot, });
+	}
+}
 
-    
+// Client
+fn handle_save_battle_events(
+mut events: EventReader<SaveBattleEvent>,
+state: Res<State<GameState>>,
+turn_state: Res<State<TurnState>>,
+game: Res<Game>,
+hot_seat: Res<HotSeat>,
+objectives: Res<Objectives>,
+battle_rng: Res<BattleRng>,
+action_used: Res<ActionUsed>,
+campaign: Res<Campaign>,
+map_query: Query<&Map>,
+unit_query: Query<(&UnitId, &UnitTeam, &UnitName, &UnitClass, &Pos, (&WTMax, &WTCurrent, &HPMax, &HPCurrent, &MPMax, &MPCurrent), (&STR, &VIT, &INT, &MEN, &AGI, &DEX, &LUK), (&UnitSprite, &DIR, &MovementRange, &Jump, &AttackRange, &AttackType), (&Level, &Experience), (&Equipment, &Inventory, &UnitRole), &UnitActions, Option<&CurrentUnit>)>,
+mut toasts: ResMut<Toasts>,
+mut save_menu: ResMut<SaveMenu>,
+) {
+	for event in events.iter() {
+		// Only offline battles can be saved, and not while a unit is moving.
+		let can_save = !game.is_multiplayer && (*state.get() == GameState::Ambush || *state.get() == GameState::SinglePlayerPause);
+		let Ok(map) = map_query.get_single() else {
+			toasts.toasts.push(Toast { message: "There is no battle to save.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		};
+		if !can_save {
+			toasts.toasts.push(Toast { message: "The battle can't be saved right now.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		}
+		
+		let mut units: Vec<SavedUnit> = Vec::new();
+		for (unit_id, unit_team, unit_name, unit_class, pos, (wt_max, wt_current, hp_max, hp_current, mp_max, mp_current), (str, vit, int, men, agi, dex, luk), (unit_sprite, dir, movement_range, jump, attack_range, attack_type), (level, experience), (equipment, inventory, role), unit_actions, current_unit) in unit_query.iter() {
+			units.push(SavedUnit {
+				unit_id: unit_id.value,
+				unit_team: unit_team.value,
+				unit_name: unit_name.value.clone(),
+				unit_class: unit_class.value.clone(),
+				pos: *pos,
+				wt_max: wt_max.value,
+				wt_current: wt_current.value,
+				hp_max: hp_max.value,
+				hp_current: hp_current.value,
+				mp_max: mp_max.value,
+				mp_current: mp_current.value,
+				str: str.value,
+				vit: vit.value,
+				int: int.value,
+				men: men.value,
+				agi: agi.value,
+				dex: dex.value,
+				luk: luk.value,
+				level: level.value,
+				experience: experience.value,
+				unit_sprite: unit_sprite.value.clone(),
+				direction: dir.direction,
+				movement_range: movement_range.value,
+				jump: jump.value,
+				attack_range: attack_range.value,
+				attack_type: *attack_type,
+				equipment: equipment.clone(),
+				inventory: inventory.items.clone(),
+				role: *role,
+				unit_actions: unit_actions.unit_actions
+					.iter()
+					.map(|unit_action_tuple| (SavedUnitAction::from_unit_action(&unit_action_tuple.0), unit_action_tuple.1))
+					.collect(),
+				is_current_unit: current_unit.is_some(),
+			});
+		}
+		units.sort_by_key(|unit| unit.unit_id);
+		
+		let save = BattleSave {
+			version: SAVE_VERSION,
+			scenario: game.scenario.clone(),
+			hot_seat: hot_seat.enabled,
+			current_unit: game.current_unit,
+			current_team: game.current_team,
+			players: game.players.clone(),
+			turn_state: turn_state.get().clone(),
+			rng: battle_rng.clone(),
+			tiles: map.map.iter().map(|map_line| map_line.iter().map(|tile| (tile.0, tile.1.clone())).collect()).collect(),
+			units: units,
+			objective_turns: objectives.turns,
+			objective_held_turns: objectives.held_turns.clone(),
+			action_used: action_used.value,
+			campaign_selected: if campaign.active { Some(campaign.selected.clone()) } else { None },
+		};
+		
+		let Some(path) = save_path(&event.slot) else {
+			toasts.toasts.push(Toast { message: format!("{} is not a valid save name.", event.slot), remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		};
+		match write_battle_save(&save, &path) {
+			Ok(()) => {
+				info!("DEBUG: Saved the battle to {}.", path);
+				toasts.toasts.push(Toast { message: format!("Saved the battle to {}.", event.slot), remaining: TOAST_DURATION_SECONDS, });
+				save_menu.saves = list_saves();
+			},
+			Err(error) => {
+				info!("DEBUG: Couldn't save the battle to {}: {}.", path, error);
+				toasts.toasts.push(Toast { message: format!("Couldn't save the battle: {}.", error), remaining: TOAST_DURATION_SECONDS, });
+			},
+		}
+	}
 }
 
-// Server
-fn read_map_system(mut events: EventReader<GameStartEvent>, mut events2: EventWriter<MapReadEvent>) {
-	
+// Client
+fn handle_load_battle_events(
+mut events: EventReader<LoadBattleEvent>,
+state: Res<State<GameState>>,
+mut game: ResMut<Game>,
+mut hot_seat: ResMut<HotSeat>,
+mut campaign: ResMut<Campaign>,
+mut pending_save: ResMut<PendingSave>,
+classes: Res<Classes>,
+mut toasts: ResMut<Toasts>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
 	for event in events.iter() {
-		//info!("DEBUG: Reading map file...");
+		// Multiplayer battles can't be left for a saved one.
+		let can_load = match state.get() {
+			GameState::MainMenu | GameState::GameOver => true,
+			GameState::Ambush | GameState::Deployment | GameState::SinglePlayerPause => !game.is_multiplayer,
+			_ => false,
+		};
+		if !can_load {
+			toasts.toasts.push(Toast { message: "A battle can't be loaded right now.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		}
+		
+		let Some(path) = save_path(&event.slot) else {
+			toasts.toasts.push(Toast { message: format!("{} is not a valid save name.", event.slot), remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		};
+		let save = match read_battle_save(&path) {
+			Ok(save) => save,
+			Err(error) => {
+				info!("DEBUG: Couldn't load the battle from {}: {}.", path, error);
+				toasts.toasts.push(Toast { message: format!("Couldn't load the battle: {}.", error), remaining: TOAST_DURATION_SECONDS, });
+				continue;
+			},
+		};
+		
+		// A campaign battle is deployed again from the campaign's roster, then restored from the save.
+		campaign.active = false;
+		if let Some(campaign_selected) = &save.campaign_selected {
+			let progress = match read_campaign_progress(CAMPAIGN_SAVE_PATH).and_then(|progress| check_roster_classes(&progress, &classes).map(|_| progress)) {
+				Ok(progress) => progress,
+				Err(error) => {
+					info!("DEBUG: Couldn't load the campaign of {}: {}.", path, error);
+					toasts.toasts.push(Toast { message: format!("Couldn't load the campaign of the battle: {}.", error), remaining: TOAST_DURATION_SECONDS, });
+					continue;
+				},
+			};
+			let definition = match load_campaign_definition(CAMPAIGN_FILE) {
+				Ok(definition) => definition,
+				Err(error) => {
+					info!("DEBUG: Couldn't load the campaign of {}: {}", path, error);
+					toasts.toasts.push(Toast { message: format!("Couldn't load the campaign of the battle: {}", error), remaining: TOAST_DURATION_SECONDS, });
+					continue;
+				},
+			};
+			campaign.definition = Some(definition);
+			campaign.progress = Some(progress);
+			campaign.selected = campaign_selected.clone();
+			campaign.active = true;
+		}
+		
+		info!("DEBUG: Loading the battle from {}...", path);
+		game.scenario = save.scenario.clone();
+		game.is_multiplayer = false;
+		game.is_spectator = false;
+		*hot_seat = HotSeat { enabled: save.hot_seat, ..default() };
+		pending_save.save = Some(save);
+		
+		info!("DEBUG: Setting GameState to LoadAmbush...");
+		next_state.set(GameState::LoadAmbush);
+		info!("DEBUG: Set GameState to LoadAmbush.");
+		next_turn_state.set(TurnState::Wait);
+	}
+}
+
+// Client
+fn apply_pending_save(
+mut commands: Commands,
+mut pending_save: ResMut<PendingSave>,
+mut map_query: Query<&mut Map>,
+unit_query: Query<(Entity, &UnitId)>,
+tile_transform_query: Query<&Transform, With<GameText>>,
+mut game: ResMut<Game>,
+mut objectives: ResMut<Objectives>,
+mut action_used: ResMut<ActionUsed>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+	let Some(save) = pending_save.save.take() else {
+		return;
+	};
 	
-		let file_contents = fs::read_to_string("src/map.txt").unwrap();
+	// The saved battle is past its deployment.
+	info!("DEBUG: Setting GameState to Ambush...");
+	next_state.set(GameState::Ambush);
+	info!("DEBUG: Set GameState to Ambush.");
+	
+	// A replay has to start at the beginning of the battle.
+	info!("DEBUG: Not recording a replay of the loaded battle.");
+	replay_recorder.replay = None;
+	
+	info!("DEBUG: Applying the saved battle...");
+	let map = &mut map_query.single_mut().map;
+	
+	for i in 0..map.len().min(save.tiles.len()) {
+		for j in 0..map[i].len().min(save.tiles[i].len()) {
+			map[i][j].0 = save.tiles[i][j].0;
+			map[i][j].1 = save.tiles[i][j].1.clone();
+			map[i][j].2.clear();
+		}
+	}
+	
+	// Units of the scenario missing from the save were dead when it was made.
+	for (entity, unit_id) in unit_query.iter() {
+		let Some(saved_unit) = save.units.iter().find(|saved_unit| saved_unit.unit_id == unit_id.value) else {
+			commands.entity(entity).despawn();
+			continue;
+		};
 		
-		//info!("DEBUG: Read map file.");
+		let mut unit_actions: Vec<UnitActionTuple> = Vec::new();
+		for (saved_unit_action, delay) in &saved_unit.unit_actions {
+			unit_actions.push(UnitActionTuple(saved_unit_action.unit_action(), *delay));
+		}
 		
-		// Separate map into lines.
-		let map_lines: Vec<&str> = file_contents.split('\n').collect();
-		//info!("DEBUG: Map line 1 is: \n{}", map_lines[0]);
+		commands.entity(entity).insert((
+			saved_unit.unit_attributes(),
+			saved_unit.pos,
+			UnitActions { unit_actions: unit_actions, processing_unit_action: false, },
+			MoveActions { move_actions: Vec::new(), },
+		));
 		
-		// Separate lines and build 2D-array.
-		info!("DEBUG: Starting to build 2D array of map...");
-		let mut map: Vec<Vec<String>> = Vec::new();
-		for i in 0..map_lines.len() {
-			let mut map_line: Vec<String> = Vec::new();
-			let line = map_lines[i];
-			let line_splitted: Vec<&str> = line.split(' ').collect();
-			for j in 0..line_splitted.len() {
-				let map_cell = line_splitted[j].to_owned();
-				map_line.push(map_cell);
+		if saved_unit.is_current_unit {
+			commands.entity(entity).insert(CurrentUnit {});
+		} else {
+			commands.entity(entity).remove::<CurrentUnit>();
+		}
+		
+		map[saved_unit.pos.x][saved_unit.pos.y].2.push(entity);
+		
+		// Place the unit on its tile, the same way `spawn_units` does.
+		if let Some(tile_entity) = map[saved_unit.pos.x][saved_unit.pos.y].3.last() {
+			if let Ok(tile_transform) = tile_transform_query.get(*tile_entity) {
+				commands.entity(entity).insert(Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y + 100.0, tile_transform.translation.z + 0.00000001));
 			}
-			map.push(map_line);
 		}
-		info!("DEBUG: Finished building 2D array of map.");
+	}
+	
+	game.current_unit = save.current_unit;
+	game.current_team = save.current_team;
+	game.players = save.players;
+	game.winner = ControlledBy::None;
+	objectives.turns = save.objective_turns;
+	objectives.held_turns = save.objective_held_turns;
+	objectives.last_team = Some(save.current_team);
+	action_used.value = save.action_used;
+	commands.insert_resource(save.rng);
+	
+	// Unit selection isn't saved, so a save made while choosing a tile goes back to the unit's turn.
+	let turn_state = match save.turn_state {
+		TurnState::ChooseMove | TurnState::ChooseAttack => TurnState::Turn,
+		turn_state => turn_state,
+	};
+	info!("DEBUG: Setting TurnState to {:?}...", turn_state);
+	next_turn_state.set(turn_state);
+	info!("DEBUG: Applied the saved battle.");
+}
+
+// Client
+fn refresh_save_menu(mut save_menu: ResMut<SaveMenu>) {
+	save_menu.saves = list_saves();
+	if save_menu.slot.is_empty() {
+		save_menu.slot = QUICK_SAVE_SLOT.to_string();
+	}
+}
+
+// Client
+fn save_menu_ui(
+mut contexts: EguiContexts,
+mut save_menu: ResMut<SaveMenu>,
+mut save_events: EventWriter<SaveBattleEvent>,
+mut load_events: EventWriter<LoadBattleEvent>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	let save_menu = &mut *save_menu;
+	
+	egui::Window::new("Paused")
+		.anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+		.resizable(false)
+		.collapsible(false)
+		.show(contexts.ctx_mut(), |ui| {
+			ui.horizontal(|ui| {
+				ui.text_edit_singleline(&mut save_menu.slot);
+				if ui.button("Save").clicked() {
+					save_events.send(SaveBattleEvent { slot: save_menu.slot.clone(), });
+				}
+			});
+			
+			ui.separator();
+			
+			if save_menu.saves.is_empty() {
+				ui.label("No saved battles.");
+			}
+			for save in &save_menu.saves {
+				ui.horizontal(|ui| {
+					ui.label(save);
+					if ui.button("Load").clicked() {
+						load_events.send(LoadBattleEvent { slot: save.clone(), });
+					}
+				});
+			}
+			
+			ui.separator();
+			
+			if ui.button("Resume").clicked() {
+				info!("DEBUG: Setting GameState to Ambush...");
+				next_state.set(GameState::Ambush);
+				info!("DEBUG: Set GameState to Ambush.");
+			}
+		});
+}
+
+// Client
+fn replay_command(mut log: ConsoleCommand<ReplayCommand>, mut play_replay_events: EventWriter<PlayReplayEvent>) {
+	if let Some(Ok(ReplayCommand { name })) = log.take() {
+		let path = replay_path(&name);
+		reply!(log, "Playing the replay {}...", path);
+		play_replay_events.send(PlayReplayEvent { path: path, });
+	}
+}
+
+// Client
+fn play_replay_from_args(args: Res<CliArgs>, mut play_replay_events: EventWriter<PlayReplayEvent>) {
+	if let Some(path) = &args.replay {
+		play_replay_events.send(PlayReplayEvent { path: path.clone(), });
+	}
+}
+
+// Client
+fn handle_play_replay_events(
+mut events: EventReader<PlayReplayEvent>,
+state: Res<State<GameState>>,
+mut game: ResMut<Game>,
+mut replay_player: ResMut<ReplayPlayer>,
+mut toasts: ResMut<Toasts>,
+mut game_start_events: EventWriter<GameStartEvent>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+	for event in events.iter() {
+		if *state.get() != GameState::MainMenu {
+			toasts.toasts.push(Toast { message: "Replays can only be watched from the main menu.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		}
 		
-		//info!("DEBUG: Printing map file...");
-		//info!("{}", file_contents);
-		//info!("DEBUG: Printed map file...");
+		let replay = match read_replay(&event.path) {
+			Ok(replay) => replay,
+			Err(error) => {
+				info!("DEBUG: Couldn't load the replay {}: {}.", event.path, error);
+				toasts.toasts.push(Toast { message: format!("Couldn't load the replay: {}.", error), remaining: TOAST_DURATION_SECONDS, });
+				continue;
+			},
+		};
 		
-		events2.send(MapReadEvent {
-						map: map,
-					});
+		// The replay is watched like a multiplayer battle, with its events standing in for the server.
+		info!("DEBUG: Playing the replay {} with {} events...", event.path, replay.events.len());
+		game.is_multiplayer = true;
+		game.is_spectator = true;
+		game.scenario = replay.scenario.clone();
+		*replay_player = ReplayPlayer {
+			replay: Some(replay),
+			name: event.path.clone(),
+			..default()
+		};
+		
+		info!("DEBUG: Setting GameState to Loading...");
+		next_state.set(GameState::Loading);
+		info!("DEBUG: Set GameState to Loading.");
+		next_turn_state.set(TurnState::Wait);
+		game_start_events.send(GameStartEvent);
+	}
+}
+
+// Client
+fn play_replay(
+mut replay_player: ResMut<ReplayPlayer>,
+unit_actions_query: Query<&UnitActions>,
+mut server_messages: ResMut<ServerMessages>,
+mut time: ResMut<Time>,
+mut game_start_events: EventWriter<GameStartEvent>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	let replay_player = &mut *replay_player;
+	let Some(replay) = &replay_player.replay else {
+		return;
+	};
+	
+	if let Some(seek_target) = replay_player.seek_target {
+		if seek_target < replay_player.next_event {
+			// Seeking backwards plays the battle again from its start.
+			info!("DEBUG: Restarting the replay to seek to event {}.", seek_target);
+			replay_player.next_event = 0;
+			info!("DEBUG: Setting GameState to Loading...");
+			next_state.set(GameState::Loading);
+			info!("DEBUG: Set GameState to Loading.");
+			game_start_events.send(GameStartEvent);
+			return;
+		} else if seek_target == replay_player.next_event {
+			replay_player.seek_target = None;
+		}
+	}
+	
+	if replay_player.seek_target.is_some() {
+		time.set_relative_speed(REPLAY_SEEK_SPEED);
+	} else {
+		time.set_relative_speed(replay_player.speed);
+	}
+	
+	// Let the previous event play out first.
+	if unit_actions_query.iter().any(|unit_actions| unit_actions.unit_actions.len() > 0) {
+		return;
+	}
+	
+	if replay_player.seek_target.is_none() {
+		if !replay_player.playing && !replay_player.step {
+			return;
+		}
+		
+		replay_player.delay -= time.delta_seconds();
+		if replay_player.delay > 0.0 && !replay_player.step {
+			return;
+		}
+	}
+	
+	let Some(event) = replay.events.get(replay_player.next_event) else {
+		replay_player.playing = false;
+		replay_player.step = false;
+		return;
+	};
+	
+	info!("DEBUG: Replaying event {}: {:?}.", replay_player.next_event, event);
+	server_messages.messages.push_back(event.server_message());
+	replay_player.next_event += 1;
+	replay_player.delay = REPLAY_EVENT_DELAY_SECONDS;
+	replay_player.step = false;
+}
+
+// Client
+fn replay_controls_ui(
+mut contexts: EguiContexts,
+mut replay_player: ResMut<ReplayPlayer>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	let replay_player = &mut *replay_player;
+	let event_count = replay_player.replay.as_ref().map_or(0, |replay| replay.events.len());
+	
+	egui::Window::new("Replay")
+		.anchor(egui::Align2::LEFT_TOP, egui::vec2(10.0, 10.0))
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			ui.label(&replay_player.name);
+			if replay_player.next_event >= event_count {
+				ui.label("End of replay.");
+			} else {
+				ui.label(format!("Event {} of {}", replay_player.next_event, event_count));
+			}
+			
+			ui.horizontal(|ui| {
+				if replay_player.playing {
+					if ui.button("Pause").clicked() {
+						replay_player.playing = false;
+					}
+				} else {
+					if ui.button("Play").clicked() {
+						replay_player.playing = true;
+					}
+					if ui.button("Step").clicked() {
+						replay_player.step = true;
+					}
+				}
+			});
+			
+			ui.horizontal(|ui| {
+				ui.label("Speed");
+				for speed in [0.5, 1.0, 2.0, 4.0] {
+					ui.selectable_value(&mut replay_player.speed, speed, format!("{}x", speed));
+				}
+			});
+			
+			// Seek once the slider is let go, since seeking backwards reloads the battle.
+			let response = ui.add(egui::Slider::new(&mut replay_player.seek_position, 0..=event_count).text("Seek"));
+			if response.drag_released() || (response.changed() && !response.dragged()) {
+				replay_player.seek_target = Some(replay_player.seek_position);
+			} else if !response.dragged() {
+				replay_player.seek_position = replay_player.seek_target.unwrap_or(replay_player.next_event);
+			}
+			
+			if ui.button("Stop").clicked() {
+				info!("DEBUG: Setting GameState to MainMenu...");
+				next_state.set(GameState::MainMenu);
+				info!("DEBUG: Set GameState to MainMenu.");
+			}
+		});
+}
+
+// Client
+fn stop_replay(
+mut replay_player: ResMut<ReplayPlayer>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut time: ResMut<Time>,
+) {
+	*replay_player = ReplayPlayer::default();
+	*replay_recorder = ReplayRecorder::default();
+	time.set_relative_speed(1.0);
+}
+
+// Client
+fn write_replay(
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut toasts: ResMut<Toasts>,
+) {
+	let Some(replay) = replay_recorder.replay.take() else {
+		return;
+	};
+	
+	let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+	let path = format!("{}/{}_{}.ron", REPLAYS_DIRECTORY, replay.scenario, seconds);
+	match write_replay_file(&replay, &path) {
+		Ok(()) => {
+			info!("DEBUG: Wrote the replay to {}.", path);
+			toasts.toasts.push(Toast { message: format!("The replay was saved to {}.", path), remaining: TOAST_DURATION_SECONDS, });
+		},
+		Err(error) => {
+			info!("DEBUG: Couldn't write the replay to {}: {}.", path, error);
+		},
 	}
 }
 
-// Client & Server
-fn setup_map_system(mut events: EventReader<MapReadEvent>, mut events2: EventWriter<MapSetupEvent>, mut commands: Commands, asset_server: Res<AssetServer>) {
+// Prototype
+fn first_ai(
+mut commands: Commands,
+map_query: Query<&Map>,
+mut unit_query: Query<(Entity, &UnitTeam, &mut UnitActions, &Pos, (&MovementRange, &Jump, &UnitRole, &UnitClass), &AttackRange, &AttackType, &mut WTCurrent, &WTMax), With<CurrentUnit>>,
+other_units_query: Query<(Entity, &UnitTeam, &Pos), Without<CurrentUnit>>,
+alliances: Res<Alliances>,
+rules: Res<ScenarioRules>,
+classes: Res<Classes>,
+mut next_state: ResMut<NextState<TurnState>>,
+) {
+	let map = &map_query.single().map;
 	
-	for event in events.iter() {
-		// Spawn camera.
-		commands.spawn(Camera2dBundle::default());
+	// Get current unit.
+	let Ok((entity, unit_team, mut unit_actions, pos, (movement_range, jump, role, unit_class), attack_range, attack_type, mut wt_current, wt_max)) = unit_query.get_single_mut() else {
+		return;
+	};
+	
+	// The AI only goes after hostile units. Allied and neutral units are left alone.
+	let hostile_units: Vec<(Entity, Pos)> = other_units_query
+		.iter()
+		.filter(|(_, other_team, _)| alliances.stance(unit_team.value, other_team.value) == Stance::Hostile)
+		.map(|(other_entity, _, other_pos)| (other_entity, *other_pos))
+		.collect();
+	
+	// A unit whose class can't attack only ever moves towards hostile units.
+	let possible_attacks = if classes.allows(&unit_class.value, Ability::Attack) {
+		find_possible_attacks(map.to_vec(), *pos, attack_range.value, *attack_type)
+	} else {
+		Vec::new()
+	};
+	if *role == UnitRole::Neutral {
+		// Neutral units never attack. They move as far as they can from the closest hostile unit.
+		let hostile_positions: Vec<Pos> = hostile_units.iter().map(|(_, hostile_pos)| *hostile_pos).collect();
+		if let Some(destination) = flee_destination(map, *pos, movement_range.value, jump.value, &hostile_positions, &rules) {
+			info!("DEBUG: AI is fleeing to {:?}.", destination);
+			unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+					origin: Pos { x: pos.x, y: pos.y, },
+					destination: destination,
+					timer: Timer::from_seconds(4.0, TimerMode::Once),
+				}, 0.0));
+		}
+	} else if let Some((target_entity, target_pos)) = hostile_units.iter().find(|(_, hostile_pos)| possible_attacks.contains(hostile_pos)) {
+		// Insert a `BasicAttack` `UnitAction` towards the first hostile unit in range.
+		info!("DEBUG: AI is attacking the unit at {:?}.", target_pos);
+		unit_actions.unit_actions.push(UnitActionTuple(UnitAction::BasicAttack {
+			target: *target_pos,
+			is_counterattack: false,
+			damage: 0,
+		}, 0.0));
 		
-		let map = &event.map;
+		commands.entity(entity).insert(Attacker {});
+		commands.entity(*target_entity).insert(Target {});
+	} else {
+		// Insert a `Move` `UnitAction` towards the closest hostile unit.
+		let hostile_positions: Vec<Pos> = hostile_units.iter().map(|(_, hostile_pos)| *hostile_pos).collect();
+		let zone_of_control = rules.zone_of_control(map, &hostile_positions);
+		let possible_movements = find_possible_movements(map.to_vec(), *pos, movement_range.value, jump.value, &zone_of_control);
 		
-		info!("DEBUG: Starting to set up map in the ECS World...");
-		// For each cell in map, generate a 2D text and position it.
-		for i in 0..map.len() {
-			for j in 0..map[i].len() {
-			
-				// Compute position.
-				let i_as_float = i as f32;
-				let j_as_float = j as f32;
-			
-				// Spawn text.
-				commands.spawn((
-					TextBundle::from_section(
-						map[i][j].as_str(),
-						TextStyle {
-							font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-							font_size: 80.0,
-							color: Color::WHITE,
-						},
-					)
-					.with_text_alignment(TextAlignment::Center)
-					.with_style(Style {
-						position_type: PositionType::Absolute,
-						top: Val::Px(60.0 * i_as_float),
-						right: Val::Px(60.0 * j_as_float),
-						max_width: Val::Px(100.0),
-						max_height: Val::Px(300.0),
-						..default()
-					}),
-					Tile,
-					Pos {
-						x: i,
-						y: j,
-					},
-				));
+		let distance_to_hostile_units = |tile: &Pos| hostile_positions
+			.iter()
+			.map(|hostile_pos| tile.x.abs_diff(hostile_pos.x) + tile.y.abs_diff(hostile_pos.y))
+			.min();
+		
+		if let Some(destination) = possible_movements.iter().min_by_key(|tile| distance_to_hostile_units(tile)) {
+			if distance_to_hostile_units(destination) < distance_to_hostile_units(pos) {
+				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+						origin: Pos { x: pos.x, y: pos.y, },
+						destination: *destination,
+						timer: Timer::from_seconds(4.0, TimerMode::Once),
+					}, 0.0));
 			}
 		}
-		info!("DEBUG: Finished setting up map in the ECS World.");
-		events2.send(MapSetupEvent);
 	}
+	
+	// End turn.
+	wt_current.value = wt_max.value;
+	
+	commands.entity(entity).remove::<CurrentUnit>();
+	
+	info!("DEBUG: AI has finished its turn.");
+	info!("DEBUG: Setting TurnState to Wait...");
+	next_state.set(TurnState::Wait);
+	info!("DEBUG: Set TurnState to Wait.");
 }
 
-// Server
-fn read_battle_system(mut events: EventReader<MapSetupEvent>, mut events2: EventWriter<UnitsReadEvent>) {
-	for event in events.iter() {
-		let mut rdr = Reader::from_path("src/the_patrol_ambush_data.csv").unwrap();
-		let mut records: Vec<StringRecord> = Vec::new();
-		for result in rdr.records(){
-			let record = result.unwrap();
-			//info!("{:?}", record);
-			records.push(record);
+// Prototype
+fn handle_unit_directions(
+mut units_query: Query<(Entity, &mut Handle<Image>, &DIR, &UnitSprite)>,
+asset_server: Res<AssetServer>,
+) {
+	for (entity, mut sprite, dir, unit_sprite) in units_query.iter_mut() {
+		match dir.direction {
+			Direction::East => {
+				let mut sprite_path = unit_sprite.value.clone();
+				sprite_path.push_str("_east.png");
+				*sprite = asset_server.load(&*sprite_path);
+			},
+			Direction::South => {
+				let mut sprite_path = &mut unit_sprite.value.clone();
+				sprite_path.push_str("_south.png");
+				*sprite = asset_server.load(&*sprite_path);
+			},
+			Direction::West => {
+				let mut sprite_path = &mut unit_sprite.value.clone();
+				sprite_path.push_str("_west.png");
+				*sprite = asset_server.load(&*sprite_path);
+			},
+			Direction::North => {
+				let mut sprite_path = &mut unit_sprite.value.clone();
+				sprite_path.push_str("_north.png");
+				*sprite = asset_server.load(&*sprite_path);
+			},
 		}
-		events2.send(UnitsReadEvent {
-							units: records,
-						});
 	}
 }
 
-// Server
-fn generate_units_system(mut events: EventReader<UnitsReadEvent>, mut events2: EventWriter<UnitsGeneratedEvent>, mut commands: Commands) {
+// Prototype
+fn choose_move(
+mut commands: Commands,
+map_query: Query<&Map>,
+unit_query: Query<(Entity, &Pos, &MovementRange, &Jump, &UnitTeam), With<CurrentUnit>>,
+other_units_query: Query<(&Pos, &UnitTeam), Without<CurrentUnit>>,
+tile_query: Query<&Transform, With<GameText>>,
+asset_server: Res<AssetServer>,
+alliances: Res<Alliances>,
+rules: Res<ScenarioRules>,
+) {
+	let map = &map_query.single().map;
+	let (entity, pos, movement_range, jump, unit_team) = unit_query.single();
 	
-	for event in events.iter() {
-		// For each record, create an Entity for an unit.
-		let records = &event.units;
-		for record in records {
-			info!("DEBUG: Creating new unit...");
-			commands.spawn((
-				UnitAttributes {
-					unit_id : UnitId { value: record[0].parse().unwrap(), },
-					unit_team : UnitTeam { value: record[1].parse().unwrap(), },
-					unit_name : UnitName { value: record[2].to_string(), },
-					unit_class : UnitClass { value: record[3].to_string(), },
-					pos_x : PosX { value: record[4].parse().unwrap(), }, 
-					pos_y : PosY { value: record[5].parse().unwrap(), },
-					wt_max : WTMax { value: record[6].parse().unwrap(), },
-					wt_current : WTCurrent{ value: record[7].parse().unwrap(), },
-					hp_max : HPMax { value: record[8].parse().unwrap(), },
-					hp_current : HPCurrent { value: record[9].parse().unwrap(), },
-					mp_max : MPMax { value: record[10].parse().unwrap(), },
-					mp_current : MPCurrent { value: record[11].parse().unwrap(), },
-					str : STR { value: record[12].parse().unwrap(), },
-					vit : VIT { value: record[13].parse().unwrap(), },
-					int : INT { value: record[14].parse().unwrap(), },
-					men : MEN { value: record[15].parse().unwrap(), },
-					agi : AGI { value: record[16].parse().unwrap(), },
-					dex : DEX { value: record[17].parse().unwrap(), },
-					luk : LUK { value: record[18].parse().unwrap(), },
-					unit_sprite : UnitSprite { value: record[19].to_string(), },
-					dir: DIR { direction: Direction::from_string(record[20].to_string()), },
-					movement_range: MovementRange { value: record[21].parse().unwrap(), },
-					attack_range: AttackRange { value: record[22].parse().unwrap(), },
-					attack_type: AttackType::from_string(record[23].to_string()),
+	let hostile_positions: Vec<Pos> = other_units_query
+		.iter()
+		.filter(|(_, other_team)| alliances.stance(unit_team.value, other_team.value) == Stance::Hostile)
+		.map(|(other_pos, _)| *other_pos)
+		.collect();
+	let zone_of_control = rules.zone_of_control(map, &hostile_positions);
+	
+	let possible_movements = find_possible_movements(map.to_vec(), *pos, movement_range.value, jump.value, &zone_of_control);
+	info!("DEBUG: Possible movements are: {:?}.", possible_movements);
+	
+	// Spawn the MoveTile indicators.
+	
+	
+	for tile in &possible_movements {
+	
+		// Compute the indicator position, based on the tile.
+		if let Ok(tile_transform) = tile_query.get(map[tile.x][tile.y].3[map[tile.x][tile.y].3.len() - 1]) {
+			
+			commands.spawn((SpriteBundle {
+				sprite: Sprite {
+					color: Color::rgba(0.0, 0.0, 1.0, 0.5),
+					..default()
 				},
-				Unit,
+				texture: asset_server.load("move_tile.png"),
+				transform: Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y, tile_transform.translation.z + 0.000000025),
+				..default()
+			},
+			MoveTile {},
 			));
 		}
-		events2.send(UnitsGeneratedEvent);
+		
+		
 	}
+	
+	commands.entity(entity).insert(MoveTiles { move_tiles: possible_movements, });
 }
 
-// Server
-fn place_units_on_map_system(mut events: EventReader<UnitsGeneratedEvent>, unit_positions: Query<(&UnitId, &PosX, &PosY)>, mut tiles: Query<(&Tile, &Pos, &mut Text)>, mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
-	
-	for event in events.iter() {
-		info!("DEBUG: Starting to place units on map...");
-		// For each Unit...
-		for (unit_id, unit_position_x, unit_position_y) in unit_positions.iter() {
-			// Get the unit X and Y coordinates.
-			let x = unit_position_x.value;
-			let y = unit_position_y.value;
-			
-			// Get the tile at coordinates (x, y)
-			for (tile, pos, mut text) in tiles.iter_mut() {
-				if pos.x == x && pos.y == y {
-					// Assign unit ID to tile.
-					info!("DEBUG: Assigning unit ID to tile.");
-					text.sections[0].value = unit_id.value.to_string();
-				}
+// Prototype
+fn start_choose_move(
+mut input: ResMut<Input<KeyCode>>,
+mut next_state: ResMut<NextState<TurnState>>,
+) {
+	if input.just_pressed(KeyCode::M) {
+		info!("DEBUG: Setting TurnState to ChooseMove...");
+		next_state.set(TurnState::ChooseMove);
+		info!("DEBUG: Set TurnState to ChooseMove.");
+	}
+}
+
+fn handle_choose_move(
+mut commands: Commands,
+mut input: ResMut<Input<KeyCode>>,
+mut unit_query: Query<(Entity, &UnitId, &MoveTiles, &Pos, &mut UnitActions), With<CurrentUnit>>,
+cursor_query: Query<&Cursor>,
+move_tiles_query: Query<Entity, With<MoveTile>>,
+mut next_state: ResMut<NextState<TurnState>>,
+game: Res<Game>,
+mut client: ResMut<Client>,
+mut predicted_move: ResMut<PredictedMove>,
+mut undoable_move: ResMut<UndoableMove>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	let cursor = cursor_query.single();
+	let (entity, unit_id, move_tiles, pos, mut unit_actions) = unit_query.single_mut();
+
+	if input.just_pressed(KeyCode::M) {
+		// Check if cursor is in a MoveTile.
+		let cursor_pos = Pos { x: cursor.x, y: cursor.y, };
+		if move_tiles.move_tiles.contains(&cursor_pos) {
+			// Remove the MoveTiles
+			for entity in move_tiles_query.iter() {
+				commands.entity(entity).despawn();
 			}
+			
+			// The move can be taken back until the unit attacks or its turn ends.
+			undoable_move.value = Some((unit_id.value, Pos { x: pos.x, y: pos.y, }, cursor_pos));
+			
+			if !game.is_multiplayer {
+				// Game is in single-player mode.
+				// Insert a `Move` `UnitAction` towards the target tile.
+				info!("DEBUG: Unit can move to this tile.");
+				info!("DEBUG: Moving unit...");
+				
+				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+						origin: Pos { x: pos.x, y: pos.y, },
+						destination: Pos { x: cursor.x, y: cursor.y },
+						timer: Timer::from_seconds(4.0, TimerMode::Once),
+				}, 0.0));
+				
+				// Set State
+				next_state.set(TurnState::Turn);
+			} else {
+				// Game is in multiplayer mode.
+				// The `MoveTiles` come from the same pathfinding the server uses, so the move
+				// is started right away and rolled back if the server rejects it.
+				info!("DEBUG: Unit can move to this tile.");
+				info!("DEBUG: Moving unit...");
+				
+				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
+						origin: Pos { x: pos.x, y: pos.y, },
+						destination: Pos { x: cursor.x, y: cursor.y },
+						timer: Timer::from_seconds(4.0, TimerMode::Once),
+				}, 0.0));
+				predicted_move.value = Some((Pos { x: pos.x, y: pos.y, }, Pos { x: cursor.x, y: cursor.y, }));
+				
+				// Send a `ClientMessage::Move` message to the server.
+				info!("DEBUG: Sending `Move` message...");
+				send_client_message(&client, &mut network_stats, ClientMessage::Move { 
+					origin: Pos { x: pos.x, y: pos.y, },
+					destination: Pos { x: cursor.x, y: cursor.y },
+				});
+				info!("DEBUG: Sent Move message.");
+				
+				// Set State
+				next_state.set(TurnState::Turn);
+			}			
 		}
-		info!("DEBUG: Finished placing units on map.");
-		
-		//info!("DEBUG: Setting GameState to Wait...");
-		////commands.insert_resource(NextState(GameState::Wait));
-		//next_state.set(GameState::Wait);	
-		//info!("DEBUG: Set GameState to Wait.");
-		
-		info!("DEBUG: Setting GameState to LoadingComplete...");
-		//commands.insert_resource(NextState(GameState::Wait));
-		next_state.set(GameState::LoadingComplete);	
-		info!("DEBUG: Set GameState to LoadingComplete.");
+	}
+	
+	if input.just_pressed(KeyCode::Escape) {
+		// Remove the MoveTiles
+		for entity in move_tiles_query.iter() {
+			commands.entity(entity).despawn();
+		}
+	
+		info!("Setting TurnState back to Turn...");
+		next_state.set(TurnState::Turn);
+		info!("Set TurnState back to Turn.");
 	}
 }
 
 // Client
-fn init_cursor_system(mut commands: Commands, asset_server: Res<AssetServer>,) {
-	commands.spawn((
-		Cursor {
-			x: 0,
-			y: 0,
-		},
-		SpriteBundle {
-			texture: asset_server.load("cursor.png"),
-			visibility: Visibility::Hidden,
-			..default()
-		}));
-}
-
-// Client & Server
-fn start_game_system(mut input: ResMut<Input<KeyCode>>, mut events: EventWriter<GameStartEvent>, mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
-	if input.just_pressed(KeyCode::Space) {
-		info!("DEBUG: Setting GameState to Loading...");
-		//commands.insert_resource(NextState(GameState::Loading));
-		next_state.set(GameState::Loading);
-		info!("DEBUG: Set GameState to Loading.");
-        events.send(GameStartEvent);
-    } 
+fn undo_move(
+input: Res<Input<KeyCode>>,
+unit_query: Query<(&UnitId, &UnitActions), With<CurrentUnit>>,
+mut undoable_move: ResMut<UndoableMove>,
+client: Res<Client>,
+game: Res<Game>,
+mut toasts: ResMut<Toasts>,
+mut undo_move_events: EventWriter<UndoMoveEvent>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	if !input.just_pressed(KeyCode::U) {
+		return;
+	}
+	
+	let Ok((unit_id, unit_actions)) = unit_query.get_single() else {
+		return;
+	};
+	let Some((undo_unit_id, origin, destination)) = undoable_move.value.filter(|(undo_unit_id, _, _)| *undo_unit_id == unit_id.value) else {
+		toasts.toasts.push(Toast { message: "There is no move to undo.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+		return;
+	};
+	if unit_actions.unit_actions.len() > 0 {
+		toasts.toasts.push(Toast { message: "The unit hasn't finished moving yet.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+		return;
+	}
+	
+	info!("DEBUG: Undoing the move of unit {}...", undo_unit_id);
+	undoable_move.value = None;
+	if !game.is_multiplayer {
+		undo_move_events.send(UndoMoveEvent { unit_id: undo_unit_id, origin: origin, destination: destination, });
+	} else {
+		// The server sends `UndoMove` back if it accepts it.
+		info!("DEBUG: Sending UndoMove message...");
+		send_client_message(&client, &mut network_stats, ClientMessage::UndoMove);
+		info!("DEBUG: Sent UndoMove message.");
+	}
 }
 
 // Client
-fn setup_cursor_system(mut commands: Commands, mut tiles: Query<(&Tile, &Pos, &mut Text)>, game: Res<Game>, units: Query<(&UnitId, &PosX, &PosY)>, mut cursors: Query<&mut Cursor>) {
-	
-	info!("DEBUG: setup_cursor_system running...");
-	// Setup cursor.
-	
-	for (unit, pos_x, pos_y) in units.iter() {
-		if unit.value == game.current_unit {
-			for (tile, pos, mut text) in tiles.iter_mut() {
-				if pos.x == pos_x.value && pos.y == pos_y.value {
-					// Place cursor at the current unit position.
-					info!("DEBUG: Current unit is {}, at coordinates ({}, {}). Placing cursor there.", unit.value, pos_x.value, pos_y.value);
-					
-					// Build cursor string.
-					let mut cursor = "[".to_owned();
-					cursor.push_str(&text.sections[0].value);
-					cursor.push_str("]");
-					
-					// Assign cursor string to map.
-					text.sections[0].value = cursor;
-					
-					// Update cursor entity.
-					for mut _cursor in cursors.iter_mut() {
-						_cursor.x = pos.x;
-						_cursor.y = pos.y;
-					}
-				}
-			}
+fn handle_undo_move_events(
+mut events: EventReader<UndoMoveEvent>,
+mut map_query: Query<&mut Map>,
+mut units: Query<(Entity, &UnitId, &mut Pos, &mut Transform, &mut DIR), (With<Unit>, Without<GameText>)>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+mut undoable_move: ResMut<UndoableMove>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+) {
+	for event in events.iter() {
+		let origin = event.origin;
+		let destination = event.destination;
+		info!("DEBUG: Undoing the move from {}, {} to {}, {}...", origin.x, origin.y, destination.x, destination.y);
+		replay_recorder.record(ReplayEvent::UndoMove { origin: origin, destination: destination, });
+		undoable_move.value = None;
+		
+		let Ok(mut map_component) = map_query.get_single_mut() else {
+			continue;
+		};
+		let map = &mut map_component.map;
+		
+		let Some((entity, _, mut pos, mut transform, mut dir)) = units.iter_mut().find(|(_, unit_id, _, _, _)| unit_id.value == event.unit_id) else {
+			info!("DEBUG: There is no unit {} to move back.", event.unit_id);
+			continue;
+		};
+		
+		// Put the unit back on its tile, facing the way it did before the move.
+		place_unit(map, entity, &mut pos, origin);
+		if let Some(translation) = unit_translation(map, &tile_transform_query, origin) {
+			transform.translation = translation;
+		}
+		if let Some((_, direction)) = undoable_move.direction.filter(|(unit_id, _)| *unit_id == event.unit_id) {
+			dir.direction = direction;
 		}
+		undoable_move.direction = None;
+		info!("DEBUG: Undid the move.");
 	}
 }
 
+// Utility
+/// Moves a unit entity from its tile of the map to another one.
+fn place_unit(map: &mut Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, entity: Entity, pos: &mut Pos, destination: Pos) {
+	map[pos.x][pos.y].2.retain(|unit_entity| *unit_entity != entity);
+	map[destination.x][destination.y].2.push(entity);
+	*pos = destination;
+}
+
+// Utility
+/// Where a unit standing on the tile is drawn, the same way `spawn_units` places it.
+fn unit_translation(map: &Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, tile_transform_query: &Query<&Transform, (With<GameText>, Without<Unit>)>, pos: Pos) -> Option<Vec3> {
+	let tile_transform = map[pos.x][pos.y].3.last().and_then(|tile_entity| tile_transform_query.get(*tile_entity).ok())?;
+	
+	Some(Vec3::new(tile_transform.translation.x, tile_transform.translation.y + 100.0, tile_transform.translation.z + 0.00000001))
+}
+
 // Client
-fn setup_cursor_system_2(
+fn setup_deployment(
 mut commands: Commands,
-mut tiles: Query<(Entity, &Pos), With<GameText>>,
-game: Res<Game>,
-units: Query<(&UnitId, &Pos)>,
-mut cursor_query: Query<(Entity, &mut Cursor, &mut Visibility)>,
 asset_server: Res<AssetServer>,
+game: Res<Game>,
+players: Res<Players>,
+client_data: Res<ClientData>,
+deployment_zones: Res<DeploymentZones>,
+mut deployment: ResMut<Deployment>,
+map_query: Query<&Map>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+mut cursor_query: Query<(&mut Cursor, &mut Visibility)>,
 ) {
+	// Offline, the teams of players deploy one after the other.
+	// Online, each client deploys its own team, hidden from the others.
+	let teams: Vec<usize> = if game.is_multiplayer {
+		deployment_zones.teams()
+	} else {
+		deployment_zones.teams().into_iter().filter(|team| matches!(game.players.get(team), Some(ControlledBy::Player))).collect()
+	};
+	let team = if game.is_multiplayer {
+		players.players.iter().find(|player| player.client_id == client_data.client_id).map(|player| player.team)
+	} else {
+		teams.first().copied()
+	};
+	info!("DEBUG: Team {:?} deploys its units.", team);
 	
-	info!("DEBUG: setup_cursor_system_2 running...");
-	// Setup cursor.
-	let (entity, mut cursor, mut visibility) = cursor_query.single_mut();
+	*deployment = Deployment {
+		teams: teams,
+		team: team,
+		selected: None,
+		confirmed: false,
+	};
 	
-	// Find the tile the current unit is on.
-	for (unit_id, pos) in units.iter() {
-		if unit_id.value == game.current_unit {
-			// Set the cursor to the current unit tile.
-			cursor.x = pos.x;
-			cursor.y = pos.y;
-			
-			// Make the cursor sprite visible.
-			*visibility = Visibility::Visible;
-			
-		}
+	let (mut cursor, mut visibility) = cursor_query.single_mut();
+	*visibility = Visibility::Visible;
+	if let Some(team) = team {
+		show_deployment_zone(&mut commands, &asset_server, &map_query.single().map, &tile_transform_query, &deployment_zones, team, &mut cursor);
 	}
 }
 
-// Prototype
-fn move_cursor_2(
-map_query: Query<&Map>,
-mut cursor_query: Query<(&mut Cursor)>,
-mut input: ResMut<Input<KeyCode>>,
+// Client
+/// Marks the tiles of the team's zone, and puts the cursor on it.
+fn show_deployment_zone(
+commands: &mut Commands,
+asset_server: &AssetServer,
+map: &Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>,
+tile_transform_query: &Query<&Transform, (With<GameText>, Without<Unit>)>,
+deployment_zones: &DeploymentZones,
+team: usize,
+cursor: &mut Cursor,
 ) {
-	let mut cursor = cursor_query.single_mut();
-	let map = &map_query.single().map;
-	if input.just_pressed(KeyCode::W) {
-		if cursor.y == map[0].len() - 1 {
-			info!("DEBUG: You can't move the cursor there.");
-		} else {		
-			// Move Cursor North.
-			cursor.y += 1;
-		}
-	}
-
-	if input.just_pressed(KeyCode::A) {
-		
-		if cursor.x == 0 {
-			info!("DEBUG: You can't move the cursor there.");
-		} else {		
-			// Move Cursor West.
-			cursor.x -= 1;
-		}
-	}
+	let tiles = deployment_zones.tiles(team, map.len(), map[0].len());
 	
-	if input.just_pressed(KeyCode::S) {
-		if cursor.y == 0 {
-			info!("DEBUG: You can't move the cursor there.");
-		} else {		
-			// Move Cursor South.
-			cursor.y -= 1;
+	for tile in &tiles {
+		if let Some(tile_transform) = map[tile.x][tile.y].3.last().and_then(|tile_entity| tile_transform_query.get(*tile_entity).ok()) {
+			commands.spawn((SpriteBundle {
+				sprite: Sprite {
+					color: Color::rgba(0.0, 1.0, 0.0, 0.5),
+					..default()
+				},
+				texture: asset_server.load("move_tile.png"),
+				transform: Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y, tile_transform.translation.z + 0.000000025),
+				..default()
+			},
+			DeploymentTile {},
+			));
 		}
 	}
 	
-	if input.just_pressed(KeyCode::D) {
-		if cursor.x == map.len() - 1 {
-			info!("DEBUG: You can't move the cursor there.");
-		} else {		
-			// Move Cursor East.
-			cursor.x += 1;
-		}
+	// Start on a unit of the zone, if there is one.
+	if let Some(tile) = tiles.iter().find(|tile| !map[tile.x][tile.y].2.is_empty()).or(tiles.first()) {
+		cursor.x = tile.x;
+		cursor.y = tile.y;
 	}
 }
 
-// Prototype
-fn position_cursor(
-map_query: Query<&Map>,
-mut cursor_query: Query<(&Cursor, &mut Transform), Without<GameText>>,
-tiles_query: Query<&Transform, With<GameText>>,
+// Client
+fn hide_deploying_units(
+deployment: Res<Deployment>,
+mut unit_query: Query<(&UnitTeam, &UnitRole, &mut Visibility), With<Unit>>,
 ) {
-	// Get the cursor.
-	let (cursor, mut transform) = cursor_query.single_mut();
-	
-	// Get the map.
-	let map = &map_query.single().map;
-	
-	// Get the transform of the tile the unit is on.
-	let tile_entity = map[cursor.x][cursor.y].3[map[cursor.x][cursor.y].3.len() - 1];
-	if let Ok(tile_transform) = tiles_query.get(tile_entity) {
-		// Set the transform of the cursor to be the same as the tile's transform.
-		transform.translation = tile_transform.translation.clone();
-		
-		// Add the required Y modifier to ensure the correct placement
-		// at the center of the tile.
-		transform.translation.y += 16.0;
-		// Add the required Z modifier to ensure the correct Z-ordering.
-		// This modifier is equal to half the unit's Z modifier,
-		// so that the cursor will be placed above the tile but below the unit.
-		transform.translation.z += 0.000000005;
+	for (unit_team, role, mut visibility) in unit_query.iter_mut() {
+		let hidden = *role == UnitRole::Regular && deployment.teams.contains(&unit_team.value) && deployment.team != Some(unit_team.value);
+		*visibility = if hidden { Visibility::Hidden } else { Visibility::Inherited };
 	}
 }
 
-fn hide_cursor(
-mut commands: Commands,
-mut cursor_query: Query<(Entity, &mut Visibility), With<Cursor>>,
+// Client
+fn deployment_controls(
+input: Res<Input<KeyCode>>,
+cursor_query: Query<&Cursor>,
+mut map_query: Query<&mut Map>,
+mut units: Query<(&UnitTeam, &mut Pos, &mut DIR, &mut Transform, &UnitRole), (With<Unit>, Without<GameText>)>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+deployment_zones: Res<DeploymentZones>,
+mut deployment: ResMut<Deployment>,
+mut toasts: ResMut<Toasts>,
+mut confirm_events: EventWriter<ConfirmDeploymentEvent>,
 ) {
-	let (entity, mut visibility) = cursor_query.single();
+	let Some(team) = deployment.team else {
+		return;
+	};
+	if deployment.confirmed {
+		return;
+	}
 	
-	visibility = &Visibility::Hidden;
-}
-
-// Client
-fn wait_turn_system(mut units: Query<(Entity, &mut WTCurrent, &WTMax, &UnitId, &UnitTeam)>, mut game: ResMut<Game>, mut commands: Commands, mut next_state: ResMut<NextState<TurnState>>) {
+	let cursor = cursor_query.single();
+	let cursor_pos = Pos { x: cursor.x, y: cursor.y, };
+	let map = &mut map_query.single_mut().map;
 	
-	// Decrease all units WT. If WT equals 0, set the unit as the current unit turn.
-	for (entity, mut wt_current, wt_max, unit_id, unit_team) in units.iter_mut() {
-		if wt_current.value == 0 {
-			info!("DEBUG: Unit with UnitId {} has WTCurrent of 0.", unit_id.value);
-		
-			game.current_unit = unit_id.value;
-			info!("DEBUG: It is now unit {} turn.", unit_id.value);
-			
-			game.current_team = unit_team.value;
-			info!("DEBUG: It is now team {} turn.", unit_team.value);
-			
-			commands.entity(entity).insert(CurrentUnit {});
-			
-			// Find if team is controlled by player.
-			if let Some(player) = game.players.get(&game.current_team) {
-				match player {
-					ControlledBy::Player => {
-						// Player turn.
-						// Set TurnState to Turn.
-						info!("DEBUG: It is now the player's turn.");
-						info!("DEBUG: Setting TurnState to Turn...");
-						next_state.set(TurnState::Turn);
-						info!("DEBUG: Set TurnState to Turn.");
-					},
-					ControlledBy::AI => {
-						// AI turn.
-						// Set TurnState to AI.
-						info!("DEBUG: It is now the AI's turn.");
-						info!("DEBUG: Setting TurnState to AI...");
-						next_state.set(TurnState::AI);
-						info!("DEBUG: Set TurnState to AI.");
-					},
-					ControlledBy::None => {
-						info!("DEBUG: There isn't any player or AI controlling the current team. Please assign a controller to this team.");
+	// The unit of the deploying team under the cursor. Guests and neutral units can't be moved.
+	let unit_at_cursor = map[cursor_pos.x][cursor_pos.y].2
+		.iter()
+		.copied()
+		.find(|entity| units.get(*entity).map_or(false, |(unit_team, _, _, _, role)| unit_team.value == team && *role == UnitRole::Regular));
+	
+	if input.just_pressed(KeyCode::R) {
+		if let Some(entity) = deployment.selected.or(unit_at_cursor) {
+			if let Ok((_, _, mut dir, _, _)) = units.get_mut(entity) {
+				dir.direction = dir.direction.turned();
+			}
+		}
+	}
+	
+	if input.just_pressed(KeyCode::Space) {
+		match deployment.selected {
+			None => {
+				if unit_at_cursor.is_none() {
+					toasts.toasts.push(Toast { message: "Pick a unit of your team to deploy.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+				}
+				deployment.selected = unit_at_cursor;
+			},
+			Some(selected) => {
+				if !deployment_zones.contains(team, cursor_pos) {
+					toasts.toasts.push(Toast { message: "Units can only be deployed in the zone.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+				} else if unit_at_cursor.is_none() && !map[cursor_pos.x][cursor_pos.y].2.is_empty() {
+					toasts.toasts.push(Toast { message: "That tile is taken.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+				} else {
+					let Ok((_, pos, _, _, _)) = units.get(selected) else {
+						deployment.selected = None;
+						return;
+					};
+					let origin = *pos;
+					
+					// Placing a unit on another unit of the team swaps them.
+					for (entity, destination) in [(unit_at_cursor.filter(|other| *other != selected), origin), (Some(selected), cursor_pos)] {
+						let Some(entity) = entity else {
+							continue;
+						};
+						if let Ok((_, mut pos, _, mut transform, _)) = units.get_mut(entity) {
+							place_unit(map, entity, &mut pos, destination);
+							if let Some(translation) = unit_translation(map, &tile_transform_query, destination) {
+								transform.translation = translation;
+							}
+						}
 					}
+					deployment.selected = None;
 				}
-			}
-		} else {
-			wt_current.value = wt_current.value - 1;
+			},
 		}
 	}
+	
+	if input.just_pressed(KeyCode::Return) {
+		confirm_events.send(ConfirmDeploymentEvent);
+	}
 }
 
 // Client
-fn end_turn_single_player(mut input: ResMut<Input<KeyCode>>, mut units: Query<(Entity, &mut WTCurrent, &WTMax), With<CurrentUnit>>, mut commands: Commands, mut next_state: ResMut<NextState<TurnState>>) {
-	if input.just_pressed(KeyCode::T) {
-		info!("DEBUG: The current unit has ended its turn.");
-		info!("DEBUG: Reseting the unit's WT.");
-		for (entity, mut wt_current, wt_max) in units.iter_mut() {
-			if wt_current.value == 0 {
-				wt_current.value = wt_max.value;
-				commands.entity(entity).remove::<CurrentUnit>();
-				break;
+fn deployment_ui(
+mut contexts: EguiContexts,
+mut deployment: ResMut<Deployment>,
+unit_query: Query<(Entity, &UnitName, &UnitTeam, &Pos, &DIR, &UnitRole)>,
+mut confirm_events: EventWriter<ConfirmDeploymentEvent>,
+) {
+	let Some(team) = deployment.team else {
+		return;
+	};
+	
+	let mut units: Vec<(Entity, &UnitName, &Pos, &DIR)> = unit_query
+		.iter()
+		.filter(|(_, _, unit_team, _, _, role)| unit_team.value == team && **role == UnitRole::Regular)
+		.map(|(entity, unit_name, _, pos, dir, _)| (entity, unit_name, pos, dir))
+		.collect();
+	units.sort_by_key(|(_, _, pos, _)| (pos.x, pos.y));
+	
+	egui::Window::new("Deployment")
+		.anchor(egui::Align2::LEFT_TOP, egui::vec2(10.0, 10.0))
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			ui.label(format!("Team {} deploys its units.", team));
+			ui.separator();
+			
+			egui::Grid::new("deployment_units").striped(true).show(ui, |ui| {
+				for (entity, unit_name, pos, dir) in &units {
+					if deployment.selected == Some(*entity) {
+						ui.label(format!("> {}", unit_name.value));
+					} else {
+						ui.label(&unit_name.value);
+					}
+					ui.label(format!("{}, {}", pos.x, pos.y));
+					ui.label(format!("{:?}", dir.direction));
+					ui.end_row();
+				}
+			});
+			
+			ui.separator();
+			if deployment.confirmed {
+				ui.label("Waiting for the other side to deploy...");
+				return;
 			}
-		}
-		
-		// Set TurnState to Wait.
-		info!("DEBUG: Setting TurnState to Wait...");
-		next_state.set(TurnState::Wait);
-		info!("DEBUG: Set TurnState to Wait.");
-	}
+			ui.label("Space: pick up a unit, then place it in the zone or swap it with another.");
+			ui.label("R: turn the unit. Enter: start the battle.");
+			ui.horizontal(|ui| {
+				if ui.add_enabled(deployment.selected.is_some(), egui::Button::new("Put down")).clicked() {
+					deployment.selected = None;
+				}
+				if ui.button("Confirm").clicked() {
+					confirm_events.send(ConfirmDeploymentEvent);
+				}
+			});
+		});
 }
 
 // Client
-fn end_turn_system(mut input: ResMut<Input<KeyCode>>, mut units: Query<(&mut WTCurrent, &WTMax)>, mut commands: Commands, mut client: ResMut<Client>) {
-	if input.just_pressed(KeyCode::T) {
-		//info!("DEBUG: The current unit has ended its turn.");
-		//info!("DEBUG: Reseting the unit's WT.");
-		//for (mut wt_current, wt_max) in units.iter_mut() {
-			//if wt_current.value == 0 {
-				//wt_current.value = wt_max.value;
-				//break;
-			//}
-		//}
+fn handle_confirm_deployment_events(
+mut events: EventReader<ConfirmDeploymentEvent>,
+mut commands: Commands,
+asset_server: Res<AssetServer>,
+game: Res<Game>,
+client: Res<Client>,
+mut network_stats: ResMut<NetworkStats>,
+map_query: Query<&Map>,
+unit_query: Query<(&UnitId, &UnitTeam, &Pos, &DIR, &UnitRole)>,
+tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
+tiles_query: Query<Entity, With<DeploymentTile>>,
+mut cursor_query: Query<&mut Cursor>,
+deployment_zones: Res<DeploymentZones>,
+mut deployment: ResMut<Deployment>,
+mut replay_recorder: ResMut<ReplayRecorder>,
+mut toasts: ResMut<Toasts>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	for _ in events.iter() {
+		let Some(team) = deployment.team else {
+			continue;
+		};
+		if deployment.confirmed {
+			continue;
+		}
 		
-		info!("DEBUG: Sending Wait message...");
-		client
-			.connection()
-			.try_send_message(ClientMessage::Wait);
-		info!("DEBUG: Sent Wait message.");
+		// Guests and neutral units keep the places the scenario gives them.
+		let units: Vec<(usize, Pos, Direction)> = unit_query
+			.iter()
+			.filter(|(_, unit_team, _, _, role)| unit_team.value == team && **role == UnitRole::Regular)
+			.map(|(unit_id, _, pos, dir, _)| (unit_id.value, *pos, dir.direction))
+			.collect();
+		let team_units: Vec<usize> = units.iter().map(|(unit_id, _, _)| *unit_id).collect();
+		// The units of the other deploying teams are hidden, and have yet to take their places.
+		let occupied: Vec<Pos> = unit_query
+			.iter()
+			.filter(|(_, unit_team, _, _, role)| !deployment.teams.contains(&unit_team.value) || **role != UnitRole::Regular)
+			.map(|(_, _, pos, _, _)| *pos)
+			.collect();
+		if let Err(error) = check_deployment(&deployment_zones, team, &team_units, &occupied, &units) {
+			toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		}
+		deployment.selected = None;
 		
-		//// Set GameState to Wait.
-		//info!("DEBUG: Setting GameState to Wait...");
-		//commands.insert_resource(NextState(GameState::Wait));
-		//info!("DEBUG: Set GameState to Wait.");
+		if game.is_multiplayer {
+			// The server reveals every deployment once all teams have deployed.
+			info!("DEBUG: Sending Deploy message...");
+			send_client_message(&client, &mut network_stats, ClientMessage::Deploy { units: units, });
+			info!("DEBUG: Sent Deploy message.");
+			deployment.confirmed = true;
+			continue;
+		}
+		
+		for entity in tiles_query.iter() {
+			commands.entity(entity).despawn();
+		}
+		
+		// In hot-seat games, the next team of players deploys.
+		let next_team = deployment.teams.iter().copied().skip_while(|other_team| *other_team != team).nth(1);
+		if let Some(next_team) = next_team {
+			info!("DEBUG: Team {} deploys its units.", next_team);
+			deployment.team = Some(next_team);
+			show_deployment_zone(&mut commands, &asset_server, &map_query.single().map, &tile_transform_query, &deployment_zones, next_team, &mut cursor_query.single_mut());
+			toasts.toasts.push(Toast { message: format!("Team {} deploys its units.", next_team), remaining: TOAST_DURATION_SECONDS, });
+			continue;
+		}
+		
+		// Offline, no Deployment message is received, so the replay records the deployment here.
+		replay_recorder.record(ReplayEvent::Deploy {
+			units: unit_query.iter().map(|(unit_id, _, pos, dir, _)| (unit_id.value, *pos, dir.direction)).collect(),
+		});
+		
+		info!("DEBUG: Setting GameState to Ambush...");
+		next_state.set(GameState::Ambush);
+		info!("DEBUG: Set GameState to Ambush.");
 	}
 }
 
 // Client
-fn setup_game_resource_system(mut commands: Commands) {
-	let mut players = HashMap::new();
-	players.insert(1, ControlledBy::Player);
-	players.insert(2, ControlledBy::AI);
+fn finish_deployment(
+mut commands: Commands,
+tiles_query: Query<Entity, With<DeploymentTile>>,
+mut unit_query: Query<&mut Visibility, (With<Unit>, Without<Cursor>)>,
+mut cursor_query: Query<&mut Visibility, (With<Cursor>, Without<Unit>)>,
+mut deployment: ResMut<Deployment>,
+) {
+	for entity in tiles_query.iter() {
+		commands.entity(entity).despawn();
+	}
+	for mut visibility in unit_query.iter_mut() {
+		*visibility = Visibility::Inherited;
+	}
+	for mut visibility in cursor_query.iter_mut() {
+		*visibility = Visibility::Hidden;
+	}
 	
-	commands.insert_resource(Game {
-		current_unit: 0,
-		current_team: 1,
-		players: players,
-		winner: ControlledBy::None,
-		is_multiplayer: false,
-	});
+	*deployment = Deployment::default();
 }
 
 // Client
-fn setup_game_resource_system_multiplayer(mut commands: Commands) {
-	let mut players = HashMap::new();
-	players.insert(1, ControlledBy::Player);
-	players.insert(2, ControlledBy::Player);
-	
-	commands.insert_resource(Game {
-		current_unit: 0,
-		current_team: 1,
-		players: players,
-		winner: ControlledBy::None,
-		is_multiplayer: true,
-	});
+fn toggle_inventory_screen(
+input: Res<Input<KeyCode>>,
+mut inventory_screen: ResMut<InventoryScreen>,
+) {
+	if input.just_pressed(KeyCode::I) {
+		inventory_screen.open = !inventory_screen.open;
+	}
 }
 
 // Client
-fn start_connection(mut client: ResMut<Client>) {
-	client
-		.open_connection(
-			ConnectionConfiguration::from_strings(
-				//"127.0.0.1:6000",
-				//"139.162.244.70:6000",
-				"178.79.171.209:6000",
-                "0.0.0.0:0",
-            ).unwrap(),
-            CertificateVerificationMode::SkipVerification,
-        ).unwrap();
+fn inventory_ui(
+mut contexts: EguiContexts,
+inventory_screen: Res<InventoryScreen>,
+mut unit_query: Query<(&UnitName, &UnitClass, &Pos, &Equipment, &Inventory, &mut UnitActions, (&HPCurrent, &HPMax, &MPCurrent, &MPMax), (&AttackRange, &AttackType)), With<CurrentUnit>>,
+items: Res<Items>,
+classes: Res<Classes>,
+turn_state: Res<State<TurnState>>,
+game: Res<Game>,
+client: Res<Client>,
+mut undoable_move: ResMut<UndoableMove>,
+mut action_used: ResMut<ActionUsed>,
+mut network_stats: ResMut<NetworkStats>,
+) {
+	if !inventory_screen.open {
+		return;
+	}
+	let Ok((unit_name, unit_class, pos, equipment, inventory, mut unit_actions, (hp_current, hp_max, mp_current, mp_max), (attack_range, attack_type))) = unit_query.get_single_mut() else {
+		return;
+	};
+	
+	// Items are used in place of an attack, once the unit has finished moving, by units whose class can use them.
+	let can_use = *turn_state.get() == TurnState::Turn && !game.is_spectator && unit_actions.unit_actions.len() == 0 && !action_used.value && classes.allows(&unit_class.value, Ability::UseItem);
+	let mut used_item: Option<usize> = None;
+	
+	egui::Window::new("Inventory")
+		.anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			ui.heading(&unit_name.value);
+			ui.label(format!("HP {}/{}, MP {}/{}", hp_current.value, hp_max.value, mp_current.value, mp_max.value));
+			ui.label(format!("{:?} attack, range {}", attack_type, attack_range.value));
+			
+			ui.separator();
+			
+			egui::Grid::new("inventory_equipment").show(ui, |ui| {
+				for slot in EquipmentSlot::ALL {
+					ui.label(format!("{:?}", slot));
+					match equipment.slot(slot) {
+						Some(item) => {
+							ui.label(item);
+							ui.label(items.get(item).map_or(String::new(), |item| item.describe()));
+						},
+						None => {
+							ui.label("-");
+							ui.label("");
+						},
+					}
+					ui.end_row();
+				}
+			});
+			
+			ui.separator();
+			
+			if inventory.items.len() == 0 {
+				ui.label("No items.");
+			}
+			for (index, item) in inventory.items.iter().enumerate() {
+				ui.horizontal(|ui| {
+					let description = items.get(item).map_or(String::new(), |item| item.describe());
+					ui.label(format!("{} ({})", item, description));
+					if ui.add_enabled(can_use, egui::Button::new("Use")).clicked() {
+						used_item = Some(index);
+					}
+				});
+			}
+		});
+	
+	let Some(item) = used_item else {
+		return;
+	};
+	if !game.is_multiplayer {
+		info!("DEBUG: Using item {}...", item);
+		unit_actions.unit_actions.push(UnitActionTuple(UnitAction::UseItem { item: item, }, 0.0));
+		// Using an item is irreversible, so the move can't be taken back anymore.
+		undoable_move.value = None;
+		action_used.value = true;
+	} else {
+		// The server sends `UseItem` back if it accepts it.
+		info!("DEBUG: Sending UseItem message...");
+		send_client_message(&client, &mut network_stats, ClientMessage::UseItem { user: *pos, item: item, });
+		info!("DEBUG: Sent UseItem message.");
+	}
 }
 
-// Client
-fn send_get_client_id_message(client: ResMut<Client>) {
-	info!("DEUBG: Sending GetClientId message...");
-	client
-		.connection()
-		.try_send_message(ClientMessage::GetClientId);
-	info!("DEUBG: Sent GetClientId message.");
+// Prototype
+fn start_choose_attack(
+mut input: ResMut<Input<KeyCode>>,
+unit_query: Query<&UnitClass, With<CurrentUnit>>,
+classes: Res<Classes>,
+action_used: Res<ActionUsed>,
+mut toasts: ResMut<Toasts>,
+mut next_state: ResMut<NextState<TurnState>>,
+) {
+	if input.just_pressed(KeyCode::F) {
+		if action_used.value {
+			toasts.toasts.push(Toast { message: "The unit has already acted this turn.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+			return;
+		}
+		
+		if !unit_query.get_single().map_or(false, |unit_class| classes.allows(&unit_class.value, Ability::Attack)) {
+			toasts.toasts.push(Toast { message: "The unit's class can't attack.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+			return;
+		}
+		
+		info!("DEBUG: Setting TurnState to ChooseAttack...");
+		next_state.set(TurnState::ChooseAttack);
+		info!("DEBUG: Set TurnState to ChooseAttack.");
+	}
 }
 
-// Client
-fn handle_server_messages(
-    mut client: ResMut<Client>,
-    mut events: EventWriter<GameStartEvent>,
-    mut commands: Commands,
-    mut client_data: ResMut<ClientData>,
-    mut game: ResMut<Game>,
-    mut next_state: ResMut<NextState<GameState>>,
+fn choose_attack(mut commands: Commands,
+map_query: Query<&Map>,
+unit_query: Query<(Entity, &Pos, &AttackRange, &AttackType), With<CurrentUnit>>,
+tile_query: Query<&Transform, With<GameText>>,
+asset_server: Res<AssetServer>,
 ) {
-    while let Ok(Some(message)) = client.connection_mut().receive_message::<ServerMessage>() {
-        match message {
-            ServerMessage::StartGame { client_id } => { 
-				info!("DEBUG: Server has sent StartGame message.");
-				
-				
-				// Start game.
-				info!("DEBUG: Starting game...");
-				info!("DEBUG: Setting GameState to Loading...");
-				//commands.insert_resource(NextState(GameState::Loading));
-				next_state.set(GameState::Loading);
-				info!("DEBUG: Set GameState to Loading.");
-				events.send(GameStartEvent);
-				
-				// Set `Game` Resource `is_multiplayer` to true.
-				game.is_multiplayer = true;
-			},
-			ServerMessage::ClientId { client_id } => {
-				// Configure ClientId.
-				client_data.client_id = client_id;
-				info!("DEBUG: Client ID is now {}.", client_data.client_id);
+	let map = &map_query.single().map;
+	let (entity, pos, attack_range, attack_type) = unit_query.single();
+	
+	let possible_attacks = find_possible_attacks(map.to_vec(), *pos, attack_range.value, attack_type.clone());
+	info!("DEBUG: Possible attacks are: {:?}.", possible_attacks);
+	
+	// Spawn the AttackTile indicators.
+	
+	
+	for tile in &possible_attacks {
+	
+		// Compute the indicator position, based on the tile.
+		if let Ok(tile_transform) = tile_query.get(map[tile.x][tile.y].3[map[tile.x][tile.y].3.len() - 1]) {
+			
+			commands.spawn((SpriteBundle {
+				sprite: Sprite {
+					color: Color::rgba(1.0, 0.0, 0.0, 0.5),
+					..default()
+				},
+				texture: asset_server.load("attack_tile.png"),
+				transform: Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y, tile_transform.translation.z + 0.000000025),
+				..default()
 			},
-			_ => { empty_system(); },
-        }
-    }
-}
-
-// Client
-fn send_start_game_message_system(mut input: ResMut<Input<KeyCode>>, client: Res<Client>, mut next_state: ResMut<NextState<GameState>>) {
-	if input.just_pressed(KeyCode::Space) {
-		client
-			.connection()
-			.try_send_message(ClientMessage::StartGame);
-	} else if input.just_pressed(KeyCode::M) {
-		next_state.set(GameState::LoadAmbush);
+			AttackTile {},
+			));
+		}
+		
+		
 	}
+	
+	commands.entity(entity).insert(AttackTiles { attack_tiles: possible_attacks, });
 }
 
-// Client
-fn handle_player_turn_server_message(
-	mut client: ResMut<Client>,
-	mut commands: Commands,
-	client_data: Res<ClientData>,
-	mut units: Query<(Entity, &UnitId, &mut WTCurrent, &mut UnitActions)>,
-//	mut current_unit_query: Query<(Entity, &mut UnitActions), With<CurrentUnit>>,
-	mut game: ResMut<Game>,
-	map_query: Query<&mut Map>,
-	mut next_state: ResMut<NextState<GameState>>,
-	mut next_turn_state: ResMut<NextState<TurnState>>,
-	state: Res<State<GameState>>,
+// Prototype
+fn handle_choose_attack(
+mut commands: Commands,
+map_query: Query<&Map>,
+mut input: ResMut<Input<KeyCode>>,
+mut unit_query: Query<(Entity, &AttackTiles, &Pos, &mut UnitActions), With<CurrentUnit>>,
+team_query: Query<&UnitTeam>,
+cursor_query: Query<&Cursor>,
+attack_tiles_query: Query<Entity, With<AttackTile>>,
+client: Res<Client>,
+game: Res<Game>,
+alliances: Res<Alliances>,
+mut toasts: ResMut<Toasts>,
+mut undoable_move: ResMut<UndoableMove>,
+mut action_used: ResMut<ActionUsed>,
+mut next_state: ResMut<NextState<TurnState>>,
+mut network_stats: ResMut<NetworkStats>,
 ) {
-	let mut map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>> = Vec::new();
-	match state.get() {
-		GameState::MainMenu => {
-			empty_system();
-		},
-		_ => {
-			map = map_query.single().map.clone();
-		}
-	}  
+	let map = &map_query.single().map;
 
-	while let Ok(Some(message)) = client.connection_mut().receive_message::<ServerMessage>() {
-		match message {
-			ServerMessage::PlayerTurn { client_id, current_unit } => {
-				info!("DEBUG: Received PlayerTurn message.");
-				info!("DEBUG: Current state is {:?}.", state.get());
-				// Update Game resouce.
-				info!("DEBUG: Setting current unit to {}.", current_unit);
-				game.current_unit = current_unit;
-				info!("DEBUG: Set current unit to {}.", game.current_unit);
-				
-				// Assign the `CurrentUnit` component to the current unit.
-				for (entity, unit_id, mut current_wt, mut unit_actions) in units.iter_mut() {
-					if unit_id.value == game.current_unit {
-						info!("DEBUG: Inserting `CurrentUnit` component into unit {}.", unit_id.value);
-						commands.entity(entity).insert(CurrentUnit {});
-					}
-				}
-				
-				if client_id == client_data.client_id {
-					// Set state to Battle.
-					info!("DEBUG: Setting GameState to Battle...");
-					//commands.insert_resource(NextState(GameState::Battle));
-					let current_state = state.get();
-					info!("DEBUG: Current state is {:?}.", current_state);
-					//match current_state {
-					//	Some(state) => {
-					//		info!("DEBUG: Current state is {:?}.", state);
-					//	},
-					//	None => {
-					//		info!("DEBUG: No current state.");
-					//	}
-					//}
-					next_state.set(GameState::Battle);
-					info!("DEBUG: Set GameState to Battle.");
-					
-					// Set `TurnState` to `Turn`.
-					info!("DEBUG: Setting TurnState to Turn...");
-					next_turn_state.set(TurnState::Turn);
-					info!("DEBUG: Set TurnState to Turn.");
-				} else {
-					// Set state to Battle.
-					info!("DEBUG: Setting GameState to Battle...");
-					next_state.set(GameState::Battle);
-					info!("DEBUG: Set GameState to Battle.");
-					//info!("DEBUG: Current state is {:?}.", state.get());
-					
-					// Set `TurnState` to `Wait`.
-					info!("DEBUG: Setting TurnState to Wait...");
-					next_turn_state.set(TurnState::Wait);
-					info!("DEBUG: Set TurnState to Wait.");
-				}
-			},
-			ServerMessage::WaitTurn { wait_turns } => {
-				info!("DEBUG: Received WaitTurn message.");
-				
-				// Update unit WTs.
-				for unit_wt in wait_turns {
-					for (entity, unit_id, mut current_wt, mut unit_actions) in units.iter_mut() {
-						if unit_id.value == unit_wt.0.value {
-							current_wt.value = unit_wt.1.value;
-							//break;
-						}
-					}
-				}
-				
-				//// Set state to WaitTurn.
-				//info!("DEBUG: Setting GameState to WaitTurn...");
-				//commands.insert_resource(NextState(GameState::WaitTurn));
-				//info!("DEBUG: Set GameState to WaitTurn.");
-			},
-			ServerMessage::Wait => {
-				// Set state to Wait.
-				info!("DEBUG: Received Wait message.");
+	let cursor = cursor_query.single();
+	let (entity, attack_tiles, pos, mut unit_actions) = unit_query.single_mut();
+
+	if input.just_pressed(KeyCode::F) {
+		// Check if cursor is in a AttackTile.
+		let cursor_pos = Pos { x: cursor.x, y: cursor.y, };
+		if attack_tiles.attack_tiles.contains(&cursor_pos) {
+			
+			if !game.is_multiplayer {
+				// Game is in single-player mode.
+			
+				// Check it there is an unit on the tile.
+				// So that the player won't attack empty tiles.
+				// In the future we will add the option to attack empty tiles.
+				// To be in accordance with Tactics Ogre.
 				
-				// Remove the `CurrentUnit` component from the current unit.
-				for (entity, unit_id, mut current_wt, mut unit_actions) in units.iter_mut() {
-					if unit_id.value == game.current_unit {
-						info!("DEBUG: Removing `CurrentUnit` component from unit.");
-						commands.entity(entity).remove::<CurrentUnit>();
+				// Check if the unit on the tile is allied.
+				let mut target_is_allied = false;
+				if map[cursor.x][cursor.y].2.len() > 0 {
+					if let (Ok(unit_team), Ok(target_team)) = (team_query.get(entity), team_query.get(map[cursor.x][cursor.y].2[0])) {
+						target_is_allied = alliances.stance(unit_team.value, target_team.value) == Stance::Allied;
 					}
 				}
 				
-				info!("DEBUG: Setting GameState to Wait...");
-				//commands.insert_resource(NextState(GameState::Wait));
-				next_state.set(GameState::Wait);
-				info!("DEBUG: Set GameState to Wait.");
-				info!("DEBUG: Current state is {:?}.", state.get());
-				
-				info!("DEBUG: Setting TurnState to Wait...");
-				next_turn_state.set(TurnState::Wait);
-				info!("DEBUG: Set TurnState to Wait.");
-			},
-			ServerMessage::Move { origin, destination } => {
-				info!("DEBUG: Received `Move` message from server.");
-				
-				// Insert `Move` `UnitAction` into current unit.
-				info!("DEBUG: Inserting `Move` `UnitAction` into current unit...");
-				for (entity, unit_id, mut current_wt, mut unit_actions) in units.iter_mut() {
-					if unit_id.value == game.current_unit {
-						unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
-							origin: Pos { x: origin.x, y: origin.y, },
-							destination: Pos { x: destination.x, y: destination.y },
-							timer: Timer::from_seconds(4.0, TimerMode::Once),
-						}, 0.0));
-						info!("DEBUG: Finished inserting `Move` `UnitAction` into current unit.");
+				if target_is_allied {
+					toasts.toasts.push(Toast { message: "You can't attack an allied unit.".to_string(), remaining: TOAST_DURATION_SECONDS, });
+				} else if map[cursor.x][cursor.y].2.len() > 0 {
+					// Remove the AttackTiles
+					for entity in attack_tiles_query.iter() {
+						commands.entity(entity).despawn();
 					}
+					
+					// Insert an `Attack` `UnitAction` towards the target unit.
+					info!("DEBUG: Unit can attack this tile.");
+					info!("DEBUG: Attacking...");
+					unit_actions.unit_actions.push(UnitActionTuple(UnitAction::BasicAttack {
+						target: Pos { x: cursor.x, y: cursor.y, },
+						is_counterattack: false,
+						damage: 0,
+					}, 0.0));
+					
+					// Insert an `Attacker` marker component on the attacking unit.
+					commands.entity(entity).insert(Attacker {});
+					
+					// Insert the `Target` marker component on the target unit.
+					let target_entity = map[cursor.x][cursor.y].2[0];
+					commands.entity(target_entity).insert(Target {});
+									
+					// Remove the AttackTiles component from the unit.
+					commands.entity(entity).remove::<AttackTiles>();
+					
+					// Attacking is irreversible, so the move can't be taken back anymore.
+					undoable_move.value = None;
+					action_used.value = true;
+					
+					// Set State
+					next_state.set(TurnState::Turn);
 				}
-			},
-			ServerMessage::BasicAttack { attacker, target, damage, is_counterattack } => {
-				info!("DEBUG: Received `BasicAttack` message from server.");
+			} else {
+				// Game is in multiplayer mode.
+				info!("DEBUG: Sending BasicAttack message...");
+				send_client_message(&client, &mut network_stats, ClientMessage::BasicAttack {
+					attacker: Pos { x: pos.x, y: pos.y, },
+					target: Pos { x: cursor.x, y: cursor.y, },
+					damage: 0,
+				});
+				info!("DEBUG: Sent BasicAttack message.");
 				
-				// Insert `BasicAttack` `UnitAction` into current unit.
-				info!("DEBUG: Inserting `BasicAttack` `UnitAction` into current unit...");
-				if let Ok((entity, unit_id, mut current_wt, mut unit_actions)) = units.get_mut(map[attacker.x][attacker.y].2[map[attacker.x][attacker.y].2.len() - 1]) {
-					unit_actions.unit_actions.push(UnitActionTuple(UnitAction::BasicAttack {
-						target: Pos { x: target.x, y: target.y, },
-						is_counterattack: is_counterattack,
-						damage: damage.clone(),
-					}, 0.0));
-					info!("DEBUG: Finished inserting `BasicAttack` `UnitAction` into current unit.");
+				// Remove the AttackTiles
+				for entity in attack_tiles_query.iter() {
+					commands.entity(entity).despawn();
 				}
-				
-				// Get the attacker and target entities from map.
-				let entity = map[attacker.x][attacker.y].2[map[attacker.x][attacker.y].2.len() - 1];
-				let target_entity = map[target.x][target.y].2[map[target.x][target.y].2.len() - 1];
-				
-				// Insert an `Attacker` marker component on the attacking unit.
-				commands.entity(entity).insert(Attacker {});
-				
-				// Insert the `Target` marker component on the target unit.
-				commands.entity(target_entity).insert(Target {});
-								
-				// Remove the AttackTiles component from the unit.
-				commands.entity(entity).remove::<AttackTiles>();
-				
-				// Set State
-				next_turn_state.set(TurnState::Turn);
-				
-				
-			},
-			ServerMessage::GameOver { winner } => {
-				info!("DEBUG: Battle is over.");
-				info!("DEBUG: Winner is: {:?}.", winner);
-				
-				info!("DEBUG: Setting GameState to MainMenu...");
-				next_state.set(GameState::MainMenu);
-				info!("DEBUG: Set GameState to MainMenu.");
-				info!("DEBUG: Setting TurnState to Wait...");
-				next_turn_state.set(TurnState::Wait);
-				info!("DEBUG: Set TurnState to Wait.");
-			},
-			_ => { empty_system(); },
+			}
+		}
+	}
+	
+	if input.just_pressed(KeyCode::Escape) {
+		// Remove the AttackTile indicators
+		for entity in attack_tiles_query.iter() {
+			commands.entity(entity).despawn();
 		}
+		
+		// Remove the AttackTiles component from the unit.
+		commands.entity(entity).remove::<AttackTiles>();
+		
+		info!("Setting TurnState back to Turn...");
+		next_state.set(TurnState::Turn);
+		info!("Set TurnState back to Turn.");
 	}
 }
 
-fn handle_wait_turn_server_message(
-	mut client: ResMut<Client>,
-	mut commands: Commands,
-	mut units: Query<(&UnitId, &mut WTCurrent)>, 
+// Prototype
+fn handle_unit_death(
+mut commands: Commands,
+mut map_query: Query<&mut Map>,
+unit_query: Query<(Entity, &UnitId, &Pos, &HPCurrent)>,
+game: Res<Game>,
+mut next_state: ResMut<NextState<TurnState>>,
 ) {
-	while let Ok(Some(message)) = client.connection_mut().receive_message::<ServerMessage>() {
-		match message {
-			ServerMessage::WaitTurn { wait_turns } => {
-				info!("DEBUG: Received WaitTurn message.");
-				
-				// Update unit WTs.
-				for unit_wt in wait_turns {
-					for (unit_id, mut current_wt) in units.iter_mut() {
-						if unit_id.value == unit_wt.0.value {
-							current_wt.value = unit_wt.1.value;
-							break;
-						}
-					}
-				}
-				
-				//// Set state to WaitTurn.
-				//info!("DEBUG: Setting GameState to WaitTurn...");
-				//commands.insert_resource(NextState(GameState::WaitTurn));
-				//info!("DEBUG: Set GameState to WaitTurn.");
-			},
-			_ => { empty_system(); },
+	for (entity, unit_id, pos, hp_current) in unit_query.iter() {
+		if hp_current.value == 0 {
+			// Remove unit.
+			let mut map = &mut map_query.single_mut().map;
+			map[pos.x][pos.y].2.remove(0);
+			
+			info!("DEBUG: Unit {} has died. Removing it...", unit_id.value);
+			commands.entity(entity).despawn();
+			
+			// If that unit has the current turn, end it.
+			if unit_id.value == game.current_unit {
+				// Set TurnState to Wait.
+				info!("DEBUG: Setting TurnState to Wait...");
+				next_state.set(TurnState::Wait);
+				info!("DEBUG: Set TurnState to Wait.");
+			}
 		}
 	}
 }
 
-// Server
-fn cursor_already_spawned(cursors: Query<&Cursor>) -> bool {
-	let mut cursor_spawned = false;
-	for cursor in cursors.iter() {
-		cursor_spawned = true;
+// Prototype
+fn handle_ambush_game_over(
+unit_query: Query<(&UnitTeam, &UnitName, &Pos, &UnitRole)>,
+mut next_state: ResMut<NextState<GameState>>,
+mut next_turn_state: ResMut<NextState<TurnState>>,
+mut game: ResMut<Game>,
+alliances: Res<Alliances>,
+objectives: Res<Objectives>,
+hot_seat: Res<HotSeat>,
+mut battle_result: ResMut<BattleResult>,
+) {
+	// In multiplayer, the server decides when the battle is over.
+	if game.is_multiplayer {
+		return;
 	}
-	return cursor_spawned;
-}
-
-// Client
-fn setup_grid_system(mut commands: Commands) {
-	// Create map.
-	info!("DEBUG: Creating map...");
-	let mut map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>> = Vec::new();
-	for i in 0..20 {
-		let mut map_line: Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)> = Vec::new();
-		for j in 0..20 {
-			map_line.push((1, TileType::Grass, Vec::new(), Vec::new()));
+	
+	let units: Vec<(usize, String, Pos, UnitRole)> = unit_query
+		.iter()
+		.map(|(unit_team, unit_name, pos, role)| (unit_team.value, unit_name.value.clone(), *pos, *role))
+		.collect();
+	// Neutral units don't keep the battle going.
+	let teams_alive: HashSet<usize> = units
+		.iter()
+		.filter(|(_, _, _, role)| *role != UnitRole::Neutral)
+		.map(|(team, _, _, _)| *team)
+		.collect();
+	
+	let mut teams: Vec<usize> = game.players.keys().copied().collect();
+	teams.sort();
+	
+	// The scenario objectives decide first. Otherwise, the battle goes on while
+	// two of the remaining teams are hostile to each other.
+	let (winning_teams, reason): (Vec<usize>, String) = if let Some((victory, objective)) = objectives.outcome(&units, &alliances) {
+		if victory {
+			let winning_teams = teams.iter().copied().filter(|team| alliances.stance(objectives.team, *team) == Stance::Allied).collect();
+			(winning_teams, format!("Objective complete: {}.", objective.describe()))
+		} else {
+			let winning_teams = teams.iter().copied().filter(|team| alliances.stance(objectives.team, *team) == Stance::Hostile).collect();
+			(winning_teams, format!("Defeat condition: {}.", objective.describe()))
 		}
-		map.push(map_line);
-	}
+	} else if !alliances.has_hostile_teams(&teams_alive) {
+		let winning_teams = teams.iter().copied().filter(|team| teams_alive.contains(team)).collect();
+		(winning_teams, String::from("No hostile units remain."))
+	} else {
+		return;
+	};
 	
-	//for i in 0..3 {
-	//	map[i][0].0 = 10;
-	//}
+	let winning_player_teams: Vec<String> = winning_teams
+		.iter()
+		.filter(|team| matches!(game.players.get(team), Some(ControlledBy::Player)))
+		.map(|team| team.to_string())
+		.collect();
 	
-	info!("DEBUG: Created map.");
+	if winning_player_teams.len() > 0 {
+		info!("DEBUG: Game over. Winner is Player.");
+		game.winner = ControlledBy::Player;
+	} else {
+		info!("DEBUG: Game over. Winner is AI.");
+		game.winner = ControlledBy::AI;
+	}
 	
-	commands.spawn((
-		Map { map: map },
-	));
+	if hot_seat.enabled {
+		battle_result.title = String::from("Game over");
+		battle_result.message = match winning_player_teams.len() {
+			0 => format!("Nobody won the battle. {}", reason),
+			1 => format!("Team {} won the battle. {}", winning_player_teams[0], reason),
+			_ => format!("Teams {} won the battle. {}", winning_player_teams.join(", "), reason),
+		};
+	} else if winning_player_teams.len() > 0 {
+		battle_result.title = String::from("Victory");
+		battle_result.message = reason;
+	} else {
+		battle_result.title = String::from("Defeat");
+		battle_result.message = reason;
+	}
+	
+	info!("DEBUG: Setting GameState to GameOver...");
+	next_state.set(GameState::GameOver);
+	info!("DEBUG: Set GameState to GameOver.");
+	info!("DEBUG: Setting TurnState to Wait...");
+	next_turn_state.set(TurnState::Wait);
+	info!("DEBUG: Set TurnState to Wait.");
 }
 
 // Client
-fn setup_camera_system(mut commands: Commands) {
-	info!("DEBUG: Spawning camera...");
-	commands.spawn(Camera2dBundle::default());
-	info!("DEBUG: Spawned camera.");
+fn setup_game_over_screen(
+mut commands: Commands,
+asset_server: Res<AssetServer>,
+battle_result: Res<BattleResult>,
+campaign: Res<Campaign>,
+) {
+	// A campaign battle goes back to the campaign screen.
+	let button_label = if campaign.active { "Continue" } else { "Main menu" };
+	
+	commands.spawn((NodeBundle {
+		style: Style {
+			width: Val::Percent(100.0),
+			height: Val::Percent(100.0),
+			flex_direction: FlexDirection::Column,
+			justify_content: JustifyContent::Center,
+			align_items: AlignItems::Center,
+			row_gap: Val::Px(20.0),
+			..default()
+		},
+		background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+		z_index: ZIndex::Global(10),
+		..default()
+	},
+	GameOverUI {},
+	))
+	.with_children(|parent| {
+		parent.spawn(TextBundle::from_section(
+			battle_result.title.clone(),
+			TextStyle {
+				font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+				font_size: 80.0,
+				color: Color::rgb(0.9, 0.9, 0.9),
+			},
+		));
+		parent.spawn(TextBundle::from_section(
+			battle_result.message.clone(),
+			TextStyle {
+				font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+				font_size: 24.0,
+				color: Color::rgb(0.9, 0.9, 0.9),
+			},
+		));
+		parent
+			.spawn((ButtonBundle {
+				style: Style {
+					width: Val::Percent(20.0),
+					height: Val::Percent(10.0),
+					border: UiRect::all(Val::Px(5.0)),
+					// horizontally center child text
+					justify_content: JustifyContent::Center,
+					// vertically center child text
+					align_items: AlignItems::Center,
+					..default()
+				},
+				border_color: BorderColor(Color::BLACK),
+				background_color: BackgroundColor(Color::BLACK),
+				..default()
+			},
+			ReturnToMainMenuButton {},
+			))
+			.with_children(|parent| {
+				parent.spawn(TextBundle::from_section(
+					button_label,
+					TextStyle {
+						font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+						font_size: 40.0,
+						color: Color::rgb(0.9, 0.9, 0.9),
+					},
+				));
+			});
+	});
 }
 
 // Client
-fn setup_text_system(mut query: Query<&mut Map>, mut commands: Commands, asset_server: Res<AssetServer>) {
-	let font = asset_server.load("fonts/FiraSans-Bold.ttf");
-	let text_style = TextStyle {
-		font,
-		font_size: 20.0,
-		color: Color::WHITE,
-	};
-	let text_alignment = TextAlignment::Center;
-	
-	info!("DEBUG: Spawning tiles...");
-	
-	let mut map = query.single_mut();
-	for i in 0..map.map.len() {
-		for j in 0..map.map[i].len() {
-			//let tile_string: String = map.map[i][j].2.to_string();
-			
-			for k in 0..map.map[i][j].0 {
-				let entity_id = commands.spawn((
-					//Text2dBundle {
-					//	text: Text::from_section(tile_string, text_style.clone()).with_alignment(text_alignment),
-					//	transform: Transform::from_xyz((i as f32) * 256.0 / 2.0 - (j as f32) * 256.0 / 2.0, (i as f32) * 128.0 / 2.0 + (j as f32) * 128.0 / 2.0, 0.0),
-					//	..default()
-					//},
-					SpriteBundle {
-						texture: asset_server.load("tile.png"),
-						transform: Transform::from_xyz((i as f32) * 256.0 / 2.0 - (j as f32) * 256.0 / 2.0, ((i as f32) * 128.0 / 2.0 + (j as f32) * 128.0 / 2.0) * (111.0 / (128.0 / 2.0) - 1.0) + (k as f32) * 30.0, 1.0),
-						..default()
-					},
-					GameText,
-				)).id();
+fn handle_game_over_screen_buttons(
+mut main_menu_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<ReturnToMainMenuButton>)>,
+mut campaign: ResMut<Campaign>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	for interaction in main_menu_button_query.iter() {
+		match *interaction {
+			Interaction::Pressed => {
+				if campaign.active {
+					campaign.active = false;
+					info!("DEBUG: Setting GameState to Campaign...");
+					next_state.set(GameState::Campaign);
+					info!("DEBUG: Set GameState to Campaign.");
+					continue;
+				}
 				
-				map.map[i][j].3.push(entity_id);
-			}
+				info!("DEBUG: Setting GameState to MainMenu...");
+				next_state.set(GameState::MainMenu);
+				info!("DEBUG: Set GameState to MainMenu.");
+			},
+			_ => { empty_system(); },
 		}
 	}
-	
-	info!("DEBUG: Spawned tiles.");
 }
 
 // Client
-fn z_order_system(
-mut query: Query<&mut Transform, With<GameText>>,
-mut unit_query: Query<&mut Transform, (With<Unit>, Without<GameText>)>,
-map_query: Query<&Map>,
+fn objectives_ui(
+mut contexts: EguiContexts,
+objectives: Res<Objectives>,
 ) {
-	//info!("DEBUG: Z-Order system running...");
-	let map = &map_query.single().map;
-	//info!("DEBUG: Map length is: {}.", map.len());
-	
-	let mut counter = 0.0;
-	let mut counter_2 = 0.0;
-	
-	for i in (0..map.len()).rev() {
-		for j in (0..map[i].len()).rev() {
-			counter += 0.00001;
-			
-			//info!("Tile Entity ID is: {:?}.", map[i][j].3);
-			for k in 0..map[i][j].3.len() {
-				counter_2 += 0.0000001;
-				if let Ok(mut tile_transform) = query.get_mut(map[i][j].3[k]) {
-					//info!("DEBUG: Tile position is: {:?}.", tile_transform.translation);
-					tile_transform.translation.z = counter + counter_2;
-				}
+	egui::Window::new("Objectives")
+		.anchor(egui::Align2::LEFT_TOP, egui::vec2(10.0, 10.0))
+		.resizable(false)
+		.show(contexts.ctx_mut(), |ui| {
+			ui.label("Victory:");
+			for objective in &objectives.victory {
+				ui.label(objective_line(&objectives, objective));
 			}
 			
-			// If there is a unit on the tile, order it.
-			if map[i][j].2.len() > 0 {
-				// Order unit.
-				if let Ok(mut unit_transform) = unit_query.get_mut(map[i][j].2[0]) {
-					if let Ok(mut tile_transform) = query.get_mut(map[i][j].3[map[i][j].3.len() - 1]) {
-						//unit_transform.translation.y += 100.0;
-					}
-					//unit_transform.translation.z = counter + counter_2 + 0.0000001;
-				}
+			ui.separator();
+			
+			ui.label("Defeat:");
+			for objective in &objectives.defeat {
+				ui.label(objective_line(&objectives, objective));
 			}
-		}
+		});
+}
+
+// Utility
+fn objective_line(objectives: &Objectives, objective: &Objective) -> String {
+	match objectives.progress(objective) {
+		Some((done, total)) => format!("- {} ({}/{})", objective.describe(), done, total),
+		None => format!("- {}", objective.describe()),
 	}
 }
 
-// Client
-fn z_unit_order_system(
-mut query: Query<&mut Transform, (With<GameText>, Without<Unit>)>,
-mut unit_query: Query<(&mut Transform, &Pos), (With<Unit>, Without<GameText>)>,
-map_query: Query<&Map>,
+// Prototype
+fn handle_ambush_to_main_menu_transition(
+mut commands: Commands,
+mut query: Query<Entity, Without<Window>>,
 ) {
-	let map = &map_query.single().map;
+	for entity in query.iter() {
+		commands.entity(entity).despawn();
+	}
+}
 
-	for (mut unit_transform, pos) in unit_query.iter_mut() {
-		// Get the tile the unit is on.
-		//map[pos.x][pos.y]
-		
-		if let Ok(mut tile_transform) = query.get(map[pos.x][pos.y].3[map[pos.x][pos.y].3.len() - 1]) {
-			unit_transform.translation.z = tile_transform.translation.z + 0.00000001;
+// Client
+fn setup_pass_controls(
+mut commands: Commands,
+asset_server: Res<AssetServer>,
+game: Res<Game>,
+mut hot_seat: ResMut<HotSeat>,
+camera_query: Query<&Transform, With<Camera>>,
+) {
+	// Remember where the previous team left the camera.
+	if let Some(team) = hot_seat.current_team {
+		if let Ok(camera_transform) = camera_query.get_single() {
+			hot_seat.camera_positions.insert(team, camera_transform.translation);
 		}
 	}
+	
+	// Cover the battlefield until the next player has the controls.
+	commands.spawn((NodeBundle {
+		style: Style {
+			width: Val::Percent(100.0),
+			height: Val::Percent(100.0),
+			flex_direction: FlexDirection::Column,
+			justify_content: JustifyContent::Center,
+			align_items: AlignItems::Center,
+			..default()
+		},
+		background_color: BackgroundColor(Color::BLACK),
+		z_index: ZIndex::Global(10),
+		..default()
+	},
+	PassControlsUI {},
+	))
+	.with_children(|parent| {
+		parent.spawn(TextBundle::from_section(
+			format!("Team {}, take the controls.", game.current_team),
+			TextStyle {
+				font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+				font_size: 40.0,
+				color: Color::rgb(0.9, 0.9, 0.9),
+			},
+		));
+		parent.spawn(TextBundle::from_section(
+			"Press Space or click to start your turn.",
+			TextStyle {
+				font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+				font_size: 24.0,
+				color: Color::rgb(0.9, 0.9, 0.9),
+			},
+		));
+	});
 }
 
 // Client
-fn move_camera_system(
-mut camera_transform_query: Query<&mut Transform, With<Camera>>,
-windows: Query<&Window, With<PrimaryWindow>>,
+fn handle_pass_controls(
+mut commands: Commands,
+input: Res<Input<KeyCode>>,
+mouse: Res<Input<MouseButton>>,
+game: Res<Game>,
+mut hot_seat: ResMut<HotSeat>,
+pass_controls_query: Query<Entity, With<PassControlsUI>>,
+mut camera_query: Query<&mut Transform, With<Camera>>,
+mut next_state: ResMut<NextState<TurnState>>,
 ) {
-	let window = windows.single();
+	if !input.just_pressed(KeyCode::Space) && !mouse.just_pressed(MouseButton::Left) {
+		return;
+	}
 	
-	let mut camera_transform = camera_transform_query.single_mut();
+	for entity in pass_controls_query.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
 	
-	if let Some(_position) = window.cursor_position() {
-		// Cursor is inside the window.
-		//info!("DEBUG: Cursor position is: {:?}.", _position);
-		//info!("DEBUG: Window width is: {:?}.", window.width());
-		//info!("DEBUG: Window height is: {:?}.", window.height());
-		
-		if _position.x <= 0.0 + 20.0 {
-			//info!("DEBUG: Camera translation is: {:?}.", camera_transform.translation);
-			camera_transform.translation -= Vec3::X * 3.0;
-		} else if _position.x >= window.width() - 20.0 {
-			camera_transform.translation += Vec3::X * 3.0;
-		}
-		
-		if _position.y <= 0.0 + 20.0 {
-			//info!("DEBUG: Camera translation is: {:?}.", camera_transform.translation);
-			camera_transform.translation += Vec3::Y * 3.0;
-		} else if _position.y >= window.height() - 20.0 {
-			camera_transform.translation -= Vec3::Y * 3.0;
+	// Bring the camera back to where this team left it.
+	if let Some(camera_position) = hot_seat.camera_positions.get(&game.current_team) {
+		if let Ok(mut camera_transform) = camera_query.get_single_mut() {
+			camera_transform.translation = *camera_position;
 		}
-		
-	} else {
-		// Cursor is not inside the window.
 	}
+	hot_seat.current_team = Some(game.current_team);
+	
+	info!("DEBUG: Team {} has the controls.", game.current_team);
+	info!("DEBUG: Setting TurnState to Turn...");
+	next_state.set(TurnState::Turn);
+	info!("DEBUG: Set TurnState to Turn.");
 }
 
 // Prototype
-fn spawn_naked_swordsman(
+fn setup_main_menu(
 mut commands: Commands,
-mut map_query: Query<&mut Map>,
 asset_server: Res<AssetServer>,
 ) {
-	let mut map = &mut map_query.single_mut().map;
-	
-	info!("DEBUG: Spawning Naked Swordsman...");
-	
-	let entity_id = commands.spawn((
-		SpriteBundle {
-			texture: asset_server.load("naked_fanatic_swordsman_east.png"),
-			transform: Transform::from_xyz((4 as f32) * 256.0 / 2.0 - (4 as f32) * 256.0 / 2.0, (4 as f32) * 128.0 / 2.0 + (4 as f32) * 128.0 / 2.0 + (map[4][4].0 as f32) * 15.0 + 100.0, 1.0),
-			..default()
-		},
-		Unit,
-		Pos {
-			x: 4,
-			y: 4,
-		},
-		UnitActions { unit_actions: Default::default(), processing_unit_action: false, },
-		NakedSwordsman {},
-	)).id();
-	
-	map[4][4].2.push(entity_id);
-	
-	info!("DEBUG: Spawned Naked Swordsman.");
+	commands.spawn(Camera2dBundle::default());
+
+//	// Spawn Ars Militaris Logo.
+//	commands.spawn(ImageBundle {
+//		style: Style {
+//			width: Val::Percent(100.0),
+//			height: Val::Percent(70.0),
+//			bottom: Val::Percent(30.0),
+//			..default()
+//		}, 
+//        image: asset_server.load("arsmilitaris_logo.png").into(),
+//        ..default()
+//    });
+
+	 commands
+        .spawn((NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                ..default()
+            },
+            ..default()
+        },
+        MainMenuUI {},
+        ))
+        .with_children(|parent| {
+			parent
+                .spawn(ImageBundle {
+					style: Style {
+						width: Val::Percent(100.0),
+						height: Val::Percent(100.0),
+						bottom: Val::Percent(0.0),
+						..default()
+					}, 
+					image: asset_server.load("arsmilitaris_logo.png").into(),
+					..default()
+                })
+                .with_children(|parent| {
+					parent
+						.spawn((ButtonBundle {
+							style: Style {
+								width: Val::Percent(20.0),
+								height: Val::Percent(10.0),
+								border: UiRect::all(Val::Px(5.0)),
+								//bottom: Val::Percent(10.0),
+								// horizontally center child text
+								justify_content: JustifyContent::Center,
+								// vertically center child text
+								align_items: AlignItems::Center,
+								..default()
+							},
+							border_color: BorderColor(Color::BLACK),
+							background_color: BackgroundColor(Color::BLACK),
+							..default()
+						},
+						StartDemoButton {},
+						))
+						.with_children(|parent| {
+							parent.spawn(TextBundle::from_section(
+								"Demo",
+								TextStyle {
+									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+									font_size: 40.0,
+									color: Color::rgb(0.9, 0.9, 0.9),
+								},
+							));
+						});
+					parent
+						.spawn((ButtonBundle {
+							style: Style {
+								width: Val::Percent(20.0),
+								height: Val::Percent(10.0),
+								border: UiRect::all(Val::Px(5.0)),
+								//bottom: Val::Percent(10.0),
+								// horizontally center child text
+								justify_content: JustifyContent::Center,
+								// vertically center child text
+								align_items: AlignItems::Center,
+								..default()
+							},
+							border_color: BorderColor(Color::BLACK),
+							background_color: BackgroundColor(Color::BLACK),
+							..default()
+						},
+						StartAmbushButton {},
+						))
+						.with_children(|parent| {
+							parent.spawn(TextBundle::from_section(
+								"Ambush",
+								TextStyle {
+									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+									font_size: 40.0,
+									color: Color::rgb(0.9, 0.9, 0.9),
+								},
+							));
+						});
+					parent
+						.spawn((ButtonBundle {
+							style: Style {
+								width: Val::Percent(20.0),
+								height: Val::Percent(10.0),
+								border: UiRect::all(Val::Px(5.0)),
+								//bottom: Val::Percent(10.0),
+								// horizontally center child text
+								justify_content: JustifyContent::Center,
+								// vertically center child text
+								align_items: AlignItems::Center,
+								..default()
+							},
+							border_color: BorderColor(Color::BLACK),
+							background_color: BackgroundColor(Color::BLACK),
+							..default()
+						},
+						StartCampaignButton {},
+						))
+						.with_children(|parent| {
+							parent.spawn(TextBundle::from_section(
+								"Campaign",
+								TextStyle {
+									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+									font_size: 40.0,
+									color: Color::rgb(0.9, 0.9, 0.9),
+								},
+							));
+						});
+					parent
+						.spawn((ButtonBundle {
+							style: Style {
+								width: Val::Percent(20.0),
+								height: Val::Percent(10.0),
+								border: UiRect::all(Val::Px(5.0)),
+								//bottom: Val::Percent(10.0),
+								// horizontally center child text
+								justify_content: JustifyContent::Center,
+								// vertically center child text
+								align_items: AlignItems::Center,
+								..default()
+							},
+							border_color: BorderColor(Color::BLACK),
+							background_color: BackgroundColor(Color::BLACK),
+							..default()
+						},
+						StartMultiplayerButton {},
+						))
+						.with_children(|parent| {
+							parent.spawn(TextBundle::from_section(
+								"Multiplayer",
+								TextStyle {
+									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+									font_size: 40.0,
+									color: Color::rgb(0.9, 0.9, 0.9),
+								},
+							));
+						});
+					parent
+						.spawn((ButtonBundle {
+							style: Style {
+								width: Val::Percent(20.0),
+								height: Val::Percent(10.0),
+								border: UiRect::all(Val::Px(5.0)),
+								//bottom: Val::Percent(10.0),
+								// horizontally center child text
+								justify_content: JustifyContent::Center,
+								// vertically center child text
+								align_items: AlignItems::Center,
+								..default()
+							},
+							border_color: BorderColor(Color::BLACK),
+							background_color: BackgroundColor(Color::BLACK),
+							..default()
+						},
+						StartHotSeatButton {},
+						))
+						.with_children(|parent| {
+							parent.spawn(TextBundle::from_section(
+								"Hot-seat",
+								TextStyle {
+									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+									font_size: 40.0,
+									color: Color::rgb(0.9, 0.9, 0.9),
+								},
+							));
+						});
+					parent
+						.spawn((ButtonBundle {
+							style: Style {
+								width: Val::Percent(20.0),
+								height: Val::Percent(10.0),
+								border: UiRect::all(Val::Px(5.0)),
+								//bottom: Val::Percent(10.0),
+								// horizontally center child text
+								justify_content: JustifyContent::Center,
+								// vertically center child text
+								align_items: AlignItems::Center,
+								..default()
+							},
+							border_color: BorderColor(Color::BLACK),
+							background_color: BackgroundColor(Color::BLACK),
+							..default()
+						},
+						QuitGameButton {},
+						))
+						.with_children(|parent| {
+							parent.spawn(TextBundle::from_section(
+								"Quit",
+								TextStyle {
+									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+									font_size: 40.0,
+									color: Color::rgb(0.9, 0.9, 0.9),
+								},
+							));
+						});
+					});
+       });
 }
 
 // Prototype
-fn move_gaul_warrior(
-mut map_query: Query<&mut Map>,
-mut unit_query: Query<(Entity, &mut Pos, &mut Handle<Image>), With<NakedSwordsman>>,
-mut input: ResMut<Input<KeyCode>>,
-mut asset_server: Res<AssetServer>,
+fn tear_down_main_menu(
+mut commands: Commands,
+main_menu_query: Query<Entity, With<MainMenuUI>>,
+main_menu_camera_query: Query<Entity, With<Camera>>,
 ) {
-	let mut map = &mut map_query.single_mut().map;
+	for entity in main_menu_query.iter() {
+		commands.entity(entity).despawn();
+	}
 	
-	if input.just_pressed(KeyCode::W) {
-		info!("DEBUG: W pressed.");
-		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
-		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
-			if pos.y == (map.len() - 1) {
-				info!("DEBUG: You can't move there.");
-			} else {
-				// Change unit sprite to face north.
-				//info!("DEBUG: Sprite is: {:?}.", sprite);
-				*sprite = asset_server.load("naked_fanatic_swordsman_north.png");
-				map[pos.x][pos.y + 1].2.push(entity_id);
-				map[pos.x][pos.y].2.pop();
-				pos.y += 1;
-			}
+	for camera_entity in main_menu_camera_query.iter() {
+		commands.entity(camera_entity).despawn();
+	}
+}
+
+// Prototype
+fn handle_main_menu_buttons(
+mut commands: Commands,
+mut ambush_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<StartAmbushButton>)>,
+mut multiplayer_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<StartMultiplayerButton>)>,
+mut hot_seat_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<StartHotSeatButton>)>,
+mut campaign_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<StartCampaignButton>)>,
+mut quit_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<QuitGameButton>)>,
+query: Query<Entity>,
+mut next_state: ResMut<NextState<GameState>>,
+mut hot_seat: ResMut<HotSeat>,
+mut game: ResMut<Game>,
+
+) {
+	for interaction in ambush_button_query.iter() {
+		match *interaction {
+			Interaction::Pressed => {
+				*hot_seat = HotSeat::default();
+				game.scenario = SCENARIOS[0].to_string();
+				next_state.set(GameState::LoadAmbush);
+			},
+			_ => { empty_system(); },
 		}
 	}
 	
-	if input.just_pressed(KeyCode::S) {
-		info!("DEBUG: S pressed.");
-		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
-		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
-			if pos.y == 0 {
-				info!("DEBUG: You can't move there.");
-			} else {
-				// Change unit sprite to face south.
-				//info!("DEBUG: Sprite is: {:?}.", sprite);
-				*sprite = asset_server.load("naked_fanatic_swordsman_south.png");
-				map[pos.x][pos.y - 1].2.push(entity_id);
-				map[pos.x][pos.y].2.pop();
-				pos.y -= 1;
-			}
+	for interaction in hot_seat_button_query.iter() {
+		match *interaction {
+			Interaction::Pressed => {
+				*hot_seat = HotSeat { enabled: true, ..default() };
+				game.scenario = SCENARIOS[0].to_string();
+				next_state.set(GameState::LoadAmbush);
+			},
+			_ => { empty_system(); },
 		}
-	} 
+	}
 	
-	if input.just_pressed(KeyCode::D) {
-		info!("DEBUG: D pressed.");
-		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
-		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
-			if pos.x == (map.len() - 1) {
-				info!("DEBUG: You can't move there.");
-			} else {
-				// Change unit sprite to face east.
-				//info!("DEBUG: Sprite is: {:?}.", sprite);
-				*sprite = asset_server.load("naked_fanatic_swordsman_east.png");
-				map[pos.x + 1][pos.y].2.push(entity_id);
-				map[pos.x][pos.y].2.pop();
-				pos.x += 1;
-			}
+	for interaction in campaign_button_query.iter() {
+		match *interaction {
+			Interaction::Pressed => {
+				info!("DEBUG: Setting GameState to Campaign...");
+				next_state.set(GameState::Campaign);
+				info!("DEBUG: Set GameState to Campaign.");
+			},
+			_ => { empty_system(); },
 		}
-	} 
+	}
 	
-	if input.just_pressed(KeyCode::A) {
-		info!("DEBUG: A pressed.");
-		//info!("DEBUG: unit_query length is: {}.", unit_query.iter_mut().len());
-		for (entity_id, mut pos, mut sprite) in unit_query.iter_mut() {
-			if pos.x == 0 {
-				info!("DEBUG: You can't move there.");
-			} else {
-				// Change unit sprite to face south.
-				//info!("DEBUG: Sprite is: {:?}.", sprite);
-				*sprite = asset_server.load("naked_fanatic_swordsman_west.png");
-				map[pos.x - 1][pos.y].2.push(entity_id);
-				map[pos.x][pos.y].2.pop();
-				pos.x -= 1;
-			}
+	for interaction in multiplayer_button_query.iter() {
+		match *interaction {
+			Interaction::Pressed => {
+				next_state.set(GameState::Lobby);
+			},
+			_ => { empty_system(); },
 		}
-	} 
-}
-
-// Client & Server
-fn grid_already_setup(query: Query<&Map>) -> bool {
-	if query.iter().len() == 0 {
-		return false;
-	} else {
-		return true;
 	}
-}
-
-// Client
-fn text_already_setup(query: Query<&GameText>) -> bool {
-	if query.iter().len() == 0 {
-		return false;
-	} else {
-		return true;
+	
+	for interaction in quit_button_query.iter() {
+		match *interaction {
+			Interaction::Pressed => {
+				for entity in query.iter() {
+					commands.entity(entity).despawn();
+				}
+			},
+			_ => { empty_system(); },
+		}
 	}
 }
 
 // Prototype
-fn center_camera_on_unit(
-unit_transform_query: Query<&Transform, With<CurrentUnit>>,
-mut camera_transform_query: Query<&mut Transform, (With<Camera>, Without<CurrentUnit>)>,
+fn setup_lobby(
+mut commands: Commands,
+client: Res<Client>,
+mut lobby: ResMut<Lobby>,
+profile: Res<PlayerProfile>,
+mut network_stats: ResMut<NetworkStats>,
 ) {
-	let unit_transform = unit_transform_query.single();
-	let mut camera_transform = camera_transform_query.single_mut();
+	commands.spawn((Camera2dBundle::default(), LobbyUI {}));
 	
-	camera_transform.translation = Vec3::new(unit_transform.translation.x, unit_transform.translation.y, camera_transform.translation.z);
+	info!("DEBUG: Entering the lobby...");
+	lobby.player_name = profile.name.clone();
+	send_client_message(&client, &mut network_stats, ClientMessage::SetPlayerName { name: lobby.player_name.clone(), });
+	send_client_message(&client, &mut network_stats, ClientMessage::ListGames);
 }
 
-fn test_ortho_projection(
-ortho_projection_query: Query<&OrthographicProjection>,
+// Prototype
+fn tear_down_lobby(
+mut commands: Commands,
+lobby_query: Query<Entity, With<LobbyUI>>,
 ) {
-	let ortho_projection = ortho_projection_query.single();
-	
-	info!("DEBUG: Orthographic projection far is: {:?}.", ortho_projection.far);
+	for entity in lobby_query.iter() {
+		commands.entity(entity).despawn();
+	}
 }
 
-
 // Prototype
-fn process_unit_actions(
-mut commands: Commands,
-mut unit_actions_query: Query<(Entity, &mut UnitActions)>,
-mut map_query: Query<&mut Map>,
-time: Res<Time>,
+fn lobby_ui(
+mut contexts: EguiContexts,
+mut lobby: ResMut<Lobby>,
+mut profile: ResMut<PlayerProfile>,
+args: Res<CliArgs>,
+client: Res<Client>,
+client_data: Res<ClientData>,
+mut next_state: ResMut<NextState<GameState>>,
+mut network_stats: ResMut<NetworkStats>,
 ) {
-	let map = &map_query.single_mut().map;
+	let lobby = &mut *lobby;
 	
-	//info!("DEBUG: unit_actions_query length is: {}.", unit_actions_query.iter().len());
-	
-	for (entity, mut unit_actions) in unit_actions_query.iter_mut() {
+	egui::CentralPanel::default().show(contexts.ctx_mut(), |ui| {
+		ui.heading("Multiplayer Lobby");
 		
-		if unit_actions.unit_actions.len() == 0 {
-			continue;
-		} else if unit_actions.processing_unit_action == true {
+		ui.horizontal(|ui| {
+			ui.label("Name:");
+			ui.text_edit_singleline(&mut lobby.player_name);
+			if ui.button("Set name").clicked() {
+				send_client_message(&client, &mut network_stats, ClientMessage::SetPlayerName { name: lobby.player_name.clone(), });
+				
+				// Remember the name for the next sessions.
+				profile.name = lobby.player_name.clone();
+				save_player_profile(&profile, &args.profile);
+			}
+		});
+		
+		ui.separator();
+		
+		match lobby.current_game.clone() {
+			None => {
+				// List the open games.
+				ui.horizontal(|ui| {
+					ui.heading("Open games");
+					if ui.button("Refresh").clicked() {
+						send_client_message(&client, &mut network_stats, ClientMessage::ListGames);
+					}
+				});
+				
+				if ui.button("Spectate the ongoing battle").clicked() {
+					send_client_message(&client, &mut network_stats, ClientMessage::Spectate);
+				}
+				
+				if lobby.games.len() == 0 {
+					ui.label("No open games.");
+				}
+				for game in &lobby.games {
+					ui.horizontal(|ui| {
+						ui.label(format!("{} ({}, {}/{} players)", game.name, game.scenario, game.players, game.teams));
+						if ui.button("Join").clicked() {
+							send_client_message(&client, &mut network_stats, ClientMessage::JoinGame { game_id: game.game_id, });
+						}
+					});
+				}
+				
+				ui.separator();
+				
+				// Create a new game.
+				ui.heading("Create a game");
+				ui.horizontal(|ui| {
+					ui.label("Name:");
+					ui.text_edit_singleline(&mut lobby.new_game_name);
+				});
+				egui::ComboBox::from_label("Scenario")
+					.selected_text(lobby.new_game_scenario.clone())
+					.show_ui(ui, |ui| {
+						for scenario in SCENARIOS {
+							ui.selectable_value(&mut lobby.new_game_scenario, scenario.to_string(), scenario);
+						}
+					});
+				if ui.button("Create").clicked() {
+					send_client_message(&client, &mut network_stats, ClientMessage::CreateGame {
+						name: lobby.new_game_name.clone(),
+						scenario: lobby.new_game_scenario.clone(),
+					});
+				}
+			},
+			Some(game) => {
+				ui.heading(&game.name);
+				
+				// Only the host can choose the scenario.
+				if game.host == client_data.client_id {
+					let mut scenario = game.scenario.clone();
+					egui::ComboBox::from_label("Scenario")
+						.selected_text(scenario.clone())
+						.show_ui(ui, |ui| {
+							for available_scenario in SCENARIOS {
+								ui.selectable_value(&mut scenario, available_scenario.to_string(), available_scenario);
+							}
+						});
+					if scenario != game.scenario {
+						send_client_message(&client, &mut network_stats, ClientMessage::SetScenario { scenario: scenario, });
+					}
+				} else {
+					ui.label(format!("Scenario: {}", game.scenario));
+				}
+				
+				ui.separator();
+				
+				egui::Grid::new("lobby_players").striped(true).show(ui, |ui| {
+					ui.label("Player");
+					ui.label("Team");
+					ui.label("Status");
+					ui.end_row();
+					
+					for player in &game.players {
+						ui.label(&player.name);
+						ui.label(format!("Team {}", player.team));
+						ui.label(if player.ready { "Ready" } else { "Not ready" });
+						ui.end_row();
+					}
+				});
+				
+				if let Some(player) = game.players.iter().find(|player| player.client_id == client_data.client_id) {
+					ui.horizontal(|ui| {
+						for team in game.teams.iter().copied() {
+							if ui.selectable_label(player.team == team, format!("Team {}", team)).clicked() && player.team != team {
+								send_client_message(&client, &mut network_stats, ClientMessage::SetTeam { team: team, });
+							}
+						}
+						
+						let ready_label = if player.ready { "Not ready" } else { "Ready" };
+						if ui.button(ready_label).clicked() {
+							send_client_message(&client, &mut network_stats, ClientMessage::SetReady { ready: !player.ready, });
+						}
+					});
+				}
+				
+				if let Some(countdown) = game.countdown {
+					ui.heading(format!("Battle starts in {}...", countdown));
+				}
+				
+				if ui.button("Leave game").clicked() {
+					send_client_message(&client, &mut network_stats, ClientMessage::LeaveGame);
+					lobby.current_game = None;
+				}
+			},
+		}
+		
+		ui.separator();
+		
+		if ui.button("Back").clicked() {
+			if lobby.current_game.take().is_some() {
+				send_client_message(&client, &mut network_stats, ClientMessage::LeaveGame);
+			}
+			
+			info!("DEBUG: Setting GameState to MainMenu...");
+			next_state.set(GameState::MainMenu);
+			info!("DEBUG: Set GameState to MainMenu.");
+		}
+	});
+}
+
+// Client
+fn handle_experience_events(
+mut events: EventReader<GainExperienceEvent>,
+mut unit_query: Query<(&UnitName, &UnitClass, &mut Level, &mut Experience, (&mut HPMax, &mut HPCurrent, &mut MPMax, &mut MPCurrent), (&mut STR, &mut VIT, &mut INT, &mut MEN, &mut AGI, &mut DEX, &mut LUK))>,
+classes: Res<Classes>,
+mut toasts: ResMut<Toasts>,
+) {
+	for event in events.iter() {
+		let Ok((unit_name, unit_class, mut level, mut experience, (mut hp_max, mut hp_current, mut mp_max, mut mp_current), (mut str, mut vit, mut int, mut men, mut agi, mut dex, mut luk))) = unit_query.get_mut(event.unit) else {
 			continue;
-		} else if time.elapsed_seconds() < unit_actions.unit_actions[0].1 {
+		};
+		
+		let levels = gain_experience(&mut level.value, &mut experience.value, event.experience);
+		if levels == 0 {
 			continue;
-		} else if unit_actions.unit_actions[0].1 == 0.0 || time.elapsed_seconds() >= unit_actions.unit_actions[0].1 {
-			unit_actions.processing_unit_action = true;
-			
-			let current_unit_action = &unit_actions.unit_actions[0].0;
+		}
 		
-			match current_unit_action {
-				UnitAction::Move { origin, destination, timer, } => {
-					info!("DEBUG: Current unit action is Move.");
-					commands.entity(entity).insert(MoveAction { 
-						origin: origin.clone(),
-						destination: destination.clone(),
-						timer: timer.clone(),
-					});
-				},
-				UnitAction::Talk { message } => {
-					info!("DEBUG: Current unit action is Talk.");
-					commands.entity(entity).insert(TalkAction { message: message.clone(), });
+		let growth = classes.growth(&unit_class.value);
+		hp_max.value += growth.hp * levels;
+		// A unit defeated by a counter-attack stays defeated.
+		if hp_current.value > 0 {
+			hp_current.value += growth.hp * levels;
+		}
+		mp_max.value += growth.mp * levels;
+		mp_current.value += growth.mp * levels;
+		str.value += growth.str * levels;
+		vit.value += growth.vit * levels;
+		int.value += growth.int * levels;
+		men.value += growth.men * levels;
+		agi.value += growth.agi * levels;
+		dex.value += growth.dex * levels;
+		luk.value += growth.luk * levels;
+		
+		info!("DEBUG: {} is now level {}.", unit_name.value, level.value);
+		toasts.toasts.push(Toast { message: format!("{} is now level {}.", unit_name.value, level.value), remaining: TOAST_DURATION_SECONDS, });
+	}
+}
+
+// Client
+fn setup_campaign(
+mut commands: Commands,
+mut campaign: ResMut<Campaign>,
+classes: Res<Classes>,
+mut toasts: ResMut<Toasts>,
+mut next_state: ResMut<NextState<GameState>>,
+) {
+	commands.spawn((Camera2dBundle::default(), CampaignUI {}));
+	
+	info!("DEBUG: Entering the campaign...");
+	let campaign = &mut *campaign;
+	if campaign.definition.is_none() {
+		match load_campaign_definition(CAMPAIGN_FILE) {
+			Ok(definition) => campaign.definition = Some(definition),
+			Err(error) => {
+				info!("DEBUG: {}", error);
+				toasts.toasts.push(Toast { message: error, remaining: TOAST_DURATION_SECONDS, });
+				next_state.set(GameState::MainMenu);
+				return;
+			},
+		}
+	}
+	
+	// Progress made since the game started is already loaded.
+	if campaign.progress.is_none() {
+		let definition = campaign.definition.as_ref().unwrap();
+		let progress = if Path::new(CAMPAIGN_SAVE_PATH).exists() {
+			match read_campaign_progress(CAMPAIGN_SAVE_PATH) {
+				Ok(progress) => check_roster_classes(&progress, &classes).map(|_| progress),
+				Err(error) => {
+					info!("DEBUG: Couldn't read the campaign save: {}.", error);
+					toasts.toasts.push(Toast { message: format!("Couldn't read the campaign save, starting a new campaign: {}.", error), remaining: TOAST_DURATION_SECONDS, });
+					new_campaign_progress(definition, &classes)
 				},
-				UnitAction::BasicAttack { target, is_counterattack, damage } => {
-					info!("DEBUG: Current unit action is BasicAttack.");
-					commands.entity(entity).insert(BasicAttackAction { target: target.clone(), is_counterattack: is_counterattack.clone(), damage: damage.clone(), });
-				}
-				UnitAction::DoNothing => {
-					info!("DEBUG: Current unit action is DoNothing.");
-					commands.entity(entity).insert(DoNothingAction);
-				}
 			}
+		} else {
+			new_campaign_progress(definition, &classes)
+		};
+		match progress {
+			Ok(progress) => campaign.progress = Some(progress),
+			Err(error) => {
+				info!("DEBUG: Couldn't start the campaign: {}", error);
+				toasts.toasts.push(Toast { message: format!("Couldn't start the campaign: {}", error), remaining: TOAST_DURATION_SECONDS, });
+				next_state.set(GameState::MainMenu);
+				return;
+			},
 		}
 	}
+	
+	if let Err(error) = prepare_campaign_battle(campaign) {
+		info!("DEBUG: Couldn't prepare the campaign battle: {}", error);
+		toasts.toasts.push(Toast { message: format!("Couldn't prepare the campaign battle: {}", error), remaining: TOAST_DURATION_SECONDS, });
+	}
 }
 
-// Prototype
-fn process_move_actions(
+// Client
+fn tear_down_campaign(
 mut commands: Commands,
-mut map_query: Query<&mut Map>,
-mut unit_query: Query<(Entity, &mut UnitActions, &mut Pos, &MoveAction, &mut MoveActions)>,
+campaign_query: Query<Entity, With<CampaignUI>>,
+) {
+	for entity in campaign_query.iter() {
+		commands.entity(entity).despawn();
+	}
+}
+
+// Client
+fn campaign_ui(
+mut contexts: EguiContexts,
+mut campaign: ResMut<Campaign>,
+mut game: ResMut<Game>,
+mut hot_seat: ResMut<HotSeat>,
+mut barracks: ResMut<Barracks>,
+classes: Res<Classes>,
+items: Res<Items>,
+mut toasts: ResMut<Toasts>,
 mut next_state: ResMut<NextState<GameState>>,
 ) {
-	let map = &mut map_query.single_mut().map;
+	let campaign = &mut *campaign;
+	let (Some(definition), Some(progress)) = (&campaign.definition, &campaign.progress) else {
+		return;
+	};
+	let next_battle = progress.current_battle.as_ref().and_then(|battle_id| definition.battle(battle_id));
+	let next_scenario = next_battle.map(|battle| battle.scenario.clone());
+	let mut start_battle = false;
+	let mut new_campaign = false;
+	let mut inventory_change: Option<InventoryChange> = None;
 	
-	for (entity, mut unit_actions, mut pos, move_action, mut move_actions) in unit_query.iter_mut() {
-		info!("DEBUG: Processing MoveAction...");
-		info!("DEBUG: Move destination is: {}, {}.", move_action.destination.x, move_action.destination.y);
+	egui::CentralPanel::default().show(contexts.ctx_mut(), |ui| {
+		ui.heading(&definition.name);
 		
-		// Calculate path.
-		let path = find_path(map.to_vec(), move_action.origin, move_action.destination);
-		if let Some(mut path) = path {
-			
-			let origin_backup: Pos = path[0];
-			
-			// Remove the first entry because it is the tile the unit is on.
-			path.remove(0);
+		match next_battle {
+			Some(battle) => {
+				ui.label(format!("Next battle: {}", battle.name));
+				ui.label(format!("Choose up to {} units to deploy ({} chosen).", campaign.deployment_slots, campaign.selected.len()));
+			},
+			None => {
+				ui.label("The campaign is over.");
+			},
+		}
+		
+		ui.separator();
+		
+		ui.heading("Roster");
+		egui::Grid::new("campaign_roster").striped(true).show(ui, |ui| {
+			ui.label("Deploy");
+			ui.label("Name");
+			ui.label("Class");
+			ui.label("Level");
+			ui.label("HP");
+			ui.label("STR");
+			ui.label("VIT");
+			ui.label("AGI");
+			ui.label("DEX");
+			ui.label("Move");
+			ui.label("Battles");
+			ui.label("");
+			ui.end_row();
 			
-			for i in 0..path.len() {
-				info!("DEBUG: Path step is {}, {}.", path[i].x, path[i].y);
-				// Create MoveAction
-				if i == 0 {
-					let new_move_action = MoveAction { origin: origin_backup, destination: path[i], timer: Timer::from_seconds(2.0, TimerMode::Once)};
-					// Add MoveAction to MoveActions component.
-					move_actions.move_actions.push(new_move_action);
+			for roster_unit in &progress.roster {
+				if roster_unit.dead {
+					ui.label("Fallen");
+				} else if campaign.required_units.contains(&roster_unit.name) {
+					// The battle is lost without these units, so they are always deployed.
+					ui.add_enabled(false, egui::Checkbox::new(&mut true, "Required"));
 				} else {
-					let new_move_action = MoveAction { origin: path[i - 1], destination: path[i], timer: Timer::from_seconds(2.0, TimerMode::Once)};
-					// Add MoveAction to MoveActions component.
-					move_actions.move_actions.push(new_move_action);
+					let mut deploy = campaign.selected.contains(&roster_unit.roster_id);
+					let can_change = deploy || campaign.selected.len() < campaign.deployment_slots;
+					if ui.add_enabled(can_change, egui::Checkbox::new(&mut deploy, "")).changed() {
+						if deploy {
+							campaign.selected.push(roster_unit.roster_id);
+						} else {
+							campaign.selected.retain(|roster_id| *roster_id != roster_unit.roster_id);
+						}
+					}
 				}
+				ui.label(&roster_unit.name);
+				ui.label(&roster_unit.class);
+				ui.label(format!("{} ({}/{} XP)", roster_unit.level, roster_unit.experience, XP_PER_LEVEL));
+				ui.label(format!("{}/{}", roster_unit.hp_current, roster_unit.hp_max));
+				ui.label(roster_unit.str.to_string());
+				ui.label(roster_unit.vit.to_string());
+				ui.label(roster_unit.agi.to_string());
+				ui.label(roster_unit.dex.to_string());
+				ui.label(roster_unit.movement_range.to_string());
+				ui.label(roster_unit.battles.to_string());
+				if ui.add_enabled(!roster_unit.dead, egui::Button::new("Equipment")).clicked() {
+					campaign.equipment_unit = Some(roster_unit.roster_id);
+				}
+				ui.end_row();
+			}
+		});
+		
+		let equipment_unit = campaign.equipment_unit.and_then(|roster_id| progress.roster.iter().find(|roster_unit| roster_unit.roster_id == roster_id && !roster_unit.dead));
+		if let Some(roster_unit) = equipment_unit {
+			ui.separator();
+			
+			ui.heading(format!("Equipment of {}", roster_unit.name));
+			egui::Grid::new("campaign_equipment").show(ui, |ui| {
+				for slot in EquipmentSlot::ALL {
+					ui.label(format!("{:?}", slot));
+					let equipped = roster_unit.equipment.slot(slot).clone();
+					egui::ComboBox::from_id_source(format!("campaign_equipment_{:?}", slot))
+						.selected_text(equipped.clone().unwrap_or(String::from("-")))
+						.show_ui(ui, |ui| {
+							if ui.selectable_label(equipped.is_none(), "-").clicked() && equipped.is_some() {
+								inventory_change = Some(InventoryChange::Equip { roster_id: roster_unit.roster_id, slot: slot, item: None, });
+							}
+							for (index, item) in progress.inventory.iter().enumerate() {
+								if items.slot(item) != Some(slot) {
+									continue;
+								}
+								let description = items.get(item).map_or(String::new(), |item| item.describe());
+								if ui.selectable_label(false, item).on_hover_text(description).clicked() {
+									inventory_change = Some(InventoryChange::Equip { roster_id: roster_unit.roster_id, slot: slot, item: Some(index), });
+								}
+							}
+						});
+					ui.label(equipped.as_ref().and_then(|item| items.get(item)).map_or(String::new(), |item| item.describe()));
+					ui.end_row();
+				}
+			});
+			
+			ui.label("Carried items:");
+			for (index, item) in roster_unit.inventory.iter().enumerate() {
+				ui.horizontal(|ui| {
+					ui.label(item);
+					if ui.button("Take back").clicked() {
+						inventory_change = Some(InventoryChange::TakeBack { roster_id: roster_unit.roster_id, item: index, });
+					}
+				});
+			}
+			
+			ui.label("Inventory:");
+			for (index, item) in progress.inventory.iter().enumerate() {
+				ui.horizontal(|ui| {
+					let description = items.get(item).map_or(String::new(), |item| item.describe());
+					ui.label(format!("{} ({})", item, description));
+					if items.consumable(item).is_some() && ui.button("Give").clicked() {
+						inventory_change = Some(InventoryChange::Give { roster_id: roster_unit.roster_id, item: index, });
+					}
+				});
 			}
 		}
 		
-		// Set GameState to Move
-		info!("DEBUG: Setting GameState to Move...");
-		next_state.set(GameState::Move);
-		info!("DEBUG: Set GameState to Move.");
+		if progress.history.len() > 0 {
+			ui.separator();
+			
+			ui.heading("Battles fought");
+			for (battle_id, victory) in &progress.history {
+				let battle_name = definition.battle(battle_id).map_or(battle_id.clone(), |battle| battle.name.clone());
+				ui.label(format!("{}: {}", battle_name, if *victory { "Victory" } else { "Defeat" }));
+			}
+		}
 		
-		break;
+		ui.separator();
+		
+		ui.horizontal(|ui| {
+			if ui.add_enabled(next_battle.is_some() && campaign.selected.len() > 0, egui::Button::new("Start battle")).clicked() {
+				start_battle = true;
+			}
+			if ui.button("Barracks").clicked() {
+				barracks.open = true;
+			}
+			if ui.button("New campaign").clicked() {
+				new_campaign = true;
+			}
+			if ui.button("Main menu").clicked() {
+				info!("DEBUG: Setting GameState to MainMenu...");
+				next_state.set(GameState::MainMenu);
+				info!("DEBUG: Set GameState to MainMenu.");
+			}
+		});
+	});
+	
+	if let Some(inventory_change) = inventory_change {
+		let progress = campaign.progress.as_mut().unwrap();
+		apply_inventory_change(progress, &items, &inventory_change);
+		if let Err(error) = write_campaign_progress(progress, CAMPAIGN_SAVE_PATH) {
+			info!("DEBUG: Couldn't save the campaign: {}.", error);
+			toasts.toasts.push(Toast { message: format!("Couldn't save the campaign: {}.", error), remaining: TOAST_DURATION_SECONDS, });
+		}
+		return;
+	}
+	
+	if new_campaign {
+		info!("DEBUG: Starting a new campaign...");
+		let progress = match new_campaign_progress(definition, &classes) {
+			Ok(progress) => progress,
+			Err(error) => {
+				info!("DEBUG: Couldn't start a new campaign: {}", error);
+				toasts.toasts.push(Toast { message: format!("Couldn't start a new campaign: {}", error), remaining: TOAST_DURATION_SECONDS, });
+				return;
+			},
+		};
+		if let Err(error) = write_campaign_progress(&progress, CAMPAIGN_SAVE_PATH) {
+			info!("DEBUG: Couldn't save the campaign: {}.", error);
+			toasts.toasts.push(Toast { message: format!("Couldn't save the campaign: {}.", error), remaining: TOAST_DURATION_SECONDS, });
+		}
+		campaign.progress = Some(progress);
+		if let Err(error) = prepare_campaign_battle(campaign) {
+			info!("DEBUG: Couldn't prepare the campaign battle: {}", error);
+			toasts.toasts.push(Toast { message: format!("Couldn't prepare the campaign battle: {}", error), remaining: TOAST_DURATION_SECONDS, });
+		}
+		return;
+	}
+	
+	if start_battle {
+		if let Some(scenario) = next_scenario {
+			info!("DEBUG: Starting the campaign battle {}...", scenario);
+			game.scenario = scenario;
+			*hot_seat = HotSeat::default();
+			campaign.active = true;
+			
+			info!("DEBUG: Setting GameState to LoadAmbush...");
+			next_state.set(GameState::LoadAmbush);
+			info!("DEBUG: Set GameState to LoadAmbush.");
+		}
 	}
 }
 
-// Prototype
-fn handle_move_state(
-mut commands: Commands,
-mut map_query: Query<&mut Map>,
-mut unit_query: Query<(Entity, &mut Transform, &mut UnitActions, &mut Pos, &MoveAction, &mut MoveActions, &mut DIR), Without<GameText>>,
-tile_transform_query: Query<&Transform, (With<GameText>, Without<Unit>)>,
-mut next_state: ResMut<NextState<GameState>>,
-mut next_turn_state: ResMut<NextState<TurnState>>,
-game: Res<Game>,
-time: Res<Time>,
+// Client
+fn barracks_ui(
+mut contexts: EguiContexts,
+mut campaign: ResMut<Campaign>,
+mut barracks: ResMut<Barracks>,
+classes: Res<Classes>,
+items: Res<Items>,
+mut toasts: ResMut<Toasts>,
 ) {
-	let mut map_component = map_query.single_mut();
-	let mut map = &mut map_component.map;
-
-	if unit_query.iter_mut().len() == 0 {
-		if !game.is_multiplayer {
-			// Game is in Single-Player mode.
-			info!("DEBUG: No MoveActions remaining. Setting GameState to Ambush.");
-			next_state.set(GameState::Ambush);
-			info!("DEBUG: No MoveActions remaining. Set GameState to Ambush.");
-			// Set TurnState to Turn.
-			info!("DEBUG: Setting TurnState to Turn...");
-			next_turn_state.set(TurnState::Turn);
-			info!("DEBUG: Set TurnState to Turn.");
-		} else {
-			// Game is in Multiplayer mode.
-			info!("DEBUG: No MoveActions remaining. Setting GameState to Battle.");
-			next_state.set(GameState::Battle);
-			info!("DEBUG: No MoveActions remaining. Set GameState to Battle.");
-			// Set TurnState to Turn.
-			info!("DEBUG: Setting TurnState to Turn...");
-			next_turn_state.set(TurnState::Turn);
-			info!("DEBUG: Set TurnState to Turn.");
-		}
-	} else {
+	if !barracks.open {
+		return;
+	}
+	let campaign = &mut *campaign;
+	let (Some(definition), Some(progress)) = (&campaign.definition, &campaign.progress) else {
+		return;
+	};
+	let recruitment = &definition.recruitment;
+	let living_units = progress.roster.iter().filter(|roster_unit| !roster_unit.dead).count();
+	let mut roster_change: Option<RosterChange> = None;
 	
-		for (entity, mut transform, mut unit_actions, mut pos, move_action_component, mut move_actions, mut dir) in unit_query.iter_mut() {
-			
-			if move_actions.move_actions.len() == 0 {
-				// This unit has completed its movement.
-				unit_actions.processing_unit_action = false;
-				unit_actions.unit_actions.remove(0);
-				commands.entity(entity).remove::<MoveAction>();
-				info!("DEBUG: Processed MoveAction.");
+	egui::Window::new("Barracks")
+		.anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+		.resizable(false)
+		.collapsible(false)
+		.show(contexts.ctx_mut(), |ui| {
+			ui.heading("Recruits");
+			if recruitment.classes.is_empty() {
+				ui.label("Nobody can be recruited in this campaign.");
 			} else {
-				let move_action = &move_actions.move_actions[0];
+				ui.label(format!("The roster has {} of {} units.", living_units, recruitment.roster_size));
+				ui.horizontal(|ui| {
+					egui::ComboBox::from_id_source("barracks_recruit_class")
+						.selected_text(barracks.recruit_class.clone().unwrap_or(String::from("Choose a class")))
+						.show_ui(ui, |ui| {
+							for class in &recruitment.classes {
+								ui.selectable_value(&mut barracks.recruit_class, Some(class.clone()), class);
+							}
+						});
+					if ui.add_enabled(barracks.recruit_class.is_some() && living_units < recruitment.roster_size, egui::Button::new("Recruit")).clicked() {
+						roster_change = barracks.recruit_class.clone().map(|class| RosterChange::Recruit { class: class, });
+					}
+				});
+			}
+			
+			ui.separator();
+			
+			ui.heading("Roster");
+			egui::Grid::new("barracks_roster").striped(true).show(ui, |ui| {
+				for roster_unit in &progress.roster {
+					if ui.selectable_label(barracks.unit == Some(roster_unit.roster_id), &roster_unit.name).clicked() {
+						barracks.unit = Some(roster_unit.roster_id);
+						barracks.new_name = roster_unit.name.clone();
+						barracks.confirm_dismiss = false;
+					}
+					ui.label(&roster_unit.class);
+					ui.label(format!("Level {}", roster_unit.level));
+					ui.label(if roster_unit.dead { "Fallen" } else { "" });
+					ui.end_row();
+				}
+			});
+			
+			let selected_unit = barracks.unit.and_then(|roster_id| progress.roster.iter().find(|roster_unit| roster_unit.roster_id == roster_id));
+			if let Some(roster_unit) = selected_unit {
+				ui.separator();
 				
-				if map[move_action.destination.x][move_action.destination.y].2.len() > 0 {
-					info!("DEBUG: Couldn't move unit. There's an unit already there.");
-					unit_actions.processing_unit_action = false;
-					unit_actions.unit_actions.remove(0);
-					commands.entity(entity).remove::<MoveAction>();
-					move_actions.move_actions.truncate(0);
-					info!("DEBUG: Processed MoveAction.");
+				ui.heading(format!("{}, {}", roster_unit.name, roster_unit.class));
+				let bonus = items.bonus(&roster_unit.equipment);
+				// Equipment raises the stats during battles only.
+				let stat = |value: usize, bonus: usize| if bonus > 0 { format!("{} (+{})", value, bonus) } else { value.to_string() };
+				egui::Grid::new("barracks_stat_sheet").show(ui, |ui| {
+					ui.label("Level");
+					ui.label(format!("{} ({}/{} XP)", roster_unit.level, roster_unit.experience, XP_PER_LEVEL));
+					ui.label("Battles");
+					ui.label(roster_unit.battles.to_string());
+					ui.end_row();
+					ui.label("HP");
+					ui.label(format!("{}/{}", roster_unit.hp_current, stat(roster_unit.hp_max, bonus.hp)));
+					ui.label("MP");
+					ui.label(format!("{}/{}", roster_unit.mp_current, stat(roster_unit.mp_max, bonus.mp)));
+					ui.end_row();
+					ui.label("STR");
+					ui.label(stat(roster_unit.str, bonus.str));
+					ui.label("VIT");
+					ui.label(stat(roster_unit.vit, bonus.vit));
+					ui.end_row();
+					ui.label("INT");
+					ui.label(stat(roster_unit.int, bonus.int));
+					ui.label("MEN");
+					ui.label(stat(roster_unit.men, bonus.men));
+					ui.end_row();
+					ui.label("AGI");
+					ui.label(stat(roster_unit.agi, bonus.agi));
+					ui.label("DEX");
+					ui.label(stat(roster_unit.dex, bonus.dex));
+					ui.end_row();
+					ui.label("LUK");
+					ui.label(stat(roster_unit.luk, bonus.luk));
+					ui.label("WT");
+					ui.label(roster_unit.wt_max.to_string());
+					ui.end_row();
+					ui.label("Move");
+					ui.label(roster_unit.movement_range.to_string());
+					ui.label("Jump");
+					ui.label(roster_unit.jump.to_string());
+					ui.end_row();
+					let attack = items.attack(&roster_unit.equipment).map_or((roster_unit.attack_range, roster_unit.attack_type), |attack| (attack.range, attack.attack_type));
+					ui.label("Attack");
+					ui.label(format!("{:?}, range {}", attack.1, attack.0));
+					ui.end_row();
+					for slot in EquipmentSlot::ALL {
+						ui.label(format!("{:?}", slot));
+						ui.label(roster_unit.equipment.slot(slot).clone().unwrap_or(String::from("-")));
+						ui.end_row();
+					}
+					ui.label("Carried");
+					ui.label(if roster_unit.inventory.is_empty() { String::from("-") } else { roster_unit.inventory.join(", ") });
+					ui.end_row();
+				});
+				
+				if campaign.story_units.contains(&roster_unit.name) {
+					ui.label(format!("The campaign can't go on without {}, who can't be renamed or dismissed.", roster_unit.name));
 				} else {
-					if move_action.timer.just_finished() {
-						// Complete processing of MoveAction.
-						
-						info!("DEBUG: Completing processing of MoveAction...");
-						map[move_action.destination.x][move_action.destination.y].2.push(entity);
-						map[pos.x][pos.y].2.pop();
-						
-						pos.x = move_action.destination.x;
-						pos.y = move_action.destination.y;
-						
-						
-						
-						move_actions.move_actions.remove(0);
-						info!("DEBUG: Processed MoveAction.");
-					
-					} else {
-						// Move is still in progress, update the unit's position gradually
-						
-						//info!("DEBUG: Move in progress, updating unit position.");
-						//move_action.timer.tick(time.delta());
-											
-						
-						let start_pos = &move_action.origin;
-						let end_pos = &move_action.destination;
-						
-						// Set the unit's direction.
-						if end_pos.x < start_pos.x {
-							dir.direction = Direction::West;
-						} else if end_pos.x > start_pos.x {
-							dir.direction = Direction::East;
-						}
-						if end_pos.y < start_pos.y {
-							dir.direction = Direction::South;
-						} else if end_pos.y > start_pos.y {
-							dir.direction = Direction::North;
+					ui.horizontal(|ui| {
+						ui.add(egui::TextEdit::singleline(&mut barracks.new_name).char_limit(UNIT_NAME_MAX_LENGTH));
+						if ui.button("Rename").clicked() {
+							roster_change = Some(RosterChange::Rename { roster_id: roster_unit.roster_id, name: barracks.new_name.clone(), });
 						}
-						
-						
-						let progress = move_action.timer.elapsed_secs() / 2.0;
-
-						// Get target tile transform.
-						let target_tile_entity = map[end_pos.x][end_pos.y].3[map[end_pos.x][end_pos.y].3.len() - 1];
-						
-						let target_tile_transform = tile_transform_query.get(target_tile_entity).unwrap();
-						
-						transform.translation.x += ((target_tile_transform.translation.x - transform.translation.x) * progress);
-						transform.translation.y += ((target_tile_transform.translation.y - transform.translation.y  + 100.0) * progress);
-						
-
-
-						
-	//					// To fix bug with z-ordering when the unit is moving.
-	//					// ATTEMPT#1:
-	//					transform.translation.z = target_tile_transform.translation.z + 0.00000001;
-
-						// ATTEMPT#2:
-						// The correct z-order depends on the tile the unit is moving to.
-						if end_pos.x > start_pos.x {
-							
-						} else if end_pos.x < start_pos.x {
-							transform.translation.z = target_tile_transform.translation.z  + 0.00000001;
-						} else if end_pos.y < start_pos.y {
-							transform.translation.z = target_tile_transform.translation.z  + 0.00000001;
-						} else if end_pos.y > start_pos.y {
-						
+					});
+					ui.horizontal(|ui| {
+						if !barracks.confirm_dismiss {
+							if ui.button("Dismiss").clicked() {
+								barracks.confirm_dismiss = true;
+							}
+						} else {
+							ui.label(format!("Dismiss {} for good?", roster_unit.name));
+							if ui.button("Dismiss").clicked() {
+								roster_change = Some(RosterChange::Dismiss { roster_id: roster_unit.roster_id, });
+							}
+							if ui.button("Keep").clicked() {
+								barracks.confirm_dismiss = false;
+							}
 						}
-						
-					}
+					});
 				}
 			}
-		}
+			
+			ui.separator();
+			
+			if ui.button("Close").clicked() {
+				barracks.open = false;
+			}
+		});
+	
+	let Some(roster_change) = roster_change else {
+		return;
+	};
+	let progress = campaign.progress.as_mut().unwrap();
+	match apply_roster_change(progress, definition, &classes, &campaign.story_units, &campaign.reserved_names, &roster_change) {
+		Ok(message) => {
+			info!("DEBUG: {}", message);
+			toasts.toasts.push(Toast { message: message, remaining: TOAST_DURATION_SECONDS, });
+		},
+		Err(message) => {
+			toasts.toasts.push(Toast { message: message, remaining: TOAST_DURATION_SECONDS, });
+			return;
+		},
+	}
+	
+	// A dismissed unit can't be deployed any more.
+	if let RosterChange::Dismiss { roster_id } = roster_change {
+		campaign.selected.retain(|selected| *selected != roster_id);
+		barracks.unit = None;
+		barracks.confirm_dismiss = false;
+	}
+	
+	if let Err(error) = write_campaign_progress(progress, CAMPAIGN_SAVE_PATH) {
+		info!("DEBUG: Couldn't save the campaign: {}.", error);
+		toasts.toasts.push(Toast { message: format!("Couldn't save the campaign: {}.", error), remaining: TOAST_DURATION_SECONDS, });
 	}
 }
 
-// Prototype
-fn tick_move_timer(
-mut move_actions_query: Query<&mut MoveActions, With<MoveAction>>,
-time: Res<Time>
+// Client
+fn leave_campaign(
+mut campaign: ResMut<Campaign>,
 ) {
-	for (mut move_actions) in move_actions_query.iter_mut() {
-		if move_actions.move_actions.len() != 0 {
-			move_actions.move_actions[0].timer.tick(time.delta());
-		}
-	}
+	campaign.active = false;
 }
 
-// Prototype
-fn process_talk_actions(
-mut commands: Commands,
-mut unit_query: Query<(Entity, &mut UnitActions, &TalkAction)>,
-asset_server: Res<AssetServer>,
-mut next_state: ResMut<NextState<GameState>>,
+// Client
+fn finish_campaign_battle(
+unit_query: Query<(&UnitId, &UnitName, &UnitClass, &UnitSprite, (&Level, &Experience), (&WTMax, &HPMax, &HPCurrent, &MPMax, &MPCurrent), (&STR, &VIT, &INT, &MEN, &AGI, &DEX, &LUK), (&MovementRange, &Jump, &AttackRange, &AttackType), (&Equipment, &Inventory))>,
+game: Res<Game>,
+items: Res<Items>,
+mut campaign: ResMut<Campaign>,
+mut battle_result: ResMut<BattleResult>,
+mut toasts: ResMut<Toasts>,
 ) {
+	let campaign = &mut *campaign;
+	let (Some(definition), Some(progress)) = (&campaign.definition, &mut campaign.progress) else {
+		return;
+	};
+	let Some(battle) = progress.current_battle.as_ref().and_then(|battle_id| definition.battle(battle_id)) else {
+		return;
+	};
 	
+	// The units as they are at the end of the battle, with what they gained in it.
+	let units: Vec<(usize, RosterUnit)> = unit_query
+		.iter()
+		.map(|(unit_id, unit_name, unit_class, unit_sprite, (level, experience), (wt_max, hp_max, hp_current, mp_max, mp_current), (str, vit, int, men, agi, dex, luk), (movement_range, jump, attack_range, attack_type), (equipment, inventory))| {
+			let mut roster_unit = RosterUnit {
+				roster_id: 0,
+				name: unit_name.value.clone(),
+				class: unit_class.value.clone(),
+				sprite: unit_sprite.value.clone(),
+				wt_max: wt_max.value,
+				hp_max: hp_max.value,
+				hp_current: hp_current.value,
+				mp_max: mp_max.value,
+				mp_current: mp_current.value,
+				str: str.value,
+				vit: vit.value,
+				int: int.value,
+				men: men.value,
+				agi: agi.value,
+				dex: dex.value,
+				luk: luk.value,
+				level: level.value,
+				experience: experience.value,
+				movement_range: movement_range.value,
+				jump: jump.value,
+				attack_range: attack_range.value,
+				attack_type: *attack_type,
+				equipment: equipment.clone(),
+				inventory: inventory.items.clone(),
+				battles: 0,
+				dead: false,
+			};
+			roster_unit.remove_equipment_bonus(&items);
+			(unit_id.value, roster_unit)
+		})
+		.collect();
+	let victory = matches!(game.winner, ControlledBy::Player);
+	update_roster(progress, &campaign.deployed, &units);
 	
-	for (entity, mut unit_actions, talk_action) in unit_query.iter_mut() {
-		
-		info!("DEBUG: Processing talk action...");
-		
-		info!("DEBUG: Setting GameState to Talk.");
-		next_state.set(GameState::Talk);
-		info!("DEBUG: Set GameState to Talk.");
-		
-		let portrait = asset_server.load("gaul_spearman_portrait.png");
-		
-		commands
-			.spawn((NodeBundle {
-				style: Style {
-					width: Val::Percent(100.0),
-					height: Val::Percent(20.0),
-					position_type: PositionType::Absolute,
-					top: Val::Percent(0.0),
-					..Default::default()
-				},
-				background_color: BackgroundColor(Color::BLACK),
-				..Default::default()
-			},
-			TalkUI {},
-			))
-			.with_children(|parent| {
-			
-				// Add the character portrait
-				parent.spawn((ImageBundle {
-					style: Style {
-						width: Val::Percent(20.0),
-						height: Val::Percent(100.0),
-						..Default::default()
-					},
-					image: UiImage::new(portrait.clone()),
-					..Default::default()
-				}));
-				
-				// Add the message text
-				parent.spawn((TextBundle {
-					text: Text::from_section(
-						talk_action.message.clone(),
-						TextStyle {
-							font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-							font_size: 20.0,
-							color: Color::WHITE,
-						},
-					),
-					..Default::default()
-				}));
-			});
-		
+	progress.history.push((battle.id.clone(), victory));
+	progress.current_battle = if victory { battle.on_victory.clone() } else { battle.on_defeat.clone() };
+	info!("DEBUG: Campaign battle {} is over, next battle is {:?}.", battle.id, progress.current_battle);
+	
+	let next_battle = match progress.current_battle.as_ref().and_then(|battle_id| definition.battle(battle_id)) {
+		Some(next_battle) => format!("Next battle: {}.", next_battle.name),
+		None => String::from("The campaign is over."),
+	};
+	battle_result.message = format!("{} {}", battle_result.message, next_battle);
+	
+	if let Err(error) = write_campaign_progress(progress, CAMPAIGN_SAVE_PATH) {
+		info!("DEBUG: Couldn't save the campaign: {}.", error);
+		toasts.toasts.push(Toast { message: format!("Couldn't save the campaign: {}.", error), remaining: TOAST_DURATION_SECONDS, });
 	}
 }
 
 // Prototype
-fn handle_talk_state(
-mouse_button_input: Res<Input<MouseButton>>,
-mut commands: Commands,
-mut unit_query: Query<(Entity, &mut UnitActions, &TalkAction)>,
-talk_ui_query: Query<(Entity, &TalkUI)>,
-mut next_state: ResMut<NextState<GameState>>,
-) {
-	if mouse_button_input.just_pressed(MouseButton::Left) {
-        info!("DEBUG: Left mouse button just pressed.");
-        
-        for (entity, mut unit_actions, talk_action) in unit_query.iter_mut() {
-			
-			// There should be only one unit with a `TalkAction`
-			
-			// Despawn the TalkUI
-			let (ui_entity, talk_ui) = talk_ui_query.single();
-			commands.entity(ui_entity).despawn();
-			
-			// Process unit action.
-			unit_actions.unit_actions.remove(0);
-			unit_actions.processing_unit_action = false;
-			commands.entity(entity).remove::<TalkAction>();
-			info!("DEBUG: Processed talk action.");
-			
-			// Set GameState back to Ambush.
-			info!("DEBUG: Setting GameState to Ambush.");
-			next_state.set(GameState::Ambush);
-			info!("DEBUG: Set GameState to Ambush.");
-        }
-    }
+fn set_loading_complete(
+mut next_state: ResMut<NextState<GameState>>) {
+	// A battle refused while loading goes back to the main menu instead.
+	if next_state.0.is_some() {
+		return;
+	}
+	info!("DEBUG: Setting GameState to LoadingComplete...");
+	next_state.set(GameState::LoadingComplete);	
+	info!("DEBUG: Set GameState to LoadingComplete.");
 }
 
 // Prototype
-fn process_do_nothing_actions(mut commands: Commands, mut unit_query: Query<(Entity, &mut UnitActions, &DoNothingAction)>) {
-	for (entity, mut unit_actions, do_nothing_action) in unit_query.iter_mut() {
-		info!("DEBUG: Processing DoNothing action...");
-		
-		unit_actions.unit_actions.remove(0);
-		unit_actions.processing_unit_action = false;
-		commands.entity(entity).remove::<DoNothingAction>();
-		
-		info!("DEBUG: Processed DoNothing action.");
-	}	
+fn is_multiplayer(
+game: Res<Game>,
+) -> bool {
+	return game.is_multiplayer;
 }
 
 // Prototype
-fn process_basic_attack_actions(
-mut commands: Commands,
-map_query: Query<&Map>,
-mut attack_unit_query: Query<(Entity, &UnitId, &mut UnitActions, &STR, &Pos, &mut DIR, &BasicAttackAction), (With<Attacker>, Without<Target>)>,
-mut target_unit_query: Query<(&UnitId, &mut UnitActions, &Pos, &mut HPCurrent, &AttackRange, &AttackType), (With<Target>, Without<Attacker>)>,
+fn is_singleplayer(
 game: Res<Game>,
-) {
-	let map = &map_query.single().map;
+) -> bool {
+	return !game.is_multiplayer;
+}
+
+// Prototype
+fn is_spectator(
+game: Res<Game>,
+) -> bool {
+	return game.is_spectator;
+}
+
+// Prototype
+fn is_replay(
+replay_player: Res<ReplayPlayer>,
+) -> bool {
+	return replay_player.replay.is_some();
+}
+
+// Client
+fn is_campaign_battle(
+campaign: Res<Campaign>,
+) -> bool {
+	return campaign.active;
+}
+
+// Utility
+fn find_path(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, start: Pos, destination: Pos, jump: usize) -> Option<Vec<Pos>> {
+    // Define a heuristic function that estimates the distance between two positions.
+    // In this case, we use the Manhattan distance (taxicab distance).
+    let heuristic = |pos: &Pos| -> usize {
+        (pos.x as isize - destination.x as isize).abs() as usize
+            + (pos.y as isize - destination.y as isize).abs() as usize
+    };
+
+	// Use RefCell to wrap the map so that it can be mutated inside the closure.
+    let map_cell = RefCell::new(map);
+
+    // Define a function that returns the valid neighboring positions of a given position.
+    let neighbors = |pos: &Pos| -> Vec<(Pos, usize)> {
+		// Access the map using borrow_mut() to allow mutation.
+		let mut map = map_cell.borrow_mut();
+    
+        // Add logic to get the valid neighboring positions based on your map layout.
+        // For example, avoid diagonal moves and ensure the position is within the map bounds.
+        // For simplicity, let's assume you have a function called `get_valid_neighbors`.
+        get_valid_neighbors((&mut map).to_vec(), *pos, jump)
+    };
+
+    // Use the `astar` function from the pathfinding library to find the path.
+    let result = astar(&start, neighbors, heuristic, |&pos| pos == destination);
+
+    if let Some((path, _)) = result {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Utility
+fn get_valid_neighbors(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, pos: Pos, jump: usize) -> Vec<(Pos, usize)> {
+	let mut neighbors: Vec<(Pos, usize)> = Vec::new(); 
 	
-	//info!("DEBUG: attack_unit_query length is: {}.", attack_unit_query.iter().len());
+	// Check if tile is at North edge.
+	if pos.y == map[0].len() - 1 {
+		// Tile is at North edge. Don't add North neighbor.
+	} else {
+		// Tile is not at North edge. 
+		// Check if there's a unit on the tile, and if the unit can jump to its height.
+		// If there isn't and it can, add North neighbor.
+		if map[pos.x][pos.y + 1].2.len() == 0 && map[pos.x][pos.y + 1].0.abs_diff(map[pos.x][pos.y].0) <= jump {
+			neighbors.push((Pos { x: pos.x, y: pos.y + 1, }, 0));
+		}
+	}
 	
-	for (entity, unit_id, mut unit_actions, str, pos, mut dir, basic_attack_action) in attack_unit_query.iter_mut() {
-		info!("DEBUG: Processing BasicAttack action...");
-		
-		// Get target entity from map.
-		let target_entity = map[basic_attack_action.target.x][basic_attack_action.target.y].2[0];
-		
-		// Get target health.
-		if let Ok((target_id, mut target_unit_actions, target_pos, mut hp_current, attack_range, attack_type)) = target_unit_query.get_mut(target_entity) {
-			// Change attacker's direction to face the target.
-			// Set the unit's direction.
-			if target_pos.x < pos.x {
-				dir.direction = Direction::West;
-			} else if target_pos.x > pos.x {
-				dir.direction = Direction::East;
-			}
-			if target_pos.y < pos.y {
-				dir.direction = Direction::South;
-			} else if target_pos.y > pos.y {
-				dir.direction = Direction::North;
-			}
-			
-			let mut damage: usize = 0;
-			if !game.is_multiplayer {
-				// Compute a random number between -3 to 3.
-				let mut rng = rand::thread_rng();
-				let random_dmg = rng.gen_range(0..7);
-				let random_dmg_modifier = random_dmg - 3;
-				
-				
-				// Add random modifier to damage.
-				// Damage is (STR / 3) + modifier.
-				damage = (str.value / 3) + random_dmg_modifier;
-			} else {
-				damage = basic_attack_action.damage;
-			}
-			
-			
-			// Subtract damage from target HP.
-			if damage > hp_current.value {
-				hp_current.value = 0;
-				
-				// Remove Attacker marker component.
-				commands.entity(entity).remove::<Attacker>();
-			
-				// Remove BasicAttack UnitAction.
-				unit_actions.unit_actions.remove(0);
-				unit_actions.processing_unit_action = false;
-				commands.entity(entity).remove::<BasicAttackAction>();
-				
-				info!("DEBUG: Processed BasicAttack action.");
-				
-				return;
-			} else {
-				hp_current.value -= damage;
-			}
-			
-			info!("DEBUG: Unit {:?} did {:?} damage to unit {:?}.", unit_id, damage, target_id);
-			info!("DEBUG: Unit {} now has {} HP.", target_id.value, hp_current.value);
-			
-			// Remove Target marker component from the target.
-			commands.entity(target_entity).remove::<Target>();
-			
-			if game.is_multiplayer {
-				// Remove Attacker marker component.
-				commands.entity(entity).remove::<Attacker>();
+	// Check if tile is at South edge.
+	if pos.y == 0 {
+		// Tile is at South edge. Don't add South neighbor.
+	} else {
+		// Tile is not at South edge.
+		// Check if there's a unit on the tile, and if the unit can jump to its height.
+		// If there isn't and it can, add South neighbor.
+		if map[pos.x][pos.y - 1].2.len() == 0 && map[pos.x][pos.y - 1].0.abs_diff(map[pos.x][pos.y].0) <= jump {
+			neighbors.push((Pos { x: pos.x, y: pos.y - 1, }, 0));
+		}
+	}
+	
+	// Check if tile is at East edge.
+	if pos.x == map.len() - 1 {
+		// Tile is at East edge. Don't add East neighbor.
+	} else {
+		// Tile is not at East edge.
+		// Check if there's a unit on the tile, and if the unit can jump to its height.
+		// If there isn't and it can, add East neighbor.
+		if map[pos.x + 1][pos.y].2.len() == 0 && map[pos.x + 1][pos.y].0.abs_diff(map[pos.x][pos.y].0) <= jump {
+			neighbors.push((Pos { x: pos.x + 1, y: pos.y, }, 0));
+		}
+	} 
+	
+	// Check if tile is at West edge.
+	if pos.x == 0 {
+		// Tile is at West edge. Don't add West neighbor.
+	} else {
+		// Tile is not at West edge.
+		// Check if there's a unit on the tile, and if the unit can jump to its height.
+		// If there isn't and it can, add West neighbor.
+		if map[pos.x - 1][pos.y].2.len() == 0 && map[pos.x - 1][pos.y].0.abs_diff(map[pos.x][pos.y].0) <= jump {
+			neighbors.push((Pos { x: pos.x - 1, y: pos.y, }, 0));
+		}
+	}
+	
+	return neighbors;
+}
+
+use std::collections::HashSet;
+
+// Prototype
+fn find_possible_movements(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, start: Pos, mut movement_range: isize, jump: usize, zone_of_control: &HashSet<Pos>) -> Vec<Pos> {
+    let mut possible_tiles_vec = Vec::new();
+    movement_range -= 1;
+	
+	let mut visited_tiles = HashSet::new();
+	
+	if movement_range >= 0 {
+		// Get neighbors.
+		let neighbors = get_valid_neighbors(map.clone(), start, jump);
+		for neighbor in &neighbors {
+			if visited_tiles.insert(neighbor.0) {
+				possible_tiles_vec.push(neighbor.0);
 				
-				// Remove BasicAttack UnitAction.
-				unit_actions.unit_actions.remove(0);
-				unit_actions.processing_unit_action = false;
-				commands.entity(entity).remove::<BasicAttackAction>();
+				// Entering a hostile unit's zone of control ends the movement.
+				if zone_of_control.contains(&neighbor.0) {
+					continue;
+				}
 				
-				info!("DEBUG: Processed BasicAttack action.");
+				let mut recursive_possible_tiles = find_possible_movements(map.clone(), neighbor.0, movement_range, jump, zone_of_control);
 				
-				return;
-			}
-			
-			match unit_actions.unit_actions[0].0 {
-				UnitAction::BasicAttack { target, is_counterattack, damage } => {
-					// If it is not already a counter-attack...
-					if !is_counterattack {
-						// If target is not a ranged unit...
-						// Insert a counter-attack.
-						match attack_type {
-							AttackType::Ranged => { 
-								info!("DEBUG: Target is a ranged unit. Won't make a counter-attack.");
-							},
-							AttackType::Melee => {
-								info!("DEBUG: Target is a melee unit. Will make a counter-attack if at range.");
-								let target_possible_attacks = find_possible_attacks(map.to_vec(), *target_pos, attack_range.value, *attack_type);
-								
-								if target_possible_attacks.contains(pos) {
-									// Insert a BasicAttack as a counter-attack.
-									target_unit_actions.unit_actions.push(UnitActionTuple(UnitAction::BasicAttack {
-										target: Pos { x: pos.x, y: pos.y, },
-										is_counterattack: true,
-										damage: 0,
-									}, 0.0));
-									
-									// Insert the Attacker marker component on the counter-attacking unit.
-									commands.entity(target_entity).insert(Attacker {});
-									
-									// Insert the Target marker component on the unit that did the initial attack.
-									// This will result in an infinite Attack and CounterAttack loop for now.
-									commands.entity(entity).insert(Target {});
-								}
-							}
-						}
+				for possible_tile in recursive_possible_tiles {
+					if !possible_tiles_vec.contains(&possible_tile) {
+						possible_tiles_vec.push(possible_tile);
 					}
-				},
-				_ => { empty_system() },
+				}
 			}
-			
 		}
-		
-		// Remove Attacker marker component.
-		commands.entity(entity).remove::<Attacker>();
-	
-		// Remove BasicAttack UnitAction.
-		unit_actions.unit_actions.remove(0);
-		unit_actions.processing_unit_action = false;
-		commands.entity(entity).remove::<BasicAttackAction>();
-		
-		info!("DEBUG: Processed BasicAttack action.");
-	}	
-}
+    }
 
-//// Prototype
-//fn cutscene_1(mut commands: Commands,
-//mut swordsman_query: Query<(Entity, &mut UnitActions), With<NakedSwordsman>>,
-//) {
-//	//info!("DEBUG: Adding third Move UnitAction...");
-//	let (entity, mut unit_actions) = swordsman_query.single_mut();
-//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: Pos { x: 2, y: 2, } }, 0.0));
-//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: Pos { x: 4, y: 2, } }, 4.0));
-//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: Pos { x: 6, y: 2, } }, 8.0));
-//	info!("DEBUG: Added Move UnitActions.");
-//	
-//	unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Talk { message: "Lorem ipsum".to_string() }, 12.0));
-//	info!("DEBUG: Added Talk UnitAction.");
-//}
+    possible_tiles_vec
+}
 
-// Client
-fn spawn_units(
-mut commands: Commands,
-asset_server: Res<AssetServer>,
-mut map_query: Query<&mut Map>,
-tile_transform_query: Query<&Transform, With<GameText>>,
-mut next_state: ResMut<NextState<GameState>>,
-) {
-	info!("DEBUG: Starting to spawn units...");
+// Utility
+fn zone_of_control(map: &Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, hostile_positions: &Vec<Pos>) -> HashSet<Pos> {
+	// Every tile next to a hostile unit is in its zone of control.
+	let mut zone_of_control = HashSet::new();
+	for hostile_position in hostile_positions {
+		for neighbor in get_valid_attack_neighbors(map.clone(), *hostile_position) {
+			zone_of_control.insert(neighbor.0);
+		}
+	}
+	
+	zone_of_control
+}
 
-	let mut map = &mut map_query.single_mut().map;
+// Utility
+/// The tile a fleeing unit moves to: the reachable tile farthest from the closest hostile unit,
+/// if it is farther than the unit's own tile.
+fn flee_destination(map: &Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, pos: Pos, movement_range: isize, jump: usize, hostile_positions: &Vec<Pos>, rules: &ScenarioRules) -> Option<Pos> {
+	// Without hostile units, there is nothing to flee from.
+	if hostile_positions.is_empty() {
+		return None;
+	}
+	
+	let distance_to_hostile_units = |tile: &Pos| hostile_positions
+		.iter()
+		.map(|hostile_pos| tile.x.abs_diff(hostile_pos.x) + tile.y.abs_diff(hostile_pos.y))
+		.min();
+	
+	let zone_of_control = rules.zone_of_control(map, hostile_positions);
+	find_possible_movements(map.to_vec(), pos, movement_range, jump, &zone_of_control)
+		.into_iter()
+		.max_by_key(|tile| distance_to_hostile_units(tile))
+		.filter(|destination| distance_to_hostile_units(destination) > distance_to_hostile_units(&pos))
+}
 
-	let mut rdr = Reader::from_path("src/the_patrol_ambush_data.csv").unwrap();
-	let mut records: Vec<StringRecord> = Vec::new();
-	for result in rdr.records(){
-		let record = result.unwrap();
-		//info!("{:?}", record);
-		records.push(record);
+// Utility
+fn get_valid_attack_neighbors(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, pos: Pos) -> Vec<(Pos, usize)> {
+	let mut neighbors: Vec<(Pos, usize)> = Vec::new(); 
+	
+	// Check if tile is at North edge.
+	if pos.y == map[0].len() - 1 {
+		// Tile is at North edge. Don't add North neighbor.
+	} else {
+		// Tile is not at North edge. 
+		neighbors.push((Pos { x: pos.x, y: pos.y + 1, }, 0));
 	}
 	
-	for record in records {
-		info!("DEBUG: Creating new unit...");
-		let entity_id = commands.spawn((
-			UnitAttributes {
-				unit_id : UnitId { value: record[0].parse().unwrap(), },
-				unit_team : UnitTeam { value: record[1].parse().unwrap(), },
-				unit_name : UnitName { value: record[2].to_string(), },
-				unit_class : UnitClass { value: record[3].to_string(), },
-				pos_x : PosX { value: record[4].parse().unwrap(), }, 
-				pos_y : PosY { value: record[5].parse().unwrap(), },
-				wt_max : WTMax { value: record[6].parse().unwrap(), },
-				wt_current : WTCurrent{ value: record[7].parse().unwrap(), },
-				hp_max : HPMax { value: record[8].parse().unwrap(), },
-				hp_current : HPCurrent { value: record[9].parse().unwrap(), },
-				mp_max : MPMax { value: record[10].parse().unwrap(), },
-				mp_current : MPCurrent { value: record[11].parse().unwrap(), },
-				str : STR { value: record[12].parse().unwrap(), },
-				vit : VIT { value: record[13].parse().unwrap(), },
-				int : INT { value: record[14].parse().unwrap(), },
-				men : MEN { value: record[15].parse().unwrap(), },
-				agi : AGI { value: record[16].parse().unwrap(), },
-				dex : DEX { value: record[17].parse().unwrap(), },
-				luk : LUK { value: record[18].parse().unwrap(), },
-				unit_sprite : UnitSprite { value: record[19].to_string(), },
-				dir: DIR { direction: Direction::from_string(record[20].to_string()), },
-				movement_range: MovementRange { value: record[21].parse().unwrap(), },
-				attack_range: AttackRange { value: record[22].parse().unwrap(), },
-				attack_type: AttackType::from_string(record[23].to_string()),
-			},
-			Unit,
-			UnitActions { unit_actions: Default::default(), processing_unit_action: false, },
-			Pos {
-				x: record[4].parse().unwrap(),
-				y: record[5].parse().unwrap(),
-			},
-			MoveActions { move_actions: Vec::new(), },
-		)).id();
-		
-		let mut path_string: String = record[19].to_string();
-		path_string.push_str("_east.png");
-		
-		// Get tile transform.
-		let tile_entity = map[record[4].parse::<usize>().unwrap()][record[5].parse::<usize>().unwrap()].3[map[record[4].parse::<usize>().unwrap()][record[5].parse::<usize>().unwrap()].3.len() - 1];
-		
-		if let Ok(tile_transform) = tile_transform_query.get(tile_entity) {
-			let unit_transform = Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y + 100.0, tile_transform.translation.z + 0.00000001);
-			
-			commands.entity(entity_id).insert(SpriteBundle {
-						texture: asset_server.load(path_string),
-						transform: unit_transform,
-						..default()
-			},);
-		}
-		
-		
-		map[record[4].parse::<usize>().unwrap()][record[5].parse::<usize>().unwrap()].2.push(entity_id);
-		
-		//info!("DEBUG: Unit transform is: {:?}.", Transform::from_xyz((record[4].parse::<usize>().unwrap() as f32) * 256.0 / 2.0 - (record[5].parse::<usize>().unwrap() as f32) * 256.0 / 2.0, (record[4].parse::<usize>().unwrap() as f32) * 128.0 / 2.0 + (record[5].parse::<usize>().unwrap() as f32) * 128.0 / 2.0 + (map[record[4].parse::<usize>().unwrap() as usize][record[5].parse::<usize>().unwrap() as usize].0 as f32) * 15.0 + 100.0, 1.0));
-		 
-		
-		//* (111.0 / (128.0 / 2.0) - 1.0)
+	// Check if tile is at South edge.
+	if pos.y == 0 {
+		// Tile is at South edge. Don't add South neighbor.
+	} else {
+		// Tile is not at South edge.
+		neighbors.push((Pos { x: pos.x, y: pos.y - 1, }, 0));
 	}
 	
-	info!("DEBUG: Finished spawning units.");
-	info!("DEBUG: Setting GameState to Ambush.");
-	next_state.set(GameState::Ambush);
-	info!("DEBUG: Set GameState to Ambush.");
+	// Check if tile is at East edge.
+	if pos.x == map.len() - 1 {
+		// Tile is at East edge. Don't add East neighbor.
+	} else {
+		// Tile is not at East edge.
+		neighbors.push((Pos { x: pos.x + 1, y: pos.y, }, 0));
+	} 
+	
+	// Check if tile is at West edge.
+	if pos.x == 0 {
+		// Tile is at West edge. Don't add West neighbor.
+	} else {
+		// Tile is not at West edge.
+		neighbors.push((Pos { x: pos.x - 1, y: pos.y, }, 0));
+	}
+	
+	return neighbors;
 }
 
-// Client
-fn loading_complete(client: Res<Client>, mut next_state: ResMut<NextState<GameState>>, state: Res<State<GameState>>) {
-	info!("DEBUG: Sending LoadingComplete message...");
-	client
-		.connection()
-		.try_send_message(ClientMessage::LoadingComplete);
-	info!("DEBUG: Sent LoadingComplete message.");
-	
-	info!("DEBUG: Setting GameState to Wait...");
-	//commands.insert_resource(NextState(GameState::Wait));
-	next_state.set(GameState::Wait);	
-	info!("DEBUG: Set GameState to Wait.");
-	info!("DEBUG: Current state is {:?}.", state.get());
+// Prototype
+fn find_possible_attacks(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, start: Pos, mut attack_range: isize, attack_type: AttackType) -> Vec<Pos> {
+    let mut possible_tiles_vec = Vec::new();
+    
+    match attack_type {
+		AttackType::Melee => {
+			// Get neighbors.
+			let neighbors = get_valid_attack_neighbors(map.clone(), start);
+			for neighbor in &neighbors {
+				possible_tiles_vec.push(neighbor.0);
+			}
+			
+			for neighbor in &neighbors {
+				for i in 1..attack_range {
+					// If there is an unit on the tile, don't search for more neighbors.
+					if map[neighbor.0.x][neighbor.0.y].2.len() > 0 {
+						break;
+					}
+					// Else...
+					// Add next neighbor.
+					let neighbors = get_valid_attack_neighbors(map.clone(), neighbor.0);
+					for neighbor in neighbors {
+						if (neighbor.0.x == start.x || neighbor.0.y == start.y) {
+							if !(neighbor.0.x == start.x && neighbor.0.y == start.y) {
+								if !(possible_tiles_vec.contains(&neighbor.0)) {
+									possible_tiles_vec.push(neighbor.0);
+								}
+							}
+						}
+					}
+				}			
+			}
+		},
+		AttackType::Ranged => {
+			attack_range -= 1;
+			
+			let mut visited_tiles = HashSet::new();
+			
+			if attack_range >= 0 {
+				// Get neighbors.
+				let neighbors = get_valid_attack_neighbors(map.clone(), start);
+				for neighbor in &neighbors {
+					if visited_tiles.insert(neighbor.0) {
+						possible_tiles_vec.push(neighbor.0);
+						let mut recursive_possible_tiles = find_possible_attacks(map.clone(), neighbor.0, attack_range, attack_type);
+						
+						for possible_tile in recursive_possible_tiles {
+							if !possible_tiles_vec.contains(&possible_tile) {
+								possible_tiles_vec.push(possible_tile);
+							}
+						}
+					}
+				}
+			}
+		},
+		_ => {
+			empty_system();
+		},
+    }
+    
+    
+
+    possible_tiles_vec
 }
 
-fn current_state(state: Res<State<GameState>>) {
-	info!("DEBUG: Current state is: {:?}.", state.get());
+// Server
+fn add_local_server(app: &mut App) {
+	app.add_plugin(QuinnetServerPlugin::default());
+	app.init_resource::<LocalServer>();
+	app.add_systems(Startup, start_local_server);
+	app.add_systems(Update, (handle_local_server_disconnections, handle_local_server_messages, tick_local_server_lobby_countdowns, tick_local_server_turn_timer).chain());
 }
 
-fn current_state2(state: Res<State<GameState>>) {
-	info!("DEBUG: Current state is: {:?}.", state.get());
+// Server
+fn run_dedicated_server(args: CliArgs) {
+	let mut app = App::new();
+	app.add_plugins(
+		MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)))
+	);
+	app.add_plugins(LogPlugin::default());
+	app.insert_resource(args);
+	let (classes, items) = load_game_data().unwrap_or_else(|error| {
+		error!("Invalid game data: {}", error);
+		std::process::exit(1);
+	});
+	app.insert_resource(classes);
+	app.insert_resource(items);
+	add_local_server(&mut app);
+	app.run();
 }
 
-fn change_state(mut next_state: ResMut<NextState<GameState>>, mut input: ResMut<Input<KeyCode>>) {
-
-	if input.just_pressed(KeyCode::Space) {
-
-		next_state.set(GameState::Wait);
-	}
+// Server
+fn start_local_server(mut server: ResMut<Server>, mut local_server: ResMut<LocalServer>, args: Res<CliArgs>, classes: Res<Classes>, items: Res<Items>) {
+	info!("DEBUG: Starting local server on port {}...", args.port);
+	local_server.classes = classes.clone();
+	local_server.items = items.clone();
+	local_server.turn_time_limit = args.turn_time_limit as f32;
+	local_server.turn_time_carry_over = args.turn_time_carry_over;
+	let certificate = server
+		.start_endpoint(
+			ServerConfiguration::from_string(&format!("0.0.0.0:{}", args.port)).unwrap(),
+			CertificateRetrievalMode::GenerateSelfSigned {
+				server_hostname: "127.0.0.1".to_string(),
+			},
+		)
+		.unwrap();
+	local_server.certificate_fingerprint = Some(certificate.fingerprint.to_base64());
+	info!("DEBUG: Started local server with certificate fingerprint {}.", certificate.fingerprint.to_base64());
 }
 
-// Test
-fn get_toggle_console_key(console_config: Res<ConsoleConfiguration>) {
-	for key in &console_config.keys {
+// Server
+fn handle_local_server_disconnections(
+mut events: EventReader<ConnectionLostEvent>,
+mut server: ResMut<Server>,
+mut local_server: ResMut<LocalServer>,
+) {
+	let endpoint = server.endpoint_mut();
+	
+	for event in events.iter() {
+		info!("DEBUG: Client {} disconnected from the local server.", event.id);
+		local_server.waiting_clients.retain(|client_id| *client_id != event.id);
+		leave_lobby_game(endpoint, &mut local_server, event.id);
+		local_server.player_names.remove(&event.id);
+		let player_id = local_server.client_players.remove(&event.id);
+		local_server.spectators.remove(&event.id);
+		local_server.loading_complete.remove(&event.id);
 		
-		match key {
-			ToggleConsoleKey::KeyCode(key_code) => {
-				info!("Console toggle key is: {:?}.", key_code);
+		let Some(team) = local_server.teams.remove(&event.id) else {
+			continue;
+		};
+		if local_server.teams.is_empty() {
+			info!("DEBUG: Every player left the battle. Ending it...");
+			end_local_server_game(endpoint, &mut local_server, &HashSet::new());
+			continue;
+		}
+		
+		// The player keeps their seat, and takes it back by identifying again.
+		info!("DEBUG: A player left the battle. Keeping team {} for them...", team);
+		if let Some(player_id) = player_id {
+			local_server.empty_seats.insert(player_id, team);
+		}
+		send_battle_message(endpoint, &local_server, ServerMessage::Players { players: battle_players(&local_server), });
+		
+		// Meanwhile, the team's units wait.
+		match local_server.current_unit {
+			Some(current_unit) if local_server.units[current_unit].unit_team == team => {
+				end_local_server_turn(endpoint, &mut local_server, current_unit);
 			},
-			_ => { empty_system(); },
+			Some(_) => {},
+			None => start_local_server_battle(endpoint, &mut local_server),
 		}
 	}
 }
 
-// Test
-fn do_nothing_command(mut log: ConsoleCommand<DoNothingCommand>, mut unit_actions_query: Query<&mut UnitActions>) {
-    if let Some(Ok(do_nothing_command)) = log.take() {
-        // handle command
-        for mut unit_actions in unit_actions_query.iter_mut() {
-			unit_actions.unit_actions.push(UnitActionTuple::default());
-			info!("DEBUG: Added DoNothing UnitAction.");
-        }
-    }
-}
-
-// Prototype
-fn talk_command(mut log: ConsoleCommand<TalkCommand>, mut unit_query: Query<(Entity, &UnitId, &mut UnitActions)>) {
-	if let Some(Ok(TalkCommand { unit_id, msg })) = log.take() {
-        // handle command
-        // Find unit with ID = unit_id.
-        for (entity_id, unit_id2, mut unit_actions) in unit_query.iter_mut() {
-			if unit_id2.value == unit_id {
-				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Talk { message: msg.clone() }, 0.0));
-			}
-        }
-    }
-}
-
-// Prototype
-fn move_command(mut log: ConsoleCommand<MoveCommand>, mut unit_query: Query<(Entity, &UnitId, &mut UnitActions, &Pos)>) {
-	if let Some(Ok(MoveCommand { unit_id, x, y })) = log.take() {
-        // handle command
-        // Find unit with ID = unit_id.
-        for (entity_id, unit_id2, mut unit_actions, pos) in unit_query.iter_mut() {
-			if unit_id2.value == unit_id {
-				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
-					origin: Pos { x: pos.x, y: pos.y, },
-					destination: Pos { x: x, y: y },
-					timer: Timer::from_seconds(4.0, TimerMode::Once),
-				}, 0.0));
-			}
-        }
-    }
-}
-
-// Prototype
-fn ars_militaris_demo(
-mut map_query: Query<&mut Map>,
-mut units_query: Query<(Entity, &UnitId, &mut UnitActions, &Pos)>,
-mut target_units_query: Query<(Entity, &Pos), With<Unit>>,
-mut demo_data: ResMut<DemoData>,
-time: Res<Time>,
+// Server
+fn handle_local_server_messages(
+mut server: ResMut<Server>,
+mut local_server: ResMut<LocalServer>,
 ) {
-	let map = &map_query.single().map;
-	
-//	let mut rng = rand::thread_rng();
-//	// Compute a random unit.
-//	let random_unit_id = rng.gen_range(0..units_query.iter().len());
-//	
-//	// Find a random unit.
-//	for (entity, unit_id, mut unit_actions, pos) in units_query.iter_mut() {
-//		if (unit_id.value - 1) == random_unit_id {
-//			// Find another unit.
-//			for (target_entity, target_pos) in target_units_query.iter_mut() {
-//				if target_entity != entity {
-//					// Compute the new target position.
-//					let target_pos = Pos { x: pos.x + 1, y: pos.y, };
-//					
-//					// Insert a random Move UnitAction on the first unit.
-//					let mut rng2 = rand::thread_rng();
-//					let random_usize: usize = rng2.gen_range(0..map.len());
-//					
-//					let mut rng3 = rand::thread_rng();
-//					let random_usize2: usize = rng3.gen_range(0..map[0].len());
-//					
-//					// Compute a move of a single tile in a random direction
-//										
-//					unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
-//						origin: Pos { x: pos.x, y: pos.y, },
-//						destination: Pos { x: target_pos.x, y: target_pos.y, },
-//						timer: Timer::from_seconds(4.0, TimerMode::Once),
-//					}, (time.elapsed().as_secs_f32()) + 6.0));
-//					
-//	//				// Insert a Move UnitAction on the first unit to move towards the target unit.
-//	//				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move { destination: target_pos, }, 0.0));
-//					break;
-//				} else {
-//					continue;
-//				}			
-//			}
-//		} else {
-//			continue;
-//		}
-//		break;
-//	}
+	let endpoint = server.endpoint_mut();
 	
-//	// Find a unit with UnitId 1.
-//	for (entity, unit_id, mut unit_actions, pos) in units_query.iter_mut() {
-//		if unit_id.value == 1 {
-//			// Move 1 tile forward.
-//			unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
-//						origin: Pos { x: pos.x, y: pos.y, },
-//						destination: Pos { x: pos.x + 1, y: pos.y, },
-//						timer: Timer::from_seconds(4.0, TimerMode::Once),
-//					}, (time.elapsed().as_secs_f32()) + 6.0));
-//			
-//		} else {
-//			continue;
-//		}
-//		break;
-//	}
-
-	// Get the current unit and make a `Move` `UnitAction` towards its target.
-	for (entity, unit_id, mut unit_actions, pos) in units_query.iter_mut() {
-		if unit_id.value == demo_data.current_unit.value {
-			unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
-				origin: Pos { x: pos.x, y: pos.y, },
-				destination: Pos { x: pos.x + 1, y: pos.y, },
-				timer: Timer::from_seconds(4.0, TimerMode::Once),
-			}, (time.elapsed().as_secs_f32()) + 6.0));
-			
-			// Set the next current unit.
-			if demo_data.current_unit.value == units_query.iter_mut().len() {
-				demo_data.current_unit.value = 1;
-			} else {
-				demo_data.current_unit.value += 1;
-			}
-		} else {
-			continue;
+	while let Ok(Some((client_id, message))) = endpoint.receive_message::<ClientMessage>() {
+		match message {
+			ClientMessage::GetClientId => {
+				info!("DEBUG: Client {} asked for its client ID.", client_id);
+				endpoint.try_send_message(client_id, ServerMessage::ClientId { client_id: client_id, });
+			},
+			ClientMessage::Identify { player_id, name, token } => {
+				if local_server.client_players.iter().any(|(other_client, other_player)| *other_player == player_id && *other_client != client_id) {
+					info!("DEBUG: Player {} is already connected. Rejecting client {}.", player_id, client_id);
+					send_error(endpoint, client_id, ErrorCode::AlreadyConnected, "This player is already connected.");
+					continue;
+				}
+				
+				// The first token a player identifies with is the one it has to use from then on.
+				let known_token = local_server.known_players.get(&player_id).and_then(|known_player| known_player.token.clone());
+				if known_token.is_some() && known_token != token {
+					info!("DEBUG: Client {} sent the wrong token for player {}.", client_id, player_id);
+					send_error(endpoint, client_id, ErrorCode::AuthenticationFailed, "The token doesn't match this player.");
+					continue;
+				}
+				
+				let name = sanitize_player_name(&name);
+				if local_server.known_players.contains_key(&player_id) {
+					info!("DEBUG: Player {} ({}) reconnected as client {}.", player_id, name, client_id);
+				} else {
+					info!("DEBUG: Client {} is player {} ({}).", client_id, player_id, name);
+				}
+				local_server.known_players.insert(player_id, KnownPlayer { name: name.clone(), token: known_token.or(token), });
+				local_server.client_players.insert(client_id, player_id);
+				local_server.player_names.insert(client_id, name);
+				
+				// A player coming back to the battle they left takes their seat again.
+				if let Some(team) = local_server.empty_seats.remove(&player_id) {
+					info!("DEBUG: Player {} takes back their seat on team {}.", player_id, team);
+					leave_lobby_game(endpoint, &mut local_server, client_id);
+					local_server.waiting_clients.retain(|waiting_client| *waiting_client != client_id);
+					local_server.teams.insert(client_id, team);
+					send_battle_message(endpoint, &local_server, ServerMessage::Players { players: battle_players(&local_server), });
+					endpoint.try_send_message(client_id, ServerMessage::Snapshot { scenario: local_server.scenario.clone(), snapshot: battle_snapshot(&local_server), });
+				}
+			},
+			ClientMessage::StartGame => {
+				if local_server.in_game {
+					info!("DEBUG: A battle is already in progress. Client {} has to wait.", client_id);
+					send_error(endpoint, client_id, ErrorCode::BattleInProgress, "A battle is already in progress. Try again later.");
+					continue;
+				}
+				
+				if !local_server.waiting_clients.contains(&client_id) {
+					local_server.waiting_clients.push(client_id);
+				}
+				info!("DEBUG: Client {} is waiting for a game. There are {} clients waiting.", client_id, local_server.waiting_clients.len());
+				
+				if local_server.waiting_clients.len() >= 2 {
+					// The first waiting client plays team 1, the second one team 2.
+					let players: Vec<(ClientId, usize)> = local_server.waiting_clients
+						.drain(0..2)
+						.enumerate()
+						.map(|(i, client_id)| (client_id, i + 1))
+						.collect();
+					start_local_server_game(endpoint, &mut local_server, players, SCENARIOS[0]);
+				}
+			},
+			ClientMessage::SetPlayerName { name } => {
+				let name = sanitize_player_name(&name);
+				info!("DEBUG: Client {} is now named {}.", client_id, name);
+				local_server.player_names.insert(client_id, name.clone());
+				
+				if let Some(game_index) = lobby_game_index_for_client(&local_server, client_id) {
+					let game = &mut local_server.lobby_games[game_index];
+					for player in game.players.iter_mut() {
+						if player.client_id == client_id {
+							player.name = name.clone();
+						}
+					}
+					send_lobby_update(endpoint, game);
+				}
+			},
+			ClientMessage::ListGames => {
+				let games = local_server.lobby_games
+					.iter()
+					.map(|game| LobbyGameInfo {
+						game_id: game.game_id,
+						name: game.name.clone(),
+						scenario: game.scenario.clone(),
+						players: game.players.len(),
+						teams: game.teams.len(),
+					})
+					.collect();
+				endpoint.try_send_message(client_id, ServerMessage::GameList { games: games, });
+			},
+			ClientMessage::CreateGame { name, scenario } => {
+				if !SCENARIOS.contains(&scenario.as_str()) {
+					info!("DEBUG: Client {} asked for unknown scenario {}. Rejecting it.", client_id, scenario);
+					send_error(endpoint, client_id, ErrorCode::UnknownScenario, "That scenario doesn't exist.");
+					continue;
+				}
+				
+				leave_lobby_game(endpoint, &mut local_server, client_id);
+				
+				let teams = scenario_teams(&scenario);
+				if teams.is_empty() {
+					info!("DEBUG: Scenario {} has no team to play. Rejecting it.", scenario);
+					send_error(endpoint, client_id, ErrorCode::UnknownScenario, "That scenario can't be played.");
+					continue;
+				}
+				
+				let game = LobbyGame {
+					game_id: local_server.next_game_id,
+					name: name,
+					scenario: scenario,
+					host: client_id,
+					players: vec![LobbyPlayer {
+						client_id: client_id,
+						name: player_name(&local_server, client_id),
+						team: teams[0],
+						ready: false,
+					}],
+					teams: teams,
+					countdown: None,
+				};
+				local_server.next_game_id += 1;
+				
+				info!("DEBUG: Client {} created game {}.", client_id, game.game_id);
+				send_lobby_update(endpoint, &game);
+				local_server.lobby_games.push(game);
+			},
+			ClientMessage::JoinGame { game_id } => {
+				let Some(game_index) = local_server.lobby_games.iter().position(|game| game.game_id == game_id) else {
+					info!("DEBUG: Client {} tried to join unknown game {}. Rejecting it.", client_id, game_id);
+					send_error(endpoint, client_id, ErrorCode::UnknownGame, "That game doesn't exist anymore.");
+					continue;
+				};
+				if local_server.lobby_games[game_index].players.iter().any(|player| player.client_id == client_id) {
+					continue;
+				}
+				
+				leave_lobby_game(endpoint, &mut local_server, client_id);
+				
+				// Leaving may have removed an empty game, so the game is looked up again.
+				let Some(game_index) = local_server.lobby_games.iter().position(|game| game.game_id == game_id) else {
+					continue;
+				};
+				let name = player_name(&local_server, client_id);
+				let game = &mut local_server.lobby_games[game_index];
+				
+				// Join the team with the fewest players.
+				let team = game.teams
+					.iter()
+					.copied()
+					.min_by_key(|team| game.players.iter().filter(|player| player.team == *team).count())
+					.unwrap_or(1);
+				
+				game.players.push(LobbyPlayer {
+					client_id: client_id,
+					name: name,
+					team: team,
+					ready: false,
+				});
+				game.countdown = None;
+				
+				info!("DEBUG: Client {} joined game {}.", client_id, game_id);
+				send_lobby_update(endpoint, game);
+			},
+			ClientMessage::LeaveGame => {
+				leave_lobby_game(endpoint, &mut local_server, client_id);
+			},
+			ClientMessage::SetReady { ready } => {
+				if let Some(game_index) = lobby_game_index_for_client(&local_server, client_id) {
+					let game = &mut local_server.lobby_games[game_index];
+					for player in game.players.iter_mut() {
+						if player.client_id == client_id {
+							player.ready = ready;
+						}
+					}
+					update_lobby_countdown(game);
+					send_lobby_update(endpoint, game);
+				}
+			},
+			ClientMessage::SetTeam { team } => {
+				if let Some(game_index) = lobby_game_index_for_client(&local_server, client_id) {
+					let game = &mut local_server.lobby_games[game_index];
+					if !game.teams.contains(&team) {
+						info!("DEBUG: Client {} asked for invalid team {}. Rejecting it.", client_id, team);
+						send_error(endpoint, client_id, ErrorCode::InvalidTeam, "That team doesn't exist.");
+						continue;
+					}
+					
+					for player in game.players.iter_mut() {
+						if player.client_id == client_id {
+							player.team = team;
+							player.ready = false;
+						}
+					}
+					update_lobby_countdown(game);
+					send_lobby_update(endpoint, game);
+				}
+			},
+			ClientMessage::SetScenario { scenario } => {
+				if !SCENARIOS.contains(&scenario.as_str()) {
+					info!("DEBUG: Client {} asked for unknown scenario {}. Rejecting it.", client_id, scenario);
+					send_error(endpoint, client_id, ErrorCode::UnknownScenario, "That scenario doesn't exist.");
+					continue;
+				}
+				
+				if let Some(game_index) = lobby_game_index_for_client(&local_server, client_id) {
+					let game = &mut local_server.lobby_games[game_index];
+					if game.host != client_id {
+						info!("DEBUG: Only the host can change the scenario.");
+						send_error(endpoint, client_id, ErrorCode::NotHost, "Only the host can change the scenario.");
+						continue;
+					}
+					
+					let teams = scenario_teams(&scenario);
+					if teams.is_empty() {
+						info!("DEBUG: Scenario {} has no team to play. Rejecting it.", scenario);
+						send_error(endpoint, client_id, ErrorCode::UnknownScenario, "That scenario can't be played.");
+						continue;
+					}
+					
+					// Everyone has to agree on the new scenario.
+					// Players on a team the new scenario doesn't have move to its first team.
+					game.teams = teams;
+					game.scenario = scenario;
+					for player in game.players.iter_mut() {
+						player.ready = false;
+						if !game.teams.contains(&player.team) {
+							player.team = game.teams[0];
+						}
+					}
+					update_lobby_countdown(game);
+					send_lobby_update(endpoint, game);
+				}
+			},
+			ClientMessage::Chat { scope, message } => {
+				let Some(message) = filter_chat_message(&message) else {
+					continue;
+				};
+				
+				// Chat goes to the other players of the battle, or of the lobby game.
+				// Spectators have no team, so they only take part in the All scope.
+				let recipients: Vec<(ClientId, usize)> = if local_server.teams.contains_key(&client_id) || local_server.spectators.contains(&client_id) {
+					local_server.teams.iter().map(|(recipient, team)| (*recipient, *team))
+						.chain(local_server.spectators.iter().map(|spectator| (*spectator, 0)))
+						.collect()
+				} else if let Some(game_index) = lobby_game_index_for_client(&local_server, client_id) {
+					local_server.lobby_games[game_index].players.iter().map(|player| (player.client_id, player.team)).collect()
+				} else {
+					info!("DEBUG: Client {} sent Chat outside of a game. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::NotInGame, "Chat is only available in a game.");
+					continue;
+				};
+				
+				let sender_team = recipients.iter().find(|(recipient, _)| *recipient == client_id).map(|(_, team)| *team);
+				for (recipient, team) in recipients {
+					if scope == ChatScope::Team && (team == 0 || Some(team) != sender_team) {
+						continue;
+					}
+					endpoint.try_send_message(recipient, ServerMessage::Chat { sender: client_id, scope: scope, message: message.clone(), });
+				}
+			},
+			ClientMessage::Spectate => {
+				if !local_server.in_game {
+					info!("DEBUG: Client {} asked to spectate, but no battle is in progress.", client_id);
+					send_error(endpoint, client_id, ErrorCode::NoBattleInProgress, "There is no battle to spectate.");
+					continue;
+				}
+				if local_server.teams.contains_key(&client_id) {
+					continue;
+				}
+				
+				info!("DEBUG: Client {} is now spectating.", client_id);
+				leave_lobby_game(endpoint, &mut local_server, client_id);
+				local_server.waiting_clients.retain(|waiting_client| *waiting_client != client_id);
+				local_server.spectators.insert(client_id);
+				
+				endpoint.try_send_message(client_id, ServerMessage::Players { players: battle_players(&local_server), });
+				endpoint.try_send_message(client_id, ServerMessage::Snapshot { scenario: local_server.scenario.clone(), snapshot: battle_snapshot(&local_server), });
+			},
+			ClientMessage::StopSpectating => {
+				if local_server.spectators.remove(&client_id) {
+					info!("DEBUG: Client {} stopped spectating.", client_id);
+				}
+			},
+			ClientMessage::LoadingComplete => {
+				if !local_server.teams.contains_key(&client_id) {
+					continue;
+				}
+				
+				info!("DEBUG: Client {} has finished loading.", client_id);
+				local_server.loading_complete.insert(client_id);
+				
+				// A player who took back their seat joins the turn in progress.
+				if let Some(current_unit) = local_server.current_unit {
+					let unit = &local_server.units[current_unit];
+					if let Some(current_client) = client_for_team(&local_server, unit.unit_team) {
+						endpoint.try_send_message(client_id, ServerMessage::PlayerTurn { client_id: current_client, current_unit: unit.unit_id, });
+					}
+					if local_server.turn_time_limit > 0.0 {
+						let reserve = local_server.turn_reserves.get(&unit.unit_team).copied().unwrap_or(0.0);
+						let seconds = (local_server.turn_time_limit - local_server.turn_elapsed).max(0.0);
+						endpoint.try_send_message(client_id, ServerMessage::TurnTimer { current_unit: unit.unit_id, seconds: seconds, reserve: reserve, });
+					}
+					continue;
+				}
+				
+				start_local_server_battle(endpoint, &mut local_server);
+			},
+			ClientMessage::WaitTurnComplete => {
+				empty_system();
+			},
+			ClientMessage::Wait { unit_id } => {
+				let Some(current_unit) = current_unit_for_client(&local_server, client_id).filter(|current_unit| local_server.units[*current_unit].unit_id == unit_id) else {
+					info!("DEBUG: Client {} sent Wait for unit {} outside its turn. Rejecting it.", client_id, unit_id);
+					send_error(endpoint, client_id, ErrorCode::NotYourTurn, "It is not your turn.");
+					continue;
+				};
+				
+				end_local_server_turn(endpoint, &mut local_server, current_unit);
+			},
+			ClientMessage::Move { origin, destination } => {
+				let Some(current_unit) = current_unit_for_client(&local_server, client_id) else {
+					info!("DEBUG: Client {} sent Move outside its turn. Rejecting it.", client_id);
+					send_move_error(endpoint, client_id, ErrorCode::NotYourTurn, "It is not your turn.", origin, destination);
+					continue;
+				};
+				
+				let unit = local_server.units[current_unit].clone();
+				let map = server_map(&local_server.heights, &local_server.units);
+				let hostile_positions: Vec<Pos> = local_server.units
+					.iter()
+					.filter(|other_unit| local_server.alliances.stance(unit.unit_team, other_unit.unit_team) == Stance::Hostile)
+					.map(|other_unit| other_unit.pos)
+					.collect();
+				let zone_of_control = local_server.rules.zone_of_control(&map, &hostile_positions);
+				let possible_movements = find_possible_movements(map, unit.pos, unit.movement_range, unit.jump, &zone_of_control);
+				if unit.pos != origin || !possible_movements.contains(&destination) {
+					info!("DEBUG: Client {} sent an invalid Move. Rejecting it.", client_id);
+					send_move_error(endpoint, client_id, ErrorCode::InvalidMove, "The unit can't move to that tile.", origin, destination);
+					continue;
+				}
+				
+				local_server.units[current_unit].pos = destination;
+				local_server.undoable_move = Some((current_unit, origin, destination));
+				send_battle_message(endpoint, &local_server, ServerMessage::Move { origin: origin, destination: destination, });
+				check_local_server_objectives(endpoint, &mut local_server);
+			},
+			ClientMessage::UndoMove => {
+				let Some(current_unit) = current_unit_for_client(&local_server, client_id) else {
+					info!("DEBUG: Client {} sent UndoMove outside its turn. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::NotYourTurn, "It is not your turn.");
+					continue;
+				};
+				
+				// Only the last move of the current unit can be taken back, before it attacks.
+				let Some((unit_index, origin, destination)) = local_server.undoable_move.filter(|(unit_index, _, _)| *unit_index == current_unit) else {
+					info!("DEBUG: Client {} has no move to undo. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::CannotUndo, "The move can't be undone.");
+					continue;
+				};
+				if local_server.units.iter().any(|unit| unit.pos == origin) {
+					info!("DEBUG: The origin of client {}'s move is taken. Rejecting the undo.", client_id);
+					send_error(endpoint, client_id, ErrorCode::CannotUndo, "The move can't be undone.");
+					continue;
+				}
+				
+				local_server.units[unit_index].pos = origin;
+				local_server.undoable_move = None;
+				send_battle_message(endpoint, &local_server, ServerMessage::UndoMove { origin: origin, destination: destination, });
+			},
+			ClientMessage::BasicAttack { attacker, target, damage: _ } => {
+				let Some(current_unit) = current_unit_for_client(&local_server, client_id) else {
+					info!("DEBUG: Client {} sent BasicAttack outside its turn. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::NotYourTurn, "It is not your turn.");
+					continue;
+				};
+				
+				// A unit attacks or uses an item once per turn.
+				if local_server.action_used {
+					info!("DEBUG: Client {} sent BasicAttack after acting. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::InvalidAttack, "The unit has already acted this turn.");
+					continue;
+				}
+				
+				let unit = local_server.units[current_unit].clone();
+				if !unit.abilities.contains(&Ability::Attack) {
+					info!("DEBUG: Client {} attacked with a unit whose class can't attack. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::InvalidAttack, "The unit's class can't attack.");
+					continue;
+				}
+				
+				let map = server_map(&local_server.heights, &local_server.units);
+				let possible_attacks = find_possible_attacks(map, unit.pos, unit.attack_range, unit.attack_type);
+				let target_index = local_server.units.iter().position(|target_unit| target_unit.pos == target);
+				if unit.pos != attacker || !possible_attacks.contains(&target) || target_index.is_none() {
+					info!("DEBUG: Client {} sent an invalid BasicAttack. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::InvalidAttack, "The unit can't attack that tile.");
+					continue;
+				}
+				
+				if local_server.alliances.stance(unit.unit_team, local_server.units[target_index.unwrap()].unit_team) == Stance::Allied {
+					info!("DEBUG: Client {} attacked an allied unit. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::InvalidAttack, "The unit can't attack an allied unit.");
+					continue;
+				}
+				
+				// Damage is (STR / 3) + a random modifier between -3 and 3.
+				let damage = ((unit.str / 3) as isize + local_server.rng.rng.gen_range(-3..=3)).max(0) as usize;
+				
+				let target_unit = &mut local_server.units[target_index.unwrap()];
+				target_unit.hp_current = target_unit.hp_current.saturating_sub(damage);
+				local_server.undoable_move = None;
+				local_server.action_used = true;
+				
+				send_battle_message(endpoint, &local_server, ServerMessage::BasicAttack {
+					attacker: attacker,
+					target: target,
+					damage: damage,
+					is_counterattack: false,
+				});
+				
+				// A melee target that survives strikes back, like it does offline.
+				let target_unit = local_server.units[target_index.unwrap()].clone();
+				let target_attacks = find_possible_attacks(server_map(&local_server.heights, &local_server.units), target_unit.pos, target_unit.attack_range, target_unit.attack_type);
+				if target_unit.hp_current > 0 && matches!(target_unit.attack_type, AttackType::Melee) && target_unit.abilities.contains(&Ability::Attack) && target_attacks.contains(&attacker) {
+					let counter_damage = ((target_unit.str / 3) as isize + local_server.rng.rng.gen_range(-3..=3)).max(0) as usize;
+					
+					let attacker_unit = &mut local_server.units[current_unit];
+					attacker_unit.hp_current = attacker_unit.hp_current.saturating_sub(counter_damage);
+					
+					send_battle_message(endpoint, &local_server, ServerMessage::BasicAttack {
+						attacker: target,
+						target: attacker,
+						damage: counter_damage,
+						is_counterattack: true,
+					});
+				}
+				
+				handle_local_server_unit_death(endpoint, &mut local_server);
+			},
+			ClientMessage::UseItem { user, item } => {
+				let Some(current_unit) = current_unit_for_client(&local_server, client_id) else {
+					info!("DEBUG: Client {} sent UseItem outside its turn. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::NotYourTurn, "It is not your turn.");
+					continue;
+				};
+				
+				if local_server.action_used {
+					info!("DEBUG: Client {} sent UseItem after acting. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::InvalidItem, "The unit has already acted this turn.");
+					continue;
+				}
+				
+				if !local_server.units[current_unit].abilities.contains(&Ability::UseItem) {
+					info!("DEBUG: Client {} used an item with a unit whose class can't use items. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::InvalidItem, "The unit's class can't use items.");
+					continue;
+				}
+				
+				let unit = &local_server.units[current_unit];
+				let restored = unit.inventory.get(item).and_then(|item_name| local_server.items.consumable(item_name));
+				let Some((hp, mp)) = restored.filter(|_| unit.pos == user) else {
+					info!("DEBUG: Client {} sent an invalid UseItem. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::InvalidItem, "The unit can't use that item.");
+					continue;
+				};
+				
+				let unit = &mut local_server.units[current_unit];
+				unit.inventory.remove(item);
+				unit.hp_current = (unit.hp_current + hp).min(unit.hp_max);
+				unit.mp_current = (unit.mp_current + mp).min(unit.mp_max);
+				local_server.undoable_move = None;
+				local_server.action_used = true;
+				
+				send_battle_message(endpoint, &local_server, ServerMessage::UseItem { user: user, item: item, });
+			},
+			ClientMessage::Deploy { units } => {
+				let Some(team) = local_server.teams.get(&client_id).copied() else {
+					continue;
+				};
+				if local_server.current_unit.is_some() || local_server.deployments.contains_key(&team) {
+					info!("DEBUG: Client {} sent Deploy after its team deployed. Rejecting it.", client_id);
+					send_error(endpoint, client_id, ErrorCode::AlreadyDeployed, "Your team has already deployed.");
+					continue;
+				}
+				
+				// Guests and neutral units keep the places the scenario gives them.
+				let team_units: Vec<usize> = local_server.units
+					.iter()
+					.filter(|unit| unit.unit_team == team && unit.role == UnitRole::Regular)
+					.map(|unit| unit.unit_id)
+					.collect();
+				let occupied: Vec<Pos> = local_server.units
+					.iter()
+					.filter(|unit| unit.unit_team != team || unit.role != UnitRole::Regular)
+					.map(|unit| unit.pos)
+					.collect();
+				if let Err(error) = check_deployment(&local_server.deployment_zones, team, &team_units, &occupied, &units) {
+					info!("DEBUG: Client {} sent an invalid Deploy: {}", client_id, error);
+					send_error(endpoint, client_id, ErrorCode::InvalidDeployment, &error);
+					continue;
+				}
+				
+				// The units are only moved once every team has deployed, so that snapshots don't reveal them.
+				info!("DEBUG: Team {} has deployed.", team);
+				local_server.deployments.insert(team, units);
+				
+				start_local_server_battle(endpoint, &mut local_server);
+			},
 		}
-		break;
 	}
 }
 
-// Prototype
-fn single_player_pause(
-mut input: ResMut<Input<KeyCode>>,
-mut next_state: ResMut<NextState<GameState>>,
+// Server
+fn tick_local_server_lobby_countdowns(
+mut server: ResMut<Server>,
+mut local_server: ResMut<LocalServer>,
+time: Res<Time>,
 ) {
-	if input.just_pressed(KeyCode::F3) {
-		info!("DEBUG: Pausing the game...");
-		info!("DEBUG: Setting GameState to SinglePlayerPause...");
-		next_state.set(GameState::SinglePlayerPause);
-		info!("DEBUG: Set GameState to SinglePlayerPause.");
+	local_server.countdown_elapsed += time.delta_seconds();
+	if local_server.countdown_elapsed < 1.0 {
+		return;
 	}
-}
-
-// Prototype
-fn handle_single_player_pause_state(
-mut input: ResMut<Input<KeyCode>>,
-mut next_state: ResMut<NextState<GameState>>,
-) {
-	if input.just_pressed(KeyCode::F3) {
-		info!("DEBUG: Unpausing the game...");
-		info!("DEBUG: Setting GameState to Ambush...");
-		next_state.set(GameState::Ambush);
-		info!("DEBUG: Set GameState to Ambush.");
+	local_server.countdown_elapsed = 0.0;
+	
+	let endpoint = server.endpoint_mut();
+	
+	for game in local_server.lobby_games.iter_mut() {
+		if let Some(countdown) = game.countdown.filter(|countdown| *countdown > 0) {
+			game.countdown = Some(countdown - 1);
+			send_lobby_update(endpoint, game);
+		}
+	}
+	
+	// Every game whose countdown is over starts, oldest first.
+	// The local server runs a single battle at a time, so the others wait at 0 for it to end.
+	while let Some(game_index) = local_server.lobby_games.iter().position(|game| game.countdown == Some(0)) {
+		if local_server.in_game {
+			info!("DEBUG: A battle is already in progress. The countdown will wait for it to end.");
+			return;
+		}
+		
+		let game = local_server.lobby_games.remove(game_index);
+		let players = game.players
+			.iter()
+			.map(|player| (player.client_id, player.team))
+			.collect();
+		start_local_server_game(endpoint, &mut local_server, players, &game.scenario);
 	}
 }
 
-// Prototype
-fn first_ai(
-mut map_query: Query<&mut Map>,
-mut unit_query: Query<(Entity, &UnitId, &mut UnitActions, &Pos, &mut WTCurrent, &WTMax), With<CurrentUnit>>,
+// Server
+fn tick_local_server_turn_timer(
+mut server: ResMut<Server>,
+mut local_server: ResMut<LocalServer>,
 time: Res<Time>,
-mut commands: Commands,
-mut next_state: ResMut<NextState<TurnState>>,
 ) {
-	let map = &map_query.single().map;
-	
-	// Get current unit.
-	if let (entity, unit_id, mut unit_actions, pos, mut wt_current, wt_max) = unit_query.single_mut() {
-		// Insert `Move` `UnitAction`.
-		unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
-				origin: Pos { x: pos.x, y: pos.y, },
-				destination: Pos { x: pos.x - 1, y: pos.y, },
-				timer: Timer::from_seconds(4.0, TimerMode::Once),
-			}, 0.0));
-		
-		// End turn.
-		wt_current.value = wt_max.value;
-		
-		commands.entity(entity).remove::<CurrentUnit>();
-		
-		info!("DEBUG: AI has finished its turn.");
-		info!("DEBUG: Setting TurnState to Wait...");
-		next_state.set(TurnState::Wait);
-		info!("DEBUG: Set TurnState to Wait.");
+	if local_server.turn_time_limit <= 0.0 {
+		return;
 	}
+	let Some(current_unit) = local_server.current_unit else {
+		return;
+	};
 	
+	local_server.turn_elapsed += time.delta_seconds();
 	
+	let unit_team = local_server.units[current_unit].unit_team;
+	let reserve = local_server.turn_reserves.get(&unit_team).copied().unwrap_or(0.0);
 	
+	// The client ends its own turn when the time is over. This only covers clients that don't.
+	if local_server.turn_elapsed >= local_server.turn_time_limit + reserve + TURN_TIMER_GRACE_SECONDS {
+		info!("DEBUG: Unit {} ran out of time. Ending its turn...", local_server.units[current_unit].unit_id);
+		end_local_server_turn(server.endpoint_mut(), &mut local_server, current_unit);
+	}
 }
 
-// Prototype
-fn handle_unit_directions(
-mut units_query: Query<(Entity, &mut Handle<Image>, &DIR, &UnitSprite)>,
-asset_server: Res<AssetServer>,
-) {
-	for (entity, mut sprite, dir, unit_sprite) in units_query.iter_mut() {
-		match dir.direction {
-			Direction::East => {
-				let mut sprite_path = unit_sprite.value.clone();
-				sprite_path.push_str("_east.png");
-				*sprite = asset_server.load(&*sprite_path);
-			},
-			Direction::South => {
-				let mut sprite_path = &mut unit_sprite.value.clone();
-				sprite_path.push_str("_south.png");
-				*sprite = asset_server.load(&*sprite_path);
-			},
-			Direction::West => {
-				let mut sprite_path = &mut unit_sprite.value.clone();
-				sprite_path.push_str("_west.png");
-				*sprite = asset_server.load(&*sprite_path);
-			},
-			Direction::North => {
-				let mut sprite_path = &mut unit_sprite.value.clone();
-				sprite_path.push_str("_north.png");
-				*sprite = asset_server.load(&*sprite_path);
-			},
-		}
+// Server
+fn end_local_server_turn(endpoint: &mut Endpoint, local_server: &mut LocalServer, current_unit: usize) {
+	// Reset the unit's WT.
+	let unit = &mut local_server.units[current_unit];
+	unit.wt_current = unit.wt_max;
+	let unit_team = unit.unit_team;
+	
+	// Unused time goes into the team's reserve, up to one full turn.
+	if local_server.turn_time_limit > 0.0 {
+		let reserve = local_server.turn_reserves.get(&unit_team).copied().unwrap_or(0.0);
+		let unused = (local_server.turn_time_limit + reserve - local_server.turn_elapsed).max(0.0);
+		let reserve = if local_server.turn_time_carry_over { unused.min(local_server.turn_time_limit) } else { 0.0 };
+		local_server.turn_reserves.insert(unit_team, reserve);
 	}
+	
+	send_battle_message(endpoint, local_server, ServerMessage::Wait);
+	start_next_turn(endpoint, local_server);
 }
 
-// Prototype
-fn choose_move(
-mut commands: Commands,
-map_query: Query<&Map>,
-unit_query: Query<(Entity, &Pos, &MovementRange), With<CurrentUnit>>,
-tile_query: Query<&Transform, With<GameText>>,
-asset_server: Res<AssetServer>,
-) {
-	let map = &map_query.single().map;
-	let (entity, pos, movement_range) = unit_query.single();
+// Server
+fn start_local_server_game(endpoint: &mut Endpoint, local_server: &mut LocalServer, players: Vec<(ClientId, usize)>, scenario: &str) {
+	info!("DEBUG: Starting local server game with scenario {}...", scenario);
 	
-	let possible_movements = find_possible_movements(map.to_vec(), *pos, movement_range.value);
-	info!("DEBUG: Possible movements are: {:?}.", possible_movements);
+	// A scenario file that can't be read refuses the battle, rather than playing it with defaults or without units.
+	let scenario_files = load_deployment_zones(scenario).and_then(|deployment_zones| {
+		Ok((
+			deployment_zones,
+			load_server_units(scenario, &local_server.classes, &local_server.items)?,
+			load_map_heights(scenario)?,
+			load_alliances(scenario)?,
+			load_scenario_rules(scenario)?,
+			load_objectives(scenario)?,
+		))
+	});
+	let (deployment_zones, units, heights, alliances, rules, objectives) = match scenario_files {
+		Ok(scenario_files) => scenario_files,
+		Err(error) => {
+			info!("DEBUG: {} Not starting the game.", error);
+			for (client_id, _) in &players {
+				send_error(endpoint, *client_id, ErrorCode::InvalidScenario, &error);
+			}
+			return;
+		},
+	};
 	
-	// Spawn the MoveTile indicators.
+	local_server.teams.clear();
+	for (client_id, team) in &players {
+		local_server.teams.insert(*client_id, *team);
+	}
 	
+	local_server.units = units;
+	local_server.heights = heights;
+	local_server.alliances = alliances;
+	local_server.rules = rules;
+	local_server.objectives = objectives;
+	local_server.deployment_zones = deployment_zones;
+	local_server.deployments.clear();
+	local_server.rng = BattleRng::default();
+	info!("DEBUG: Seeded the battle with {}.", local_server.rng.seed);
+	local_server.loading_complete.clear();
+	local_server.current_unit = None;
+	local_server.in_game = true;
+	local_server.scenario = scenario.to_string();
 	
-	for tile in &possible_movements {
+	for (client_id, _) in &players {
+		endpoint.try_send_message(*client_id, ServerMessage::StartGame { client_id: *client_id, });
+	}
+	send_battle_message(endpoint, local_server, ServerMessage::Players { players: battle_players(local_server), });
 	
-		// Compute the indicator position, based on the tile.
-		if let Ok(tile_transform) = tile_query.get(map[tile.x][tile.y].3[map[tile.x][tile.y].3.len() - 1]) {
-			
-			commands.spawn((SpriteBundle {
-				sprite: Sprite {
-					color: Color::rgba(0.0, 0.0, 1.0, 0.5),
-					..default()
-				},
-				texture: asset_server.load("move_tile.png"),
-				transform: Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y, tile_transform.translation.z + 0.000000025),
-				..default()
-			},
-			MoveTile {},
-			));
-		}
-		
-		
+	info!("DEBUG: Started local server game.");
+}
+
+// Server
+fn leave_lobby_game(endpoint: &mut Endpoint, local_server: &mut LocalServer, client_id: ClientId) {
+	let Some(game_index) = lobby_game_index_for_client(local_server, client_id) else {
+		return;
+	};
+	
+	let game = &mut local_server.lobby_games[game_index];
+	game.players.retain(|player| player.client_id != client_id);
+	info!("DEBUG: Client {} left game {}.", client_id, game.game_id);
+	
+	if game.players.len() == 0 {
+		local_server.lobby_games.remove(game_index);
+		return;
 	}
 	
-	commands.entity(entity).insert(MoveTiles { move_tiles: possible_movements, });
+	// If the host left, the next player becomes the host.
+	if game.host == client_id {
+		game.host = game.players[0].client_id;
+	}
+	update_lobby_countdown(game);
+	send_lobby_update(endpoint, game);
 }
 
-// Prototype
-fn start_choose_move(
-mut input: ResMut<Input<KeyCode>>,
-mut next_state: ResMut<NextState<TurnState>>,
-) {
-	if input.just_pressed(KeyCode::M) {
-		info!("DEBUG: Setting TurnState to ChooseMove...");
-		next_state.set(TurnState::ChooseMove);
-		info!("DEBUG: Set TurnState to ChooseMove.");
+// Server
+fn update_lobby_countdown(game: &mut LobbyGame) {
+	// A battle needs one ready player on each team.
+	let every_team_played = game.teams.iter().all(|team| game.players.iter().filter(|player| player.team == *team).count() == 1);
+	let all_ready = game.players.iter().all(|player| player.ready);
+	
+	if game.players.len() == game.teams.len() && every_team_played && all_ready {
+		if game.countdown.is_none() {
+			game.countdown = Some(5);
+		}
+	} else {
+		game.countdown = None;
 	}
 }
 
-fn handle_choose_move(
-mut commands: Commands,
-mut input: ResMut<Input<KeyCode>>,
-mut unit_query: Query<(Entity, &MoveTiles, &Pos, &mut UnitActions), With<CurrentUnit>>,
-cursor_query: Query<&Cursor>,
-move_tiles_query: Query<Entity, With<MoveTile>>,
-mut next_state: ResMut<NextState<TurnState>>,
-game: Res<Game>,
-mut client: ResMut<Client>,
-) {
-	let cursor = cursor_query.single();
-	let (entity, move_tiles, pos, mut unit_actions) = unit_query.single_mut();
+// Server
+fn send_lobby_update(endpoint: &mut Endpoint, game: &LobbyGame) {
+	for player in &game.players {
+		endpoint.try_send_message(player.client_id, ServerMessage::LobbyUpdate { game: game.clone(), });
+	}
+}
 
-	if input.just_pressed(KeyCode::M) {
-		// Check if cursor is in a MoveTile.
-		let cursor_pos = Pos { x: cursor.x, y: cursor.y, };
-		if move_tiles.move_tiles.contains(&cursor_pos) {
-			// Remove the MoveTiles
-			for entity in move_tiles_query.iter() {
-				commands.entity(entity).despawn();
-			}
-			
-			if !game.is_multiplayer {
-				// Game is in single-player mode.
-				// Insert a `Move` `UnitAction` towards the target tile.
-				info!("DEBUG: Unit can move to this tile.");
-				info!("DEBUG: Moving unit...");
-				
-				unit_actions.unit_actions.push(UnitActionTuple(UnitAction::Move {
-						origin: Pos { x: pos.x, y: pos.y, },
-						destination: Pos { x: cursor.x, y: cursor.y },
-						timer: Timer::from_seconds(4.0, TimerMode::Once),
-				}, 0.0));
-				
-				// Set State
-				next_state.set(TurnState::Turn);
-			} else {
-				// Game is in multiplayer mode.
-				// Send a `ClientMessage::Move` message to the server.
-				info!("DEBUG: Unit can move to this tile.");
-				info!("DEBUG: Sending `Move` message...");
-				client
-					.connection()
-					.try_send_message(ClientMessage::Move { 
-						origin: Pos { x: pos.x, y: pos.y, },
-						destination: Pos { x: cursor.x, y: cursor.y },
-					});
-				info!("DEBUG: Sent Move message.");
-				
-			}			
+// Server
+fn lobby_game_index_for_client(local_server: &LocalServer, client_id: ClientId) -> Option<usize> {
+	local_server.lobby_games
+		.iter()
+		.position(|game| game.players.iter().any(|player| player.client_id == client_id))
+}
+
+// Server
+fn player_name(local_server: &LocalServer, client_id: ClientId) -> String {
+	match local_server.player_names.get(&client_id) {
+		Some(name) => name.clone(),
+		None => format!("Player {}", client_id),
+	}
+}
+
+// Server
+fn sanitize_player_name(name: &str) -> String {
+	let name: String = name.trim().chars().take(24).collect();
+	if name.is_empty() {
+		String::from("Player")
+	} else {
+		name
+	}
+}
+
+// Server
+fn start_next_turn(endpoint: &mut Endpoint, local_server: &mut LocalServer) {
+	// The units of a player who left wait for them, so they wait out their turns.
+	// There is always a turn to give, as long as one unit has a player.
+	if !local_server.units.iter().any(|unit| unit_has_player(local_server, unit)) {
+		return;
+	}
+	
+	let next_unit = loop {
+		// Decrease all units WT until one of them reaches 0.
+		let ticks = local_server.units.iter().map(|unit| unit.wt_current).min().unwrap_or(0);
+		for unit in local_server.units.iter_mut() {
+			unit.wt_current -= ticks;
+		}
+		
+		let wait_turns = local_server.units
+			.iter()
+			.map(|unit| (UnitId { value: unit.unit_id, }, WTCurrent { value: unit.wt_current, }))
+			.collect();
+		send_battle_message(endpoint, local_server, ServerMessage::WaitTurn { wait_turns: wait_turns, });
+		
+		// On a tie, the unit with the lowest `UnitId` goes first.
+		let next_unit = local_server.units
+			.iter()
+			.enumerate()
+			.filter(|(_, unit)| unit.wt_current == 0)
+			.min_by_key(|(_, unit)| unit.unit_id)
+			.map(|(index, unit)| (index, unit.unit_id, unit.unit_team));
+		
+		match next_unit {
+			Some((index, _, _)) if !unit_has_player(local_server, &local_server.units[index]) => {
+				local_server.units[index].wt_current = local_server.units[index].wt_max;
+			},
+			next_unit => break next_unit,
+		}
+	};
+	
+	if let Some((index, unit_id, unit_team)) = next_unit {
+		let unit_positions: Vec<(usize, Pos)> = local_server.units.iter().map(|unit| (unit.unit_team, unit.pos)).collect();
+		local_server.objectives.start_unit_turn(unit_team, &unit_positions, &local_server.alliances);
+		if check_local_server_objectives(endpoint, local_server) {
+			return;
+		}
+		
+		local_server.current_unit = Some(index);
+		local_server.undoable_move = None;
+		local_server.action_used = false;
+		
+		if let Some(client_id) = client_for_team(local_server, unit_team) {
+			info!("DEBUG: It is now unit {} turn, controlled by client {}.", unit_id, client_id);
+			send_battle_message(endpoint, local_server, ServerMessage::PlayerTurn { client_id: client_id, current_unit: unit_id, });
+		}
+		
+		local_server.turn_elapsed = 0.0;
+		if local_server.turn_time_limit > 0.0 {
+			let reserve = local_server.turn_reserves.get(&unit_team).copied().unwrap_or(0.0);
+			send_battle_message(endpoint, local_server, ServerMessage::TurnTimer { current_unit: unit_id, seconds: local_server.turn_time_limit, reserve: reserve, });
 		}
 	}
+}
+
+// Server
+fn handle_local_server_unit_death(endpoint: &mut Endpoint, local_server: &mut LocalServer) {
+	let current_unit_id = local_server.current_unit.map(|index| local_server.units[index].unit_id);
+	
+	local_server.units.retain(|unit| unit.hp_current > 0);
+	
+	// Units were removed, so the current unit index has to be found again.
+	local_server.current_unit = current_unit_id
+		.and_then(|unit_id| local_server.units.iter().position(|unit| unit.unit_id == unit_id));
+	
+	if check_local_server_objectives(endpoint, local_server) {
+		return;
+	}
 	
-	if input.just_pressed(KeyCode::Escape) {
-		// Remove the MoveTiles
-		for entity in move_tiles_query.iter() {
-			commands.entity(entity).despawn();
-		}
+	// Neutral units don't keep the battle going.
+	let mut teams_alive: HashSet<usize> = HashSet::new();
+	for unit in local_server.units.iter().filter(|unit| unit.role != UnitRole::Neutral) {
+		teams_alive.insert(unit.unit_team);
+	}
 	
-		info!("Setting TurnState back to Turn...");
-		next_state.set(TurnState::Turn);
-		info!("Set TurnState back to Turn.");
+	if !local_server.alliances.has_hostile_teams(&teams_alive) {
+		end_local_server_game(endpoint, local_server, &teams_alive);
 	}
 }
 
-// Prototype
-fn start_choose_attack(
-mut input: ResMut<Input<KeyCode>>,
-mut next_state: ResMut<NextState<TurnState>>,
-) {
-	if input.just_pressed(KeyCode::F) {
-		info!("DEBUG: Setting TurnState to ChooseAttack...");
-		next_state.set(TurnState::ChooseAttack);
-		info!("DEBUG: Set TurnState to ChooseAttack.");
-	}
+// Server
+/// Ends the battle if the scenario objectives decide it, and returns whether they did.
+fn check_local_server_objectives(endpoint: &mut Endpoint, local_server: &mut LocalServer) -> bool {
+	let Some(winning_teams) = local_server_objectives_outcome(local_server) else {
+		return false;
+	};
+	
+	end_local_server_game(endpoint, local_server, &winning_teams);
+	true
 }
 
-fn choose_attack(mut commands: Commands,
-map_query: Query<&Map>,
-unit_query: Query<(Entity, &Pos, &AttackRange, &AttackType), With<CurrentUnit>>,
-tile_query: Query<&Transform, With<GameText>>,
-asset_server: Res<AssetServer>,
-) {
-	let map = &map_query.single().map;
-	let (entity, pos, attack_range, attack_type) = unit_query.single();
+// Server
+/// The teams that win the battle, once the scenario objectives decide it. Like offline,
+/// the team of the objectives and its allies win a victory, and its enemies win a defeat.
+fn local_server_objectives_outcome(local_server: &LocalServer) -> Option<HashSet<usize>> {
+	let units: Vec<(usize, String, Pos, UnitRole)> = local_server.units
+		.iter()
+		.map(|unit| (unit.unit_team, unit.name.clone(), unit.pos, unit.role))
+		.collect();
+	let (victory, _) = local_server.objectives.outcome(&units, &local_server.alliances)?;
 	
-	let possible_attacks = find_possible_attacks(map.to_vec(), *pos, attack_range.value, attack_type.clone());
-	info!("DEBUG: Possible attacks are: {:?}.", possible_attacks);
+	let stance = if victory { Stance::Allied } else { Stance::Hostile };
+	let winning_teams = local_server.teams
+		.values()
+		.copied()
+		.chain(local_server.units.iter().filter(|unit| unit.role != UnitRole::Neutral).map(|unit| unit.unit_team))
+		.filter(|team| local_server.alliances.stance(local_server.objectives.team, *team) == stance)
+		.collect();
+	Some(winning_teams)
+}
+
+// Server
+fn end_local_server_game(endpoint: &mut Endpoint, local_server: &mut LocalServer, winning_teams: &HashSet<usize>) {
+	info!("DEBUG: Local server game is over.");
 	
-	// Spawn the AttackTile indicators.
+	// A team with a player behind it is announced before a team left to the scenario.
+	let player_team = winning_teams.iter().copied().filter(|team| client_for_team(local_server, *team).is_some()).min();
+	let (winner, winning_team) = match player_team {
+		Some(team) => (ControlledBy::Player, Some(team)),
+		None if winning_teams.len() > 0 => (ControlledBy::AI, winning_teams.iter().min().copied()),
+		None => (ControlledBy::None, None),
+	};
 	
+	if let Some(team) = winning_team {
+		send_battle_message(endpoint, local_server, ServerMessage::WinningTeam { team: team, });
+	}
+	send_battle_message(endpoint, local_server, ServerMessage::GameOver { winner: winner, });
+	reset_local_server_game(local_server);
+}
+
+// Server
+fn reset_local_server_game(local_server: &mut LocalServer) {
+	local_server.teams.clear();
+	local_server.loading_complete.clear();
+	local_server.units.clear();
+	local_server.alliances = Alliances::default();
+	local_server.rules = ScenarioRules::default();
+	local_server.objectives = Objectives::default();
+	local_server.heights.clear();
+	local_server.current_unit = None;
+	local_server.in_game = false;
+	local_server.spectators.clear();
+	local_server.turn_elapsed = 0.0;
+	local_server.turn_reserves.clear();
+	local_server.undoable_move = None;
+	local_server.action_used = false;
+	local_server.deployment_zones = DeploymentZones::default();
+	local_server.deployments.clear();
+	local_server.empty_seats.clear();
+}
+
+// Server
+/// Starts the battle once every player has loaded and every team with a zone has deployed.
+fn start_local_server_battle(endpoint: &mut Endpoint, local_server: &mut LocalServer) {
+	if local_server.current_unit.is_some() || local_server.loading_complete.len() != local_server.teams.len() {
+		return;
+	}
 	
-	for tile in &possible_attacks {
+	let deploying_teams = local_server.deployment_zones.teams();
+	if !local_server.teams.values().all(|team| !deploying_teams.contains(team) || local_server.deployments.contains_key(team)) {
+		info!("DEBUG: Waiting for every team to deploy.");
+		return;
+	}
 	
-		// Compute the indicator position, based on the tile.
-		if let Ok(tile_transform) = tile_query.get(map[tile.x][tile.y].3[map[tile.x][tile.y].3.len() - 1]) {
-			
-			commands.spawn((SpriteBundle {
-				sprite: Sprite {
-					color: Color::rgba(1.0, 0.0, 0.0, 0.5),
-					..default()
-				},
-				texture: asset_server.load("attack_tile.png"),
-				transform: Transform::from_xyz(tile_transform.translation.x, tile_transform.translation.y, tile_transform.translation.z + 0.000000025),
-				..default()
-			},
-			AttackTile {},
-			));
+	// Each team deployed hidden from the others, so the deployments are revealed together.
+	if !local_server.deployments.is_empty() {
+		let units = reveal_deployments(local_server);
+		send_battle_message(endpoint, local_server, ServerMessage::Deployment { units: units, });
+	}
+	
+	start_next_turn(endpoint, local_server);
+}
+
+// Server
+/// Moves the units to the places their teams deployed them in, and returns the deployed units.
+fn reveal_deployments(local_server: &mut LocalServer) -> Vec<(usize, Pos, Direction)> {
+	let units: Vec<(usize, Pos, Direction)> = local_server.deployments.values().flatten().copied().collect();
+	for (unit_id, pos, direction) in &units {
+		if let Some(unit) = local_server.units.iter_mut().find(|unit| unit.unit_id == *unit_id) {
+			unit.pos = *pos;
+			unit.direction = *direction;
 		}
-		
-		
 	}
+	units
+}
+
+// Server
+fn battle_snapshot(local_server: &LocalServer) -> BattleSnapshot {
+	BattleSnapshot {
+		units: local_server.units.iter().map(|unit| UnitSnapshot {
+			unit_id: unit.unit_id,
+			pos: unit.pos,
+			direction: unit.direction,
+			wt_current: unit.wt_current,
+			hp_current: unit.hp_current,
+		}).collect(),
+		current_unit: local_server.current_unit.map(|current_unit| local_server.units[current_unit].unit_id),
+	}
+}
+
+// Server
+fn battle_players(local_server: &LocalServer) -> Vec<PlayerInfo> {
+	local_server.teams
+		.iter()
+		.map(|(client_id, team)| PlayerInfo {
+			client_id: *client_id,
+			name: player_name(local_server, *client_id),
+			team: *team,
+		})
+		.collect()
+}
+
+// Server
+fn send_error(endpoint: &mut Endpoint, client_id: ClientId, code: ErrorCode, message: &str) {
+	endpoint.try_send_message(client_id, ServerMessage::Error { code: code, message: message.to_string(), rejected_move: None, });
+}
+
+// Server
+fn send_move_error(endpoint: &mut Endpoint, client_id: ClientId, code: ErrorCode, message: &str, origin: Pos, destination: Pos) {
+	endpoint.try_send_message(client_id, ServerMessage::Error { code: code, message: message.to_string(), rejected_move: Some((origin, destination)), });
+}
+
+// Server
+fn send_battle_message(endpoint: &mut Endpoint, local_server: &LocalServer, message: ServerMessage) {
+	// Clients in the lobby must not receive battle messages.
+	for client_id in local_server.teams.keys().chain(local_server.spectators.iter()) {
+		endpoint.try_send_message(*client_id, message.clone());
+	}
+}
+
+// Server
+fn current_unit_for_client(local_server: &LocalServer, client_id: ClientId) -> Option<usize> {
+	let current_unit = local_server.current_unit?;
+	let team = local_server.teams.get(&client_id)?;
 	
-	commands.entity(entity).insert(AttackTiles { attack_tiles: possible_attacks, });
+	if local_server.units[current_unit].unit_team == *team {
+		Some(current_unit)
+	} else {
+		None
+	}
 }
 
-// Prototype
-fn handle_choose_attack(
-mut commands: Commands,
-map_query: Query<&Map>,
-mut input: ResMut<Input<KeyCode>>,
-mut unit_query: Query<(Entity, &AttackTiles, &Pos, &mut UnitActions), With<CurrentUnit>>,
-cursor_query: Query<&Cursor>,
-attack_tiles_query: Query<Entity, With<AttackTile>>,
-client: Res<Client>,
-game: Res<Game>,
-mut next_state: ResMut<NextState<TurnState>>,
-) {
-	let map = &map_query.single().map;
+// Server
+fn unit_has_player(local_server: &LocalServer, unit: &ServerUnit) -> bool {
+	unit.role != UnitRole::Neutral && client_for_team(local_server, unit.unit_team).is_some()
+}
 
-	let cursor = cursor_query.single();
-	let (entity, attack_tiles, pos, mut unit_actions) = unit_query.single_mut();
+// Server
+fn client_for_team(local_server: &LocalServer, unit_team: usize) -> Option<ClientId> {
+	local_server.teams
+		.iter()
+		.find(|(_, team)| **team == unit_team)
+		.map(|(client_id, _)| *client_id)
+}
 
-	if input.just_pressed(KeyCode::F) {
-		// Check if cursor is in a AttackTile.
-		let cursor_pos = Pos { x: cursor.x, y: cursor.y, };
-		if attack_tiles.attack_tiles.contains(&cursor_pos) {
-			
-			if !game.is_multiplayer {
-				// Game is in single-player mode.
-			
-				// Check it there is an unit on the tile.
-				// So that the player won't attack empty tiles.
-				// In the future we will add the option to attack empty tiles.
-				// To be in accordance with Tactics Ogre.
-				
-				if map[cursor.x][cursor.y].2.len() > 0 {
-					// Remove the AttackTiles
-					for entity in attack_tiles_query.iter() {
-						commands.entity(entity).despawn();
-					}
-					
-					// Insert an `Attack` `UnitAction` towards the target unit.
-					info!("DEBUG: Unit can attack this tile.");
-					info!("DEBUG: Attacking...");
-					unit_actions.unit_actions.push(UnitActionTuple(UnitAction::BasicAttack {
-						target: Pos { x: cursor.x, y: cursor.y, },
-						is_counterattack: false,
-						damage: 0,
-					}, 0.0));
-					
-					// Insert an `Attacker` marker component on the attacking unit.
-					commands.entity(entity).insert(Attacker {});
-					
-					// Insert the `Target` marker component on the target unit.
-					let target_entity = map[cursor.x][cursor.y].2[0];
-					commands.entity(target_entity).insert(Target {});
-									
-					// Remove the AttackTiles component from the unit.
-					commands.entity(entity).remove::<AttackTiles>();
-					
-					// Set State
-					next_state.set(TurnState::Turn);
-				}
-			} else {
-				// Game is in multiplayer mode.
-				info!("DEBUG: Sending BasicAttack message...");
-				client
-					.connection()
-					.try_send_message(ClientMessage::BasicAttack {
-						attacker: Pos { x: pos.x, y: pos.y, },
-						target: Pos { x: cursor.x, y: cursor.y, },
-						damage: 0,
-					});
-				info!("DEBUG: Sent BasicAttack message.");
-				
-				// Remove the AttackTiles
-				for entity in attack_tiles_query.iter() {
-					commands.entity(entity).despawn();
-				}
+// Server
+fn load_server_units(scenario: &str, classes: &Classes, items: &Items) -> Result<Vec<ServerUnit>, String> {
+	let mut units: Vec<ServerUnit> = Vec::new();
+	for record in read_unit_records(scenario)? {
+		let mut unit_attributes = record.unit_attributes(classes).map_err(|error| format!("Invalid unit in src/{}_data.csv: {}", scenario, error))?;
+		unit_attributes.apply_equipment(items);
+		units.push(ServerUnit {
+			unit_id: record.unit_id,
+			unit_team: record.unit_team,
+			name: unit_attributes.unit_name.value.clone(),
+			pos: Pos {
+				x: record.pos_x,
+				y: record.pos_y,
+			},
+			direction: unit_attributes.dir.direction,
+			wt_max: unit_attributes.wt_max.value,
+			wt_current: unit_attributes.wt_current.value,
+			hp_max: unit_attributes.hp_max.value,
+			hp_current: unit_attributes.hp_current.value,
+			mp_max: unit_attributes.mp_max.value,
+			mp_current: unit_attributes.mp_current.value,
+			str: unit_attributes.str.value,
+			movement_range: unit_attributes.movement_range.value,
+			jump: unit_attributes.jump.value,
+			attack_range: unit_attributes.attack_range.value,
+			attack_type: unit_attributes.attack_type,
+			inventory: unit_attributes.inventory.items,
+			role: unit_attributes.role,
+			abilities: classes.get(&record.unit_class).map_or(Vec::new(), |class| class.abilities.clone()),
+		});
+	}
+	Ok(units)
+}
+
+// Server
+fn server_map(heights: &Vec<Vec<usize>>, units: &Vec<ServerUnit>) -> Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>> {
+	// Same layout as `setup_grid_system`. The server has no unit entities,
+	// so each unit is marked on its tile by a placeholder built from its `UnitId`.
+	let mut map = build_map(heights);
+	
+	for unit in units {
+		map[unit.pos.x][unit.pos.y].2.push(Entity::from_raw(unit.unit_id as u32));
+	}
+	
+	map
+}
+
+// Utility
+/// The map of a scenario without a map file.
+fn flat_map_heights() -> Vec<Vec<usize>> {
+	vec![vec![1; 20]; 20]
+}
+
+// Utility
+/// The height of every tile of a scenario, read from `src/<scenario>_map.txt`.
+/// Each line is a row of heights separated by spaces.
+fn load_map_heights(scenario: &str) -> Result<Vec<Vec<usize>>, String> {
+	let path = format!("src/{}_map.txt", scenario);
+	
+	// Scenarios without a map file are played on flat ground.
+	let Ok(contents) = fs::read_to_string(&path) else {
+		return Ok(flat_map_heights());
+	};
+	
+	let mut heights: Vec<Vec<usize>> = Vec::new();
+	for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+		let mut row: Vec<usize> = Vec::new();
+		for height in line.split_whitespace() {
+			// Every tile is at least one tile high, so units always have a tile to stand on.
+			match height.parse::<usize>() {
+				Ok(height) if height > 0 => row.push(height),
+				_ => return Err(format!("Invalid height {} in {}.", height, path)),
 			}
 		}
+		heights.push(row);
 	}
 	
-	if input.just_pressed(KeyCode::Escape) {
-		// Remove the AttackTile indicators
-		for entity in attack_tiles_query.iter() {
-			commands.entity(entity).despawn();
+	if heights.is_empty() || heights.iter().any(|row| row.len() != heights[0].len()) {
+		return Err(format!("The map in {} isn't a rectangle.", path));
+	}
+	
+	Ok(heights)
+}
+
+// Utility
+fn build_map(heights: &Vec<Vec<usize>>) -> Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>> {
+	heights
+		.iter()
+		.map(|row| row.iter().map(|height| (*height, TileType::Grass, Vec::new(), Vec::new())).collect())
+		.collect()
+}
+
+// Utility
+fn load_alliances(scenario: &str) -> Result<Alliances, String> {
+	let path = format!("src/{}_alliances.csv", scenario);
+	let mut alliances = Alliances::default();
+	
+	// Scenarios without an alliance table have every team fighting every other team.
+	let Ok(mut rdr) = Reader::from_path(&path) else {
+		return Ok(alliances);
+	};
+	
+	for result in rdr.records() {
+		let record = result.map_err(|error| format!("Invalid alliances in {}: {}.", path, error))?;
+		if record.len() < 3 {
+			return Err(format!("Invalid alliances in {}: expected team, other_team and stance.", path));
 		}
 		
-		// Remove the AttackTiles component from the unit.
-		commands.entity(entity).remove::<AttackTiles>();
-		
-		info!("Setting TurnState back to Turn...");
-		next_state.set(TurnState::Turn);
-		info!("Set TurnState back to Turn.");
+		let team = record[0].parse().map_err(|_| format!("Invalid team {} in {}.", &record[0], path))?;
+		let other_team = record[1].parse().map_err(|_| format!("Invalid team {} in {}.", &record[1], path))?;
+		let stance = Stance::from_string(record[2].to_string()).map_err(|error| format!("Invalid alliances in {}: {}", path, error))?;
+		alliances.set_stance(team, other_team, stance);
 	}
+	
+	Ok(alliances)
 }
 
-// Prototype
-fn handle_unit_death(
-mut commands: Commands,
-mut map_query: Query<&mut Map>,
-unit_query: Query<(Entity, &UnitId, &Pos, &HPCurrent)>,
-game: Res<Game>,
-mut next_state: ResMut<NextState<TurnState>>,
-) {
-	for (entity, unit_id, pos, hp_current) in unit_query.iter() {
-		if hp_current.value == 0 {
-			// Remove unit.
-			let mut map = &mut map_query.single_mut().map;
-			map[pos.x][pos.y].2.remove(0);
-			
-			info!("DEBUG: Unit {} has died. Removing it...", unit_id.value);
-			commands.entity(entity).despawn();
-			
-			// If that unit has the current turn, end it.
-			if unit_id.value == game.current_unit {
-				// Set TurnState to Wait.
-				info!("DEBUG: Setting TurnState to Wait...");
-				next_state.set(TurnState::Wait);
-				info!("DEBUG: Set TurnState to Wait.");
-			}
+// Utility
+fn load_scenario_rules(scenario: &str) -> Result<ScenarioRules, String> {
+	let path = format!("src/{}_rules.ron", scenario);
+	
+	// Scenarios without a rules file use the default rules.
+	let Ok(contents) = fs::read_to_string(&path) else {
+		return Ok(ScenarioRules::default());
+	};
+	
+	ron::from_str::<ScenarioRules>(&contents).map_err(|error| format!("Invalid rules in {}: {}.", path, error))
+}
+
+// Utility
+fn load_objectives(scenario: &str) -> Result<Objectives, String> {
+	let path = format!("src/{}_objectives.ron", scenario);
+	
+	// Scenarios without objectives are won by defeating every enemy.
+	let Ok(contents) = fs::read_to_string(&path) else {
+		return Ok(Objectives::default());
+	};
+	
+	let objectives = ron::from_str::<Objectives>(&contents).map_err(|error| format!("Invalid objectives in {}: {}.", path, error))?;
+	
+	// A unit missing from the scenario counts as defeated from the start, so a misspelt name would decide the battle.
+	let records = read_unit_records(scenario)?;
+	check_objective_units(&objectives, &records).map_err(|error| format!("Invalid objectives in {}: {}", path, error))?;
+	Ok(objectives)
+}
+
+// Utility
+/// Checks that every unit named by an objective is one of the scenario's units.
+fn check_objective_units(objectives: &Objectives, records: &Vec<UnitRecord>) -> Result<(), String> {
+	for unit_name in objectives.unit_names() {
+		if !records.iter().any(|record| record.unit_name == unit_name) {
+			return Err(format!("The scenario has no unit named {}.", unit_name));
 		}
 	}
+	Ok(())
+}
+
+// Utility
+fn load_deployment_zones(scenario: &str) -> Result<DeploymentZones, String> {
+	let path = format!("src/{}_deployment.ron", scenario);
+	
+	// Scenarios without deployment zones start with the units where the scenario puts them.
+	let Ok(contents) = fs::read_to_string(&path) else {
+		return Ok(DeploymentZones::default());
+	};
+	
+	ron::from_str::<DeploymentZones>(&contents).map_err(|error| format!("Invalid deployment zones in {}: {}.", path, error))
 }
 
-// Prototype
-fn handle_ambush_game_over(
-unit_query: Query<(Entity, &UnitTeam)>,
-mut next_state: ResMut<NextState<GameState>>,
-mut next_turn_state: ResMut<NextState<TurnState>>,
-mut game: ResMut<Game>,
-) {
-	let mut player_still_alive: bool = false;
-	let mut ai_still_alive: bool = false;
-	for (entity, unit_team) in unit_query.iter() {
-		if unit_team.value == 1 {
-			player_still_alive = true;
+// Utility
+/// Checks that a team deploys each of its units once, on free tiles of its zone.
+fn check_deployment(deployment_zones: &DeploymentZones, team: usize, team_units: &Vec<usize>, occupied: &Vec<Pos>, units: &Vec<(usize, Pos, Direction)>) -> Result<(), String> {
+	let deploys_each_unit_once = units.len() == team_units.len()
+		&& team_units.iter().all(|unit_id| units.iter().filter(|(deployed_unit_id, _, _)| deployed_unit_id == unit_id).count() == 1);
+	if !deploys_each_unit_once {
+		return Err(String::from("Every unit of the team has to be deployed once."));
+	}
+	
+	for (index, (_, pos, _)) in units.iter().enumerate() {
+		if !deployment_zones.contains(team, *pos) {
+			return Err(format!("Tile {}, {} is outside the deployment zone.", pos.x, pos.y));
 		}
-		
-		if unit_team.value == 2 {
-			ai_still_alive = true;
+		if occupied.contains(pos) || units[..index].iter().any(|(_, other_pos, _)| other_pos == pos) {
+			return Err(format!("Tile {}, {} is taken.", pos.x, pos.y));
 		}
 	}
 	
-	if !player_still_alive {
-		// Player lost.
-		info!("DEBUG: Game over. Winner is AI.");
-		game.winner = ControlledBy::AI;
-		
-		info!("DEBUG: Setting GameState to MainMenu...");
-		next_state.set(GameState::MainMenu);
-		info!("DEBUG: Set GameState to MainMenu.");
-		info!("DEBUG: Setting TurnState to Wait...");
-		next_turn_state.set(TurnState::Wait);
-		info!("DEBUG: Set TurnState to Wait.");
+	Ok(())
+}
+
+// Utility
+fn save_path(slot: &str) -> Option<String> {
+	// Slot names become file names, so they are kept to a safe set of characters.
+	if slot.is_empty() || !slot.chars().all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '-') {
+		return None;
 	}
 	
-	if !ai_still_alive {
-		// Player won.
-		info!("DEBUG: Game over. Winner is Player.");
-		game.winner = ControlledBy::Player;
-		
-		info!("DEBUG: Setting GameState to MainMenu...");
-		next_state.set(GameState::MainMenu);
-		info!("DEBUG: Set GameState to MainMenu.");
-		info!("DEBUG: Setting TurnState to Wait...");
-		next_turn_state.set(TurnState::Wait);
-		info!("DEBUG: Set TurnState to Wait.");
-	}
+	Some(format!("{}/{}.ron", SAVES_DIRECTORY, slot))
 }
 
-// Prototype
-fn handle_ambush_to_main_menu_transition(
-mut commands: Commands,
-mut query: Query<Entity, Without<Window>>,
-) {
-	for entity in query.iter() {
-		commands.entity(entity).despawn();
+// Utility
+fn list_saves() -> Vec<String> {
+	let mut saves: Vec<String> = Vec::new();
+	if let Ok(entries) = fs::read_dir(SAVES_DIRECTORY) {
+		for entry in entries.flatten() {
+			let path = entry.path();
+			if path.extension().map_or(false, |extension| extension == "ron") {
+				if let Some(slot) = path.file_stem().and_then(|file_stem| file_stem.to_str()) {
+					saves.push(slot.to_string());
+				}
+			}
+		}
 	}
+	saves.sort();
+	saves
 }
 
-// Prototype
-fn setup_main_menu(
-mut commands: Commands,
-asset_server: Res<AssetServer>,
-) {
-	commands.spawn(Camera2dBundle::default());
-
-//	// Spawn Ars Militaris Logo.
-//	commands.spawn(ImageBundle {
-//		style: Style {
-//			width: Val::Percent(100.0),
-//			height: Val::Percent(70.0),
-//			bottom: Val::Percent(30.0),
-//			..default()
-//		}, 
-//        image: asset_server.load("arsmilitaris_logo.png").into(),
-//        ..default()
-//    });
+// Utility
+fn write_battle_save(save: &BattleSave, path: &str) -> Result<(), String> {
+	let contents = ron::ser::to_string_pretty(save, ron::ser::PrettyConfig::default()).map_err(|error| error.to_string())?;
+	fs::create_dir_all(SAVES_DIRECTORY).map_err(|error| error.to_string())?;
+	fs::write(path, contents).map_err(|error| error.to_string())
+}
 
-	 commands
-        .spawn((NodeBundle {
-            style: Style {
-                width: Val::Percent(100.0),
-                ..default()
-            },
-            ..default()
-        },
-        MainMenuUI {},
-        ))
-        .with_children(|parent| {
-			parent
-                .spawn(ImageBundle {
-					style: Style {
-						width: Val::Percent(100.0),
-						height: Val::Percent(100.0),
-						bottom: Val::Percent(0.0),
-						..default()
-					}, 
-					image: asset_server.load("arsmilitaris_logo.png").into(),
-					..default()
-                })
-                .with_children(|parent| {
-					parent
-						.spawn((ButtonBundle {
-							style: Style {
-								width: Val::Percent(20.0),
-								height: Val::Percent(10.0),
-								border: UiRect::all(Val::Px(5.0)),
-								//bottom: Val::Percent(10.0),
-								// horizontally center child text
-								justify_content: JustifyContent::Center,
-								// vertically center child text
-								align_items: AlignItems::Center,
-								..default()
-							},
-							border_color: BorderColor(Color::BLACK),
-							background_color: BackgroundColor(Color::BLACK),
-							..default()
-						},
-						StartDemoButton {},
-						))
-						.with_children(|parent| {
-							parent.spawn(TextBundle::from_section(
-								"Demo",
-								TextStyle {
-									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-									font_size: 40.0,
-									color: Color::rgb(0.9, 0.9, 0.9),
-								},
-							));
-						});
-					parent
-						.spawn((ButtonBundle {
-							style: Style {
-								width: Val::Percent(20.0),
-								height: Val::Percent(10.0),
-								border: UiRect::all(Val::Px(5.0)),
-								//bottom: Val::Percent(10.0),
-								// horizontally center child text
-								justify_content: JustifyContent::Center,
-								// vertically center child text
-								align_items: AlignItems::Center,
-								..default()
-							},
-							border_color: BorderColor(Color::BLACK),
-							background_color: BackgroundColor(Color::BLACK),
-							..default()
-						},
-						StartAmbushButton {},
-						))
-						.with_children(|parent| {
-							parent.spawn(TextBundle::from_section(
-								"Ambush",
-								TextStyle {
-									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-									font_size: 40.0,
-									color: Color::rgb(0.9, 0.9, 0.9),
-								},
-							));
-						});
-					parent
-						.spawn((ButtonBundle {
-							style: Style {
-								width: Val::Percent(20.0),
-								height: Val::Percent(10.0),
-								border: UiRect::all(Val::Px(5.0)),
-								//bottom: Val::Percent(10.0),
-								// horizontally center child text
-								justify_content: JustifyContent::Center,
-								// vertically center child text
-								align_items: AlignItems::Center,
-								..default()
-							},
-							border_color: BorderColor(Color::BLACK),
-							background_color: BackgroundColor(Color::BLACK),
-							..default()
-						},
-						StartMultiplayerButton {},
-						))
-						.with_children(|parent| {
-							parent.spawn(TextBundle::from_section(
-								"Multiplayer",
-								TextStyle {
-									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-									font_size: 40.0,
-									color: Color::rgb(0.9, 0.9, 0.9),
-								},
-							));
-						});
-					parent
-						.spawn((ButtonBundle {
-							style: Style {
-								width: Val::Percent(20.0),
-								height: Val::Percent(10.0),
-								border: UiRect::all(Val::Px(5.0)),
-								//bottom: Val::Percent(10.0),
-								// horizontally center child text
-								justify_content: JustifyContent::Center,
-								// vertically center child text
-								align_items: AlignItems::Center,
-								..default()
-							},
-							border_color: BorderColor(Color::BLACK),
-							background_color: BackgroundColor(Color::BLACK),
-							..default()
-						},
-						QuitGameButton {},
-						))
-						.with_children(|parent| {
-							parent.spawn(TextBundle::from_section(
-								"Quit",
-								TextStyle {
-									font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-									font_size: 40.0,
-									color: Color::rgb(0.9, 0.9, 0.9),
-								},
-							));
-						});
-					});
-       });
+// Utility
+fn read_battle_save(path: &str) -> Result<BattleSave, String> {
+	let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+	parse_battle_save(&contents)
 }
 
-// Prototype
-fn tear_down_main_menu(
-mut commands: Commands,
-main_menu_query: Query<Entity, With<MainMenuUI>>,
-main_menu_camera_query: Query<Entity, With<Camera>>,
-) {
-	for entity in main_menu_query.iter() {
-		commands.entity(entity).despawn();
+// Utility
+fn parse_battle_save(contents: &str) -> Result<BattleSave, String> {
+	// The version is read on its own first, so that a save from another version
+	// is reported as such instead of as a parse error.
+	#[derive(Deserialize)]
+	struct SaveVersion {
+		version: u32,
 	}
 	
-	for camera_entity in main_menu_camera_query.iter() {
-		commands.entity(camera_entity).despawn();
+	let save_version = ron::from_str::<SaveVersion>(contents).map_err(|error| error.to_string())?;
+	if save_version.version != SAVE_VERSION {
+		return Err(format!("the save is version {}, but this version of the game reads version {}", save_version.version, SAVE_VERSION));
 	}
+	
+	ron::from_str::<BattleSave>(contents).map_err(|error| error.to_string())
 }
 
-// Prototype
-fn handle_main_menu_buttons(
-mut commands: Commands,
-mut ambush_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<StartAmbushButton>)>,
-mut multiplayer_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<StartMultiplayerButton>)>,
-mut quit_button_query: Query<(&Interaction), (Changed<Interaction>, With<Button>, With<QuitGameButton>)>,
-query: Query<Entity>,
-client: Res<Client>,
-mut next_state: ResMut<NextState<GameState>>,
-
-) {
-	for interaction in ambush_button_query.iter() {
-		match *interaction {
-			Interaction::Pressed => {
-				next_state.set(GameState::LoadAmbush);
-			},
-			_ => { empty_system(); },
-		}
-	}
-	
-	for interaction in multiplayer_button_query.iter() {
-		match *interaction {
-			Interaction::Pressed => {
-				client
-					.connection()
-					.try_send_message(ClientMessage::StartGame);
-			},
-			_ => { empty_system(); },
-		}
+// Utility
+fn replay_path(name: &str) -> String {
+	// A plain name refers to a replay in the replays directory, anything else is a path.
+	if !name.is_empty() && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '-') {
+		return format!("{}/{}.ron", REPLAYS_DIRECTORY, name);
 	}
 	
-	for interaction in quit_button_query.iter() {
-		match *interaction {
-			Interaction::Pressed => {
-				for entity in query.iter() {
-					commands.entity(entity).despawn();
-				}
-			},
-			_ => { empty_system(); },
-		}
+	name.to_string()
+}
+
+// Utility
+fn write_replay_file(replay: &Replay, path: &str) -> Result<(), String> {
+	let contents = ron::to_string(replay).map_err(|error| error.to_string())?;
+	fs::create_dir_all(REPLAYS_DIRECTORY).map_err(|error| error.to_string())?;
+	fs::write(path, contents).map_err(|error| error.to_string())
+}
+
+// Utility
+fn read_replay(path: &str) -> Result<Replay, String> {
+	let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+	parse_replay(&contents)
+}
+
+// Utility
+fn parse_replay(contents: &str) -> Result<Replay, String> {
+	#[derive(Deserialize)]
+	struct ReplayVersion {
+		version: u32,
+	}
+	
+	let replay_version = ron::from_str::<ReplayVersion>(contents).map_err(|error| error.to_string())?;
+	if replay_version.version != REPLAY_VERSION {
+		return Err(format!("the replay is version {}, but this version of the game reads version {}", replay_version.version, REPLAY_VERSION));
 	}
+	
+	ron::from_str::<Replay>(contents).map_err(|error| error.to_string())
 }
 
-// Prototype
-fn set_loading_complete(
-mut next_state: ResMut<NextState<GameState>>) {
-	info!("DEBUG: Setting GameState to LoadingComplete...");
-	next_state.set(GameState::LoadingComplete);	
-	info!("DEBUG: Set GameState to LoadingComplete.");
+// Utility
+fn load_classes() -> Result<Classes, String> {
+	let contents = fs::read_to_string(CLASSES_FILE).map_err(|error| format!("Couldn't read the classes {}: {}.", CLASSES_FILE, error))?;
+	ron::from_str::<Classes>(&contents).map_err(|error| format!("Invalid classes in {}: {}.", CLASSES_FILE, error))
 }
 
-// Prototype
-fn is_multiplayer(
-game: Res<Game>,
-) -> bool {
-	return game.is_multiplayer;
+// Utility
+/// The teams of a scenario that players can take, in order. Teams without regular units have no player.
+fn scenario_teams(scenario: &str) -> Vec<usize> {
+	// A scenario whose units can't be read has no team to take.
+	let records = read_unit_records(scenario).unwrap_or_else(|error| {
+		info!("DEBUG: {}", error);
+		Vec::new()
+	});
+	// Guests and neutral units need the AI, which the server doesn't run.
+	if records.iter().any(|record| record.role.unwrap_or_default() != UnitRole::Regular) {
+		info!("DEBUG: Scenario {} has guests or neutral units, which multiplayer doesn't support.", scenario);
+		return Vec::new();
+	}
+	let mut teams: Vec<usize> = records
+		.iter()
+		.filter(|record| record.role.unwrap_or_default() == UnitRole::Regular)
+		.map(|record| record.unit_team)
+		.collect();
+	teams.sort();
+	teams.dedup();
+	teams
 }
 
-// Prototype
-fn is_singleplayer(
-game: Res<Game>,
-) -> bool {
-	return !game.is_multiplayer;
+// Utility
+fn read_unit_records(scenario: &str) -> Result<Vec<UnitRecord>, String> {
+	let path = format!("src/{}_data.csv", scenario);
+	let mut rdr = Reader::from_path(&path).map_err(|error| format!("Couldn't read the units {}: {}.", path, error))?;
+	rdr.deserialize()
+		.map(|result| result.map_err(|error| format!("Invalid unit in {}: {}.", path, error)))
+		.collect()
 }
 
 // Utility
-fn find_path(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, start: Pos, destination: Pos) -> Option<Vec<Pos>> {
-    // Define a heuristic function that estimates the distance between two positions.
-    // In this case, we use the Manhattan distance (taxicab distance).
-    let heuristic = |pos: &Pos| -> usize {
-        (pos.x as isize - destination.x as isize).abs() as usize
-            + (pos.y as isize - destination.y as isize).abs() as usize
-    };
+fn load_items() -> Result<Items, String> {
+	let contents = fs::read_to_string(ITEMS_FILE).map_err(|error| format!("Couldn't read the items {}: {}.", ITEMS_FILE, error))?;
+	ron::from_str::<Items>(&contents).map_err(|error| format!("Invalid items in {}: {}.", ITEMS_FILE, error))
+}
 
-	// Use RefCell to wrap the map so that it can be mutated inside the closure.
-    let map_cell = RefCell::new(map);
+// Utility
+fn referenced_scenarios() -> Vec<String> {
+	// The lobby's scenarios and the campaign's.
+	let mut scenarios: Vec<String> = SCENARIOS.iter().map(|scenario| scenario.to_string()).collect();
+	if let Ok(definition) = load_campaign_definition(CAMPAIGN_FILE) {
+		scenarios.extend(definition.battles.iter().map(|battle| battle.scenario.clone()));
+	}
+	scenarios.sort();
+	scenarios.dedup();
+	scenarios
+}
 
-    // Define a function that returns the valid neighboring positions of a given position.
-    let neighbors = |pos: &Pos| -> Vec<(Pos, usize)> {
-		// Access the map using borrow_mut() to allow mutation.
-		let mut map = map_cell.borrow_mut();
-    
-        // Add logic to get the valid neighboring positions based on your map layout.
-        // For example, avoid diagonal moves and ensure the position is within the map bounds.
-        // For simplicity, let's assume you have a function called `get_valid_neighbors`.
-        get_valid_neighbors((&mut map).to_vec(), *pos)
-    };
+// Utility
+/// Loads the classes and items, and checks them against every scenario and the campaign,
+/// so that invalid game data is reported at startup, before any battle can run into it.
+fn load_game_data() -> Result<(Classes, Items), String> {
+	let classes = load_classes()?;
+	let items = load_items()?;
+	validate_unit_classes(&classes)?;
+	validate_unit_items(&items)?;
+	Ok((classes, items))
+}
 
-    // Use the `astar` function from the pathfinding library to find the path.
-    let result = astar(&start, neighbors, heuristic, |&pos| pos == destination);
+// Utility
+fn validate_unit_classes(classes: &Classes) -> Result<(), String> {
+	// A class lists each of its abilities once.
+	for (name, class) in classes.classes.iter() {
+		for (index, ability) in class.abilities.iter().enumerate() {
+			if class.abilities[..index].contains(ability) {
+				return Err(format!("The class {} lists the ability {:?} twice.", name, ability));
+			}
+		}
+	}
+	
+	// Every class a scenario or the campaign refers to has to exist. The roster is checked when the campaign is loaded.
+	for scenario in referenced_scenarios().iter() {
+		for record in read_unit_records(scenario)? {
+			if classes.get(&record.unit_class).is_none() {
+				return Err(format!("Unit {} of {} has the unknown class {}.", record.unit_id, scenario, record.unit_class));
+			}
+			// Items are only given to units that can use them.
+			if !record.inventory_items().is_empty() && !classes.allows(&record.unit_class, Ability::UseItem) {
+				return Err(format!("Unit {} of {} carries items, but its class {} can't use them.", record.unit_id, scenario, record.unit_class));
+			}
+		}
+	}
+	
+	if let Ok(definition) = load_campaign_definition(CAMPAIGN_FILE) {
+		for class in definition.recruitment.classes.iter() {
+			if classes.get(class).is_none() {
+				return Err(format!("The campaign recruits the unknown class {}.", class));
+			}
+		}
+	}
+	
+	Ok(())
+}
 
-    if let Some((path, _)) = result {
-        Some(path)
-    } else {
-        None
-    }
+// Utility
+/// A campaign saved before a class was removed can't be played on.
+fn check_roster_classes(progress: &CampaignProgress, classes: &Classes) -> Result<(), String> {
+	match progress.roster.iter().find(|unit| classes.get(&unit.class).is_none()) {
+		Some(unit) => Err(format!("{} of the campaign roster has the unknown class {}.", unit.name, unit.class)),
+		None => Ok(()),
+	}
 }
 
 // Utility
-fn get_valid_neighbors(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, pos: Pos) -> Vec<(Pos, usize)> {
-	let mut neighbors: Vec<(Pos, usize)> = Vec::new(); 
+fn validate_unit_items(items: &Items) -> Result<(), String> {
+	// Equipped items have to exist and fit their slot, carried items have to be consumables.
+	let check = |owner: &str, equipment: &Equipment, inventory: &Vec<String>| -> Result<(), String> {
+		for slot in EquipmentSlot::ALL {
+			if let Some(item) = equipment.slot(slot) {
+				if items.slot(item) != Some(slot) {
+					return Err(format!("{} has {} equipped as its {:?}, which isn't an item for that slot.", owner, item, slot));
+				}
+			}
+		}
+		for item in inventory {
+			if items.consumable(item).is_none() {
+				return Err(format!("{} carries {}, which isn't a consumable item.", owner, item));
+			}
+		}
+		Ok(())
+	};
 	
-	// Check if tile is at North edge.
-	if pos.y == map[0].len() - 1 {
-		// Tile is at North edge. Don't add North neighbor.
-	} else {
-		// Tile is not at North edge. 
-		// Check if there's a unit on the tile.
-		// If there isn't, add North neighbor.
-		if map[pos.x][pos.y + 1].2.len() == 0 {
-			neighbors.push((Pos { x: pos.x, y: pos.y + 1, }, 0));
+	for scenario in referenced_scenarios().iter() {
+		for record in read_unit_records(scenario)? {
+			let equipment = Equipment {
+				weapon: record.weapon.clone(),
+				shield: record.shield.clone(),
+				armor: record.armor.clone(),
+				accessory: record.accessory.clone(),
+			};
+			check(&format!("Unit {} of {}", record.unit_id, scenario), &equipment, &record.inventory_items())?;
 		}
 	}
 	
-	// Check if tile is at South edge.
-	if pos.y == 0 {
-		// Tile is at South edge. Don't add South neighbor.
-	} else {
-		// Tile is not at South edge.
-		// Check if there's a unit on the tile.
-		// If there isn't, add South neighbor.
-		if map[pos.x][pos.y - 1].2.len() == 0 {
-			neighbors.push((Pos { x: pos.x, y: pos.y - 1, }, 0));
+	if let Ok(contents) = fs::read_to_string(CAMPAIGN_FILE) {
+		if let Ok(definition) = ron::from_str::<CampaignDefinition>(&contents) {
+			if let Some(item) = definition.inventory.iter().find(|item| items.get(item).is_none()) {
+				return Err(format!("The campaign starts with the unknown item {}.", item));
+			}
 		}
 	}
 	
-	// Check if tile is at East edge.
-	if pos.x == map.len() - 1 {
-		// Tile is at East edge. Don't add East neighbor.
-	} else {
-		// Tile is not at East edge.
-		// Check if there's a unit on the tile.
-		// If there isn't, add East neighbor.
-		if map[pos.x + 1][pos.y].2.len() == 0 {
-			neighbors.push((Pos { x: pos.x + 1, y: pos.y, }, 0));
+	if let Ok(progress) = read_campaign_progress(CAMPAIGN_SAVE_PATH) {
+		for unit in progress.roster.iter() {
+			check(&format!("{} of the campaign roster", unit.name), &unit.equipment, &unit.inventory)?;
 		}
-	} 
-	
-	// Check if tile is at West edge.
-	if pos.x == 0 {
-		// Tile is at West edge. Don't add West neighbor.
-	} else {
-		// Tile is not at West edge.
-		// Check if there's a unit on the tile.
-		// If there isn't, add West neighbor.
-		if map[pos.x - 1][pos.y].2.len() == 0 {
-			neighbors.push((Pos { x: pos.x - 1, y: pos.y, }, 0));
+		if let Some(item) = progress.inventory.iter().find(|item| items.get(item).is_none()) {
+			return Err(format!("The campaign inventory has the unknown item {}.", item));
 		}
 	}
 	
-	return neighbors;
+	Ok(())
 }
 
-use std::collections::HashSet;
+// Utility
+fn first_level() -> usize {
+	1
+}
 
-// Prototype
-fn find_possible_movements(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, start: Pos, mut movement_range: isize) -> Vec<Pos> {
-    let mut possible_tiles_vec = Vec::new();
-    movement_range -= 1;
-	
-	let mut visited_tiles = HashSet::new();
+// Utility
+fn gain_experience(level: &mut usize, experience: &mut usize, amount: usize) -> usize {
+	// Returns the number of levels gained.
+	*experience += amount;
+	let levels = *experience / XP_PER_LEVEL;
+	*experience %= XP_PER_LEVEL;
+	*level += levels;
+	levels
+}
+
+// Utility
+fn load_campaign_definition(path: &str) -> Result<CampaignDefinition, String> {
+	let contents = fs::read_to_string(path).map_err(|error| format!("Couldn't read the campaign {}: {}.", path, error))?;
+	ron::from_str::<CampaignDefinition>(&contents).map_err(|error| format!("Invalid campaign in {}: {}.", path, error))
+}
+
+// Utility
+/// The rows of the team's own units, leaving out its guests and neutral units.
+fn team_records(scenario: &str, team: usize) -> Result<Vec<UnitRecord>, String> {
+	Ok(read_unit_records(scenario)?
+		.into_iter()
+		.filter(|record| record.unit_team == team && record.role.unwrap_or_default() == UnitRole::Regular)
+		.collect())
+}
+
+// Utility
+fn new_campaign_progress(definition: &CampaignDefinition, classes: &Classes) -> Result<CampaignProgress, String> {
+	// The roster starts out as the team's units in the first battle.
+	let roster = match definition.battle(&definition.start) {
+		Some(battle) => team_records(&battle.scenario, definition.team)?
+			.iter()
+			.enumerate()
+			.map(|(index, record)| record.unit_attributes(classes).map(|unit_attributes| RosterUnit::from_unit_attributes(index + 1, &unit_attributes)))
+			.collect::<Result<Vec<RosterUnit>, String>>()?,
+		None => Vec::new(),
+	};
 	
-	if movement_range >= 0 {
-		// Get neighbors.
-		let neighbors = get_valid_neighbors(map.clone(), start);
-		for neighbor in &neighbors {
-			if visited_tiles.insert(neighbor.0) {
-				possible_tiles_vec.push(neighbor.0);
-				let mut recursive_possible_tiles = find_possible_movements(map.clone(), neighbor.0, movement_range);
-				
-				for possible_tile in recursive_possible_tiles {
-					if !possible_tiles_vec.contains(&possible_tile) {
-						possible_tiles_vec.push(possible_tile);
-					}
+	Ok(CampaignProgress {
+		version: CAMPAIGN_SAVE_VERSION,
+		current_battle: Some(definition.start.clone()),
+		next_roster_id: roster.len() + 1,
+		roster: roster,
+		history: Vec::new(),
+		inventory: definition.inventory.clone(),
+		rng: BattleRng::default(),
+	})
+}
+
+// Utility
+/// The units the defeat objectives of the campaign's battles name, for the roster's team.
+fn story_units(definition: &CampaignDefinition) -> Vec<String> {
+	let mut story_units: Vec<String> = Vec::new();
+	for battle in &definition.battles {
+		// An invalid objectives file is reported when the battle starts.
+		let Ok(objectives) = load_objectives(&battle.scenario) else {
+			continue;
+		};
+		if objectives.team != definition.team {
+			continue;
+		}
+		for objective in &objectives.defeat {
+			if let Objective::UnitDefeated { unit_name } = objective {
+				if !story_units.contains(unit_name) {
+					story_units.push(unit_name.clone());
 				}
 			}
 		}
-    }
+	}
+	story_units
+}
 
-    possible_tiles_vec
+// Utility
+/// The names of the units of the campaign's scenarios, and of every unit their objectives name.
+fn reserved_names(definition: &CampaignDefinition) -> Vec<String> {
+	let mut names: Vec<String> = Vec::new();
+	for battle in &definition.battles {
+		// An invalid scenario file is reported when the battle starts.
+		if let Ok(records) = read_unit_records(&battle.scenario) {
+			names.extend(records.into_iter().map(|record| record.unit_name));
+		}
+		if let Ok(objectives) = load_objectives(&battle.scenario) {
+			names.extend(objectives.unit_names());
+		}
+	}
+	names.sort();
+	names.dedup();
+	names
 }
 
 // Utility
-fn get_valid_attack_neighbors(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, pos: Pos) -> Vec<(Pos, usize)> {
-	let mut neighbors: Vec<(Pos, usize)> = Vec::new(); 
+fn prepare_campaign_battle(campaign: &mut Campaign) -> Result<(), String> {
+	let (Some(definition), Some(progress)) = (&campaign.definition, &campaign.progress) else {
+		return Ok(());
+	};
+	campaign.story_units = story_units(definition);
+	campaign.reserved_names = reserved_names(definition);
+	let Some(battle) = progress.current_battle.as_ref().and_then(|battle_id| definition.battle(battle_id)) else {
+		campaign.deployment_slots = 0;
+		campaign.required_units.clear();
+		campaign.selected.clear();
+		return Ok(());
+	};
 	
-	// Check if tile is at North edge.
-	if pos.y == map[0].len() - 1 {
-		// Tile is at North edge. Don't add North neighbor.
-	} else {
-		// Tile is not at North edge. 
-		neighbors.push((Pos { x: pos.x, y: pos.y + 1, }, 0));
-	}
+	// Every row of the roster's own units in the scenario is a deployment slot.
+	let deployment_slots = team_records(&battle.scenario, definition.team)?.len();
 	
-	// Check if tile is at South edge.
-	if pos.y == 0 {
-		// Tile is at South edge. Don't add South neighbor.
+	// Units whose defeat loses the battle have to be deployed.
+	let objectives = load_objectives(&battle.scenario)?;
+	let required_units: Vec<String> = if objectives.team == definition.team {
+		objectives.defeat
+			.iter()
+			.filter_map(|objective| match objective {
+				Objective::UnitDefeated { unit_name } => Some(unit_name.clone()),
+				_ => None,
+			})
+			.collect()
 	} else {
-		// Tile is not at South edge.
-		neighbors.push((Pos { x: pos.x, y: pos.y - 1, }, 0));
+		Vec::new()
+	};
+	
+	// Required units come first, then the rest of the living roster in order.
+	let living_units: Vec<&RosterUnit> = progress.roster.iter().filter(|roster_unit| !roster_unit.dead).collect();
+	let mut selected: Vec<usize> = living_units
+		.iter()
+		.filter(|roster_unit| required_units.contains(&roster_unit.name))
+		.map(|roster_unit| roster_unit.roster_id)
+		.collect();
+	for roster_unit in living_units {
+		if !selected.contains(&roster_unit.roster_id) {
+			selected.push(roster_unit.roster_id);
+		}
 	}
+	selected.truncate(deployment_slots);
 	
-	// Check if tile is at East edge.
-	if pos.x == map.len() - 1 {
-		// Tile is at East edge. Don't add East neighbor.
-	} else {
-		// Tile is not at East edge.
-		neighbors.push((Pos { x: pos.x + 1, y: pos.y, }, 0));
-	} 
+	campaign.deployment_slots = deployment_slots;
+	campaign.required_units = required_units;
+	campaign.selected = selected;
 	
-	// Check if tile is at West edge.
-	if pos.x == 0 {
-		// Tile is at West edge. Don't add West neighbor.
-	} else {
-		// Tile is not at West edge.
-		neighbors.push((Pos { x: pos.x - 1, y: pos.y, }, 0));
+	Ok(())
+}
+
+// Utility
+fn update_roster(progress: &mut CampaignProgress, deployed: &Vec<(usize, usize)>, units: &Vec<(usize, RosterUnit)>) {
+	for (unit_id, roster_id) in deployed {
+		let Some(roster_unit) = progress.roster.iter_mut().find(|roster_unit| roster_unit.roster_id == *roster_id) else {
+			continue;
+		};
+		
+		// Units no longer on the battlefield have died.
+		match units.iter().find(|(id, unit)| id == unit_id && unit.hp_current > 0) {
+			Some((_, unit)) => {
+				// The unit's weapon may have replaced its own attack during the battle.
+				*roster_unit = RosterUnit {
+					roster_id: roster_unit.roster_id,
+					attack_range: roster_unit.attack_range,
+					attack_type: roster_unit.attack_type,
+					battles: roster_unit.battles + 1,
+					dead: false,
+					..unit.clone()
+				};
+			},
+			None => {
+				roster_unit.battles += 1;
+				roster_unit.hp_current = 0;
+				roster_unit.dead = true;
+			},
+		}
 	}
-	
-	return neighbors;
 }
 
-// Prototype
-fn find_possible_attacks(map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>>, start: Pos, mut attack_range: isize, attack_type: AttackType) -> Vec<Pos> {
-    let mut possible_tiles_vec = Vec::new();
-    
-    match attack_type {
-		AttackType::Melee => {
-			// Get neighbors.
-			let neighbors = get_valid_attack_neighbors(map.clone(), start);
-			for neighbor in &neighbors {
-				possible_tiles_vec.push(neighbor.0);
+// Utility
+fn apply_inventory_change(progress: &mut CampaignProgress, items: &Items, inventory_change: &InventoryChange) {
+	match inventory_change {
+		InventoryChange::Equip { roster_id, slot, item } => {
+			if item.map_or(false, |item| progress.inventory.get(item).and_then(|item| items.slot(item)) != Some(*slot)) {
+				return;
 			}
+			let Some(roster_unit) = progress.roster.iter_mut().find(|roster_unit| roster_unit.roster_id == *roster_id) else {
+				return;
+			};
 			
-			for neighbor in &neighbors {
-				for i in 1..attack_range {
-					// If there is an unit on the tile, don't search for more neighbors.
-					if map[neighbor.0.x][neighbor.0.y].2.len() > 0 {
-						break;
-					}
-					// Else...
-					// Add next neighbor.
-					let neighbors = get_valid_attack_neighbors(map.clone(), neighbor.0);
-					for neighbor in neighbors {
-						if (neighbor.0.x == start.x || neighbor.0.y == start.y) {
-							if !(neighbor.0.x == start.x && neighbor.0.y == start.y) {
-								if !(possible_tiles_vec.contains(&neighbor.0)) {
-									possible_tiles_vec.push(neighbor.0);
-								}
-							}
-						}
-					}
-				}			
+			let equipped = match item {
+				Some(item) => Some(progress.inventory.remove(*item)),
+				None => None,
+			};
+			if let Some(unequipped) = std::mem::replace(roster_unit.equipment.slot_mut(*slot), equipped) {
+				progress.inventory.push(unequipped);
 			}
 		},
-		AttackType::Ranged => {
-			attack_range -= 1;
-			
-			let mut visited_tiles = HashSet::new();
+		InventoryChange::Give { roster_id, item } => {
+			if progress.inventory.get(*item).and_then(|item| items.consumable(item)).is_none() {
+				return;
+			}
+			let Some(roster_unit) = progress.roster.iter_mut().find(|roster_unit| roster_unit.roster_id == *roster_id) else {
+				return;
+			};
 			
-			if attack_range >= 0 {
-				// Get neighbors.
-				let neighbors = get_valid_attack_neighbors(map.clone(), start);
-				for neighbor in &neighbors {
-					if visited_tiles.insert(neighbor.0) {
-						possible_tiles_vec.push(neighbor.0);
-						let mut recursive_possible_tiles = find_possible_attacks(map.clone(), neighbor.0, attack_range, attack_type);
-						
-						for possible_tile in recursive_possible_tiles {
-							if !possible_tiles_vec.contains(&possible_tile) {
-								possible_tiles_vec.push(possible_tile);
-							}
-						}
-					}
+			roster_unit.inventory.push(progress.inventory.remove(*item));
+		},
+		InventoryChange::TakeBack { roster_id, item } => {
+			let Some(roster_unit) = progress.roster.iter_mut().find(|roster_unit| roster_unit.roster_id == *roster_id) else {
+				return;
+			};
+			if *item < roster_unit.inventory.len() {
+				progress.inventory.push(roster_unit.inventory.remove(*item));
+			}
+		},
+	}
+}
+
+// Utility
+/// Makes the change to the roster, and returns what to tell the player about it.
+fn apply_roster_change(progress: &mut CampaignProgress, definition: &CampaignDefinition, classes: &Classes, story_units: &Vec<String>, reserved_names: &Vec<String>, roster_change: &RosterChange) -> Result<String, String> {
+	match roster_change {
+		RosterChange::Recruit { class } => {
+			let recruitment = &definition.recruitment;
+			let Some(class_definition) = classes.get(class).filter(|_| recruitment.classes.contains(class)) else {
+				return Err(format!("{} can't be recruited.", class));
+			};
+			if progress.roster.iter().filter(|roster_unit| !roster_unit.dead).count() >= recruitment.roster_size {
+				return Err(String::from("The roster is full."));
+			}
+			
+			// Saves from before the counter start it after the highest id.
+			let roster_id = progress.next_roster_id.max(progress.roster.iter().map(|roster_unit| roster_unit.roster_id).max().unwrap_or(0) + 1);
+			progress.next_roster_id = roster_id + 1;
+			let free_names: Vec<&String> = recruitment.names
+				.iter()
+				.filter(|name| !progress.roster.iter().any(|roster_unit| roster_unit.name == **name))
+				.collect();
+			let name = if free_names.is_empty() {
+				format!("{} {}", class, roster_id)
+			} else {
+				free_names[progress.rng.rng.gen_range(0..free_names.len())].clone()
+			};
+			
+			let recruit = RosterUnit::recruit(roster_id, name, class, class_definition, recruitment.stat_variance, &mut progress.rng.rng);
+			let message = format!("{} the {} joins the roster.", recruit.name, recruit.class);
+			progress.roster.push(recruit);
+			Ok(message)
+		},
+		RosterChange::Rename { roster_id, name } => {
+			let name: String = name.trim().chars().take(UNIT_NAME_MAX_LENGTH).collect();
+			if name.is_empty() {
+				return Err(String::from("The unit needs a name."));
+			}
+			// Objectives find units by their names.
+			if story_units.contains(&name) || progress.roster.iter().any(|roster_unit| roster_unit.name == name && roster_unit.roster_id != *roster_id) {
+				return Err(format!("{} is already taken.", name));
+			}
+			let Some(roster_unit) = progress.roster.iter_mut().find(|roster_unit| roster_unit.roster_id == *roster_id) else {
+				return Err(String::from("The unit isn't in the roster."));
+			};
+			if story_units.contains(&roster_unit.name) {
+				return Err(format!("{} can't be renamed.", roster_unit.name));
+			}
+			// A unit of the campaign's scenarios, of any team, keeps its name to itself. Keeping the unit's own name is fine.
+			if name != roster_unit.name && reserved_names.contains(&name) {
+				return Err(format!("{} is already taken.", name));
+			}
+			
+			let message = format!("{} is now called {}.", roster_unit.name, name);
+			roster_unit.name = name;
+			Ok(message)
+		},
+		RosterChange::Dismiss { roster_id } => {
+			let Some(index) = progress.roster.iter().position(|roster_unit| roster_unit.roster_id == *roster_id) else {
+				return Err(String::from("The unit isn't in the roster."));
+			};
+			if story_units.contains(&progress.roster[index].name) {
+				return Err(format!("{} can't be dismissed.", progress.roster[index].name));
+			}
+			
+			let roster_unit = progress.roster.remove(index);
+			for slot in EquipmentSlot::ALL {
+				if let Some(item) = roster_unit.equipment.slot(slot) {
+					progress.inventory.push(item.clone());
 				}
 			}
+			progress.inventory.extend(roster_unit.inventory);
+			Ok(format!("{} leaves the roster.", roster_unit.name))
 		},
-		_ => {
-			empty_system();
+	}
+}
+
+// Utility
+fn write_campaign_progress(progress: &CampaignProgress, path: &str) -> Result<(), String> {
+	let contents = ron::ser::to_string_pretty(progress, ron::ser::PrettyConfig::default()).map_err(|error| error.to_string())?;
+	fs::write(path, contents).map_err(|error| error.to_string())
+}
+
+// Utility
+fn read_campaign_progress(path: &str) -> Result<CampaignProgress, String> {
+	let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+	parse_campaign_progress(&contents)
+}
+
+// Utility
+fn parse_campaign_progress(contents: &str) -> Result<CampaignProgress, String> {
+	#[derive(Deserialize)]
+	struct CampaignSaveVersion {
+		version: u32,
+	}
+	
+	let campaign_save_version = ron::from_str::<CampaignSaveVersion>(contents).map_err(|error| error.to_string())?;
+	if campaign_save_version.version != CAMPAIGN_SAVE_VERSION {
+		return Err(format!("the campaign save is version {}, but this version of the game reads version {}", campaign_save_version.version, CAMPAIGN_SAVE_VERSION));
+	}
+	
+	ron::from_str::<CampaignProgress>(contents).map_err(|error| error.to_string())
+}
+
+// Utility
+fn load_player_profile(path: &str) -> PlayerProfile {
+	if let Ok(contents) = fs::read_to_string(path) {
+		match ron::from_str::<PlayerProfile>(&contents) {
+			Ok(profile) => {
+				info!("DEBUG: Loaded player profile {} from {}.", profile.player_id, path);
+				return profile;
+			},
+			Err(error) => {
+				info!("DEBUG: Couldn't read player profile {}: {}. Creating a new one.", path, error);
+			},
+		}
+	}
+	
+	let profile = PlayerProfile {
+		player_id: Uuid::new_v4(),
+		name: String::from("Player"),
+		token: None,
+	};
+	save_player_profile(&profile, path);
+	profile
+}
+
+// Utility
+fn save_player_profile(profile: &PlayerProfile, path: &str) {
+	match ron::ser::to_string_pretty(profile, ron::ser::PrettyConfig::default()) {
+		Ok(contents) => {
+			if let Err(error) = fs::write(path, contents) {
+				info!("DEBUG: Couldn't save player profile to {}: {}.", path, error);
+			}
 		},
-    }
-    
-    
+		Err(error) => {
+			info!("DEBUG: Couldn't serialize player profile: {}.", error);
+		},
+	}
+}
 
-    possible_tiles_vec
+// Utility
+fn display_name(players: &Players, client_id: ClientId) -> String {
+	match players.players.iter().find(|player| player.client_id == client_id) {
+		Some(player) => player.name.clone(),
+		None => format!("Player {}", client_id),
+	}
 }
 
 // Logging
@@ -3666,4 +11682,1433 @@ fn test_write_to_console(mut console_line: EventWriter<PrintConsoleLine>) {
 
 fn empty_system() {
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CLIENT_ID: ClientId = 1;
+	const OTHER_CLIENT_ID: ClientId = 2;
+
+	// Builds a headless app running the server message handlers, without any network.
+	fn test_app() -> App {
+		let mut app = App::new();
+		app.add_plugins(MinimalPlugins);
+		app.add_state::<GameState>();
+		app.add_state::<TurnState>();
+		app.add_event::<GameStartEvent>();
+		app.init_resource::<Game>();
+		app.insert_resource(ClientData { client_id: CLIENT_ID, });
+		app.insert_resource(Classes { classes: HashMap::from([("Test Class".to_string(), test_class(StatGrowth::default()))]), });
+		app.init_resource::<Items>();
+		app.init_resource::<HotSeat>();
+		app.init_resource::<Alliances>();
+		app.init_resource::<ScenarioRules>();
+		app.init_resource::<Objectives>();
+		app.init_resource::<DeploymentZones>();
+		app.init_resource::<Deployment>();
+		app.init_resource::<BattleResult>();
+		app.init_resource::<BattleRng>();
+		add_server_message_handlers(&mut app);
+		app.add_systems(Update, (process_unit_actions, apply_deferred, process_basic_attack_actions, process_use_item_actions)
+			.chain()
+			.run_if(in_state(GameState::Battle))
+		);
+		app
+	}
+
+	// Puts the app in the state a client is in after loading a multiplayer battle.
+	fn setup_battle(app: &mut App) {
+		let mut map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>> = Vec::new();
+		for _ in 0..20 {
+			let mut map_line: Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)> = Vec::new();
+			for _ in 0..20 {
+				map_line.push((1, TileType::Grass, Vec::new(), Vec::new()));
+			}
+			map.push(map_line);
+		}
+		app.world.spawn(Map { map: map, });
+
+		app.world.resource_mut::<Game>().is_multiplayer = true;
+		app.world.resource_mut::<NextState<GameState>>().set(GameState::Wait);
+		app.update();
+	}
+
+	fn test_class(growth: StatGrowth) -> ClassDefinition {
+		ClassDefinition {
+			sprite: "hannibal".to_string(),
+			stats: BaseStats { wt: 600, hp: 60, mp: 0, str: 60, vit: 60, int: 60, men: 60, agi: 60, dex: 60, luk: 50, },
+			growth: growth,
+			movement: 6,
+			jump: 1,
+			attack: AttackProfile { range: 1, attack_type: AttackType::Melee, },
+			abilities: vec![Ability::Attack, Ability::UseItem],
+		}
+	}
+
+	fn spawn_test_unit(app: &mut App, unit_id: usize, unit_team: usize, pos: Pos) -> Entity {
+		let entity = app.world.spawn((
+			UnitAttributes {
+				unit_id: UnitId { value: unit_id, },
+				unit_team: UnitTeam { value: unit_team, },
+				unit_name: UnitName { value: "Test Unit".to_string(), },
+				unit_class: UnitClass { value: "Test Class".to_string(), },
+				pos_x: PosX { value: pos.x, },
+				pos_y: PosY { value: pos.y, },
+				wt_max: WTMax { value: 600, },
+				wt_current: WTCurrent { value: 600, },
+				hp_max: HPMax { value: 60, },
+				hp_current: HPCurrent { value: 60, },
+				mp_max: MPMax { value: 0, },
+				mp_current: MPCurrent { value: 0, },
+				str: STR { value: 60, },
+				vit: VIT { value: 60, },
+				int: INT { value: 60, },
+				men: MEN { value: 60, },
+				agi: AGI { value: 60, },
+				dex: DEX { value: 60, },
+				luk: LUK { value: 50, },
+				level: Level { value: 1, },
+				experience: Experience { value: 0, },
+				unit_sprite: UnitSprite { value: "hannibal".to_string(), },
+				dir: DIR { direction: Direction::East, },
+				movement_range: MovementRange { value: 6, },
+				jump: Jump { value: 1, },
+				attack_range: AttackRange { value: 1, },
+				attack_type: AttackType::Melee,
+				equipment: Equipment::default(),
+				inventory: Inventory::default(),
+				role: UnitRole::Regular,
+			},
+			Unit,
+			UnitActions { unit_actions: Default::default(), processing_unit_action: false, },
+			pos,
+			MoveActions { move_actions: Vec::new(), },
+		)).id();
+
+		let mut map_query = app.world.query::<&mut Map>();
+		map_query.single_mut(&mut app.world).map[pos.x][pos.y].2.push(entity);
+
+		entity
+	}
+
+	fn send(app: &mut App, message: ServerMessage) {
+		app.world.resource_mut::<ServerMessages>().messages.push_back(message);
+	}
+
+	fn game_state(app: &App) -> GameState {
+		app.world.resource::<State<GameState>>().get().clone()
+	}
+
+	fn turn_state(app: &App) -> TurnState {
+		app.world.resource::<State<TurnState>>().get().clone()
+	}
+
+	#[test]
+	fn client_id_message_sets_client_id() {
+		let mut app = test_app();
+		send(&mut app, ServerMessage::ClientId { client_id: 42, });
+		app.update();
+
+		assert_eq!(app.world.resource::<ClientData>().client_id, 42);
+	}
+
+	#[test]
+	fn start_game_message_starts_loading_multiplayer_battle() {
+		let mut app = test_app();
+		send(&mut app, ServerMessage::StartGame { client_id: CLIENT_ID, });
+		app.update();
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::Loading);
+		assert!(app.world.resource::<Game>().is_multiplayer);
+	}
+
+	#[test]
+	fn snapshot_message_loads_battle_as_spectator() {
+		let mut app = test_app();
+		send(&mut app, ServerMessage::Snapshot {
+			scenario: SCENARIOS[0].to_string(),
+			snapshot: BattleSnapshot {
+				units: vec![UnitSnapshot { unit_id: 1, pos: Pos { x: 2, y: 3, }, direction: Direction::West, wt_current: 4, hp_current: 50, }],
+				current_unit: Some(1),
+			},
+		});
+		app.update();
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::Loading);
+		let game = app.world.resource::<Game>();
+		assert!(game.is_multiplayer);
+		assert!(game.is_spectator);
+		assert!(app.world.resource::<PendingSnapshot>().snapshot.is_some());
+	}
+
+	#[test]
+	fn lobby_update_message_tracks_current_game_scenario() {
+		let mut app = test_app();
+		send(&mut app, ServerMessage::LobbyUpdate {
+			game: LobbyGame {
+				game_id: 3,
+				name: "Test Game".to_string(),
+				scenario: SCENARIOS[0].to_string(),
+				host: OTHER_CLIENT_ID,
+				players: vec![
+					LobbyPlayer { client_id: OTHER_CLIENT_ID, name: "Host".to_string(), team: 1, ready: true, },
+					LobbyPlayer { client_id: CLIENT_ID, name: "Guest".to_string(), team: 2, ready: true, },
+				],
+				teams: vec![1, 2],
+				countdown: Some(2),
+			},
+		});
+		app.update();
+
+		let current_game = app.world.resource::<Lobby>().current_game.clone().unwrap();
+		assert_eq!(current_game.game_id, 3);
+		assert_eq!(current_game.countdown, Some(2));
+
+		send(&mut app, ServerMessage::StartGame { client_id: CLIENT_ID, });
+		app.update();
+
+		assert!(app.world.resource::<Lobby>().current_game.is_none());
+		assert_eq!(app.world.resource::<Game>().scenario, SCENARIOS[0]);
+	}
+
+	#[test]
+	fn lobby_countdown_needs_one_ready_player_per_team() {
+		let player = |client_id: ClientId, team: usize| LobbyPlayer { client_id: client_id, name: format!("Player {}", client_id), team: team, ready: true, };
+		let mut game = LobbyGame {
+			game_id: 1,
+			name: "Test Game".to_string(),
+			scenario: SCENARIOS[0].to_string(),
+			host: 1,
+			players: vec![player(1, 1), player(2, 2)],
+			teams: vec![1, 2, 3],
+			countdown: None,
+		};
+
+		update_lobby_countdown(&mut game);
+		assert_eq!(game.countdown, None);
+
+		game.players.push(player(3, 3));
+		update_lobby_countdown(&mut game);
+		assert_eq!(game.countdown, Some(5));
+	}
+
+	#[test]
+	fn player_turn_message_for_this_client_starts_turn() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		let other_unit = spawn_test_unit(&mut app, 2, 2, Pos { x: 5, y: 5, });
+
+		send(&mut app, ServerMessage::PlayerTurn { client_id: CLIENT_ID, current_unit: 1, });
+		app.update();
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::Battle);
+		assert_eq!(turn_state(&app), TurnState::Turn);
+		assert_eq!(app.world.resource::<Game>().current_unit, 1);
+		assert!(app.world.get::<CurrentUnit>(unit).is_some());
+		assert!(app.world.get::<CurrentUnit>(other_unit).is_none());
+	}
+
+	#[test]
+	fn player_turn_message_for_other_client_waits() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 2, 2, Pos { x: 5, y: 5, });
+
+		send(&mut app, ServerMessage::PlayerTurn { client_id: OTHER_CLIENT_ID, current_unit: 2, });
+		app.update();
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::Battle);
+		assert_eq!(turn_state(&app), TurnState::Wait);
+		assert!(app.world.get::<CurrentUnit>(unit).is_some());
+	}
+
+	#[test]
+	fn wait_turn_message_updates_unit_wt() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+
+		send(&mut app, ServerMessage::WaitTurn { wait_turns: vec![(UnitId { value: 1, }, WTCurrent { value: 17, })], });
+		app.update();
+
+		assert_eq!(app.world.get::<WTCurrent>(unit).unwrap().value, 17);
+	}
+
+	#[test]
+	fn messages_received_during_state_change_are_not_dropped() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.resource_mut::<NextState<GameState>>().set(GameState::LoadingComplete);
+
+		send(&mut app, ServerMessage::WaitTurn { wait_turns: vec![(UnitId { value: 1, }, WTCurrent { value: 0, })], });
+		send(&mut app, ServerMessage::PlayerTurn { client_id: CLIENT_ID, current_unit: 1, });
+		app.update();
+		app.update();
+
+		assert_eq!(app.world.get::<WTCurrent>(unit).unwrap().value, 0);
+		assert!(app.world.get::<CurrentUnit>(unit).is_some());
+		assert_eq!(game_state(&app), GameState::Battle);
+	}
+
+	#[test]
+	fn messages_are_handled_in_the_order_they_were_sent() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+
+		// The move belongs to the unit whose turn was announced just before it.
+		send(&mut app, ServerMessage::PlayerTurn { client_id: OTHER_CLIENT_ID, current_unit: 1, });
+		send(&mut app, ServerMessage::Move { origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, });
+		app.update();
+		app.update();
+
+		assert_eq!(app.world.get::<UnitActions>(unit).unwrap().unit_actions.len(), 1);
+
+		// A new battle that starts right after the last one ends is not lost.
+		send(&mut app, ServerMessage::WinningTeam { team: 2, });
+		send(&mut app, ServerMessage::GameOver { winner: ControlledBy::Player, });
+		send(&mut app, ServerMessage::StartGame { client_id: CLIENT_ID, });
+		app.update();
+		app.update();
+		assert_eq!(game_state(&app), GameState::GameOver);
+		app.update();
+		assert_eq!(game_state(&app), GameState::Loading);
+	}
+
+	#[test]
+	fn move_message_queues_move_action_on_current_unit() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.resource_mut::<Game>().current_unit = 1;
+
+		send(&mut app, ServerMessage::Move { origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, });
+		app.update();
+
+		let unit_actions = app.world.get::<UnitActions>(unit).unwrap();
+		assert_eq!(unit_actions.unit_actions.len(), 1);
+		match &unit_actions.unit_actions[0].0 {
+			UnitAction::Move { origin, destination, .. } => {
+				assert_eq!(*origin, Pos { x: 1, y: 1, });
+				assert_eq!(*destination, Pos { x: 3, y: 1, });
+			},
+			_ => panic!("Expected a Move UnitAction."),
+		}
+	}
+
+	#[test]
+	fn move_message_confirms_predicted_move() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.resource_mut::<Game>().current_unit = 1;
+		app.world.resource_mut::<PredictedMove>().value = Some((Pos { x: 1, y: 1, }, Pos { x: 3, y: 1, }));
+
+		send(&mut app, ServerMessage::Move { origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, });
+		app.update();
+
+		assert!(app.world.resource::<PredictedMove>().value.is_none());
+		assert_eq!(app.world.get::<UnitActions>(unit).unwrap().unit_actions.len(), 0);
+	}
+
+	#[test]
+	fn refused_move_rolls_back_predicted_move() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.entity_mut(unit).insert(Transform::default());
+		app.world.resource_mut::<Game>().current_unit = 1;
+
+		// The predicted move is still queued, but the unit already stands one step along it.
+		app.world.get_mut::<UnitActions>(unit).unwrap().unit_actions.push(UnitActionTuple(UnitAction::Move {
+			origin: Pos { x: 1, y: 1, },
+			destination: Pos { x: 3, y: 1, },
+			timer: Timer::from_seconds(4.0, TimerMode::Once),
+		}, 0.0));
+		app.world.resource_mut::<PredictedMove>().value = Some((Pos { x: 1, y: 1, }, Pos { x: 3, y: 1, }));
+		*app.world.get_mut::<Pos>(unit).unwrap() = Pos { x: 2, y: 1, };
+		{
+			let mut map_query = app.world.query::<&mut Map>();
+			let map = &mut map_query.single_mut(&mut app.world).map;
+			map[1][1].2.clear();
+			map[2][1].2.push(unit);
+		}
+
+		// The refusal of another move leaves the predicted move alone.
+		send(&mut app, ServerMessage::Error {
+			code: ErrorCode::InvalidMove,
+			message: "The unit can't move to that tile.".to_string(),
+			rejected_move: Some((Pos { x: 2, y: 1, }, Pos { x: 2, y: 3, })),
+		});
+		app.update();
+		assert!(app.world.resource::<PredictedMove>().value.is_some());
+		assert_eq!(*app.world.get::<Pos>(unit).unwrap(), Pos { x: 2, y: 1, });
+
+		send(&mut app, ServerMessage::Error {
+			code: ErrorCode::InvalidMove,
+			message: "The unit can't move to that tile.".to_string(),
+			rejected_move: Some((Pos { x: 1, y: 1, }, Pos { x: 3, y: 1, })),
+		});
+		app.update();
+		app.update();
+
+		assert!(app.world.resource::<PredictedMove>().value.is_none());
+		assert_eq!(*app.world.get::<Pos>(unit).unwrap(), Pos { x: 1, y: 1, });
+		assert_eq!(app.world.get::<UnitActions>(unit).unwrap().unit_actions.len(), 0);
+
+		let mut map_query = app.world.query::<&Map>();
+		let map = &map_query.single(&app.world).map;
+		assert_eq!(map[1][1].2, vec![unit]);
+		assert!(map[2][1].2.is_empty());
+	}
+
+	#[test]
+	fn refusal_without_a_move_rolls_back_predicted_move() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.entity_mut(unit).insert(Transform::default());
+		app.world.resource_mut::<Game>().current_unit = 1;
+
+		// An `Error` sent before the server looked at the move doesn't name it.
+		app.world.get_mut::<UnitActions>(unit).unwrap().unit_actions.push(UnitActionTuple(UnitAction::Move {
+			origin: Pos { x: 1, y: 1, },
+			destination: Pos { x: 3, y: 1, },
+			timer: Timer::from_seconds(4.0, TimerMode::Once),
+		}, 0.0));
+		app.world.resource_mut::<PredictedMove>().value = Some((Pos { x: 1, y: 1, }, Pos { x: 3, y: 1, }));
+		send(&mut app, ServerMessage::Error { code: ErrorCode::NotYourTurn, message: "It is not your turn.".to_string(), rejected_move: None, });
+		app.update();
+		assert!(app.world.resource::<PredictedMove>().value.is_none());
+		assert_eq!(app.world.get::<UnitActions>(unit).unwrap().unit_actions.len(), 0);
+		assert_eq!(*app.world.get::<Pos>(unit).unwrap(), Pos { x: 1, y: 1, });
+	}
+
+	#[test]
+	fn error_message_shows_toast_and_returns_to_turn() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		app.world.resource_mut::<NextState<TurnState>>().set(TurnState::ChooseAttack);
+		app.update();
+
+		send(&mut app, ServerMessage::Error { code: ErrorCode::InvalidAttack, message: "The unit can't attack that tile.".to_string(), rejected_move: None, });
+		app.update();
+		app.update();
+
+		assert_eq!(turn_state(&app), TurnState::Turn);
+		assert_eq!(app.world.resource::<Toasts>().toasts.len(), 1);
+	}
+
+	#[test]
+	fn basic_attack_message_applies_server_damage() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let attacker = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		let target = spawn_test_unit(&mut app, 2, 2, Pos { x: 2, y: 1, });
+
+		send(&mut app, ServerMessage::PlayerTurn { client_id: CLIENT_ID, current_unit: 1, });
+		send(&mut app, ServerMessage::BasicAttack {
+			attacker: Pos { x: 1, y: 1, },
+			target: Pos { x: 2, y: 1, },
+			damage: 13,
+			is_counterattack: false,
+		});
+		for _ in 0..4 {
+			app.update();
+		}
+
+		assert_eq!(turn_state(&app), TurnState::Turn);
+		assert_eq!(app.world.get::<HPCurrent>(target).unwrap().value, 47);
+		assert!(app.world.get::<Attacker>(attacker).is_none());
+		assert!(app.world.get::<Target>(target).is_none());
+		assert!(app.world.get::<UnitActions>(attacker).unwrap().unit_actions.is_empty());
+	}
+
+	#[test]
+	fn counterattack_message_waits_for_the_attack() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let attacker = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		let target = spawn_test_unit(&mut app, 2, 2, Pos { x: 2, y: 1, });
+
+		// The server sends the counter-attack right after the attack it answers.
+		send(&mut app, ServerMessage::PlayerTurn { client_id: CLIENT_ID, current_unit: 1, });
+		send(&mut app, ServerMessage::BasicAttack {
+			attacker: Pos { x: 1, y: 1, },
+			target: Pos { x: 2, y: 1, },
+			damage: 13,
+			is_counterattack: false,
+		});
+		send(&mut app, ServerMessage::BasicAttack {
+			attacker: Pos { x: 2, y: 1, },
+			target: Pos { x: 1, y: 1, },
+			damage: 9,
+			is_counterattack: true,
+		});
+		for _ in 0..8 {
+			app.update();
+		}
+
+		assert_eq!(app.world.get::<HPCurrent>(target).unwrap().value, 47);
+		assert_eq!(app.world.get::<HPCurrent>(attacker).unwrap().value, 51);
+		assert!(app.world.get::<Attacker>(target).is_none());
+		assert!(app.world.get::<Target>(attacker).is_none());
+		assert!(app.world.get::<UnitActions>(target).unwrap().unit_actions.is_empty());
+	}
+
+	#[test]
+	fn wait_message_ends_current_unit_turn() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+
+		send(&mut app, ServerMessage::PlayerTurn { client_id: CLIENT_ID, current_unit: 1, });
+		app.update();
+		app.update();
+		send(&mut app, ServerMessage::Wait);
+		app.update();
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::Wait);
+		assert_eq!(turn_state(&app), TurnState::Wait);
+		assert!(app.world.get::<CurrentUnit>(unit).is_none());
+	}
+
+	#[test]
+	fn turn_timer_message_is_cleared_by_wait_message() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+
+		send(&mut app, ServerMessage::TurnTimer { current_unit: 1, seconds: 30.0, reserve: 5.0, });
+		app.update();
+
+		let turn_timer = app.world.resource::<TurnTimer>();
+		assert_eq!(turn_timer.current_unit, Some(1));
+		assert_eq!(turn_timer.remaining, 30.0);
+		assert_eq!(turn_timer.reserve, 5.0);
+
+		send(&mut app, ServerMessage::Wait);
+		app.update();
+
+		assert_eq!(app.world.resource::<TurnTimer>().current_unit, None);
+	}
+
+	#[test]
+	fn game_over_message_shows_game_over_screen() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+
+		send(&mut app, ServerMessage::WinningTeam { team: 1, });
+		send(&mut app, ServerMessage::GameOver { winner: ControlledBy::Player, });
+		app.update();
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::GameOver);
+		assert_eq!(turn_state(&app), TurnState::Wait);
+	}
+
+	#[test]
+	fn local_server_certificate_is_pinned() {
+		// The client of the local loopback server pins the certificate it generated.
+		let args = CliArgs::parse_from(["amclient", "--local-server"]);
+		let mut local_server = LocalServer::default();
+		local_server.certificate_fingerprint = Some("local".to_string());
+		assert_eq!(pinned_fingerprint(&args, Some(&local_server)), Some("local".to_string()));
+
+		let CertificateVerificationMode::TrustOnFirstUse(config) = certificate_verification_mode(true) else {
+			panic!("A pinned fingerprint still goes through trust on first use.");
+		};
+		assert_eq!(config.verifier_behaviour.len(), 3);
+		assert!(config.verifier_behaviour.values().all(|behaviour| matches!(behaviour, CertVerifierBehaviour::RequestClientAction)));
+		assert!(matches!(pinned_certificate_action("local", "local"), CertVerifierAction::TrustOnce));
+		assert!(matches!(pinned_certificate_action("other", "local"), CertVerifierAction::AbortConnection));
+
+		// A remote server is pinned from the command line, or trusted on first use.
+		let args = CliArgs::parse_from(["amclient", "--server-fingerprint", "remote"]);
+		assert_eq!(pinned_fingerprint(&args, None), Some("remote".to_string()));
+		assert_eq!(pinned_fingerprint(&CliArgs::parse_from(["amclient"]), None), None);
+
+		let CertificateVerificationMode::TrustOnFirstUse(config) = certificate_verification_mode(false) else {
+			panic!("Servers are trusted on first use.");
+		};
+		assert!(matches!(config.verifier_behaviour.get(&CertVerificationStatus::UnknownCertificate), Some(CertVerifierBehaviour::ImmediateAction(CertVerifierAction::TrustAndStore))));
+		assert!(matches!(config.verifier_behaviour.get(&CertVerificationStatus::UntrustedCertificate), Some(CertVerifierBehaviour::RequestClientAction)));
+	}
+
+	#[test]
+	fn network_stats_report_packet_loss_and_message_counts() {
+		let mut network_stats = NetworkStats::default();
+		network_stats.sent_packets = 200;
+		network_stats.lost_packets = 5;
+		network_stats.received.insert(ServerMessage::Wait.name(), 3);
+
+		let lines = format_network_stats(&network_stats);
+		assert!(lines.contains(&String::from("Packet loss: 2.5% (5/200)")));
+		assert!(lines.contains(&String::from("  Wait: 3")));
+	}
+
+	#[test]
+	fn game_over_message_announces_winner_by_name() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+
+		send(&mut app, ServerMessage::Players {
+			players: vec![
+				PlayerInfo { client_id: CLIENT_ID, name: "Hannibal".to_string(), team: 1, },
+				PlayerInfo { client_id: OTHER_CLIENT_ID, name: "Scipio".to_string(), team: 2, },
+			],
+		});
+		send(&mut app, ServerMessage::PlayerTurn { client_id: OTHER_CLIENT_ID, current_unit: 1, });
+		app.update();
+		assert_eq!(app.world.resource::<Players>().current_player, Some(OTHER_CLIENT_ID));
+
+		send(&mut app, ServerMessage::WinningTeam { team: 2, });
+		send(&mut app, ServerMessage::GameOver { winner: ControlledBy::Player, });
+		app.update();
+
+		let battle_result = app.world.resource::<BattleResult>();
+		assert_eq!(battle_result.title, "Defeat");
+		assert_eq!(battle_result.message, "Scipio won the battle.");
+		assert!(app.world.resource::<Players>().players.is_empty());
+	}
+
+	#[test]
+	fn hot_seat_passes_controls_between_teams() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		app.insert_resource(HotSeat { enabled: true, current_team: Some(1), ..default() });
+		app.world.resource_mut::<Game>().players.insert(1, ControlledBy::Player);
+		app.world.resource_mut::<Game>().players.insert(2, ControlledBy::Player);
+		app.add_systems(Update, wait_turn_system
+			.run_if(in_state(TurnState::Wait))
+		);
+
+		let entity = spawn_test_unit(&mut app, 1, 2, Pos { x: 2, y: 2, });
+		app.world.get_mut::<WTCurrent>(entity).unwrap().value = 0;
+		app.update();
+		app.update();
+
+		assert_eq!(app.world.resource::<Game>().current_team, 2);
+		assert_eq!(turn_state(&app), TurnState::PassControls);
+	}
+
+	#[test]
+	fn alliances_decide_when_battle_is_over() {
+		let mut alliances = Alliances::default();
+		alliances.set_stance(1, 3, Stance::Allied);
+		alliances.set_stance(2, 3, Stance::Neutral);
+
+		assert_eq!(alliances.stance(3, 1), Stance::Allied);
+		assert_eq!(alliances.stance(1, 2), Stance::Hostile);
+		assert!(alliances.has_hostile_teams(&HashSet::from([1, 2, 3])));
+		assert!(!alliances.has_hostile_teams(&HashSet::from([1, 3])));
+		assert!(!alliances.has_hostile_teams(&HashSet::from([2, 3])));
+	}
+
+	#[test]
+	fn zone_of_control_stops_movement() {
+		let mut map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>> = Vec::new();
+		for _ in 0..5 {
+			let mut map_line: Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)> = Vec::new();
+			for _ in 0..2 {
+				map_line.push((1, TileType::Grass, Vec::new(), Vec::new()));
+			}
+			map.push(map_line);
+		}
+		map[2][1].2.push(Entity::from_raw(2));
+
+		let possible_movements = find_possible_movements(map.clone(), Pos { x: 0, y: 0, }, 6, 2, &HashSet::new());
+		assert!(possible_movements.contains(&Pos { x: 3, y: 0, }));
+
+		// Zone of control is off unless the scenario turns it on.
+		assert!(ScenarioRules::default().zone_of_control(&map, &vec![Pos { x: 2, y: 1, }]).is_empty());
+		
+		// The pursuit turns it on.
+		let rules = load_scenario_rules("the_pursuit").unwrap();
+		assert!(rules.zone_of_control);
+		let zone_of_control = rules.zone_of_control(&map, &vec![Pos { x: 2, y: 1, }]);
+		let possible_movements = find_possible_movements(map, Pos { x: 0, y: 0, }, 6, 2, &zone_of_control);
+		assert!(possible_movements.contains(&Pos { x: 2, y: 0, }));
+		assert!(!possible_movements.contains(&Pos { x: 3, y: 0, }));
+	}
+
+	#[test]
+	fn objectives_decide_victory_and_defeat() {
+		let mut objectives = Objectives {
+			team: 1,
+			victory: vec![Objective::UnitDefeated { unit_name: "Hannibal".to_string(), }, Objective::HoldTile { pos: Pos { x: 5, y: 5, }, turns: 2, }],
+			defeat: vec![Objective::UnitDefeated { unit_name: "Hanno".to_string(), }],
+			..default()
+		};
+		let alliances = Alliances::default();
+
+		let units = vec![
+			(1, "Hanno".to_string(), Pos { x: 5, y: 5, }, UnitRole::Regular),
+			(2, "Hannibal".to_string(), Pos { x: 9, y: 9, }, UnitRole::Regular),
+		];
+		assert_eq!(objectives.outcome(&units, &alliances), None);
+
+		// Units of the team that act one after another share a team turn.
+		objectives.start_unit_turn(1, &vec![(1, Pos { x: 5, y: 5, })], &alliances);
+		objectives.start_unit_turn(1, &vec![(1, Pos { x: 5, y: 5, })], &alliances);
+		assert_eq!(objectives.turns, 1);
+		assert_eq!(objectives.outcome(&units, &alliances), None);
+
+		objectives.start_unit_turn(2, &vec![(1, Pos { x: 5, y: 5, })], &alliances);
+		objectives.start_unit_turn(1, &vec![(1, Pos { x: 5, y: 5, })], &alliances);
+		assert_eq!(objectives.turns, 2);
+		assert_eq!(objectives.outcome(&units, &alliances), Some((true, Objective::HoldTile { pos: Pos { x: 5, y: 5, }, turns: 2, })));
+
+		// Losing the VIP outweighs any objective completed on the same turn.
+		let units = vec![(2, "Hannibal".to_string(), Pos { x: 9, y: 9, }, UnitRole::Regular)];
+		assert_eq!(objectives.outcome(&units, &alliances), Some((false, Objective::UnitDefeated { unit_name: "Hanno".to_string(), })));
+	}
+
+	#[test]
+	fn server_checks_the_scenario_objectives() {
+		let mut local_server = LocalServer::default();
+		local_server.teams.insert(CLIENT_ID, 1);
+		local_server.units = load_server_units(SCENARIOS[0], &load_classes().unwrap(), &load_items().unwrap()).unwrap();
+		local_server.objectives = load_objectives(SCENARIOS[0]).unwrap();
+		assert_eq!(local_server_objectives_outcome(&local_server), None);
+
+		// Team 1 wins once every enemy is defeated.
+		let all_units = local_server.units.clone();
+		local_server.units.retain(|unit| unit.unit_team == 1);
+		assert_eq!(local_server_objectives_outcome(&local_server), Some(HashSet::from([1])));
+
+		// Losing Hanno is checked first.
+		local_server.units = all_units;
+		local_server.units.retain(|unit| unit.name != "Hanno");
+		assert_eq!(local_server_objectives_outcome(&local_server), Some(HashSet::from([2])));
+	}
+
+	#[test]
+	fn objectives_only_name_units_of_the_scenario() {
+		for scenario in referenced_scenarios() {
+			assert!(load_objectives(&scenario).is_ok());
+		}
+
+		// A misspelt leader would count as defeated from the start.
+		let records = read_unit_records(SCENARIOS[0]).unwrap();
+		let objectives = Objectives { defeat: vec![Objective::UnitDefeated { unit_name: "Hano".to_string(), }], ..default() };
+		assert!(check_objective_units(&objectives, &records).is_err());
+		let objectives = Objectives { defeat: vec![Objective::UnitDefeated { unit_name: "Hanno".to_string(), }], ..default() };
+		assert!(check_objective_units(&objectives, &records).is_ok());
+	}
+
+	#[test]
+	fn lobby_scenarios_are_playable_in_multiplayer() {
+		// The server doesn't run the AI, so the lobby's scenarios have no guests or neutral units.
+		for scenario in SCENARIOS {
+			assert!(scenario_teams(scenario).len() >= 2);
+		}
+
+		// The campaign's village has both, and is refused in multiplayer.
+		assert!(referenced_scenarios().contains(&"the_village".to_string()));
+		assert!(!SCENARIOS.contains(&"the_village"));
+		assert!(scenario_teams("the_village").is_empty());
+	}
+
+	#[test]
+	fn battle_save_keeps_rng_state_and_checks_version() {
+		let mut battle_rng = BattleRng::from_seed(42);
+		battle_rng.rng.gen_range(0..7);
+
+		let mut save = BattleSave {
+			version: SAVE_VERSION,
+			scenario: SCENARIOS[0].to_string(),
+			hot_seat: false,
+			current_unit: 3,
+			current_team: 1,
+			players: HashMap::from([(1, ControlledBy::Player), (2, ControlledBy::AI)]),
+			turn_state: TurnState::Turn,
+			rng: battle_rng.clone(),
+			tiles: vec![vec![(1, TileType::Grass)]],
+			units: Vec::new(),
+			objective_turns: 2,
+			objective_held_turns: HashMap::from([(Pos { x: 5, y: 5, }, 1)]),
+			action_used: false,
+			campaign_selected: None,
+		};
+		let contents = ron::ser::to_string(&save).unwrap();
+
+		let mut loaded = parse_battle_save(&contents).unwrap();
+		assert_eq!(loaded.current_unit, 3);
+		assert_eq!(loaded.turn_state, TurnState::Turn);
+		assert_eq!(loaded.rng.seed, 42);
+		assert_eq!(loaded.rng.rng.gen::<u64>(), battle_rng.rng.gen::<u64>());
+
+		save.version = SAVE_VERSION + 1;
+		let contents = ron::ser::to_string(&save).unwrap();
+		assert!(parse_battle_save(&contents).is_err());
+	}
+
+	#[test]
+	fn undo_move_message_puts_unit_back() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 3, y: 1, });
+		app.world.entity_mut(unit).insert(Transform::default());
+		app.world.resource_mut::<Game>().current_unit = 1;
+		app.world.resource_mut::<UndoableMove>().value = Some((1, Pos { x: 1, y: 1, }, Pos { x: 3, y: 1, }));
+		app.world.resource_mut::<UndoableMove>().direction = Some((1, Direction::North));
+
+		send(&mut app, ServerMessage::UndoMove { origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, });
+		app.update();
+
+		assert!(app.world.resource::<UndoableMove>().value.is_none());
+		assert_eq!(*app.world.get::<Pos>(unit).unwrap(), Pos { x: 1, y: 1, });
+		assert_eq!(app.world.get::<DIR>(unit).unwrap().direction, Direction::North);
+
+		let mut map_query = app.world.query::<&Map>();
+		let map = &map_query.single(&app.world).map;
+		assert_eq!(map[1][1].2, vec![unit]);
+		assert!(map[3][1].2.is_empty());
+	}
+
+	#[test]
+	fn undo_move_puts_unit_back_offline() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		app.world.resource_mut::<Game>().is_multiplayer = false;
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 3, y: 1, });
+		app.world.entity_mut(unit).insert(Transform::default());
+		app.world.resource_mut::<Game>().current_unit = 1;
+		app.world.resource_mut::<UndoableMove>().direction = Some((1, Direction::North));
+
+		// Offline, there is no server to send `UndoMove` back.
+		app.world.send_event(UndoMoveEvent { unit_id: 1, origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, });
+		app.update();
+
+		assert_eq!(*app.world.get::<Pos>(unit).unwrap(), Pos { x: 1, y: 1, });
+		assert_eq!(app.world.get::<DIR>(unit).unwrap().direction, Direction::North);
+
+		let mut map_query = app.world.query::<&Map>();
+		let map = &map_query.single(&app.world).map;
+		assert_eq!(map[1][1].2, vec![unit]);
+		assert!(map[3][1].2.is_empty());
+	}
+
+	#[test]
+	fn replay_records_the_end_of_single_player_turns() {
+		let mut replay_recorder = ReplayRecorder::default();
+		replay_recorder.start(SCENARIOS[0], 42);
+		replay_recorder.record_turn(vec![(1, 0), (2, 5)], 1);
+		replay_recorder.record(ReplayEvent::Move { origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, });
+		replay_recorder.record_turn(vec![(1, 10), (2, 0)], 2);
+
+		let mut replay = replay_recorder.replay.unwrap();
+		assert_eq!(replay.events, vec![
+			ReplayEvent::WaitTurn { wait_turns: vec![(1, 0), (2, 5)], },
+			ReplayEvent::Turn { unit_id: 1, },
+			ReplayEvent::Move { origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, },
+			ReplayEvent::Wait,
+			ReplayEvent::WaitTurn { wait_turns: vec![(1, 10), (2, 0)], },
+			ReplayEvent::Turn { unit_id: 2, },
+		]);
+
+		let loaded = parse_replay(&ron::to_string(&replay).unwrap()).unwrap();
+		assert_eq!(loaded.seed, 42);
+		assert_eq!(loaded.events, replay.events);
+
+		replay.version = REPLAY_VERSION + 1;
+		assert!(parse_replay(&ron::to_string(&replay).unwrap()).is_err());
+	}
+
+	#[test]
+	fn replay_events_play_through_message_handlers() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		app.world.resource_mut::<Game>().is_spectator = true;
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+
+		send(&mut app, ReplayEvent::Turn { unit_id: 1, }.server_message());
+		app.update();
+		send(&mut app, ReplayEvent::Move { origin: Pos { x: 1, y: 1, }, destination: Pos { x: 3, y: 1, }, }.server_message());
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::Battle);
+		assert_eq!(turn_state(&app), TurnState::Wait);
+		assert!(app.world.get::<CurrentUnit>(unit).is_some());
+		assert_eq!(app.world.get::<UnitActions>(unit).unwrap().unit_actions.len(), 1);
+	}
+
+	#[test]
+	fn campaign_deploys_required_units_first() {
+		let definition = load_campaign_definition(CAMPAIGN_FILE).unwrap();
+		let mut progress = new_campaign_progress(&definition, &load_classes().unwrap()).unwrap();
+		assert_eq!(progress.current_battle, Some(definition.start.clone()));
+		assert_eq!(progress.roster.len(), 8);
+		assert_eq!(progress.roster[0].name, "Hanno");
+		progress.roster.rotate_left(1);
+
+		let mut campaign = Campaign {
+			definition: Some(definition),
+			progress: Some(progress),
+			..default()
+		};
+		prepare_campaign_battle(&mut campaign).unwrap();
+		assert_eq!(campaign.deployment_slots, 8);
+		assert_eq!(campaign.required_units, vec!["Hanno".to_string()]);
+		assert_eq!(campaign.selected[0], 1);
+		assert_eq!(campaign.selected.len(), 8);
+
+		// The pursuit has fewer slots, and fallen units can't be deployed.
+		let progress = campaign.progress.as_mut().unwrap();
+		progress.current_battle = Some("pursuit".to_string());
+		progress.roster.iter_mut().find(|roster_unit| roster_unit.roster_id == 2).unwrap().dead = true;
+		prepare_campaign_battle(&mut campaign).unwrap();
+		assert_eq!(campaign.deployment_slots, 6);
+		assert!(campaign.required_units.is_empty());
+		assert_eq!(campaign.selected, vec![3, 4, 5, 6, 7, 8]);
+	}
+
+	#[test]
+	fn campaign_roster_keeps_hp_and_deaths_between_battles() {
+		let definition = load_campaign_definition(CAMPAIGN_FILE).unwrap();
+		let mut progress = new_campaign_progress(&definition, &load_classes().unwrap()).unwrap();
+
+		// Roster unit 1 survived as unit 4 and gained a level, roster unit 2 died as unit 5.
+		let mut survivor = progress.roster[0].clone();
+		survivor.hp_current = 25;
+		survivor.level = 2;
+		let units = vec![(4, survivor), (9, progress.roster[2].clone())];
+		update_roster(&mut progress, &vec![(4, 1), (5, 2)], &units);
+		assert_eq!(progress.roster[0].hp_current, 25);
+		assert_eq!(progress.roster[0].level, 2);
+		assert_eq!(progress.roster[0].battles, 1);
+		assert!(!progress.roster[0].dead);
+		assert!(progress.roster[1].dead);
+		assert_eq!(progress.roster[2].battles, 0);
+
+		let loaded = parse_campaign_progress(&ron::to_string(&progress).unwrap()).unwrap();
+		assert_eq!(loaded.roster[0].hp_current, 25);
+		assert_eq!(loaded.roster[0].level, 2);
+		assert!(loaded.roster[1].dead);
+
+		progress.version = CAMPAIGN_SAVE_VERSION + 1;
+		assert!(parse_campaign_progress(&ron::to_string(&progress).unwrap()).is_err());
+
+		// A roster unit of a removed class fails the campaign's loading, not the game's startup.
+		let classes = load_classes().unwrap();
+		assert!(check_roster_classes(&progress, &classes).is_ok());
+		progress.roster[0].class = "Removed Class".to_string();
+		assert!(check_roster_classes(&progress, &classes).is_err());
+	}
+
+	#[test]
+	fn attacks_earn_experience_and_level_ups_follow_class_growth() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let mut classes = Classes::default();
+		classes.classes.insert("Test Class".to_string(), test_class(StatGrowth { hp: 5, str: 2, ..default() }));
+		app.insert_resource(classes);
+		app.add_systems(Update, handle_experience_events);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.get_mut::<Experience>(unit).unwrap().value = XP_PER_LEVEL - 5;
+
+		app.world.send_event(GainExperienceEvent { unit: unit, experience: XP_PER_ATTACK, });
+		app.update();
+
+		assert_eq!(app.world.get::<Level>(unit).unwrap().value, 2);
+		assert_eq!(app.world.get::<Experience>(unit).unwrap().value, 5);
+		assert_eq!(app.world.get::<HPMax>(unit).unwrap().value, 65);
+		assert_eq!(app.world.get::<STR>(unit).unwrap().value, 62);
+		assert_eq!(app.world.get::<VIT>(unit).unwrap().value, 61);
+		assert_eq!(app.world.resource::<Toasts>().toasts.len(), 1);
+
+		// Enough experience for several levels at once.
+		let mut level = 1;
+		let mut experience = 0;
+		assert_eq!(gain_experience(&mut level, &mut experience, 2 * XP_PER_LEVEL + 30), 2);
+		assert_eq!((level, experience), (3, 30));
+	}
+
+	#[test]
+	fn unit_rows_override_their_class() {
+		let mut classes = Classes::default();
+		classes.classes.insert("Test Class".to_string(), test_class(StatGrowth::default()));
+		let csv = "unit_id,unit_team,unit_name,unit_class,pos_x,pos_y,WT_CURRENT,DIR,HP_MAX,STR,Jump,AttackType\n\
+			1,1,Hanno,Test Class,4,3,600,West,,,,\n\
+			2,1,Mutt,Test Class,4,4,601,West,80,70,3,Ranged\n";
+		let records: Vec<UnitRecord> = Reader::from_reader(csv.as_bytes()).deserialize().map(|result| result.unwrap()).collect();
+
+		let unit_attributes = records[0].unit_attributes(&classes).unwrap();
+		assert_eq!(unit_attributes.hp_max.value, 60);
+		assert_eq!(unit_attributes.hp_current.value, 60);
+		assert_eq!(unit_attributes.str.value, 60);
+		assert_eq!(unit_attributes.jump.value, 1);
+		assert_eq!(unit_attributes.unit_sprite.value, "hannibal");
+
+		let unit_attributes = records[1].unit_attributes(&classes).unwrap();
+		assert_eq!(unit_attributes.hp_max.value, 80);
+		assert_eq!(unit_attributes.hp_current.value, 80);
+		assert_eq!(unit_attributes.str.value, 70);
+		assert_eq!(unit_attributes.jump.value, 3);
+		assert!(matches!(unit_attributes.attack_type, AttackType::Ranged));
+		assert_eq!(unit_attributes.wt_current.value, 601);
+
+		// Every scenario only uses classes that exist.
+		assert!(validate_unit_classes(&load_classes().unwrap()).is_ok());
+		assert!(validate_unit_classes(&classes).is_err());
+		assert!(records[0].unit_attributes(&Classes::default()).is_err());
+
+		// Villagers neither attack nor use items, and a class lists each ability once.
+		let mut classes = load_classes().unwrap();
+		assert!(classes.allows("Gaul Warrior", Ability::Attack));
+		assert!(!classes.allows("Villager", Ability::Attack));
+		assert!(!classes.allows("Villager", Ability::UseItem));
+		classes.classes.get_mut("Gaul Warrior").unwrap().abilities.push(Ability::Attack);
+		assert!(validate_unit_classes(&classes).is_err());
+
+		// Units carrying items need a class that can use them.
+		let mut classes = load_classes().unwrap();
+		classes.classes.get_mut("Carthaginian Officer").unwrap().abilities = vec![Ability::Attack];
+		assert!(validate_unit_classes(&classes).is_err());
+
+		// Rows keep the wait time they had before classes existed.
+		let records = read_unit_records("the_patrol_ambush").unwrap();
+		assert_eq!(records[0].unit_attributes(&load_classes().unwrap()).unwrap().wt_max.value, 600);
+		assert_eq!(records[15].unit_attributes(&load_classes().unwrap()).unwrap().wt_max.value, 615);
+	}
+
+	#[test]
+	fn server_map_follows_the_scenario_heights() {
+		// Scenarios without a map file are flat.
+		assert_eq!(load_map_heights("the_patrol_ambush").unwrap(), flat_map_heights());
+
+		let heights = load_map_heights("the_retreat").unwrap();
+		let map = server_map(&heights, &Vec::new());
+		assert_eq!(map.len(), heights.len());
+		assert_eq!(map[6][4].0, 3);
+
+		// A unit can't climb more than its jump in one step.
+		let possible_movements = find_possible_movements(map.clone(), Pos { x: 5, y: 4, }, 1, 1, &HashSet::new());
+		assert!(!possible_movements.contains(&Pos { x: 6, y: 4, }));
+		assert!(possible_movements.contains(&Pos { x: 5, y: 5, }));
+		let possible_movements = find_possible_movements(map, Pos { x: 5, y: 4, }, 1, 2, &HashSet::new());
+		assert!(possible_movements.contains(&Pos { x: 6, y: 4, }));
+	}
+
+	#[test]
+	fn jump_limits_climbing() {
+		let mut map: Vec<Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)>> = Vec::new();
+		for _ in 0..3 {
+			let mut map_line: Vec<(usize, TileType, Vec<Entity>, Vec<Entity>)> = Vec::new();
+			for _ in 0..1 {
+				map_line.push((1, TileType::Grass, Vec::new(), Vec::new()));
+			}
+			map.push(map_line);
+		}
+		map[1][0].0 = 3;
+
+		let possible_movements = find_possible_movements(map.clone(), Pos { x: 0, y: 0, }, 6, 1, &HashSet::new());
+		assert!(!possible_movements.contains(&Pos { x: 1, y: 0, }));
+		let possible_movements = find_possible_movements(map, Pos { x: 0, y: 0, }, 6, 2, &HashSet::new());
+		assert!(possible_movements.contains(&Pos { x: 1, y: 0, }));
+		assert!(possible_movements.contains(&Pos { x: 2, y: 0, }));
+	}
+
+	#[test]
+	fn use_item_message_uses_up_a_consumable() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		app.world.insert_resource(load_items().unwrap());
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.get_mut::<HPCurrent>(unit).unwrap().value = 30;
+		app.world.get_mut::<Inventory>(unit).unwrap().items = vec!["Posca".to_string(), "Healing Herbs".to_string()];
+
+		send(&mut app, ServerMessage::PlayerTurn { client_id: CLIENT_ID, current_unit: 1, });
+		send(&mut app, ServerMessage::UseItem { user: Pos { x: 1, y: 1, }, item: 1, });
+		for _ in 0..4 {
+			app.update();
+		}
+
+		assert_eq!(app.world.get::<HPCurrent>(unit).unwrap().value, 50);
+		assert_eq!(app.world.get::<Inventory>(unit).unwrap().items, vec!["Posca".to_string()]);
+		assert!(app.world.get::<UnitActions>(unit).unwrap().unit_actions.is_empty());
+
+		// Using the item took the unit's action for this turn.
+		assert!(app.world.resource::<ActionUsed>().value);
+
+		// Healing never goes past the unit's maximum HP.
+		app.world.get_mut::<HPCurrent>(unit).unwrap().value = 55;
+		send(&mut app, ServerMessage::PlayerTurn { client_id: CLIENT_ID, current_unit: 1, });
+		app.update();
+		assert!(!app.world.resource::<ActionUsed>().value);
+		send(&mut app, ServerMessage::UseItem { user: Pos { x: 1, y: 1, }, item: 0, });
+		for _ in 0..4 {
+			app.update();
+		}
+		assert_eq!(app.world.get::<HPCurrent>(unit).unwrap().value, 60);
+		assert!(app.world.get::<Inventory>(unit).unwrap().items.is_empty());
+	}
+
+	#[test]
+	fn offline_item_use_earns_experience() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		app.world.resource_mut::<Game>().is_multiplayer = false;
+		app.world.resource_mut::<NextState<GameState>>().set(GameState::Battle);
+		app.world.insert_resource(load_items().unwrap());
+		app.add_systems(Update, handle_experience_events);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		app.world.get_mut::<Inventory>(unit).unwrap().items = vec!["Posca".to_string()];
+
+		app.world.get_mut::<UnitActions>(unit).unwrap().unit_actions.push(UnitActionTuple(UnitAction::UseItem { item: 0, }, 0.0));
+		for _ in 0..4 {
+			app.update();
+		}
+
+		assert!(app.world.get::<Inventory>(unit).unwrap().items.is_empty());
+		assert_eq!(app.world.get::<Experience>(unit).unwrap().value, XP_PER_ITEM);
+	}
+
+	#[test]
+	fn equipment_changes_stats_and_attack() {
+		let items = load_items().unwrap();
+		let mut classes = Classes::default();
+		classes.classes.insert("Test Class".to_string(), test_class(StatGrowth::default()));
+		let csv = "unit_id,unit_team,unit_name,unit_class,pos_x,pos_y,WT_CURRENT,DIR,Weapon,Armor,Items\n\
+			1,1,Hanno,Test Class,4,3,600,West,Sling,Linen Cuirass,Healing Herbs; Posca\n";
+		let records: Vec<UnitRecord> = Reader::from_reader(csv.as_bytes()).deserialize().map(|result| result.unwrap()).collect();
+
+		let mut unit_attributes = records[0].unit_attributes(&classes).unwrap();
+		assert_eq!(unit_attributes.inventory.items, vec!["Healing Herbs".to_string(), "Posca".to_string()]);
+		unit_attributes.apply_equipment(&items);
+		assert_eq!(unit_attributes.hp_max.value, 70);
+		assert_eq!(unit_attributes.vit.value, 63);
+		assert_eq!(unit_attributes.dex.value, 64);
+		assert_eq!(unit_attributes.attack_range.value, 4);
+		assert!(matches!(unit_attributes.attack_type, AttackType::Ranged));
+
+		// The roster keeps the unit's own stats and attack.
+		let mut roster_unit = RosterUnit::from_unit_attributes(1, &unit_attributes);
+		roster_unit.remove_equipment_bonus(&items);
+		assert_eq!((roster_unit.hp_max, roster_unit.vit, roster_unit.dex), (60, 60, 60));
+
+		let mut progress = CampaignProgress {
+			version: CAMPAIGN_SAVE_VERSION,
+			current_battle: None,
+			roster: vec![roster_unit],
+			history: Vec::new(),
+			inventory: vec!["Bronze Sword".to_string(), "Healing Herbs".to_string()],
+			rng: BattleRng::from_seed(1),
+			next_roster_id: 2,
+		};
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Equip { roster_id: 1, slot: EquipmentSlot::Weapon, item: Some(0), });
+		assert_eq!(progress.roster[0].equipment.weapon, Some("Bronze Sword".to_string()));
+		assert_eq!(progress.inventory, vec!["Healing Herbs".to_string(), "Sling".to_string()]);
+
+		// Only consumables can be carried, and only items of the slot equipped.
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Give { roster_id: 1, item: 1, });
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Equip { roster_id: 1, slot: EquipmentSlot::Armor, item: Some(0), });
+		assert_eq!(progress.roster[0].inventory.len(), 2);
+		assert_eq!(progress.roster[0].equipment.armor, Some("Linen Cuirass".to_string()));
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Give { roster_id: 1, item: 0, });
+		assert_eq!(progress.roster[0].inventory.len(), 3);
+		assert_eq!(progress.inventory, vec!["Sling".to_string()]);
+
+		assert!(validate_unit_items(&items).is_ok());
+	}
+
+	#[test]
+	fn equipment_round_trip_keeps_the_units_own_hp_and_attack() {
+		let items = load_items().unwrap();
+		let mut classes = Classes::default();
+		classes.classes.insert("Test Class".to_string(), test_class(StatGrowth::default()));
+		let csv = "unit_id,unit_team,unit_name,unit_class,pos_x,pos_y,WT_CURRENT,DIR\n\
+			1,1,Hanno,Test Class,4,3,600,West\n";
+		let records: Vec<UnitRecord> = Reader::from_reader(csv.as_bytes()).deserialize().map(|result| result.unwrap()).collect();
+		let mut progress = CampaignProgress {
+			version: CAMPAIGN_SAVE_VERSION,
+			current_battle: None,
+			roster: vec![RosterUnit::from_unit_attributes(1, &records[0].unit_attributes(&classes).unwrap())],
+			history: Vec::new(),
+			inventory: vec!["Sling".to_string(), "Linen Cuirass".to_string()],
+			rng: BattleRng::from_seed(1),
+			next_roster_id: 2,
+		};
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Equip { roster_id: 1, slot: EquipmentSlot::Weapon, item: Some(0), });
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Equip { roster_id: 1, slot: EquipmentSlot::Armor, item: Some(0), });
+
+		// The unit fights with its equipment and is hurt.
+		let mut unit_attributes = records[0].unit_attributes(&classes).unwrap();
+		progress.roster[0].apply(&mut unit_attributes);
+		unit_attributes.apply_equipment(&items);
+		assert_eq!((unit_attributes.hp_max.value, unit_attributes.hp_current.value), (70, 70));
+		assert_eq!(unit_attributes.attack_range.value, 4);
+		unit_attributes.hp_current.value = 35;
+		let mut battle_unit = RosterUnit::from_unit_attributes(0, &unit_attributes);
+		battle_unit.remove_equipment_bonus(&items);
+		update_roster(&mut progress, &vec![(1, 1)], &vec![(1, battle_unit)]);
+		assert_eq!((progress.roster[0].hp_max, progress.roster[0].hp_current), (60, 25));
+		assert_eq!((progress.roster[0].vit, progress.roster[0].dex), (60, 60));
+
+		// Unequipped, the unit is as hurt as it was and has its own attack back.
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Equip { roster_id: 1, slot: EquipmentSlot::Weapon, item: None, });
+		apply_inventory_change(&mut progress, &items, &InventoryChange::Equip { roster_id: 1, slot: EquipmentSlot::Armor, item: None, });
+		let mut unit_attributes = records[0].unit_attributes(&classes).unwrap();
+		progress.roster[0].apply(&mut unit_attributes);
+		unit_attributes.apply_equipment(&items);
+		assert_eq!((unit_attributes.hp_max.value, unit_attributes.hp_current.value), (60, 25));
+		assert_eq!(unit_attributes.attack_range.value, 1);
+		assert!(matches!(unit_attributes.attack_type, AttackType::Melee));
+
+		// Taking off armor doesn't kill a unit that survived with less HP than it gave.
+		let mut battle_unit = progress.roster[0].clone();
+		battle_unit.equipment.armor = Some("Linen Cuirass".to_string());
+		battle_unit.hp_current = 4;
+		battle_unit.remove_equipment_bonus(&items);
+		assert_eq!(battle_unit.hp_current, 1);
+	}
+
+	#[test]
+	fn deployment_message_places_units_and_starts_battle() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		let unit = spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		let other_unit = spawn_test_unit(&mut app, 2, 1, Pos { x: 1, y: 2, });
+		let enemy_unit = spawn_test_unit(&mut app, 3, 2, Pos { x: 9, y: 1, });
+		app.world.resource_mut::<NextState<GameState>>().set(GameState::Deployment);
+		app.update();
+
+		// The two units of the team swap places, and the enemy is revealed further back.
+		send(&mut app, ServerMessage::Deployment {
+			units: vec![
+				(1, Pos { x: 1, y: 2, }, Direction::North),
+				(2, Pos { x: 1, y: 1, }, Direction::East),
+				(3, Pos { x: 8, y: 4, }, Direction::West),
+			],
+		});
+		app.update();
+		app.update();
+
+		assert_eq!(game_state(&app), GameState::Wait);
+		assert_eq!(*app.world.get::<Pos>(unit).unwrap(), Pos { x: 1, y: 2, });
+		assert_eq!(app.world.get::<DIR>(unit).unwrap().direction, Direction::North);
+		assert_eq!(*app.world.get::<Pos>(other_unit).unwrap(), Pos { x: 1, y: 1, });
+		assert_eq!(*app.world.get::<Pos>(enemy_unit).unwrap(), Pos { x: 8, y: 4, });
+
+		let mut map_query = app.world.query::<&Map>();
+		let map = &map_query.single(&app.world).map;
+		assert_eq!(map[1][1].2, vec![other_unit]);
+		assert_eq!(map[1][2].2, vec![unit]);
+		assert!(map[9][1].2.is_empty());
+		assert_eq!(map[8][4].2, vec![enemy_unit]);
+	}
+
+	#[test]
+	fn already_deployed_error_keeps_the_deployment_confirmed() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+
+		send(&mut app, ServerMessage::Error { code: ErrorCode::AlreadyDeployed, message: "Your team has already deployed.".to_string(), rejected_move: None, });
+		app.update();
+		assert!(app.world.resource::<Deployment>().confirmed);
+
+		send(&mut app, ServerMessage::Error { code: ErrorCode::InvalidDeployment, message: "A unit is outside the deployment zone.".to_string(), rejected_move: None, });
+		app.update();
+		assert!(!app.world.resource::<Deployment>().confirmed);
+	}
+
+	#[test]
+	fn deployment_keeps_units_in_their_zone() {
+		let deployment_zones = load_deployment_zones(SCENARIOS[0]).unwrap();
+		assert!(deployment_zones.contains(1, Pos { x: 2, y: 9, }));
+		assert!(!deployment_zones.contains(1, Pos { x: 3, y: 0, }));
+		assert_eq!(deployment_zones.teams(), vec![1, 2]);
+		assert_eq!(deployment_zones.tiles(1, 10, 10).len(), 30);
+		assert!(load_deployment_zones(SCENARIOS[1]).unwrap().zones.is_empty());
+
+		let team_units = vec![1, 2];
+		let occupied = vec![Pos { x: 0, y: 5, }];
+		let deployment = vec![(1, Pos { x: 0, y: 0, }, Direction::East), (2, Pos { x: 2, y: 3, }, Direction::South)];
+		assert!(check_deployment(&deployment_zones, 1, &team_units, &occupied, &deployment).is_ok());
+
+		// Outside the zone, on a taken tile, two units on one tile, or a unit left out.
+		let outside = vec![(1, Pos { x: 0, y: 0, }, Direction::East), (2, Pos { x: 5, y: 3, }, Direction::East)];
+		assert!(check_deployment(&deployment_zones, 1, &team_units, &occupied, &outside).is_err());
+		let taken = vec![(1, Pos { x: 0, y: 5, }, Direction::East), (2, Pos { x: 2, y: 3, }, Direction::East)];
+		assert!(check_deployment(&deployment_zones, 1, &team_units, &occupied, &taken).is_err());
+		let stacked = vec![(1, Pos { x: 1, y: 1, }, Direction::East), (2, Pos { x: 1, y: 1, }, Direction::East)];
+		assert!(check_deployment(&deployment_zones, 1, &team_units, &occupied, &stacked).is_err());
+		let missing = vec![(1, Pos { x: 1, y: 1, }, Direction::East), (1, Pos { x: 1, y: 2, }, Direction::East)];
+		assert!(check_deployment(&deployment_zones, 1, &team_units, &occupied, &missing).is_err());
+	}
+
+	#[test]
+	fn snapshots_hide_deployments_until_every_team_has_deployed() {
+		let mut local_server = LocalServer::default();
+		local_server.units = load_server_units(SCENARIOS[0], &load_classes().unwrap(), &load_items().unwrap()).unwrap();
+		local_server.deployment_zones = load_deployment_zones(SCENARIOS[0]).unwrap();
+		let team = local_server.deployment_zones.teams()[0];
+		let unit = local_server.units.iter().find(|unit| unit.unit_team == team).unwrap().clone();
+		let deployed = Pos { x: unit.pos.x + 1, y: unit.pos.y, };
+		local_server.deployments.insert(team, vec![(unit.unit_id, deployed, Direction::North)]);
+
+		// Spectators and returning players see the scenario's places until the reveal.
+		let unit_snapshot = |local_server: &LocalServer| battle_snapshot(local_server).units.into_iter().find(|unit_snapshot| unit_snapshot.unit_id == unit.unit_id).unwrap();
+		assert_eq!(unit_snapshot(&local_server).pos, unit.pos);
+		assert_eq!(unit_snapshot(&local_server).direction, unit.direction);
+
+		// The reveal moves the unit, and keeps the facing it was deployed with.
+		assert_eq!(reveal_deployments(&mut local_server), vec![(unit.unit_id, deployed, Direction::North)]);
+		assert_eq!(unit_snapshot(&local_server).pos, deployed);
+		assert_eq!(unit_snapshot(&local_server).direction, Direction::North);
+	}
+
+	fn test_recruitment_campaign() -> (CampaignDefinition, Classes) {
+		let mut classes = Classes::default();
+		classes.classes.insert("Test Class".to_string(), test_class(StatGrowth::default()));
+		let definition = CampaignDefinition {
+			name: "Test Campaign".to_string(),
+			team: 1,
+			start: "test".to_string(),
+			inventory: Vec::new(),
+			recruitment: Recruitment {
+				classes: vec!["Test Class".to_string()],
+				names: vec!["Mago".to_string(), "Gisco".to_string()],
+				stat_variance: 5,
+				roster_size: 2,
+			},
+			battles: Vec::new(),
+		};
+		(definition, classes)
+	}
+
+	#[test]
+	fn recruits_are_drawn_from_the_campaign_rng() {
+		let (definition, classes) = test_recruitment_campaign();
+		let mut progress = new_campaign_progress(&definition, &classes).unwrap();
+		progress.rng = BattleRng::from_seed(7);
+		let mut other_progress = progress.clone();
+		let recruit = RosterChange::Recruit { class: "Test Class".to_string(), };
+
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &Vec::new(), &Vec::new(), &recruit).is_ok());
+		assert!(apply_roster_change(&mut other_progress, &definition, &classes, &Vec::new(), &Vec::new(), &recruit).is_ok());
+		let (first, other) = (progress.roster.last().unwrap(), other_progress.roster.last().unwrap());
+		// The same seed recruits the same unit.
+		assert_eq!((&first.name, first.str, first.agi, first.hp_max), (&other.name, other.str, other.agi, other.hp_max));
+		assert!(first.name == "Mago" || first.name == "Gisco");
+		assert_eq!(first.roster_id, progress.roster.iter().map(|roster_unit| roster_unit.roster_id).max().unwrap());
+		for stat in [first.hp_max, first.str, first.vit, first.int, first.men, first.agi, first.dex] {
+			assert!((55..=65).contains(&stat));
+		}
+		assert!((45..=55).contains(&first.luk));
+		assert_eq!(first.hp_current, first.hp_max);
+
+		// The second recruit gets the other name, then the roster is full.
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &Vec::new(), &Vec::new(), &recruit).is_ok());
+		assert_ne!(progress.roster[0].name, progress.roster[1].name);
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &Vec::new(), &Vec::new(), &recruit).is_err());
+		assert!(apply_roster_change(&mut other_progress, &definition, &classes, &Vec::new(), &Vec::new(), &RosterChange::Recruit { class: "Gaul Warrior".to_string(), }).is_err());
+	}
+
+	#[test]
+	fn renaming_and_dismissing_spare_story_units() {
+		let (definition, classes) = test_recruitment_campaign();
+		let mut progress = new_campaign_progress(&definition, &classes).unwrap();
+		let recruit = RosterChange::Recruit { class: "Test Class".to_string(), };
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &Vec::new(), &Vec::new(), &recruit).is_ok());
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &Vec::new(), &Vec::new(), &recruit).is_ok());
+		let (first_id, second_id) = (progress.roster[0].roster_id, progress.roster[1].roster_id);
+		let story_units = vec!["Hanno".to_string()];
+
+		// Names are trimmed and can't clash with other units or the story units.
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &Vec::new(), &RosterChange::Rename { roster_id: first_id, name: "  Hanno ".to_string(), }).is_err());
+		let second_name = progress.roster[1].name.clone();
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &Vec::new(), &RosterChange::Rename { roster_id: first_id, name: second_name, }).is_err());
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &Vec::new(), &RosterChange::Rename { roster_id: first_id, name: " ".to_string(), }).is_err());
+		// Nor with the units of the campaign's scenarios, whichever their team.
+		let reserved_names = reserved_names(&load_campaign_definition(CAMPAIGN_FILE).unwrap());
+		assert!(reserved_names.contains(&"Naked Fanatic".to_string()));
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &reserved_names, &RosterChange::Rename { roster_id: first_id, name: "Naked Fanatic".to_string(), }).is_err());
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &reserved_names, &RosterChange::Rename { roster_id: first_id, name: " Hamilcar ".to_string(), }).is_ok());
+		assert_eq!(progress.roster[0].name, "Hamilcar");
+
+		// A story unit stays in the roster.
+		progress.roster[1].name = "Hanno".to_string();
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &Vec::new(), &RosterChange::Rename { roster_id: second_id, name: "Bostar".to_string(), }).is_err());
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &Vec::new(), &RosterChange::Dismiss { roster_id: second_id, }).is_err());
+
+		// A dismissed unit leaves its equipment and items behind.
+		progress.roster[0].equipment.weapon = Some("Sling".to_string());
+		progress.roster[0].inventory = vec!["Posca".to_string()];
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &Vec::new(), &RosterChange::Dismiss { roster_id: first_id, }).is_ok());
+		assert_eq!(progress.roster.len(), 1);
+		assert_eq!(progress.inventory, vec!["Sling".to_string(), "Posca".to_string()]);
+
+		// The next recruit doesn't get the dismissed unit's id.
+		assert!(apply_roster_change(&mut progress, &definition, &classes, &story_units, &Vec::new(), &recruit).is_ok());
+		assert!(progress.roster.iter().all(|roster_unit| roster_unit.roster_id != first_id));
+		assert_eq!(progress.roster.last().unwrap().roster_id, second_id + 1);
+	}
+
+	#[test]
+	fn neutral_units_only_count_by_name_in_objectives() {
+		let objectives = Objectives {
+			team: 1,
+			victory: vec![Objective::DefeatAllEnemies],
+			defeat: vec![Objective::AllUnitsLost, Objective::UnitDefeated { unit_name: "Village Elder".to_string(), }],
+			..default()
+		};
+		let mut alliances = Alliances::default();
+		alliances.set_stance(1, 3, Stance::Allied);
+
+		// Neutral units of a hostile team aren't enemies to defeat.
+		let units = vec![
+			(1, "Hanno".to_string(), Pos { x: 1, y: 1, }, UnitRole::Regular),
+			(2, "Herdsman".to_string(), Pos { x: 9, y: 9, }, UnitRole::Neutral),
+			(3, "Village Elder".to_string(), Pos { x: 5, y: 5, }, UnitRole::Neutral),
+		];
+		assert_eq!(objectives.outcome(&units, &alliances), Some((true, Objective::DefeatAllEnemies)));
+
+		// Nor do allied neutral units keep the team in the battle.
+		let units = vec![
+			(2, "Herdsman".to_string(), Pos { x: 9, y: 9, }, UnitRole::Neutral),
+			(3, "Village Elder".to_string(), Pos { x: 5, y: 5, }, UnitRole::Neutral),
+		];
+		assert_eq!(objectives.outcome(&units, &alliances), Some((false, Objective::AllUnitsLost)));
+
+		// But they can be lost by name.
+		let units = vec![
+			(1, "Hanno".to_string(), Pos { x: 1, y: 1, }, UnitRole::Regular),
+			(2, "Gaul Warrior".to_string(), Pos { x: 9, y: 9, }, UnitRole::Regular),
+		];
+		assert_eq!(objectives.outcome(&units, &alliances), Some((false, Objective::UnitDefeated { unit_name: "Village Elder".to_string(), })));
+	}
+
+	#[test]
+	fn guests_and_neutral_units_are_left_to_the_ai() {
+		let mut app = test_app();
+		setup_battle(&mut app);
+		app.world.resource_mut::<Game>().is_multiplayer = false;
+		app.world.resource_mut::<Game>().players.insert(1, ControlledBy::Player);
+		app.add_systems(Update, wait_turn_system.run_if(in_state(TurnState::Wait)));
+
+		// The guest is on the player's team, but its turn goes to the AI.
+		spawn_test_unit(&mut app, 1, 1, Pos { x: 1, y: 1, });
+		let guest = spawn_test_unit(&mut app, 2, 1, Pos { x: 1, y: 2, });
+		app.world.entity_mut(guest).insert((UnitRole::Guest, WTCurrent { value: 0, }));
+		app.update();
+		app.update();
+		assert_eq!(turn_state(&app), TurnState::AI);
+		assert!(app.world.get::<CurrentUnit>(guest).is_some());
+
+		// A neutral unit runs from the closest hostile unit, as far as it can.
+		let map = app.world.query::<&Map>().single(&app.world).map.clone();
+		let hostile_positions = vec![Pos { x: 3, y: 5, }];
+		let destination = flee_destination(&map, Pos { x: 5, y: 5, }, 3, 1, &hostile_positions, &ScenarioRules::default()).unwrap();
+		assert_eq!(destination.x.abs_diff(3) + destination.y.abs_diff(5), 5);
+		assert_eq!(flee_destination(&map, Pos { x: 5, y: 5, }, 3, 1, &Vec::new(), &ScenarioRules::default()), None);
+	}
+}